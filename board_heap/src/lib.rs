@@ -0,0 +1,605 @@
+/*!
+A free list heap for the boards, replacing the bump allocator that couldn't free anything
+
+Free blocks are kept in a singly linked list in address order, each block's header (its size
+and the next free block) is stored in the free memory itself.  Allocation takes the first block
+that fits, splitting off what is left over before and after it, and freeing puts the block back
+in order and merges it with free neighbours so the free space doesn't splinter.
+*/
+
+#![no_std]
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::{RefCell, UnsafeCell};
+use core::mem::{MaybeUninit, align_of, size_of};
+use core::ptr::{NonNull, null_mut};
+
+use critical_section::Mutex;
+use net_common::HeapStats;
+
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+// every block size is a multiple of BLOCK_ALIGN and at least MIN_BLOCK, so any freed block
+// can hold a FreeBlock
+const BLOCK_ALIGN: usize = align_of::<FreeBlock>();
+const MIN_BLOCK: usize = size_of::<FreeBlock>();
+
+fn align_up(addr: usize, align: usize) -> Option<usize> {
+    Some(addr.checked_add(align - 1)? & !(align - 1))
+}
+
+/// the bytes taken from the heap for an allocation with this layout
+fn block_size(layout: &Layout) -> Option<usize> {
+    align_up(layout.size().max(MIN_BLOCK), BLOCK_ALIGN)
+}
+
+/// where in a free block an allocation would go, the free bytes left before and after it
+fn fit(start: usize, block_size: usize, size: usize, align: usize) -> Option<(usize, usize)> {
+    let mut aligned = align_up(start, align)?;
+    // the space skipped for alignment has to be big enough to stay on the free list
+    if aligned != start && aligned - start < MIN_BLOCK {
+        aligned = align_up(start + MIN_BLOCK, align)?;
+    }
+    let end = aligned.checked_add(size)?;
+    let block_end = start + block_size;
+    if end > block_end {
+        return None;
+    }
+    let back = block_end - end;
+    // too small to track, and the allocation can't grow to cover it because dealloc only
+    // gets the layout
+    if back != 0 && back < MIN_BLOCK {
+        return None;
+    }
+    Some((aligned - start, back))
+}
+
+pub struct Heap {
+    /// the lowest address free block
+    free: Option<NonNull<FreeBlock>>,
+    size: usize,
+    used: usize,
+    high_water: usize,
+    allocations: usize,
+    failed: usize,
+}
+
+// the pointers are only into the heap's own memory
+unsafe impl Send for Heap {}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Heap {
+    /// every allocation fails until init
+    pub const fn empty() -> Self {
+        Self {
+            free: None,
+            size: 0,
+            used: 0,
+            high_water: 0,
+            allocations: 0,
+            failed: 0,
+        }
+    }
+
+    /// # Safety
+    /// the memory has to be valid to read and write for as long as the heap is used, and used
+    /// by nothing else, and this is only called once
+    pub unsafe fn init(&mut self, start: *mut u8, size: usize) {
+        let Some(aligned) = align_up(start as usize, BLOCK_ALIGN) else {
+            return;
+        };
+        let offset = aligned - start as usize;
+        let size = size.saturating_sub(offset) & !(BLOCK_ALIGN - 1);
+        if size < MIN_BLOCK {
+            return;
+        }
+        let block = unsafe { start.add(offset) } as *mut FreeBlock;
+        unsafe { block.write(FreeBlock { size, next: None }) };
+        self.free = NonNull::new(block);
+        self.size = size;
+    }
+
+    /// 0 before init
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// None if there is no free block big enough
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let rv = block_size(&layout).and_then(|size| Some((self.first_fit(size, &layout)?, size)));
+        let Some((ptr, size)) = rv else {
+            self.failed += 1;
+            return None;
+        };
+        self.used += size;
+        self.high_water = self.high_water.max(self.used);
+        self.allocations += 1;
+        Some(ptr)
+    }
+
+    fn first_fit(&mut self, size: usize, layout: &Layout) -> Option<NonNull<u8>> {
+        let align = layout.align().max(BLOCK_ALIGN);
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.free;
+        while let Some(block) = current {
+            let (block_size, next) = unsafe {
+                let block = block.as_ref();
+                (block.size, block.next)
+            };
+            let Some((front, back)) = fit(block.as_ptr() as usize, block_size, size, align) else {
+                prev = current;
+                current = next;
+                continue;
+            };
+
+            let ptr = unsafe { block.as_ptr().cast::<u8>().add(front) };
+            let after = if back == 0 {
+                next
+            } else {
+                let tail = unsafe { ptr.add(size) }.cast::<FreeBlock>();
+                unsafe { tail.write(FreeBlock { size: back, next }) };
+                NonNull::new(tail)
+            };
+            if front == 0 {
+                match prev {
+                    Some(prev) => unsafe { (*prev.as_ptr()).next = after },
+                    None => self.free = after,
+                }
+            } else {
+                // the skipped front part stays where it is on the list
+                unsafe {
+                    (*block.as_ptr()).size = front;
+                    (*block.as_ptr()).next = after;
+                }
+            }
+            return NonNull::new(ptr);
+        }
+        None
+    }
+
+    /// # Safety
+    /// ptr has to have come from allocate on this heap with the same layout, and not already
+    /// been deallocated
+    pub unsafe fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        // this worked when it was allocated
+        let size = block_size(&layout).unwrap_or(MIN_BLOCK);
+        self.used -= size;
+        self.allocations -= 1;
+
+        let addr = ptr.as_ptr() as usize;
+        let mut prev: Option<NonNull<FreeBlock>> = None;
+        let mut next = self.free;
+        while let Some(block) = next {
+            if block.as_ptr() as usize > addr {
+                break;
+            }
+            prev = next;
+            next = unsafe { block.as_ref().next };
+        }
+
+        let block = ptr.as_ptr().cast::<FreeBlock>();
+        unsafe {
+            block.write(FreeBlock { size, next });
+            if let Some(next) = next
+                && addr + size == next.as_ptr() as usize
+            {
+                (*block).size += next.as_ref().size;
+                (*block).next = next.as_ref().next;
+            }
+            match prev {
+                Some(prev) if prev.as_ptr() as usize + prev.as_ref().size == addr => {
+                    (*prev.as_ptr()).size += (*block).size;
+                    (*prev.as_ptr()).next = (*block).next;
+                }
+                Some(prev) => (*prev.as_ptr()).next = NonNull::new(block),
+                None => self.free = NonNull::new(block),
+            }
+        }
+    }
+
+    /// the address and size of each free block, lowest address first
+    pub fn free_blocks(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let mut current = self.free;
+        core::iter::from_fn(move || {
+            let block = current?;
+            let block_ref = unsafe { block.as_ref() };
+            current = block_ref.next;
+            Some((block.as_ptr() as usize, block_ref.size))
+        })
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        let largest_free = self.free_blocks().map(|(_, size)| size).max().unwrap_or(0);
+        HeapStats {
+            size: self.size as u32,
+            used: self.used as u32,
+            high_water: self.high_water as u32,
+            allocations: self.allocations as u32,
+            failed: self.failed as u32,
+            largest_free: largest_free as u32,
+        }
+    }
+}
+
+/// a Heap over its own N byte arena behind a critical section, for use as the
+/// #[global_allocator]
+pub struct LockedHeap<const N: usize> {
+    arena: UnsafeCell<MaybeUninit<[u8; N]>>,
+    heap: Mutex<RefCell<Heap>>,
+}
+
+// the arena is only touched through the heap, inside the critical section
+unsafe impl<const N: usize> Sync for LockedHeap<N> {}
+
+impl<const N: usize> Default for LockedHeap<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LockedHeap<N> {
+    pub const fn new() -> Self {
+        Self {
+            arena: UnsafeCell::new(MaybeUninit::uninit()),
+            heap: Mutex::new(RefCell::new(Heap::empty())),
+        }
+    }
+
+    /// the heap is set up on first use, there is no init to forget to call
+    fn with_heap<R>(&self, f: impl FnOnce(&mut Heap) -> R) -> R {
+        critical_section::with(|cs| {
+            let mut heap = self.heap.borrow_ref_mut(cs);
+            if heap.size() == 0 {
+                unsafe { heap.init(self.arena.get().cast::<u8>(), N) };
+            }
+            f(&mut heap)
+        })
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.with_heap(|heap| heap.stats())
+    }
+}
+
+unsafe impl<const N: usize> GlobalAlloc for LockedHeap<N> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with_heap(|heap| heap.allocate(layout))
+            .map(|ptr| ptr.as_ptr())
+            .unwrap_or(null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(ptr) = NonNull::new(ptr) {
+            self.with_heap(|heap| unsafe { heap.deallocate(ptr, layout) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use fastrand::Rng;
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    const ARENA_SIZE: usize = 64 * 1024;
+    const ALIGNS: [usize; 8] = [1, 2, 4, 8, 16, 64, 256, 4096];
+
+    /// mostly small like the firmware's allocations, sometimes big
+    fn random_layout(rng: &mut Rng) -> Layout {
+        let size = match rng.usize(..10) {
+            0 => rng.usize(1..=8192),
+            1..=3 => rng.usize(1..=512),
+            _ => rng.usize(1..=64),
+        };
+        let align = ALIGNS[rng.usize(..ALIGNS.len())];
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    struct Live {
+        ptr: NonNull<u8>,
+        layout: Layout,
+        fill: u8,
+    }
+
+    impl Live {
+        fn bytes(&self) -> &[u8] {
+            unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.layout.size()) }
+        }
+
+        fn fill(&self) {
+            unsafe { self.ptr.as_ptr().write_bytes(self.fill, self.layout.size()) };
+        }
+
+        fn check(&self) {
+            assert!(
+                self.bytes().iter().all(|&b| b == self.fill),
+                "allocation at {:?} was overwritten",
+                self.ptr
+            );
+        }
+    }
+
+    fn check_heap(heap: &Heap, live: &[Live], arena: (usize, usize)) {
+        let stats = heap.stats();
+        let mut free_total = 0;
+        let mut last_end = None;
+        for (addr, size) in heap.free_blocks() {
+            assert!(
+                addr >= arena.0 && addr + size <= arena.1,
+                "free block outside the arena"
+            );
+            if let Some(last_end) = last_end {
+                // adjacent free blocks should have been merged
+                assert!(addr > last_end, "free list out of order or not merged");
+            }
+            for allocation in live {
+                let start = allocation.ptr.as_ptr() as usize;
+                let end = start + allocation.layout.size();
+                assert!(
+                    end <= addr || start >= addr + size,
+                    "allocation in a free block"
+                );
+            }
+            last_end = Some(addr + size);
+            free_total += size;
+        }
+        assert_eq!(free_total + stats.used as usize, stats.size as usize);
+        assert_eq!(stats.allocations as usize, live.len());
+        assert!(stats.high_water >= stats.used);
+    }
+
+    #[repr(align(4096))]
+    struct Arena([u8; ARENA_SIZE + 4096]);
+
+    /// a heap over a fresh arena starting offset bytes past a 4096 byte boundary
+    fn heap_at(offset: usize) -> (Heap, Box<Arena>) {
+        let mut arena = Box::new(Arena([0; ARENA_SIZE + 4096]));
+        let mut heap = Heap::empty();
+        unsafe { heap.init(arena.0.as_mut_ptr().add(offset), ARENA_SIZE) };
+        (heap, arena)
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    fn free_sizes(heap: &Heap) -> Vec<usize> {
+        heap.free_blocks().map(|(_, size)| size).collect()
+    }
+
+    /// randomized alloc/free, checking after every few steps that allocations don't overlap
+    /// or get overwritten, and that the free list and the stats add up
+    fn stress(steps: usize, seed: u64) {
+        let mut rng = Rng::with_seed(seed);
+        // deliberately misaligned so init has to round
+        let (mut heap, arena) = heap_at(3);
+        let start = arena.0.as_ptr() as usize + 3;
+        let bounds = (start, start + ARENA_SIZE);
+
+        let mut live: Vec<Live> = Vec::new();
+        let mut failed = 0;
+        for step in 0..steps {
+            // allocate a bit more often than free so the heap fills up and allocations fail
+            if live.is_empty() || rng.usize(..100) < 55 {
+                let layout = random_layout(&mut rng);
+                match heap.allocate(layout) {
+                    Some(ptr) => {
+                        assert_eq!(ptr.as_ptr() as usize % layout.align(), 0, "misaligned");
+                        let allocation = Live {
+                            ptr,
+                            layout,
+                            fill: step as u8,
+                        };
+                        allocation.fill();
+                        live.push(allocation);
+                    }
+                    None => failed += 1,
+                }
+            } else {
+                let allocation = live.swap_remove(rng.usize(..live.len()));
+                allocation.check();
+                unsafe { heap.deallocate(allocation.ptr, allocation.layout) };
+            }
+            if step % 64 == 0 {
+                check_heap(&heap, &live, bounds);
+            }
+        }
+        check_heap(&heap, &live, bounds);
+        let stats = heap.stats();
+        assert_eq!(stats.failed, failed);
+        assert!(failed > 0, "the heap never filled up");
+
+        for allocation in live.drain(..) {
+            allocation.check();
+            unsafe { heap.deallocate(allocation.ptr, allocation.layout) };
+        }
+        // everything merged back into one block
+        let free: Vec<_> = heap.free_blocks().collect();
+        assert_eq!(free.len(), 1);
+        assert_eq!(free[0].1, heap.size());
+    }
+
+    #[test]
+    fn stress_heap() {
+        for seed in [1, 7, 12345] {
+            stress(50_000, seed);
+        }
+    }
+
+    /// the same through the GlobalAlloc interface, where realloc is alloc, copy and free
+    #[test]
+    fn stress_locked_heap() {
+        static LOCKED: LockedHeap<ARENA_SIZE> = LockedHeap::new();
+        let mut rng = Rng::with_seed(3);
+        let mut live: Vec<Live> = Vec::new();
+        for step in 0..20_000 {
+            let layout = random_layout(&mut rng);
+            match rng.usize(..3) {
+                0 | 1 if live.len() < 32 => {
+                    let ptr = unsafe { LOCKED.alloc(layout) };
+                    if let Some(ptr) = NonNull::new(ptr) {
+                        let allocation = Live {
+                            ptr,
+                            layout,
+                            fill: step as u8,
+                        };
+                        allocation.fill();
+                        live.push(allocation);
+                    }
+                }
+                _ if !live.is_empty() => {
+                    let index = rng.usize(..live.len());
+                    let old = &live[index];
+                    old.check();
+                    let new_size = layout.size();
+                    let ptr = unsafe { LOCKED.realloc(old.ptr.as_ptr(), old.layout, new_size) };
+                    if let Some(ptr) = NonNull::new(ptr) {
+                        let kept = old.layout.size().min(new_size);
+                        let fill = old.fill;
+                        let new_layout =
+                            Layout::from_size_align(new_size, old.layout.align()).unwrap();
+                        let bytes = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), kept) };
+                        assert!(
+                            bytes.iter().all(|&b| b == fill),
+                            "realloc lost the contents"
+                        );
+                        let allocation = Live {
+                            ptr,
+                            layout: new_layout,
+                            fill: step as u8,
+                        };
+                        allocation.fill();
+                        live[index] = allocation;
+                    }
+                }
+                _ => {}
+            }
+            if rng.usize(..4) == 0 && !live.is_empty() {
+                let allocation = live.swap_remove(rng.usize(..live.len()));
+                allocation.check();
+                unsafe { LOCKED.dealloc(allocation.ptr.as_ptr(), allocation.layout) };
+            }
+            assert_eq!(LOCKED.stats().allocations as usize, live.len());
+        }
+        for allocation in live.drain(..) {
+            allocation.check();
+            unsafe { LOCKED.dealloc(allocation.ptr.as_ptr(), allocation.layout) };
+        }
+        let stats = LOCKED.stats();
+        assert_eq!(stats.used, 0);
+        assert_eq!(stats.largest_free, stats.size);
+    }
+
+    #[test]
+    fn coalesces_with_both_neighbours() {
+        let (mut heap, _arena) = heap_at(0);
+        let small = layout(64, 8);
+        let [a, b, c] = [(); 3].map(|_| heap.allocate(small).unwrap());
+        // allocated in address order from the start
+        assert_eq!(b.as_ptr() as usize - a.as_ptr() as usize, 64);
+        assert_eq!(c.as_ptr() as usize - b.as_ptr() as usize, 64);
+        let tail = ARENA_SIZE - 3 * 64;
+        assert_eq!(free_sizes(&heap), [tail]);
+
+        unsafe { heap.deallocate(a, small) };
+        assert_eq!(free_sizes(&heap), [64, tail]);
+        // merges with the following free block
+        unsafe { heap.deallocate(c, small) };
+        assert_eq!(free_sizes(&heap), [64, 64 + tail]);
+        // and both sides at once
+        unsafe { heap.deallocate(b, small) };
+        assert_eq!(free_sizes(&heap), [ARENA_SIZE]);
+
+        // merging with the previous free block only
+        let [a, b, _c] = [(); 3].map(|_| heap.allocate(small).unwrap());
+        unsafe { heap.deallocate(a, small) };
+        unsafe { heap.deallocate(b, small) };
+        assert_eq!(free_sizes(&heap), [128, tail]);
+        assert_eq!(heap.stats().largest_free as usize, tail);
+        // first fit reuses the merged space
+        let big = layout(128, 8);
+        assert_eq!(heap.allocate(big), Some(a));
+        assert_eq!(free_sizes(&heap), [tail]);
+    }
+
+    #[test]
+    fn alignment() {
+        let (mut heap, arena) = heap_at(0);
+        let base = arena.0.as_ptr() as usize;
+        let byte = heap.allocate(layout(1, 1)).unwrap();
+        assert_eq!(byte.as_ptr() as usize, base);
+
+        // the gap skipped to align stays on the free list
+        let page = layout(100, 4096);
+        let aligned = heap.allocate(page).unwrap();
+        assert_eq!(aligned.as_ptr() as usize, base + 4096);
+        let blocks: Vec<_> = heap.free_blocks().collect();
+        assert_eq!(blocks[0], (base + MIN_BLOCK, 4096 - MIN_BLOCK));
+        // which smaller allocations then use
+        let filler = heap.allocate(layout(8, 8)).unwrap();
+        assert_eq!(filler.as_ptr() as usize, base + MIN_BLOCK);
+
+        for align in ALIGNS {
+            let ptr = heap.allocate(layout(3, align)).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % align, 0, "{align}");
+            unsafe { heap.deallocate(ptr, layout(3, align)) };
+        }
+
+        unsafe {
+            heap.deallocate(byte, layout(1, 1));
+            heap.deallocate(filler, layout(8, 8));
+            heap.deallocate(aligned, page);
+        }
+        assert_eq!(free_sizes(&heap), [ARENA_SIZE]);
+        assert_eq!(heap.stats().used, 0);
+    }
+
+    #[test]
+    fn gap_too_small_to_track() {
+        let (mut heap, arena) = heap_at(0);
+        let base = arena.0.as_ptr() as usize;
+        // one BLOCK_ALIGN past a 16 byte boundary, a 16 byte aligned allocation can't leave
+        // that little in front, so it goes further on
+        let first = heap.allocate(layout(MIN_BLOCK + BLOCK_ALIGN, 1)).unwrap();
+        let aligned = heap.allocate(layout(8, 16)).unwrap();
+        let addr = aligned.as_ptr() as usize;
+        assert_eq!(addr % 16, 0);
+        let gap = addr - (base + MIN_BLOCK + BLOCK_ALIGN);
+        assert!(gap >= MIN_BLOCK, "{gap}");
+        unsafe {
+            heap.deallocate(first, layout(MIN_BLOCK + BLOCK_ALIGN, 1));
+            heap.deallocate(aligned, layout(8, 16));
+        }
+        assert_eq!(free_sizes(&heap), [ARENA_SIZE]);
+    }
+
+    #[test]
+    fn exhaustion() {
+        let (mut heap, _arena) = heap_at(0);
+        assert_eq!(heap.allocate(layout(ARENA_SIZE + 1, 1)), None);
+        let all = heap.allocate(layout(ARENA_SIZE, 8)).unwrap();
+        assert_eq!(heap.allocate(layout(1, 1)), None);
+        let stats = heap.stats();
+        assert_eq!(stats.failed, 2);
+        assert_eq!(stats.used as usize, ARENA_SIZE);
+        assert_eq!(stats.largest_free, 0);
+        unsafe { heap.deallocate(all, layout(ARENA_SIZE, 8)) };
+        assert_eq!(heap.stats().high_water as usize, ARENA_SIZE);
+
+        // nothing before init
+        let mut empty = Heap::empty();
+        assert_eq!(empty.allocate(layout(1, 1)), None);
+        assert_eq!(empty.size(), 0);
+    }
+}
@@ -0,0 +1,714 @@
+/*!
+Settings that survive a reset, kept as a key/value log in a few reserved pages (erase sectors) of
+flash through the embedded-storage NorFlash traits
+
+Every change appends a record to the current page, so nothing is erased until the page fills.
+Then the latest record for each key is copied to the next page, that page gets a header with a
+higher sequence number, and the full page is erased, so the erases rotate through all the pages.
+Headers and records have a crc and the new page header is written last, a reset at any point
+loses at most the change being written.
+
+The caller gives each record a version, a value stored with another version than the one asked
+for reads as missing so a changed layout falls back to the defaults.
+
+```text
+page:   [header: magic, format, sequence, crc] [record] [record] ... [erased]
+record: [key, version, kind, len, crc] [value, padded to the flash write size]
+```
+
+Checked on the host against RamFlash by `cargo test`.
+*/
+
+#![no_std]
+
+use embedded_storage::nor_flash::NorFlash;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+mod ram;
+
+pub use ram::{RamFlash, RamFlashError};
+
+/// longest value that can be stored
+pub const MAX_VALUE_LEN: usize = 128;
+/// most keys that can be live at once, compacting keeps track of each
+pub const MAX_KEYS: usize = 32;
+/// the largest flash write size supported, the stm32h7 is 32
+pub const MAX_WRITE_SIZE: usize = 64;
+
+const PAGE_MAGIC: u32 = u32::from_le_bytes(*b"SETS");
+/// changes if the page or record layout here changes, pages in another format are erased
+const FORMAT: u16 = 1;
+const PAGE_HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 12;
+// an unprogrammed record header is all ones, so neither kind is 0xff
+const KIND_VALUE: u8 = 0xa5;
+const KIND_REMOVED: u8 = 0x5a;
+const ERASED_KEY: u16 = 0xffff;
+// room for a whole record at the largest write size
+const BUF_LEN: usize = 256;
+
+static CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    Flash(E),
+    /// fewer than 2 pages, pages outside the flash, or a write size larger than MAX_WRITE_SIZE
+    Unsupported,
+    /// 0xffff is what erased flash reads as
+    InvalidKey,
+    /// longer than MAX_VALUE_LEN
+    TooLarge,
+    /// more than MAX_KEYS keys have values, or their values don't all fit in one page
+    TooManyKeys,
+    /// the buffer is too small for the value
+    Buffer,
+    Postcard(postcard::Error),
+}
+
+/// what is at an offset in a page
+enum Slot {
+    /// erased, or too close to the end of the page for a record
+    End,
+    /// a bad crc or kind, most likely a write cut short by a reset, with where the next record
+    /// would be if the length can be believed
+    Corrupt { next: Option<u32> },
+    Record {
+        key: u16,
+        version: u8,
+        removed: bool,
+        len: usize,
+        next: u32,
+    },
+}
+
+pub struct Settings<S> {
+    flash: S,
+    /// offset of the first page in the flash
+    start: u32,
+    pages: u32,
+    /// records are appended to this page
+    page: u32,
+    sequence: u32,
+    /// offset in the page of the next record, the page size when it is full
+    end: u32,
+}
+
+impl<S: NorFlash> Settings<S> {
+    const PAGE_SIZE: u32 = S::ERASE_SIZE as u32;
+
+    fn aligned(len: usize) -> usize {
+        len.div_ceil(S::WRITE_SIZE) * S::WRITE_SIZE
+    }
+
+    fn records_start() -> u32 {
+        Self::aligned(PAGE_HEADER_LEN) as u32
+    }
+
+    fn page_offset(&self, page: u32) -> u32 {
+        self.start + page * Self::PAGE_SIZE
+    }
+
+    /// use the pages from `start` (a multiple of the erase size) in the flash, finishing a
+    /// compaction a reset interrupted, or starting empty if there are no valid pages
+    pub fn mount(flash: S, start: u32, pages: u32) -> Result<Self, Error<S::Error>> {
+        let fits = (pages as usize)
+            .checked_mul(S::ERASE_SIZE)
+            .and_then(|len| len.checked_add(start as usize))
+            .is_some_and(|end| end <= flash.capacity());
+        if pages < 2
+            || !fits
+            || !(start as usize).is_multiple_of(S::ERASE_SIZE)
+            || S::WRITE_SIZE > MAX_WRITE_SIZE
+            || !S::WRITE_SIZE.is_multiple_of(S::READ_SIZE)
+        {
+            return Err(Error::Unsupported);
+        }
+        let mut settings = Self {
+            flash,
+            start,
+            pages,
+            page: 0,
+            sequence: 0,
+            end: Self::records_start(),
+        };
+
+        let mut newest: Option<(u32, u32)> = None;
+        for page in 0..pages {
+            if let Some(sequence) = settings.page_sequence(page)? {
+                let newer = newest.is_none_or(|(_, newest_sequence)| {
+                    (sequence.wrapping_sub(newest_sequence) as i32) > 0
+                });
+                if newer {
+                    newest = Some((page, sequence));
+                }
+            }
+        }
+        let Some((page, sequence)) = newest else {
+            settings.erase(0)?;
+            settings.write_page_header(0, 0)?;
+            return Ok(settings);
+        };
+        settings.page = page;
+        settings.sequence = sequence;
+
+        // the page a compaction copied from, if a reset came before it was erased
+        for other in (0..pages).filter(|other| *other != page) {
+            if settings.page_sequence(other)?.is_some() {
+                settings.erase(other)?;
+            }
+        }
+
+        let mut offset = Self::records_start();
+        let mut buf = [0; BUF_LEN];
+        loop {
+            match settings.read_slot(page, offset, &mut buf)? {
+                Slot::End => {
+                    break;
+                }
+                Slot::Record { next, .. } => {
+                    offset = next;
+                }
+                Slot::Corrupt { next } => {
+                    // the cut short write may reach past where its length says, only write to
+                    // a fresh page from here on
+                    match next {
+                        Some(next) => offset = next,
+                        None => offset = Self::PAGE_SIZE,
+                    }
+                    settings.end = Self::PAGE_SIZE;
+                }
+            }
+            if offset >= Self::PAGE_SIZE {
+                break;
+            }
+        }
+        if settings.end != Self::PAGE_SIZE {
+            settings.end = offset;
+        }
+        Ok(settings)
+    }
+
+    /// the flash back, to use for something else
+    pub fn release(self) -> S {
+        self.flash
+    }
+
+    /// the page being written to
+    pub fn page(&self) -> u32 {
+        self.page
+    }
+
+    /// how many times the pages have been compacted
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// bytes left in the current page before the next compaction
+    pub fn free(&self) -> u32 {
+        Self::PAGE_SIZE - self.end
+    }
+
+    fn erase(&mut self, page: u32) -> Result<(), Error<S::Error>> {
+        let offset = self.page_offset(page);
+        self.flash
+            .erase(offset, offset + Self::PAGE_SIZE)
+            .map_err(Error::Flash)
+    }
+
+    /// None if the header isn't valid
+    fn page_sequence(&mut self, page: u32) -> Result<Option<u32>, Error<S::Error>> {
+        let mut header = [0; MAX_WRITE_SIZE];
+        let header = &mut header[..Self::records_start() as usize];
+        self.flash
+            .read(self.page_offset(page), header)
+            .map_err(Error::Flash)?;
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let format = u16::from_le_bytes(header[4..6].try_into().unwrap());
+        let sequence = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let crc = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        let valid = magic == PAGE_MAGIC && format == FORMAT && crc == CRC.checksum(&header[..12]);
+        Ok(valid.then_some(sequence))
+    }
+
+    fn write_page_header(&mut self, page: u32, sequence: u32) -> Result<(), Error<S::Error>> {
+        let mut header = [0xff; MAX_WRITE_SIZE];
+        header[0..4].copy_from_slice(&PAGE_MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&FORMAT.to_le_bytes());
+        header[8..12].copy_from_slice(&sequence.to_le_bytes());
+        let crc = CRC.checksum(&header[..12]);
+        header[12..16].copy_from_slice(&crc.to_le_bytes());
+        let len = Self::records_start() as usize;
+        self.flash
+            .write(self.page_offset(page), &header[..len])
+            .map_err(Error::Flash)
+    }
+
+    /// the record at offset is left in buf, header then value
+    fn read_slot(
+        &mut self,
+        page: u32,
+        offset: u32,
+        buf: &mut [u8; BUF_LEN],
+    ) -> Result<Slot, Error<S::Error>> {
+        let header_len = Self::aligned(RECORD_HEADER_LEN);
+        if offset as usize + header_len > Self::PAGE_SIZE as usize {
+            return Ok(Slot::End);
+        }
+        let base = self.page_offset(page) + offset;
+        self.flash
+            .read(base, &mut buf[..header_len])
+            .map_err(Error::Flash)?;
+        let header = &buf[..RECORD_HEADER_LEN];
+        if header.iter().all(|byte| *byte == 0xff) {
+            return Ok(Slot::End);
+        }
+
+        let key = u16::from_le_bytes([header[0], header[1]]);
+        let version = header[2];
+        let kind = header[3];
+        let len = u16::from_le_bytes([header[4], header[5]]) as usize;
+        let crc = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        let total = Self::aligned(RECORD_HEADER_LEN + len);
+        if len > MAX_VALUE_LEN || offset as usize + total > Self::PAGE_SIZE as usize {
+            return Ok(Slot::Corrupt { next: None });
+        }
+        let next = offset + total as u32;
+        if total > header_len {
+            self.flash
+                .read(base + header_len as u32, &mut buf[header_len..total])
+                .map_err(Error::Flash)?;
+        }
+        let mut digest = CRC.digest();
+        digest.update(&buf[..8]);
+        digest.update(&buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + len]);
+        if digest.finalize() != crc || !(kind == KIND_VALUE || kind == KIND_REMOVED) {
+            return Ok(Slot::Corrupt { next: Some(next) });
+        }
+        Ok(Slot::Record {
+            key,
+            version,
+            removed: kind == KIND_REMOVED,
+            len,
+            next,
+        })
+    }
+
+    /// offset of the latest record for the key in the current page, None if there is none or
+    /// it was removed
+    fn find(&mut self, key: u16) -> Result<Option<u32>, Error<S::Error>> {
+        let mut found = None;
+        let mut offset = Self::records_start();
+        let mut buf = [0; BUF_LEN];
+        while offset < Self::PAGE_SIZE {
+            match self.read_slot(self.page, offset, &mut buf)? {
+                Slot::End | Slot::Corrupt { next: None } => {
+                    break;
+                }
+                Slot::Corrupt { next: Some(next) } => {
+                    offset = next;
+                }
+                Slot::Record {
+                    key: record_key,
+                    removed,
+                    next,
+                    ..
+                } => {
+                    if record_key == key {
+                        found = (!removed).then_some(offset);
+                    }
+                    offset = next;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// copy the value stored with this version into buf, returning its length, None if there
+    /// is no value or it has another version
+    pub fn get(
+        &mut self,
+        key: u16,
+        version: u8,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, Error<S::Error>> {
+        let Some(offset) = self.find(key)? else {
+            return Ok(None);
+        };
+        let mut record = [0; BUF_LEN];
+        let Slot::Record {
+            version: stored_version,
+            len,
+            ..
+        } = self.read_slot(self.page, offset, &mut record)?
+        else {
+            return Ok(None);
+        };
+        if stored_version != version {
+            return Ok(None);
+        }
+        let value = &record[RECORD_HEADER_LEN..RECORD_HEADER_LEN + len];
+        buf.get_mut(..len)
+            .ok_or(Error::Buffer)?
+            .copy_from_slice(value);
+        Ok(Some(len))
+    }
+
+    /// does nothing if the same value and version are already stored, to save the flash
+    pub fn set(&mut self, key: u16, version: u8, value: &[u8]) -> Result<(), Error<S::Error>> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::TooLarge);
+        }
+        let mut current = [0; MAX_VALUE_LEN];
+        if let Some(len) = self.get(key, version, &mut current)?
+            && current[..len] == *value
+        {
+            return Ok(());
+        }
+        self.append(key, version, KIND_VALUE, value)
+    }
+
+    /// back to having no value
+    pub fn remove(&mut self, key: u16) -> Result<(), Error<S::Error>> {
+        if self.find(key)?.is_none() {
+            return Ok(());
+        }
+        self.append(key, 0, KIND_REMOVED, &[])
+    }
+
+    /// the value stored with this version as postcard, None if there is none or it doesn't
+    /// decode
+    pub fn load<T: DeserializeOwned>(
+        &mut self,
+        key: u16,
+        version: u8,
+    ) -> Result<Option<T>, Error<S::Error>> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        let Some(len) = self.get(key, version, &mut buf)? else {
+            return Ok(None);
+        };
+        Ok(postcard::from_bytes(&buf[..len]).ok())
+    }
+
+    pub fn store<T: Serialize>(
+        &mut self,
+        key: u16,
+        version: u8,
+        value: &T,
+    ) -> Result<(), Error<S::Error>> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        let bytes = postcard::to_slice(value, &mut buf).map_err(Error::Postcard)?;
+        self.set(key, version, bytes)
+    }
+
+    fn append(
+        &mut self,
+        key: u16,
+        version: u8,
+        kind: u8,
+        value: &[u8],
+    ) -> Result<(), Error<S::Error>> {
+        if key == ERASED_KEY {
+            return Err(Error::InvalidKey);
+        }
+        let total = Self::aligned(RECORD_HEADER_LEN + value.len());
+        if self.end as usize + total > Self::PAGE_SIZE as usize {
+            self.compact()?;
+        }
+        // a page always has room for one record, and compacting only keeps what fit before
+        if self.end as usize + total > Self::PAGE_SIZE as usize {
+            return Err(Error::TooManyKeys);
+        }
+
+        let mut buf = [0xff; BUF_LEN];
+        buf[0..2].copy_from_slice(&key.to_le_bytes());
+        buf[2] = version;
+        buf[3] = kind;
+        buf[4..6].copy_from_slice(&(value.len() as u16).to_le_bytes());
+        buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + value.len()].copy_from_slice(value);
+        let mut digest = CRC.digest();
+        digest.update(&buf[..8]);
+        digest.update(value);
+        buf[8..12].copy_from_slice(&digest.finalize().to_le_bytes());
+
+        let offset = self.page_offset(self.page) + self.end;
+        if let Err(err) = self.flash.write(offset, &buf[..total]) {
+            // unknown how much was written, start a fresh page next time
+            self.end = Self::PAGE_SIZE;
+            return Err(Error::Flash(err));
+        }
+        self.end += total as u32;
+        Ok(())
+    }
+
+    /// copy the latest record of each key to the next page, then erase this one
+    fn compact(&mut self) -> Result<(), Error<S::Error>> {
+        // key, offset and length of the latest record of each key
+        let mut live = heapless::Vec::<(u16, u32, usize), MAX_KEYS>::new();
+        let mut offset = Self::records_start();
+        let mut buf = [0; BUF_LEN];
+        while offset < Self::PAGE_SIZE {
+            match self.read_slot(self.page, offset, &mut buf)? {
+                Slot::End | Slot::Corrupt { next: None } => {
+                    break;
+                }
+                Slot::Corrupt { next: Some(next) } => {
+                    offset = next;
+                }
+                Slot::Record {
+                    key, removed, next, ..
+                } => {
+                    let total = (next - offset) as usize;
+                    live.retain(|(live_key, _, _)| *live_key != key);
+                    if !removed {
+                        live.push((key, offset, total))
+                            .map_err(|_| Error::TooManyKeys)?;
+                    }
+                    offset = next;
+                }
+            }
+        }
+
+        let next_page = (self.page + 1) % self.pages;
+        self.erase(next_page)?;
+        let mut end = Self::records_start();
+        for (_, offset, total) in live {
+            let from = self.page_offset(self.page) + offset;
+            self.flash
+                .read(from, &mut buf[..total])
+                .map_err(Error::Flash)?;
+            let to = self.page_offset(next_page) + end;
+            self.flash.write(to, &buf[..total]).map_err(Error::Flash)?;
+            end += total as u32;
+        }
+        // until this is written the old page is still the newest
+        let sequence = self.sequence.wrapping_add(1);
+        self.write_page_header(next_page, sequence)?;
+        let old_page = self.page;
+        self.page = next_page;
+        self.sequence = sequence;
+        self.end = end;
+        self.erase(old_page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use fastrand::Rng;
+    use std::collections::HashMap;
+    use std::vec::Vec;
+
+    // 4 small pages with the stm32h7 write size, so compactions happen often
+    type Flash = RamFlash<{ 4 * 2048 }, 32, 2048>;
+    const PAGES: u32 = 4;
+    const KEYS: u16 = 12;
+
+    /// key -> version and value
+    type Model = HashMap<u16, (u8, Vec<u8>)>;
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Set(u16, u8, Vec<u8>),
+        Remove(u16),
+    }
+
+    impl Op {
+        fn random(rng: &mut Rng) -> Self {
+            let key = rng.usize(..KEYS as usize) as u16;
+            if rng.usize(..8) == 0 {
+                return Op::Remove(key);
+            }
+            let version = rng.usize(..2) as u8;
+            let len = match rng.usize(..4) {
+                0 => rng.usize(..=MAX_VALUE_LEN),
+                _ => rng.usize(..24),
+            };
+            let value = (0..len).map(|_| rng.u8(..)).collect();
+            Op::Set(key, version, value)
+        }
+
+        fn apply(&self, settings: &mut Settings<Flash>) -> Result<(), Error<RamFlashError>> {
+            match self {
+                Op::Set(key, version, value) => settings.set(*key, *version, value),
+                Op::Remove(key) => settings.remove(*key),
+            }
+        }
+
+        fn apply_model(&self, model: &mut Model) {
+            match self {
+                Op::Set(key, version, value) => {
+                    model.insert(*key, (*version, value.clone()));
+                }
+                Op::Remove(key) => {
+                    model.remove(key);
+                }
+            }
+        }
+    }
+
+    /// every key and version as stored
+    fn read_all(settings: &mut Settings<Flash>) -> Model {
+        let mut model = Model::new();
+        let mut buf = [0; MAX_VALUE_LEN];
+        for key in 0..KEYS {
+            for version in 0..2 {
+                if let Some(len) = settings.get(key, version, &mut buf).unwrap() {
+                    let old = model.insert(key, (version, buf[..len].to_vec()));
+                    assert!(old.is_none(), "key {key} has a value for both versions");
+                }
+            }
+        }
+        model
+    }
+
+    fn remount(flash: Flash) -> Settings<Flash> {
+        Settings::mount(flash, 0, PAGES).unwrap()
+    }
+
+    /// random sets and removes, checking after each one that every key reads back what was
+    /// last stored, including across a remount, and that a reset at any byte of a write
+    /// (cutting it short) loses at most that write
+    fn power_loss(seed: u64, steps: usize) {
+        let mut rng = Rng::with_seed(seed);
+        let mut settings = remount(Flash::new());
+        let mut model = Model::new();
+        let mut power_losses = 0;
+        for step in 0..steps {
+            let op = Op::random(&mut rng);
+
+            if rng.usize(..4) == 0 {
+                // cut the write short at a random byte, after the reset either the old or the
+                // new value can be there but nothing else may change
+                let mut flash = settings.release();
+                // mostly within one record, sometimes into a compaction
+                let budget = match rng.usize(..2) {
+                    0 => rng.usize(..192),
+                    _ => rng.usize(..2 * 2048),
+                };
+                flash.set_write_budget(Some(budget));
+                let mut cut = remount(flash);
+                if op.apply(&mut cut).is_err() {
+                    power_losses += 1;
+                }
+                let mut flash = cut.release();
+                flash.set_write_budget(None);
+                settings = remount(flash);
+                let mut with_op = model.clone();
+                op.apply_model(&mut with_op);
+                let after = read_all(&mut settings);
+                assert!(
+                    after == model || after == with_op,
+                    "seed {seed} step {step}: {op:?} cut short changed other keys"
+                );
+                model = after;
+            } else {
+                op.apply(&mut settings)
+                    .unwrap_or_else(|err| panic!("seed {seed} step {step} {op:?}: {err:?}"));
+                op.apply_model(&mut model);
+                assert_eq!(read_all(&mut settings), model, "step {step} after {op:?}");
+            }
+
+            if step % 97 == 0 {
+                settings = remount(settings.release());
+                assert_eq!(read_all(&mut settings), model, "step {step} remounted");
+            }
+        }
+
+        assert!(power_losses > 0, "no write was cut short");
+        // each compaction erases the page after the full one and then the full one, so the
+        // pages take turns
+        assert!(
+            settings.sequence() > PAGES,
+            "not enough writes to wrap around the pages"
+        );
+    }
+
+    #[test]
+    fn power_loss_seeds() {
+        for seed in [7, 1234, 0xdead_beef] {
+            power_loss(seed, 2000);
+        }
+    }
+
+    #[test]
+    fn set_get_remove() {
+        let mut settings = remount(Flash::new());
+        let mut buf = [0; MAX_VALUE_LEN];
+        assert_eq!(settings.get(1, 0, &mut buf), Ok(None));
+        settings.set(1, 0, b"hello").unwrap();
+        assert_eq!(settings.get(1, 0, &mut buf), Ok(Some(5)));
+        assert_eq!(&buf[..5], b"hello");
+        // another version reads as missing
+        assert_eq!(settings.get(1, 1, &mut buf), Ok(None));
+        assert_eq!(settings.get(1, 0, &mut [0; 4]), Err(Error::Buffer));
+
+        settings.remove(1).unwrap();
+        assert_eq!(settings.get(1, 0, &mut buf), Ok(None));
+        let mut settings = remount(settings.release());
+        assert_eq!(settings.get(1, 0, &mut buf), Ok(None));
+    }
+
+    #[test]
+    fn unchanged_values_arent_written() {
+        let mut settings = remount(Flash::new());
+        settings.set(1, 0, b"hello").unwrap();
+        let free = settings.free();
+        settings.set(1, 0, b"hello").unwrap();
+        assert_eq!(settings.free(), free);
+        // nothing to remove
+        settings.remove(2).unwrap();
+        assert_eq!(settings.free(), free);
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut settings = remount(Flash::new());
+        settings.store(3, 2, &(42u32, true)).unwrap();
+        let mut settings = remount(settings.release());
+        assert_eq!(settings.load::<(u32, bool)>(3, 2), Ok(Some((42, true))));
+        assert_eq!(settings.load::<(u32, bool)>(3, 1), Ok(None));
+        // doesn't decode
+        settings.set(4, 0, &[0xff; 8]).unwrap();
+        assert_eq!(settings.load::<(u32, bool)>(4, 0), Ok(None));
+    }
+
+    #[test]
+    fn rejects() {
+        let mut settings = remount(Flash::new());
+        assert_eq!(settings.set(ERASED_KEY, 0, b"x"), Err(Error::InvalidKey));
+        let long = [0; MAX_VALUE_LEN + 1];
+        assert_eq!(settings.set(1, 0, &long), Err(Error::TooLarge));
+        assert!(matches!(
+            Settings::mount(Flash::new(), 0, 1),
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            Settings::mount(Flash::new(), 2048, PAGES),
+            Err(Error::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn compaction_rotates_the_pages() {
+        let mut settings = remount(Flash::new());
+        let mut buf = [0; MAX_VALUE_LEN];
+        for i in 0..1000u32 {
+            settings.set((i % 3) as u16, 0, &i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(settings.page(), settings.sequence() % PAGES);
+        let mut settings = remount(settings.release());
+        for key in 0..3u16 {
+            assert_eq!(settings.get(key, 0, &mut buf), Ok(Some(4)));
+            let last = (997..1000).find(|i| i % 3 == key as u32).unwrap();
+            assert_eq!(buf[..4], last.to_le_bytes());
+        }
+        let sequence = settings.sequence();
+        let flash = settings.release();
+        assert!(sequence > PAGES);
+        // the page after the full one and the full one each compaction, plus the first mount
+        assert!(flash.erases() >= 2 * (sequence - 1));
+    }
+}
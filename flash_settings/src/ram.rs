@@ -0,0 +1,141 @@
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RamFlashError {
+    OutOfBounds,
+    NotAligned,
+    /// writing to bytes that weren't erased, which nor flash can't do
+    NotErased,
+    /// the write budget ran out part way through, like a reset during the write
+    PowerLoss,
+}
+
+impl NorFlashError for RamFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            RamFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            RamFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            RamFlashError::NotErased | RamFlashError::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// flash in ram, for the simulation and for checking Settings on the host, it only allows
+/// what nor flash does: erased bytes are 0xff and a byte has to be erased before it is written
+#[derive(Clone)]
+pub struct RamFlash<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> {
+    bytes: [u8; SIZE],
+    /// bytes that can be written before a write stops part way, None for no limit
+    write_budget: Option<usize>,
+    erases: u32,
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> Default
+    for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize>
+    RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+    /// all erased
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0xff; SIZE],
+            write_budget: None,
+            erases: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// fail with PowerLoss after this many more bytes are written, to act like a reset in the
+    /// middle of a write
+    pub fn set_write_budget(&mut self, budget: Option<usize>) {
+        self.write_budget = budget;
+    }
+
+    /// how many pages have been erased
+    pub fn erases(&self) -> u32 {
+        self.erases
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<(), RamFlashError> {
+        let offset = offset as usize;
+        if offset + len > SIZE {
+            return Err(RamFlashError::OutOfBounds);
+        }
+        if !offset.is_multiple_of(align) || !len.is_multiple_of(align) {
+            return Err(RamFlashError::NotAligned);
+        }
+        Ok(())
+    }
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ErrorType
+    for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+    type Error = RamFlashError;
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> ReadNorFlash
+    for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const WRITE_SIZE: usize, const ERASE_SIZE: usize> NorFlash
+    for RamFlash<SIZE, WRITE_SIZE, ERASE_SIZE>
+{
+    const WRITE_SIZE: usize = WRITE_SIZE;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if to < from {
+            return Err(RamFlashError::OutOfBounds);
+        }
+        self.check(from, (to - from) as usize, ERASE_SIZE)?;
+        self.bytes[from as usize..to as usize].fill(0xff);
+        self.erases += (to - from) / ERASE_SIZE as u32;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        self.check(offset, bytes.len(), WRITE_SIZE)?;
+        let offset = offset as usize;
+        if self.bytes[offset..offset + bytes.len()]
+            .iter()
+            .any(|byte| *byte != 0xff)
+        {
+            return Err(RamFlashError::NotErased);
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            if let Some(budget) = &mut self.write_budget {
+                if *budget == 0 {
+                    return Err(RamFlashError::PowerLoss);
+                }
+                *budget -= 1;
+            }
+            self.bytes[offset + i] = *byte;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+/*!
+Which board this is and what it is running, a BoardInfoGet sent to the board is answered with
+a BoardInfo, see net_loopback board_info
+
+The mac address is made from the stm32 unique device id so every board gets a different one
+without configuring it, the git hash and build time come from the firmware build.rs.
+*/
+
+use heapless::String;
+use serde::{Deserialize, Serialize};
+
+use crate::Epoch;
+
+/// an 8 character hash with a -dirty suffix fits
+pub const GIT_HASH_LEN: usize = 16;
+
+/// why the board last started, from the reset flags
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum ResetCause {
+    /// no flags were set, or there are no flags (the simulation)
+    #[default]
+    Unknown,
+    PowerOn,
+    Brownout,
+    /// the reset button or the debugger
+    Pin,
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    /// entering stop or standby mode when that is set to reset
+    LowPower,
+}
+
+impl ResetCause {
+    /// from the stm32h7 RCC_RSR register, where a power on reset also sets the pin and brownout
+    /// flags
+    pub fn from_stm32h7_rsr(rsr: u32) -> Self {
+        const LPWRRSTF: u32 = 1 << 30;
+        const WWDG1RSTF: u32 = 1 << 28;
+        const IWDG1RSTF: u32 = 1 << 26;
+        const SFTRSTF: u32 = 1 << 24;
+        const PORRSTF: u32 = 1 << 23;
+        const PINRSTF: u32 = 1 << 22;
+        const BORRSTF: u32 = 1 << 21;
+
+        let flags = [
+            (LPWRRSTF, ResetCause::LowPower),
+            (WWDG1RSTF, ResetCause::WindowWatchdog),
+            (IWDG1RSTF, ResetCause::IndependentWatchdog),
+            (SFTRSTF, ResetCause::Software),
+            (PORRSTF, ResetCause::PowerOn),
+            (BORRSTF, ResetCause::Brownout),
+            (PINRSTF, ResetCause::Pin),
+        ];
+        flags
+            .into_iter()
+            .find(|(flag, _)| rsr & flag != 0)
+            .map_or(ResetCause::Unknown, |(_, cause)| cause)
+    }
+}
+
+/// a locally administered unicast address from a hash (fnv-1a) of the 96 bit unique device id
+pub fn mac_from_unique_id(unique_id: &[u8; 12]) -> [u8; 6] {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in unique_id {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    let bytes = hash.to_le_bytes();
+    [0x02, bytes[0], bytes[1], bytes[2], bytes[3], bytes[4]]
+}
+
+/// what a board knows about itself at startup
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub unique_id: [u8; 12],
+    pub mac: [u8; 6],
+    pub reset_cause: ResetCause,
+}
+
+impl Identity {
+    /// with the mac from the unique id
+    pub fn new(unique_id: [u8; 12], reset_cause: ResetCause) -> Self {
+        Self {
+            unique_id,
+            mac: mac_from_unique_id(&unique_id),
+            reset_cause,
+        }
+    }
+
+    /// a mac from the board config instead of the one from the unique id, if there is one
+    pub fn with_mac(self, mac: Option<[u8; 6]>) -> Self {
+        Self {
+            mac: mac.unwrap_or(self.mac),
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BoardInfoGet {}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BoardInfo {
+    pub unique_id: [u8; 12],
+    pub mac: [u8; 6],
+    /// of the firmware source, with -dirty if there were uncommitted changes
+    pub git_hash: String<GIT_HASH_LEN>,
+    /// when the firmware was built
+    pub build_time: Epoch,
+    pub uptime_ms: u64,
+    pub reset_cause: ResetCause,
+}
+
+impl BoardInfo {
+    /// a longer git hash is truncated
+    pub fn new(identity: &Identity, git_hash: &str, build_time: Epoch, uptime_ms: u64) -> Self {
+        let mut info = Self {
+            unique_id: identity.unique_id,
+            mac: identity.mac,
+            build_time,
+            uptime_ms,
+            reset_cause: identity.reset_cause,
+            ..Default::default()
+        };
+        for c in git_hash.chars() {
+            if info.git_hash.push(c).is_err() {
+                break;
+            }
+        }
+        info
+    }
+}
@@ -0,0 +1,800 @@
+/*!
+The settings that differ between boards, from a TOML file like boards/default.toml
+
+The firmware build.rs reads the file named by the BOARD_CONFIG environmental variable with the
+toml feature and writes it out as a `CONFIG: BoardConfig` const, so a bad entry is a build error
+instead of a board that can't be reached.  The host tools read the same files to find the boards.
+
+Every entry is optional, the defaults are what the firmware used before there was a file, and
+boards/default.toml lists them all.
+*/
+
+use crate::config::Params;
+use crate::netconfig::{Host, Ipv4Settings, MAX_DNS_SERVERS};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Ports {
+    /// on the host, where telemetry is sent
+    pub telemetry: u16,
+    /// on the board, commands are received and answered here
+    pub commands: u16,
+    /// on the host, where log records are sent
+    pub log: u16,
+    /// on the board, ntp requests are sent from here
+    pub ntp_local: u16,
+    /// on the board, NetStatus announcements are sent from here.  This and the ones below are
+    /// nucleo_embassy only, nucleo_postcard sends everything from commands
+    pub net_status_local: u16,
+    /// on the board, log records are sent from here
+    pub log_local: u16,
+    /// on the board, telemetry is sent from here
+    pub telemetry_local: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoardConfig<'a> {
+    pub name: &'a str,
+    /// the static address used without dhcp
+    pub local_ip: [u8; 4],
+    pub prefix_len: u8,
+    /// 0.0.0.0 for none
+    pub gateway: [u8; 4],
+    pub dns_servers: &'a [[u8; 4]],
+    /// how long to wait for a dhcp lease before using local_ip, 0 to not use dhcp at all
+    pub dhcp_timeout_ms: u64,
+    /// None to make one from the unique device id, see board::mac_from_unique_id
+    pub mac: Option<[u8; 6]>,
+    /// where telemetry and log records are sent
+    pub remote_ip: [u8; 4],
+    /// send to this hostname instead of remote_ip once it resolves, empty for none
+    pub remote_host: &'a str,
+    /// ips or hostnames each with an optional :port, the best one that answers is used
+    pub ntp_servers: &'a [&'a str],
+    pub ports: Ports,
+    pub ntp_poll_ms: u32,
+    /// how often a TimeStamp is sent, 0 for one with each new ntp result
+    pub telemetry_period_ms: u32,
+}
+
+impl BoardConfig<'_> {
+    /// the runtime parameters the board starts with
+    pub const fn params(&self) -> Params {
+        let mut params = Params::new(self.remote_ip);
+        params.remote_port = self.ports.telemetry;
+        params.ntp_poll_ms = self.ntp_poll_ms;
+        params.telemetry_period_ms = self.telemetry_period_ms;
+        params.local_ip = self.local_ip;
+        params.dhcp_timeout_ms = self.dhcp_timeout_ms as u32;
+        params
+    }
+
+    /// the settings used without dhcp
+    pub fn static_settings(&self) -> Ipv4Settings {
+        let mut dns_servers = [[0; 4]; MAX_DNS_SERVERS];
+        for (dns_server, ip) in dns_servers.iter_mut().zip(self.dns_servers) {
+            *dns_server = *ip;
+        }
+        Ipv4Settings {
+            address: self.local_ip,
+            prefix_len: self.prefix_len,
+            gateway: self.gateway,
+            dns_servers,
+        }
+    }
+}
+
+/// an ntp server entry as a host and port, default_port if it has none, None if either is
+/// invalid
+pub fn split_port(server: &str, default_port: u16) -> Option<(Host<'_>, u16)> {
+    let (host, port) = match server.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok().filter(|port| *port != 0)?),
+        None => (server, default_port),
+    };
+    Some((Host::parse(host)?, port))
+}
+
+#[cfg(feature = "toml")]
+pub use read::{ConfigError, build_script};
+
+#[cfg(feature = "toml")]
+mod read {
+    use core::fmt;
+    use core::net::Ipv4Addr;
+    use serde::Deserialize;
+    use std::boxed::Box;
+    use std::format;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+
+    use super::{BoardConfig, Ports, split_port};
+    use crate::config::ParamId;
+    use crate::logging::LOG_PORT;
+    use crate::netconfig::{Host, MAX_DNS_SERVERS};
+
+    #[derive(Debug)]
+    pub enum ConfigError {
+        Io(std::io::Error),
+        /// not toml, an unknown key or a value of the wrong type
+        Toml(toml::de::Error),
+        /// the key as `section.name`, the value and what is wrong with it
+        Invalid {
+            key: &'static str,
+            value: String,
+            reason: &'static str,
+        },
+    }
+
+    impl fmt::Display for ConfigError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ConfigError::Io(err) => write!(f, "{err}"),
+                ConfigError::Toml(err) => write!(f, "{err}"),
+                ConfigError::Invalid { key, value, reason } => {
+                    write!(f, "{key} = {value}: {reason}")
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for ConfigError {}
+
+    fn invalid(key: &'static str, value: impl fmt::Debug, reason: &'static str) -> ConfigError {
+        ConfigError::Invalid {
+            key,
+            value: format!("{value:?}"),
+            reason,
+        }
+    }
+
+    // the file as written, checked and converted by BoardConfig::from_toml
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields, default)]
+    struct File {
+        name: Option<String>,
+        network: Network,
+        remote: Remote,
+        ports: FilePorts,
+        ntp: Ntp,
+        telemetry: Telemetry,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields, default)]
+    struct Network {
+        local_ip: Option<String>,
+        netmask: Option<String>,
+        gateway: Option<String>,
+        dns_servers: Vec<String>,
+        dhcp_timeout_ms: Option<u64>,
+        mac: Option<String>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields, default)]
+    struct Remote {
+        ip: Option<String>,
+        host: String,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields, default)]
+    struct FilePorts {
+        telemetry: Option<u16>,
+        commands: Option<u16>,
+        log: Option<u16>,
+        ntp_local: Option<u16>,
+        net_status_local: Option<u16>,
+        log_local: Option<u16>,
+        telemetry_local: Option<u16>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields, default)]
+    struct Ntp {
+        servers: Option<Vec<String>>,
+        poll_ms: Option<u32>,
+    }
+
+    #[derive(Deserialize, Default)]
+    #[serde(deny_unknown_fields, default)]
+    struct Telemetry {
+        period_ms: Option<u32>,
+    }
+
+    fn ipv4(key: &'static str, value: &str) -> Result<Ipv4Addr, ConfigError> {
+        value
+            .parse()
+            .map_err(|_| invalid(key, value, "not an ipv4 address"))
+    }
+
+    /// an address a board can have or send to
+    fn host_ipv4(key: &'static str, value: &str) -> Result<Ipv4Addr, ConfigError> {
+        let ip = ipv4(key, value)?;
+        if ip.is_unspecified() || ip.is_loopback() || ip.is_broadcast() || ip.is_multicast() {
+            return Err(invalid(key, value, "not a unicast address"));
+        }
+        Ok(ip)
+    }
+
+    /// the number of leading ones, if they are all ones
+    fn netmask_prefix_len(key: &'static str, value: &str) -> Result<u8, ConfigError> {
+        let mask = u32::from(ipv4(key, value)?);
+        let len = mask.leading_ones();
+        if len == 0 || mask.checked_shl(len).unwrap_or(0) != 0 {
+            return Err(invalid(key, value, "not a netmask like 255.255.255.0"));
+        }
+        Ok(len as u8)
+    }
+
+    fn mac_address(key: &'static str, value: &str) -> Result<[u8; 6], ConfigError> {
+        let octets: Vec<u8> = value
+            .split(':')
+            .map(|octet| match octet.len() {
+                2 => u8::from_str_radix(octet, 16).ok(),
+                _ => None,
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| invalid(key, value, "not a mac address like 02:00:00:00:00:01"))?;
+        let mac: [u8; 6] = octets
+            .try_into()
+            .map_err(|_| invalid(key, value, "not a mac address like 02:00:00:00:00:01"))?;
+        if mac[0] & 1 != 0 || mac == [0; 6] {
+            return Err(invalid(key, value, "not a unicast mac address"));
+        }
+        Ok(mac)
+    }
+
+    fn port(key: &'static str, value: Option<u16>, default: u16) -> Result<u16, ConfigError> {
+        match value.unwrap_or(default) {
+            0 => Err(invalid(key, 0, "ports start at 1")),
+            port => Ok(port),
+        }
+    }
+
+    fn in_range(key: &'static str, id: ParamId, value: u32) -> Result<u32, ConfigError> {
+        let (min, max) = id.range();
+        if value < min || value > max {
+            return Err(invalid(key, value, "out of range, see net_common::config"));
+        }
+        Ok(value)
+    }
+
+    fn leak(value: String) -> &'static str {
+        Box::leak(value.into_boxed_str())
+    }
+
+    impl BoardConfig<'static> {
+        /// checks every entry, the strings are leaked as this is read once at startup (or in
+        /// build.rs)
+        pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+            let file: File = toml::from_str(text).map_err(ConfigError::Toml)?;
+            let network = file.network;
+
+            let local_ip = host_ipv4(
+                "network.local_ip",
+                network.local_ip.as_deref().unwrap_or("192.168.0.123"),
+            )?;
+            let prefix_len = netmask_prefix_len(
+                "network.netmask",
+                network.netmask.as_deref().unwrap_or("255.255.255.0"),
+            )?;
+            let gateway = network.gateway.as_deref().unwrap_or("192.168.0.1");
+            let gateway_ip = ipv4("network.gateway", gateway)?;
+            // the same prefix_len as local_ip
+            let subnet = |ip: Ipv4Addr| u32::from(ip).checked_shr(32 - prefix_len as u32);
+            if !gateway_ip.is_unspecified() {
+                if subnet(gateway_ip) != subnet(local_ip) {
+                    return Err(invalid(
+                        "network.gateway",
+                        gateway,
+                        "not on the same subnet as network.local_ip",
+                    ));
+                }
+                if gateway_ip == local_ip {
+                    return Err(invalid("network.gateway", gateway, "the same as local_ip"));
+                }
+            }
+            if network.dns_servers.len() > MAX_DNS_SERVERS {
+                return Err(invalid(
+                    "network.dns_servers",
+                    &network.dns_servers,
+                    "more servers than netconfig::MAX_DNS_SERVERS",
+                ));
+            }
+            let dns_servers = network
+                .dns_servers
+                .iter()
+                .map(|ip| host_ipv4("network.dns_servers", ip).map(|ip| ip.octets()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mac = match network.mac {
+                Some(value) => Some(mac_address("network.mac", &value)?),
+                None => None,
+            };
+
+            let remote_ip = host_ipv4(
+                "remote.ip",
+                file.remote.ip.as_deref().unwrap_or("192.168.0.100"),
+            )?;
+            let remote_host = file.remote.host.trim().to_string();
+            if !remote_host.is_empty() && Host::parse(&remote_host).is_none() {
+                return Err(invalid(
+                    "remote.host",
+                    remote_host,
+                    "neither an ipv4 address nor a hostname",
+                ));
+            }
+
+            let ports = Ports {
+                telemetry: port("ports.telemetry", file.ports.telemetry, 34200)?,
+                commands: port("ports.commands", file.ports.commands, 34201)?,
+                log: port("ports.log", file.ports.log, LOG_PORT)?,
+                ntp_local: port("ports.ntp_local", file.ports.ntp_local, 35201)?,
+                net_status_local: port(
+                    "ports.net_status_local",
+                    file.ports.net_status_local,
+                    35202,
+                )?,
+                log_local: port("ports.log_local", file.ports.log_local, 35203)?,
+                telemetry_local: port("ports.telemetry_local", file.ports.telemetry_local, 35204)?,
+            };
+            let bound = [
+                ("ports.commands", ports.commands),
+                ("ports.ntp_local", ports.ntp_local),
+                ("ports.net_status_local", ports.net_status_local),
+                ("ports.log_local", ports.log_local),
+                ("ports.telemetry_local", ports.telemetry_local),
+            ];
+            for (i, (key, port)) in bound.iter().enumerate() {
+                if bound[..i].iter().any(|(_, earlier)| earlier == port) {
+                    return Err(invalid(
+                        key,
+                        port,
+                        "the same as an earlier port bound on the board",
+                    ));
+                }
+            }
+
+            let ntp_servers = file
+                .ntp
+                .servers
+                .unwrap_or_else(|| std::vec!["192.168.0.100".into()]);
+            if ntp_servers.is_empty() {
+                return Err(invalid(
+                    "ntp.servers",
+                    &ntp_servers,
+                    "needs at least one server",
+                ));
+            }
+            let ntp_servers = ntp_servers
+                .into_iter()
+                .map(|server| {
+                    let server = server.trim().to_string();
+                    match split_port(&server, 123) {
+                        Some(_) => Ok(leak(server)),
+                        None => Err(invalid(
+                            "ntp.servers",
+                            server,
+                            "not an ipv4 address or hostname with an optional :port",
+                        )),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            Ok(BoardConfig {
+                name: leak(file.name.unwrap_or_else(|| "default".into())),
+                local_ip: local_ip.octets(),
+                prefix_len,
+                gateway: gateway_ip.octets(),
+                dns_servers: Box::leak(dns_servers.into_boxed_slice()),
+                dhcp_timeout_ms: in_range(
+                    "network.dhcp_timeout_ms",
+                    ParamId::DhcpTimeoutMs,
+                    u32::try_from(network.dhcp_timeout_ms.unwrap_or(10000)).unwrap_or(u32::MAX),
+                )? as u64,
+                mac,
+                remote_ip: remote_ip.octets(),
+                remote_host: leak(remote_host),
+                ntp_servers: Box::leak(ntp_servers.into_boxed_slice()),
+                ports,
+                ntp_poll_ms: in_range(
+                    "ntp.poll_ms",
+                    ParamId::NtpPollMs,
+                    file.ntp.poll_ms.unwrap_or(1000),
+                )?,
+                telemetry_period_ms: in_range(
+                    "telemetry.period_ms",
+                    ParamId::TelemetryPeriodMs,
+                    file.telemetry.period_ms.unwrap_or(0),
+                )?,
+            })
+        }
+
+        pub fn read(path: impl AsRef<std::path::Path>) -> Result<Self, ConfigError> {
+            let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+            Self::from_toml(&text)
+        }
+    }
+
+    /// the firmware build.rs, writes `CONFIG` from the BOARD_CONFIG file (a relative path is
+    /// from the firmware directory), `GIT_HASH` and `BUILD_TIME_SECS` to constants.rs in
+    /// OUT_DIR, for the firmware to include!().  Panics with the problem if anything is invalid
+    pub fn build_script() {
+        use std::env;
+        use std::process::Command;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let config_path =
+            env::var("BOARD_CONFIG").unwrap_or_else(|_| "../boards/default.toml".into());
+        let config = BoardConfig::read(&config_path)
+            .unwrap_or_else(|err| panic!("invalid board config {config_path}: {err}"));
+        std::println!(
+            "cargo:warning=board config {config_path} ({}): local ip {:?}, remote ip (should be \
+             the computer linked to the target device) {:?}, ntp servers {:?}",
+            config.name,
+            config.local_ip,
+            config.remote_ip,
+            config.ntp_servers,
+        );
+
+        // which firmware a board is running, see BoardInfo
+        let git_hash = Command::new("git")
+            .args(["rev-parse", "--short=8", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string());
+        let mut git_hash = git_hash.unwrap_or_else(|| "unknown".into());
+        // exits with 1 if there are uncommitted changes
+        let dirty = Command::new("git")
+            .args(["diff", "--quiet", "HEAD"])
+            .status()
+            .is_ok_and(|status| status.code() == Some(1));
+        if dirty {
+            git_hash.push_str("-dirty");
+        }
+        std::println!("cargo:warning=git hash: {git_hash}");
+
+        // set SOURCE_DATE_EPOCH for reproducible builds, otherwise this is when build.rs
+        // last ran
+        let build_time_secs: u64 = match env::var("SOURCE_DATE_EPOCH") {
+            Ok(secs) => secs.parse().expect("SOURCE_DATE_EPOCH must be an integer"),
+            Err(_) => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system time is before 1970")
+                .as_secs(),
+        };
+
+        let out_dir = env::var("OUT_DIR").expect("No out dir");
+        let dest_path = std::path::Path::new(&out_dir).join("constants.rs");
+        std::fs::write(dest_path, constants(&config, &git_hash, build_time_secs))
+            .expect("Could not write file");
+
+        std::println!("cargo:rerun-if-env-changed=BOARD_CONFIG");
+        std::println!("cargo:rerun-if-changed={config_path}");
+        std::println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+        // a new commit, or source changes for the dirty flag
+        std::println!("cargo:rerun-if-changed=src");
+        std::println!("cargo:rerun-if-changed=../.git/HEAD");
+        std::println!("cargo:rerun-if-changed=../.git/index");
+        std::println!("cargo:rerun-if-changed=../.git/refs/heads");
+    }
+
+    /// the contents of constants.rs
+    pub(super) fn constants(config: &BoardConfig, git_hash: &str, build_time_secs: u64) -> String {
+        format!(
+            "pub const CONFIG: net_common::board_config::BoardConfig<'static> = {};
+pub const GIT_HASH: &str = {git_hash:?};
+pub const BUILD_TIME_SECS: u64 = {build_time_secs};
+",
+            config.to_rust()
+        )
+    }
+
+    impl BoardConfig<'_> {
+        /// as a rust expression, for build.rs to write into a const
+        pub fn to_rust(&self) -> String {
+            let path = "net_common::board_config";
+            let ports = &self.ports;
+            format!(
+                "{path}::BoardConfig {{
+    name: {:?},
+    local_ip: {:?},
+    prefix_len: {},
+    gateway: {:?},
+    dns_servers: &{:?},
+    dhcp_timeout_ms: {},
+    mac: {:?},
+    remote_ip: {:?},
+    remote_host: {:?},
+    ntp_servers: &{:?},
+    ports: {path}::Ports {{
+        telemetry: {},
+        commands: {},
+        log: {},
+        ntp_local: {},
+        net_status_local: {},
+        log_local: {},
+        telemetry_local: {},
+    }},
+    ntp_poll_ms: {},
+    telemetry_period_ms: {},
+}}",
+                self.name,
+                self.local_ip,
+                self.prefix_len,
+                self.gateway,
+                self.dns_servers,
+                self.dhcp_timeout_ms,
+                self.mac,
+                self.remote_ip,
+                self.remote_host,
+                self.ntp_servers,
+                ports.telemetry,
+                ports.commands,
+                ports.log,
+                ports.ntp_local,
+                ports.net_status_local,
+                ports.log_local,
+                ports.telemetry_local,
+                self.ntp_poll_ms,
+                self.telemetry_period_ms,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::{String, ToString};
+
+    const DEFAULT_TOML: &str = include_str!("../../boards/default.toml");
+
+    /// an expression both compiled and as text, whitespace doesn't count in the text
+    macro_rules! rust {
+        ($($rust:tt)*) => {
+            ($($rust)*, stringify!($($rust)*))
+        };
+    }
+
+    fn without_whitespace(text: &str) -> String {
+        text.split_whitespace().collect()
+    }
+
+    fn error(text: &str) -> String {
+        BoardConfig::from_toml(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn default_toml() {
+        let config = BoardConfig::from_toml(DEFAULT_TOML).unwrap();
+        assert_eq!(config.name, "default");
+        assert_eq!(config.local_ip, [192, 168, 0, 123]);
+        assert_eq!(config.prefix_len, 24);
+        assert_eq!(config.ntp_servers, ["192.168.0.100"]);
+        assert_eq!(config.ports.commands, 34201);
+        assert_eq!(config.ports.telemetry_local, 35204);
+        // it lists the defaults
+        assert_eq!(BoardConfig::from_toml("").unwrap(), config);
+        assert_eq!(config.params().remote_port, config.ports.telemetry);
+    }
+
+    #[test]
+    fn to_rust_round_trips() {
+        // what build.rs writes for boards/default.toml, compiled against the net_common the
+        // firmware uses
+        let (generated, text) = rust! {
+            net_common::board_config::BoardConfig {
+                name: "default",
+                local_ip: [192, 168, 0, 123],
+                prefix_len: 24,
+                gateway: [192, 168, 0, 1],
+                dns_servers: &[],
+                dhcp_timeout_ms: 10000,
+                mac: None,
+                remote_ip: [192, 168, 0, 100],
+                remote_host: "",
+                ntp_servers: &["192.168.0.100"],
+                ports: net_common::board_config::Ports {
+                    telemetry: 34200,
+                    commands: 34201,
+                    log: 34203,
+                    ntp_local: 35201,
+                    net_status_local: 35202,
+                    log_local: 35203,
+                    telemetry_local: 35204,
+                },
+                ntp_poll_ms: 1000,
+                telemetry_period_ms: 0,
+            }
+        };
+        let config = BoardConfig::from_toml(DEFAULT_TOML).unwrap();
+        assert_eq!(
+            without_whitespace(&config.to_rust()),
+            without_whitespace(text)
+        );
+        assert_eq!(std::format!("{generated:?}"), std::format!("{config:?}"));
+
+        let (generated, text) = rust! {
+            net_common::board_config::BoardConfig {
+                name: "lab",
+                local_ip: [10, 1, 2, 3],
+                prefix_len: 16,
+                gateway: [0, 0, 0, 0],
+                dns_servers: &[[10, 1, 0, 1], [10, 1, 0, 2]],
+                dhcp_timeout_ms: 0,
+                mac: Some([2, 0, 0, 0, 0, 171]),
+                remote_ip: [10, 1, 0, 100],
+                remote_host: "lab-pc",
+                ntp_servers: &["10.1.0.100:12300", "pool.ntp.org"],
+                ports: net_common::board_config::Ports {
+                    telemetry: 34200,
+                    commands: 34201,
+                    log: 34203,
+                    ntp_local: 35201,
+                    net_status_local: 35202,
+                    log_local: 35203,
+                    telemetry_local: 35204,
+                },
+                ntp_poll_ms: 5000,
+                telemetry_period_ms: 100,
+            }
+        };
+        let config = BoardConfig::from_toml(
+            r#"
+            name = "lab"
+            [network]
+            local_ip = "10.1.2.3"
+            netmask = "255.255.0.0"
+            gateway = "0.0.0.0"
+            dns_servers = ["10.1.0.1", "10.1.0.2"]
+            dhcp_timeout_ms = 0
+            mac = "02:00:00:00:00:ab"
+            [remote]
+            ip = "10.1.0.100"
+            host = "lab-pc"
+            [ntp]
+            servers = [" 10.1.0.100:12300", "pool.ntp.org"]
+            poll_ms = 5000
+            [telemetry]
+            period_ms = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            without_whitespace(&config.to_rust()),
+            without_whitespace(text)
+        );
+        assert_eq!(std::format!("{generated:?}"), std::format!("{config:?}"));
+    }
+
+    #[test]
+    fn constants_for_build_rs() {
+        let config = BoardConfig::from_toml(DEFAULT_TOML).unwrap();
+        let constants = read::constants(&config, "0123abcd-dirty", 1_700_000_000);
+        assert!(constants.starts_with(
+            "pub const CONFIG: net_common::board_config::BoardConfig<'static> = \
+             net_common::board_config::BoardConfig {"
+        ));
+        assert!(constants.contains("pub const GIT_HASH: &str = \"0123abcd-dirty\";\n"));
+        assert!(constants.ends_with("pub const BUILD_TIME_SECS: u64 = 1700000000;\n"));
+    }
+
+    #[test]
+    fn bad_addresses() {
+        assert_eq!(
+            error("[network]\nlocal_ip = \"192.168.0\""),
+            "network.local_ip = \"192.168.0\": not an ipv4 address"
+        );
+        assert_eq!(
+            error("[network]\nlocal_ip = \"224.0.0.1\""),
+            "network.local_ip = \"224.0.0.1\": not a unicast address"
+        );
+        assert_eq!(
+            error("[network]\nnetmask = \"255.0.255.0\""),
+            "network.netmask = \"255.0.255.0\": not a netmask like 255.255.255.0"
+        );
+        assert_eq!(
+            error("[network]\ngateway = \"10.0.0.1\""),
+            "network.gateway = \"10.0.0.1\": not on the same subnet as network.local_ip"
+        );
+        assert_eq!(
+            error("[network]\ndns_servers = [\"8.8.8\"]"),
+            "network.dns_servers = \"8.8.8\": not an ipv4 address"
+        );
+        assert_eq!(
+            error("[remote]\nip = \"127.0.0.1\""),
+            "remote.ip = \"127.0.0.1\": not a unicast address"
+        );
+        assert_eq!(
+            error("[remote]\nhost = \"under_score\""),
+            "remote.host = \"under_score\": neither an ipv4 address nor a hostname"
+        );
+        assert_eq!(
+            error("[ntp]\nservers = [\"192.168.0.100:0\"]"),
+            "ntp.servers = \"192.168.0.100:0\": not an ipv4 address or hostname with an \
+             optional :port"
+        );
+        assert_eq!(
+            error("[ntp]\nservers = []"),
+            "ntp.servers = []: needs at least one server"
+        );
+    }
+
+    #[test]
+    fn bad_macs() {
+        for mac in [
+            "02:00:00:00:00",
+            "02:00:00:00:00:01:02",
+            "02:00:00:00:00:0g",
+            "2:0:0:0:0:1",
+        ] {
+            assert_eq!(
+                error(&std::format!("[network]\nmac = \"{mac}\"")),
+                std::format!("network.mac = \"{mac}\": not a mac address like 02:00:00:00:00:01")
+            );
+        }
+        for mac in ["01:00:5e:00:00:01", "00:00:00:00:00:00"] {
+            assert_eq!(
+                error(&std::format!("[network]\nmac = \"{mac}\"")),
+                std::format!("network.mac = \"{mac}\": not a unicast mac address")
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(
+            error("[ports]\ncommands = 0"),
+            "ports.commands = 0: ports start at 1"
+        );
+        assert_eq!(
+            error("[ports]\nlog_local = 35201"),
+            "ports.log_local = 35201: the same as an earlier port bound on the board"
+        );
+        // the remote ports can be the same as the board ones
+        assert!(BoardConfig::from_toml("[ports]\ntelemetry = 34201").is_ok());
+        assert_eq!(
+            error("[ntp]\npoll_ms = 10"),
+            "ntp.poll_ms = 10: out of range, see net_common::config"
+        );
+        assert_eq!(
+            error("[telemetry]\nperiod_ms = 3600001"),
+            "telemetry.period_ms = 3600001: out of range, see net_common::config"
+        );
+        assert_eq!(
+            error("[network]\ndhcp_timeout_ms = 5000000000"),
+            "network.dhcp_timeout_ms = 4294967295: out of range, see net_common::config"
+        );
+        // too big for a u16, and keys that don't exist, are toml errors
+        for text in [
+            "[ports]\ncommands = 70000",
+            "[ports]\ncomands = 1",
+            "name = 1",
+        ] {
+            assert!(
+                matches!(BoardConfig::from_toml(text), Err(ConfigError::Toml(_))),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn split_ports() {
+        let ip = |octets: [u8; 4]| Host::Ip(octets.into());
+        assert_eq!(split_port("10.0.0.1", 123), Some((ip([10, 0, 0, 1]), 123)));
+        assert_eq!(
+            split_port("10.0.0.1:12300", 123),
+            Some((ip([10, 0, 0, 1]), 12300))
+        );
+        assert_eq!(
+            split_port("pool.ntp.org", 123),
+            Some((Host::Name("pool.ntp.org"), 123))
+        );
+        // a typo isn't the default port
+        assert_eq!(split_port("10.0.0.1:12e", 123), None);
+        assert_eq!(split_port("10.0.0.1:", 123), None);
+        // no ipv6
+        assert_eq!(split_port("fe80::1", 123), None);
+    }
+}
@@ -0,0 +1,457 @@
+/*!
+What a board does with a packet received on its command port (34201), without any io so the
+firmware only has to send the response, and the same handling runs on a computer
+
+```text
+Bench            -> Echo, the packet goes back unchanged without decoding, see throughput
+EchoRequest      -> EchoReply with the disciplined receive and transmit times
+TimeStamp        -> the same TimeStamp with tick_ms set to when it was received
+ConfigGet/Set    -> ConfigValue
+HeapStatsGet     -> HeapStats
+HealthGet        -> Health, if the board tracks it
+BoardInfoGet     -> BoardInfo
+PtpSync/FollowUp -> PtpDelayReq after the FollowUp, the DelayResp adds a sample to the clock
+Firmware*        -> FirmwareStatus, if the board can be updated
+```
+
+Replies all go to whoever sent the request.
+*/
+
+use crate::config::{ConfigStatus, Params};
+use crate::discipline::Sample;
+use crate::ptp::{PtpSlave, SlaveOutput};
+use crate::{BoardInfo, EchoReply, Epoch, Health, HeapStats, Message};
+
+/// the parts of the board the commands read or change
+pub trait Board {
+    /// the disciplined time at a local time (since boot)
+    fn corrected(&mut self, local: Epoch) -> Epoch;
+    /// the local time now, for the transmit times of replies
+    fn local_now(&self) -> Epoch;
+    /// a ptp measurement for the clock discipline
+    fn add_sample(&mut self, sample: Sample);
+    fn params(&mut self) -> &mut Params;
+    fn heap_stats(&self) -> HeapStats;
+    fn board_info(&self) -> BoardInfo;
+    /// a ConfigSet changed params(), e.g. to save them
+    fn params_changed(&mut self) {}
+    fn health(&mut self) -> Option<Health> {
+        None
+    }
+    /// the FirmwareStatus reply to a firmware message, see firmware::Updater
+    fn firmware_update(&mut self, _msg: &Message) -> Option<Message> {
+        None
+    }
+}
+
+// the same size as Message, see the clippy.toml
+#[derive(Debug)]
+pub enum Response {
+    /// send the received packet back as is
+    Echo,
+    Reply(Message),
+    /// nothing to send, or the packet wasn't a message
+    Nothing,
+}
+
+/// the command state kept between packets, only the ptp exchange in progress
+#[derive(Default)]
+pub struct Dispatcher {
+    ptp: PtpSlave,
+}
+
+impl Dispatcher {
+    pub const fn new() -> Self {
+        Self {
+            ptp: PtpSlave::new(),
+        }
+    }
+
+    pub fn ptp(&self) -> &PtpSlave {
+        &self.ptp
+    }
+
+    /// a better local time the PtpDelayReq reply went out at than handle() had, e.g. a stamp
+    pub fn delay_req_sent(&mut self, local: Epoch) {
+        self.ptp.delay_req_sent(local);
+    }
+
+    /// rx_local is the local time the packet was received, send the response right away
+    pub fn handle(
+        &mut self,
+        rx: &[u8],
+        rx_local: Epoch,
+        board: &mut impl Board,
+        crc_digest: crc::Digest<'_, u32>,
+    ) -> Response {
+        // throughput test packets aren't decoded, see net_loopback throughput
+        if Message::matches_header(rx, Message::BENCH) {
+            return Response::Echo;
+        }
+        let Ok(msg) = Message::decode(rx, crc_digest) else {
+            return Response::Nothing;
+        };
+        let reply = match &msg {
+            // see net_loopback timestamp_txrx
+            Message::EchoRequest(request) => {
+                let receive = board.corrected(rx_local);
+                let transmit = board.local_now();
+                let transmit = board.corrected(transmit);
+                Some(Message::EchoReply(EchoReply::new(
+                    request, receive, transmit,
+                )))
+            }
+            Message::TimeStamp(stamp) => {
+                let mut stamp = stamp.clone();
+                stamp.tick_ms = rx_local.as_duration().as_millis() as u64;
+                Some(Message::TimeStamp(stamp))
+            }
+            // see net_loopback heap_stats
+            Message::HeapStatsGet(_) => Some(Message::HeapStats(board.heap_stats())),
+            // see net_loopback health
+            Message::HealthGet(_) => board.health().map(Message::Health),
+            // see net_loopback board_info
+            Message::BoardInfoGet(_) => Some(Message::BoardInfo(board.board_info())),
+            // see net_loopback ptp_master
+            _ => match self.ptp.handle(&msg, rx_local) {
+                Some(SlaveOutput::DelayReq(req)) => {
+                    self.ptp.delay_req_sent(board.local_now());
+                    Some(Message::PtpDelayReq(req))
+                }
+                Some(SlaveOutput::Measured(measurement)) => {
+                    board.add_sample(measurement.sample);
+                    None
+                }
+                // see net_loopback config
+                None => match board.params().handle(&msg) {
+                    Some(reply) => {
+                        if let Message::ConfigSet(_) = &msg
+                            && let Message::ConfigValue(value) = &reply
+                            && value.status == ConfigStatus::Ok
+                        {
+                            board.params_changed();
+                        }
+                        Some(reply)
+                    }
+                    // see net_loopback firmware_update
+                    None => board.firmware_update(&msg),
+                },
+            },
+        };
+        match reply {
+            Some(reply) => Response::Reply(reply),
+            None => Response::Nothing,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{Identity, ResetCause};
+    use crate::config::{ConfigGet, ConfigSet, ConfigValue, ParamId};
+    use crate::firmware::{FirmwareStatus, FirmwareVerify};
+    use crate::ptp::{PtpDelayResp, PtpFollowUp, PtpSync};
+    use crate::{BenchData, EchoRequest, HealthGet, TimeStamp};
+    use core::time::Duration;
+
+    const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+    /// disciplined - local
+    const OFFSET: Duration = Duration::from_secs(1_000_000);
+
+    struct FakeBoard {
+        now: Epoch,
+        params: Params,
+        samples: std::vec::Vec<Sample>,
+        params_changed: usize,
+        health: Option<Health>,
+        /// answer firmware messages
+        updatable: bool,
+    }
+
+    impl FakeBoard {
+        fn new() -> Self {
+            Self {
+                now: Epoch::from(Duration::from_secs(10)),
+                params: Params::new([192, 168, 1, 2]),
+                samples: std::vec::Vec::new(),
+                params_changed: 0,
+                health: None,
+                updatable: false,
+            }
+        }
+    }
+
+    impl Board for FakeBoard {
+        fn corrected(&mut self, local: Epoch) -> Epoch {
+            local.saturating_add(OFFSET)
+        }
+
+        fn local_now(&self) -> Epoch {
+            self.now
+        }
+
+        fn add_sample(&mut self, sample: Sample) {
+            self.samples.push(sample);
+        }
+
+        fn params(&mut self) -> &mut Params {
+            &mut self.params
+        }
+
+        fn heap_stats(&self) -> HeapStats {
+            HeapStats {
+                used: 1234,
+                ..HeapStats::default()
+            }
+        }
+
+        fn board_info(&self) -> BoardInfo {
+            let identity = Identity::new([7; 12], ResetCause::PowerOn);
+            BoardInfo::new(&identity, "abc123", Epoch::default(), 42)
+        }
+
+        fn params_changed(&mut self) {
+            self.params_changed += 1;
+        }
+
+        fn health(&mut self) -> Option<Health> {
+            self.health.clone()
+        }
+
+        fn firmware_update(&mut self, msg: &Message) -> Option<Message> {
+            match msg {
+                Message::FirmwareVerify(_) if self.updatable => {
+                    Some(Message::FirmwareStatus(FirmwareStatus::default()))
+                }
+                _ => None,
+            }
+        }
+    }
+
+    fn handle(dispatcher: &mut Dispatcher, board: &mut FakeBoard, msg: Message) -> Response {
+        let rx = msg.encode::<2048>(CRC.digest()).unwrap();
+        let rx_local = board.now;
+        dispatcher.handle(&rx, rx_local, board, CRC.digest())
+    }
+
+    fn reply(response: Response) -> Message {
+        match response {
+            Response::Reply(reply) => reply,
+            other => panic!("expected a reply, got {other:?}"),
+        }
+    }
+
+    fn config_value(response: Response) -> ConfigValue {
+        match reply(response) {
+            Message::ConfigValue(value) => value,
+            other => panic!("expected a ConfigValue, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn bench_is_echoed() {
+        let mut board = FakeBoard::new();
+        let bench = Message::Bench(BenchData {
+            sequence: 3,
+            ..BenchData::default()
+        });
+        let response = handle(&mut Dispatcher::new(), &mut board, bench);
+        assert!(matches!(response, Response::Echo));
+    }
+
+    #[test]
+    fn garbage_is_ignored() {
+        let mut board = FakeBoard::new();
+        let mut dispatcher = Dispatcher::new();
+        let response = dispatcher.handle(&[1, 2, 3, 4, 5], board.now, &mut board, CRC.digest());
+        assert!(matches!(response, Response::Nothing));
+        // a message with a bad crc
+        let mut rx = Message::HeapStatsGet(Default::default())
+            .encode::<64>(CRC.digest())
+            .unwrap();
+        let last = rx.len() - 1;
+        rx[last] ^= 0xff;
+        let response = dispatcher.handle(&rx, board.now, &mut board, CRC.digest());
+        assert!(matches!(response, Response::Nothing));
+    }
+
+    #[test]
+    fn echo_request() {
+        let mut board = FakeBoard::new();
+        let request = EchoRequest {
+            counter: 9,
+            origin: Epoch::from(Duration::from_secs(5)),
+        };
+        let mut dispatcher = Dispatcher::new();
+        let rx = Message::EchoRequest(request.clone())
+            .encode::<64>(CRC.digest())
+            .unwrap();
+        let rx_local = board.now;
+        board.now = rx_local.saturating_add(Duration::from_micros(30));
+        let response = dispatcher.handle(&rx, rx_local, &mut board, CRC.digest());
+        let Message::EchoReply(echo) = reply(response) else {
+            panic!("expected an EchoReply");
+        };
+        assert_eq!(echo.counter, request.counter);
+        assert_eq!(echo.origin, request.origin);
+        assert_eq!(echo.receive, rx_local.saturating_add(OFFSET));
+        assert_eq!(echo.transmit, board.now.saturating_add(OFFSET));
+    }
+
+    #[test]
+    fn time_stamp_tick_ms() {
+        let mut board = FakeBoard::new();
+        let stamp = TimeStamp {
+            counter: 4,
+            ..TimeStamp::default()
+        };
+        let response = handle(
+            &mut Dispatcher::new(),
+            &mut board,
+            Message::TimeStamp(stamp),
+        );
+        let Message::TimeStamp(stamp) = reply(response) else {
+            panic!("expected a TimeStamp");
+        };
+        assert_eq!(stamp.counter, 4);
+        assert_eq!(stamp.tick_ms, 10_000);
+    }
+
+    #[test]
+    fn heap_stats_and_board_info() {
+        let mut board = FakeBoard::new();
+        let mut dispatcher = Dispatcher::new();
+        let response = handle(
+            &mut dispatcher,
+            &mut board,
+            Message::HeapStatsGet(Default::default()),
+        );
+        let Message::HeapStats(stats) = reply(response) else {
+            panic!("expected HeapStats");
+        };
+        assert_eq!(stats.used, 1234);
+        let response = handle(
+            &mut dispatcher,
+            &mut board,
+            Message::BoardInfoGet(Default::default()),
+        );
+        let Message::BoardInfo(info) = reply(response) else {
+            panic!("expected BoardInfo");
+        };
+        assert_eq!(info.unique_id, [7; 12]);
+        assert_eq!(info.uptime_ms, 42);
+    }
+
+    #[test]
+    fn health_if_tracked() {
+        let mut board = FakeBoard::new();
+        let mut dispatcher = Dispatcher::new();
+        let response = handle(
+            &mut dispatcher,
+            &mut board,
+            Message::HealthGet(HealthGet {}),
+        );
+        assert!(matches!(response, Response::Nothing));
+        board.health = Some(Health {
+            uptime_ms: 77,
+            ..Health::default()
+        });
+        let response = handle(
+            &mut dispatcher,
+            &mut board,
+            Message::HealthGet(HealthGet {}),
+        );
+        let Message::Health(health) = reply(response) else {
+            panic!("expected Health");
+        };
+        assert_eq!(health.uptime_ms, 77);
+    }
+
+    #[test]
+    fn firmware_if_updatable() {
+        let mut board = FakeBoard::new();
+        let mut dispatcher = Dispatcher::new();
+        let verify = || Message::FirmwareVerify(FirmwareVerify {});
+        assert!(matches!(
+            handle(&mut dispatcher, &mut board, verify()),
+            Response::Nothing
+        ));
+        board.updatable = true;
+        let response = handle(&mut dispatcher, &mut board, verify());
+        assert!(matches!(reply(response), Message::FirmwareStatus(_)));
+    }
+
+    #[test]
+    fn config_get_and_set() {
+        let mut board = FakeBoard::new();
+        let mut dispatcher = Dispatcher::new();
+        let get = || {
+            Message::ConfigGet(ConfigGet {
+                id: ParamId::RemotePort,
+            })
+        };
+        let set = |value| {
+            Message::ConfigSet(ConfigSet {
+                id: ParamId::RemotePort,
+                value,
+            })
+        };
+
+        let value = config_value(handle(&mut dispatcher, &mut board, get()));
+        assert_eq!((value.status, value.value), (ConfigStatus::Ok, 34200));
+        assert_eq!(board.params_changed, 0);
+
+        let value = config_value(handle(&mut dispatcher, &mut board, set(4000)));
+        assert_eq!((value.status, value.value), (ConfigStatus::Ok, 4000));
+        assert_eq!(board.params.remote_port, 4000);
+        assert_eq!(board.params_changed, 1);
+
+        // rejected sets don't change anything to save
+        let value = config_value(handle(&mut dispatcher, &mut board, set(0)));
+        assert_eq!(
+            (value.status, value.value),
+            (ConfigStatus::OutOfRange, 4000)
+        );
+        assert_eq!(board.params_changed, 1);
+    }
+
+    #[test]
+    fn ptp_exchange() {
+        let mut board = FakeBoard::new();
+        let mut dispatcher = Dispatcher::new();
+        // the master is OFFSET ahead, with no delay either way
+        let t1 = board.now.saturating_add(OFFSET);
+        let sync = Message::PtpSync(PtpSync { sequence: 1 });
+        assert!(matches!(
+            handle(&mut dispatcher, &mut board, sync),
+            Response::Nothing
+        ));
+
+        let follow_up = Message::PtpFollowUp(PtpFollowUp {
+            sequence: 1,
+            origin: t1,
+        });
+        board.now = board.now.saturating_add(Duration::from_micros(100));
+        let Message::PtpDelayReq(req) = reply(handle(&mut dispatcher, &mut board, follow_up))
+        else {
+            panic!("expected a PtpDelayReq");
+        };
+        assert_eq!(req.sequence, 1);
+        // a stamp from when it actually went out replaces the local_now() handle() used
+        let t3 = board.now.saturating_add(Duration::from_micros(20));
+        dispatcher.delay_req_sent(t3);
+        assert!(board.samples.is_empty());
+
+        let delay_resp = Message::PtpDelayResp(PtpDelayResp {
+            sequence: 1,
+            receive: t3.saturating_add(OFFSET),
+        });
+        board.now = board.now.saturating_add(Duration::from_micros(200));
+        let response = handle(&mut dispatcher, &mut board, delay_resp);
+        assert!(matches!(response, Response::Nothing));
+        assert_eq!(board.samples.len(), 1);
+        assert_eq!(board.samples[0].offset_ns, OFFSET.as_nanos() as i64);
+        assert_eq!(board.samples[0].rtt_ns, 0);
+    }
+}
@@ -0,0 +1,209 @@
+/*!
+Runtime parameters of a board that can be read and changed over the network with
+ConfigGet/ConfigSet messages, the board answers both with a ConfigValue.
+
+Every value is a u32 on the wire, the ParamId says how to interpret it.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::Message;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum ParamId {
+    /// milliseconds between sntp requests
+    #[default]
+    NtpPollMs,
+    /// where telemetry is sent, the octets of the ipv4 address big endian
+    RemoteIp,
+    RemotePort,
+    /// milliseconds between unrequested TimeStamp messages, 0 for one with each new ntp result
+    TelemetryPeriodMs,
+    /// a LedPattern
+    LedPattern,
+    /// the static address used without dhcp, big endian like RemoteIp, boards that save their
+    /// parameters use it from the next reset
+    LocalIp,
+    /// how long to wait for a dhcp lease before using LocalIp, 0 to not use dhcp, from the next
+    /// reset like LocalIp
+    DhcpTimeoutMs,
+}
+
+impl ParamId {
+    pub const ALL: [ParamId; 7] = [
+        ParamId::NtpPollMs,
+        ParamId::RemoteIp,
+        ParamId::RemotePort,
+        ParamId::TelemetryPeriodMs,
+        ParamId::LedPattern,
+        ParamId::LocalIp,
+        ParamId::DhcpTimeoutMs,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParamId::NtpPollMs => "ntp_poll_ms",
+            ParamId::RemoteIp => "remote_ip",
+            ParamId::RemotePort => "remote_port",
+            ParamId::TelemetryPeriodMs => "telemetry_period_ms",
+            ParamId::LedPattern => "led_pattern",
+            ParamId::LocalIp => "local_ip",
+            ParamId::DhcpTimeoutMs => "dhcp_timeout_ms",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ParamId> {
+        ParamId::ALL.into_iter().find(|id| id.name() == name)
+    }
+
+    /// inclusive range of valid values
+    pub fn range(&self) -> (u32, u32) {
+        match self {
+            ParamId::NtpPollMs => (100, 3_600_000),
+            ParamId::RemoteIp => (0, u32::MAX),
+            ParamId::RemotePort => (1, u16::MAX as u32),
+            ParamId::TelemetryPeriodMs => (0, 3_600_000),
+            ParamId::LedPattern => (0, LedPattern::ALL.len() as u32 - 1),
+            ParamId::LocalIp => (0, u32::MAX),
+            ParamId::DhcpTimeoutMs => (0, 3_600_000),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum LedPattern {
+    /// the leds show the state of the board, see health::BoardState
+    #[default]
+    Blink,
+    Off,
+    On,
+    /// all leds blink quickly, to find the board on the bench
+    Fast,
+}
+
+impl LedPattern {
+    pub const ALL: [LedPattern; 4] = [
+        LedPattern::Blink,
+        LedPattern::Off,
+        LedPattern::On,
+        LedPattern::Fast,
+    ];
+
+    pub fn from_u32(value: u32) -> Option<LedPattern> {
+        LedPattern::ALL.get(value as usize).copied()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LedPattern::Blink => "blink",
+            LedPattern::Off => "off",
+            LedPattern::On => "on",
+            LedPattern::Fast => "fast",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum ConfigStatus {
+    #[default]
+    Ok,
+    OutOfRange,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ConfigGet {
+    pub id: ParamId,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ConfigSet {
+    pub id: ParamId,
+    pub value: u32,
+}
+
+/// the reply to both ConfigGet and ConfigSet, value is the current value after any set
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ConfigValue {
+    pub id: ParamId,
+    pub status: ConfigStatus,
+    pub value: u32,
+}
+
+/// the current value of every parameter, serializable so a board can save them
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct Params {
+    pub ntp_poll_ms: u32,
+    pub remote_ip: [u8; 4],
+    pub remote_port: u16,
+    /// how often a TimeStamp is sent, 0 for one with each new ntp result
+    pub telemetry_period_ms: u32,
+    pub led_pattern: LedPattern,
+    pub local_ip: [u8; 4],
+    pub dhcp_timeout_ms: u32,
+}
+
+impl Params {
+    /// the defaults, matching what the firmware did before these were configurable
+    pub const fn new(remote_ip: [u8; 4]) -> Self {
+        Self {
+            ntp_poll_ms: 1000,
+            remote_ip,
+            remote_port: 34200,
+            telemetry_period_ms: 0,
+            led_pattern: LedPattern::Blink,
+            local_ip: [192, 168, 0, 123],
+            dhcp_timeout_ms: 10000,
+        }
+    }
+
+    pub fn get(&self, id: ParamId) -> u32 {
+        match id {
+            ParamId::NtpPollMs => self.ntp_poll_ms,
+            ParamId::RemoteIp => u32::from_be_bytes(self.remote_ip),
+            ParamId::RemotePort => self.remote_port as u32,
+            ParamId::TelemetryPeriodMs => self.telemetry_period_ms,
+            ParamId::LedPattern => self.led_pattern as u32,
+            ParamId::LocalIp => u32::from_be_bytes(self.local_ip),
+            ParamId::DhcpTimeoutMs => self.dhcp_timeout_ms,
+        }
+    }
+
+    /// leaves the parameter unchanged if the value is invalid
+    pub fn set(&mut self, id: ParamId, value: u32) -> ConfigStatus {
+        let (min, max) = id.range();
+        if value < min || value > max {
+            return ConfigStatus::OutOfRange;
+        }
+        match id {
+            ParamId::NtpPollMs => self.ntp_poll_ms = value,
+            ParamId::RemoteIp => self.remote_ip = value.to_be_bytes(),
+            ParamId::RemotePort => self.remote_port = value as u16,
+            ParamId::TelemetryPeriodMs => self.telemetry_period_ms = value,
+            ParamId::LedPattern => {
+                let Some(pattern) = LedPattern::from_u32(value) else {
+                    return ConfigStatus::OutOfRange;
+                };
+                self.led_pattern = pattern;
+            }
+            ParamId::LocalIp => self.local_ip = value.to_be_bytes(),
+            ParamId::DhcpTimeoutMs => self.dhcp_timeout_ms = value,
+        }
+        ConfigStatus::Ok
+    }
+
+    /// answer a ConfigGet or ConfigSet, None for any other message
+    pub fn handle(&mut self, msg: &Message) -> Option<Message> {
+        let (id, status) = match msg {
+            Message::ConfigGet(get) => (get.id, ConfigStatus::Ok),
+            Message::ConfigSet(set) => (set.id, self.set(set.id, set.value)),
+            _ => {
+                return None;
+            }
+        };
+        Some(Message::ConfigValue(ConfigValue {
+            id,
+            status,
+            value: self.get(id),
+        }))
+    }
+}
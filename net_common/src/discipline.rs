@@ -0,0 +1,346 @@
+/*!
+Turn raw sntp samples into a smooth corrected clock
+
+Each exchange gives an offset (server time - local time) with an error of up to half the round
+trip, and the round trip varies a lot more than the link latency would suggest.  So:
+
+- the lowest round trip sample out of the last FILTER_LEN is taken as the best estimate
+  of the offset at that time, like the ntp clock filter
+- a line fit through the last REGRESSION_LEN selected samples gives the offset now and
+  the frequency error of the local oscillator
+- the applied offset is slewed toward the fit at up to MAX_SLEW_PPM, only errors larger than
+  STEP_THRESHOLD_NS (first sync, a different server) are stepped
+- the corrected clock never goes backwards, after a backwards step it holds still until
+  the local clock catches up
+
+There is nothing hardware specific here, the local time is whatever monotonic tick count the
+board has expressed as an Epoch since boot.
+*/
+
+use heapless::HistoryBuffer;
+
+use crate::Epoch;
+
+pub const FILTER_LEN: usize = 8;
+pub const REGRESSION_LEN: usize = 16;
+/// fewer selected samples than this are averaged, the frequency from a line through two or
+/// three noisy points is worse than assuming none
+pub const MIN_FIT_SAMPLES: usize = 4;
+/// fastest the applied offset changes, same as the ntpd limit
+pub const MAX_SLEW_PPM: i64 = 500;
+/// offset errors larger than this are stepped instead of slewed, same as ntpd
+pub const STEP_THRESHOLD_NS: i64 = 128_000_000;
+
+/// one sntp exchange
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sample {
+    /// the local clock when the reply arrived
+    pub local: Epoch,
+    /// server time - local time
+    pub offset_ns: i64,
+    pub rtt_ns: u64,
+}
+
+pub struct Discipline {
+    /// the most recent raw samples
+    raw: HistoryBuffer<Sample, FILTER_LEN>,
+    /// the minimum round trip samples, the line fit is through these
+    selected: HistoryBuffer<Sample, REGRESSION_LEN>,
+    /// the offset now() added the last time it was called, and when that was (local time)
+    applied_offset_ns: i64,
+    applied_at: Epoch,
+    synced: bool,
+    last_output: Epoch,
+}
+
+impl Default for Discipline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Discipline {
+    pub const fn new() -> Self {
+        Self {
+            raw: HistoryBuffer::new(),
+            selected: HistoryBuffer::new(),
+            applied_offset_ns: 0,
+            applied_at: Epoch::ZERO,
+            synced: false,
+            last_output: Epoch::ZERO,
+        }
+    }
+
+    /// forget all samples and go back to the unsynced local clock
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// forget the samples but keep the applied offset, so a change of server is slewed (or
+    /// stepped if far enough off) from where the clock is rather than fitted with a line
+    /// through both servers' samples
+    pub fn clear_samples(&mut self) {
+        self.raw.clear();
+        self.selected.clear();
+    }
+
+    /// now() has applied at least one sample
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    /// returns true if the sample (or an earlier one with a lower round trip) was selected for
+    /// the line fit
+    pub fn add_sample(&mut self, sample: Sample) -> bool {
+        self.raw.write(sample);
+        let best = *self
+            .raw
+            .as_slice()
+            .iter()
+            .min_by_key(|sample| sample.rtt_ns)
+            .expect("just written");
+
+        // every selected sample has to be newer than the last one, so the same low round trip
+        // sample isn't used repeatedly while it is still in the filter
+        if let Some(last) = self.selected.recent()
+            && best.local <= last.local
+        {
+            return false;
+        }
+
+        // the fit through older samples is no good after a large jump, e.g. a different server
+        if let Some((offset_ns, _)) = self.estimate(best.local)
+            && best.offset_ns.saturating_sub(offset_ns).unsigned_abs() > STEP_THRESHOLD_NS as u64
+        {
+            self.selected.clear();
+        }
+        self.selected.write(best);
+        true
+    }
+
+    /// the fitted offset (ns) at the given local time and the frequency error (parts per
+    /// billion, positive means the local clock is slow), None before any samples
+    pub fn estimate(&self, local: Epoch) -> Option<(i64, i64)> {
+        let reference = self.selected.recent()?.local;
+        let samples = self.selected.as_slice();
+        let count = samples.len() as i128;
+
+        // relative to the newest sample to keep the sums small
+        let x = |local: &Epoch| -> i128 { local.as_nanos() as i128 - reference.as_nanos() as i128 };
+        let mean_x = samples.iter().map(|s| x(&s.local)).sum::<i128>() / count;
+        let mean_y = samples.iter().map(|s| s.offset_ns as i128).sum::<i128>() / count;
+        let mut sum_xy = 0;
+        let mut sum_xx = 0;
+        for sample in samples {
+            let dx = x(&sample.local) - mean_x;
+            sum_xy += dx * (sample.offset_ns as i128 - mean_y);
+            sum_xx += dx * dx;
+        }
+        // too few samples or all at the same time, no frequency information
+        if samples.len() < MIN_FIT_SAMPLES || sum_xx == 0 {
+            return Some((mean_y as i64, 0));
+        }
+
+        // a real oscillator is within a few tens of ppm, anything beyond the slew limit is noise
+        let max_freq_ppb = MAX_SLEW_PPM as i128 * 1000;
+        let freq_ppb = (sum_xy * 1_000_000_000 / sum_xx).clamp(-max_freq_ppb, max_freq_ppb);
+        let offset_ns = mean_y + freq_ppb * (x(&local) - mean_x) / 1_000_000_000;
+        Some((
+            offset_ns.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+            freq_ppb as i64,
+        ))
+    }
+
+    /// the offset now() most recently applied
+    pub fn applied_offset_ns(&self) -> i64 {
+        self.applied_offset_ns
+    }
+
+    /// the corrected time at the given local time, which should be the current time and
+    /// not go backwards between calls, the local time is returned unchanged until synced
+    pub fn now(&mut self, local: Epoch) -> Epoch {
+        match self.estimate(local) {
+            None if !self.synced => {
+                return local;
+            }
+            // cleared samples, keep the offset as is
+            None => {}
+            Some((target_ns, _freq_ppb)) if !self.synced => {
+                self.applied_offset_ns = target_ns;
+                self.synced = true;
+            }
+            Some((target_ns, _freq_ppb)) => {
+                let error_ns = target_ns.saturating_sub(self.applied_offset_ns);
+                if error_ns.unsigned_abs() > STEP_THRESHOLD_NS as u64 {
+                    self.applied_offset_ns = target_ns;
+                } else {
+                    let elapsed_ns = local
+                        .signed_nanos_since(&self.applied_at)
+                        .unwrap_or(0)
+                        .max(0);
+                    let max_slew_ns = elapsed_ns.saturating_mul(MAX_SLEW_PPM) / 1_000_000;
+                    self.applied_offset_ns += error_ns.clamp(-max_slew_ns, max_slew_ns);
+                }
+            }
+        }
+        self.applied_at = local;
+
+        // slewing is slower than time passes so only a backwards step can make this go back
+        let corrected = local
+            .saturating_add_signed_nanos(self.applied_offset_ns)
+            .max(self.last_output);
+        self.last_output = corrected;
+        corrected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: i64 = 1_000_000;
+
+    fn at_ms(ms: u64) -> Epoch {
+        Epoch::from_nanos(ms as u128 * 1_000_000).unwrap()
+    }
+
+    fn sample(local_ms: u64, offset_ns: i64, rtt_ns: u64) -> Sample {
+        Sample {
+            local: at_ms(local_ms),
+            offset_ns,
+            rtt_ns,
+        }
+    }
+
+    /// synced at offset_ns from a single sample at local_ms
+    fn synced(local_ms: u64, offset_ns: i64) -> Discipline {
+        let mut discipline = Discipline::new();
+        assert!(discipline.add_sample(sample(local_ms, offset_ns, 2 * MS as u64)));
+        discipline.now(at_ms(local_ms));
+        assert!(discipline.is_synced());
+        assert_eq!(discipline.applied_offset_ns(), offset_ns);
+        discipline
+    }
+
+    #[test]
+    fn unsynced_is_the_local_clock() {
+        let mut discipline = Discipline::new();
+        assert_eq!(discipline.estimate(at_ms(1000)), None);
+        assert_eq!(discipline.now(at_ms(1000)), at_ms(1000));
+        assert!(!discipline.is_synced());
+
+        let mut discipline = synced(1000, 5 * MS);
+        discipline.reset();
+        assert!(!discipline.is_synced());
+        assert_eq!(discipline.now(at_ms(2000)), at_ms(2000));
+    }
+
+    #[test]
+    fn selects_the_minimum_round_trip() {
+        let mut discipline = Discipline::new();
+        // the low round trip sample is selected and the ones after it aren't while it is
+        // still in the filter
+        assert!(discipline.add_sample(sample(1000, 100_000, 5 * MS as u64)));
+        assert!(discipline.add_sample(sample(2000, 10_000, MS as u64)));
+        for i in 0..FILTER_LEN as u64 - 1 {
+            assert!(!discipline.add_sample(sample(3000 + i * 1000, 900_000, 5 * MS as u64)));
+        }
+        assert_eq!(discipline.estimate(at_ms(10_000)), Some((55_000, 0)));
+
+        // once it has gone out of the filter the best of the rest is selected
+        assert!(discipline.add_sample(sample(20_000, 200_000, 4 * MS as u64)));
+        assert_eq!(
+            discipline.estimate(at_ms(20_000)),
+            Some(((100_000 + 10_000 + 200_000) / 3, 0))
+        );
+    }
+
+    #[test]
+    fn estimates_the_frequency_of_a_drifting_clock() {
+        // the local clock is 20 ppm slow and starts 1ms behind
+        let drift_ppb = 20_000;
+        let offset_at = |ms: u64| MS + drift_ppb * ms as i64 / 1000;
+        let mut discipline = Discipline::new();
+        for i in 0..REGRESSION_LEN as u64 {
+            let ms = i * 1000;
+            // noisy round trips, with the offset error inside each one's uncertainty
+            let rtt_ns = 2 * MS as u64 - i * 1000;
+            assert!(discipline.add_sample(sample(ms, offset_at(ms), rtt_ns)));
+        }
+        let (offset_ns, freq_ppb) = discipline.estimate(at_ms(30_000)).unwrap();
+        assert!((freq_ppb - drift_ppb).abs() <= 10, "{freq_ppb}");
+        assert!((offset_ns - offset_at(30_000)).abs() <= 1000, "{offset_ns}");
+
+        // beyond the slew limit is clamped
+        let mut discipline = Discipline::new();
+        for i in 0..MIN_FIT_SAMPLES as u64 {
+            let ms = i * 1000;
+            let rtt_ns = 2 * MS as u64 - i * 1000;
+            // 1000 ppm
+            discipline.add_sample(sample(ms, ms as i64 * 1000, rtt_ns));
+        }
+        let (_, freq_ppb) = discipline.estimate(at_ms(4000)).unwrap();
+        assert_eq!(freq_ppb, MAX_SLEW_PPM * 1000);
+    }
+
+    #[test]
+    fn slews_small_errors() {
+        let mut discipline = synced(1000, 0);
+        // the two samples average to 5ms
+        assert!(discipline.add_sample(sample(2000, 10 * MS, MS as u64)));
+        let mut previous = discipline.now(at_ms(2000));
+        // MAX_SLEW_PPM of the 1s since now() was last called
+        assert_eq!(discipline.applied_offset_ns(), MAX_SLEW_PPM * 1000);
+        for ms in (2100..20_000).step_by(100) {
+            let corrected = discipline.now(at_ms(ms));
+            let applied = discipline.applied_offset_ns();
+            assert!(applied <= 5 * MS);
+            // never faster than the slew limit
+            let step = corrected.signed_nanos_since(&previous).unwrap();
+            assert!((100 * MS..=100 * MS + 100 * MS / 1000 * MAX_SLEW_PPM / 1000).contains(&step));
+            previous = corrected;
+        }
+        assert_eq!(discipline.applied_offset_ns(), 5 * MS);
+    }
+
+    #[test]
+    fn steps_large_errors() {
+        let mut discipline = synced(1000, 0);
+        let error_ns = STEP_THRESHOLD_NS + MS;
+        // the old samples are dropped rather than averaged with
+        assert!(discipline.add_sample(sample(2000, error_ns, MS as u64)));
+        assert_eq!(discipline.estimate(at_ms(2000)), Some((error_ns, 0)));
+        assert_eq!(
+            discipline.now(at_ms(2000)),
+            at_ms(2000).saturating_add_signed_nanos(error_ns)
+        );
+        assert_eq!(discipline.applied_offset_ns(), error_ns);
+
+        // just under the threshold is slewed
+        let mut discipline = synced(1000, 0);
+        discipline.clear_samples();
+        assert!(discipline.add_sample(sample(2000, STEP_THRESHOLD_NS, MS as u64)));
+        discipline.now(at_ms(2000));
+        assert_eq!(discipline.applied_offset_ns(), MAX_SLEW_PPM * 1000);
+    }
+
+    #[test]
+    fn never_goes_backwards() {
+        let mut discipline = synced(10_000, 0);
+        assert_eq!(discipline.now(at_ms(10_000)), at_ms(10_000));
+        // a step back of a second holds the output still until the local clock catches up
+        discipline.clear_samples();
+        assert!(discipline.add_sample(sample(10_000, -1000 * MS, MS as u64)));
+        let mut previous = Epoch::ZERO;
+        for ms in (10_000..12_000).step_by(10) {
+            let corrected = discipline.now(at_ms(ms));
+            assert!(corrected >= previous);
+            if ms <= 11_000 {
+                assert_eq!(corrected, at_ms(10_000));
+            } else {
+                assert_eq!(corrected, at_ms(ms - 1000));
+            }
+            previous = corrected;
+        }
+    }
+}
@@ -0,0 +1,76 @@
+/*!
+A host pings a board with an EchoRequest and the board answers whoever sent it with an
+EchoReply, with the same four timestamps as an ntp exchange
+
+```text
+host            board
+  t1  -- EchoRequest -->  t2
+  t4  <-- EchoReply --    t3
+```
+
+t1 and t4 are from the host clock, t2 and t3 from the board's disciplined clock, so:
+
+offset (board - host) = ((t2 - t1) + (t3 - t4)) / 2
+round trip = (t4 - t1) - (t3 - t2)
+
+Several hosts can ping the same board at once, the counter and origin come back unchanged to
+match replies to requests.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::Epoch;
+
+/// sent at t1
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct EchoRequest {
+    pub counter: u64,
+    pub origin: Epoch,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct EchoReply {
+    /// from the request
+    pub counter: u64,
+    /// t1, from the request
+    pub origin: Epoch,
+    /// t2, when the board received the request
+    pub receive: Epoch,
+    /// t3, right before the board sent this
+    pub transmit: Epoch,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EchoMeasurement {
+    /// board time - host time
+    pub offset_ns: i64,
+    /// the network round trip, without the time the board took to reply
+    pub roundtrip_ns: i64,
+    /// t3 - t2
+    pub turnaround_ns: i64,
+}
+
+impl EchoReply {
+    pub fn new(request: &EchoRequest, receive: Epoch, transmit: Epoch) -> Self {
+        Self {
+            counter: request.counter,
+            origin: request.origin,
+            receive,
+            transmit,
+        }
+    }
+
+    /// arrival is when the reply got back to the host (t4), None if the timestamps don't fit in
+    /// i64 nanoseconds
+    pub fn measure(&self, arrival: Epoch) -> Option<EchoMeasurement> {
+        let outbound = self.receive.signed_nanos_since(&self.origin)? as i128;
+        let inbound = self.transmit.signed_nanos_since(&arrival)? as i128;
+        let total = arrival.signed_nanos_since(&self.origin)? as i128;
+        let turnaround = self.transmit.signed_nanos_since(&self.receive)? as i128;
+        Some(EchoMeasurement {
+            offset_ns: i64::try_from((outbound + inbound) / 2).ok()?,
+            roundtrip_ns: i64::try_from(total - turnaround).ok()?,
+            turnaround_ns: i64::try_from(turnaround).ok()?,
+        })
+    }
+}
@@ -0,0 +1,513 @@
+/*!
+Time since the unix epoch, with arithmetic that can't silently wrap, e.g. when a board applies
+a negative ntp offset to its tick count shortly after boot.
+
+Signed offsets are i64 nanoseconds (good for +/- 292 years), or i64 microseconds for
+sntpc::NtpResult::offset.
+*/
+
+use core::cmp::Ordering;
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+pub const NANOS_PER_SEC: u32 = 1_000_000_000;
+const NANOS_PER_MICRO: i128 = 1_000;
+
+/// seconds from 1900 (ntp era 0) to 1970
+pub const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// nanos may be >= NANOS_PER_SEC if the fields were set directly, all methods and comparisons
+/// treat it as carrying into secs
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default)]
+pub struct Epoch {
+    pub secs: u64,
+    pub nanos: u32,
+}
+
+impl Epoch {
+    pub const ZERO: Epoch = Epoch { secs: 0, nanos: 0 };
+    pub const MAX: Epoch = Epoch {
+        secs: u64::MAX,
+        nanos: NANOS_PER_SEC - 1,
+    };
+
+    /// None if carrying nanos into secs overflows
+    pub const fn checked_new(secs: u64, nanos: u32) -> Option<Epoch> {
+        let Some(secs) = secs.checked_add((nanos / NANOS_PER_SEC) as u64) else {
+            return None;
+        };
+        Some(Epoch {
+            secs,
+            nanos: nanos % NANOS_PER_SEC,
+        })
+    }
+
+    /// carry nanos into secs, saturating at Epoch::MAX
+    pub const fn new(secs: u64, nanos: u32) -> Epoch {
+        match Self::checked_new(secs, nanos) {
+            Some(epoch) => epoch,
+            None => Epoch::MAX,
+        }
+    }
+
+    pub fn normalize(&mut self) {
+        *self = self.normalized();
+    }
+
+    pub const fn normalized(&self) -> Epoch {
+        Self::new(self.secs, self.nanos)
+    }
+
+    pub const fn as_nanos(&self) -> u128 {
+        self.secs as u128 * NANOS_PER_SEC as u128 + self.nanos as u128
+    }
+
+    /// None if beyond Epoch::MAX
+    pub const fn from_nanos(nanos: u128) -> Option<Epoch> {
+        let secs = nanos / NANOS_PER_SEC as u128;
+        if secs > u64::MAX as u128 {
+            return None;
+        }
+        Some(Epoch {
+            secs: secs as u64,
+            nanos: (nanos % NANOS_PER_SEC as u128) as u32,
+        })
+    }
+
+    /// self + nanos, None if before Epoch::ZERO or after Epoch::MAX
+    fn checked_offset(&self, nanos: i128) -> Option<Epoch> {
+        let total = self.as_nanos() as i128 + nanos;
+        if total < 0 {
+            return None;
+        }
+        Self::from_nanos(total as u128)
+    }
+
+    /// None if the result would be before 1970 or after Epoch::MAX
+    pub fn checked_add_signed_nanos(&self, nanos: i64) -> Option<Epoch> {
+        self.checked_offset(nanos as i128)
+    }
+
+    pub fn checked_sub_signed_nanos(&self, nanos: i64) -> Option<Epoch> {
+        // negating in i128 so i64::MIN doesn't overflow
+        self.checked_offset(-(nanos as i128))
+    }
+
+    /// clamps to Epoch::ZERO or Epoch::MAX
+    pub fn saturating_add_signed_nanos(&self, nanos: i64) -> Epoch {
+        self.checked_add_signed_nanos(nanos)
+            .unwrap_or(if nanos < 0 { Epoch::ZERO } else { Epoch::MAX })
+    }
+
+    pub fn saturating_sub_signed_nanos(&self, nanos: i64) -> Epoch {
+        self.checked_sub_signed_nanos(nanos)
+            .unwrap_or(if nanos > 0 { Epoch::ZERO } else { Epoch::MAX })
+    }
+
+    /// add a signed microsecond offset like sntpc::NtpResult::offset
+    pub fn checked_add_micros(&self, micros: i64) -> Option<Epoch> {
+        self.checked_offset(micros as i128 * NANOS_PER_MICRO)
+    }
+
+    pub fn saturating_add_micros(&self, micros: i64) -> Epoch {
+        self.checked_add_micros(micros)
+            .unwrap_or(if micros < 0 { Epoch::ZERO } else { Epoch::MAX })
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Epoch> {
+        Self::from_nanos(self.as_nanos().checked_add(duration.as_nanos())?)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Epoch> {
+        Self::from_nanos(self.as_nanos().checked_sub(duration.as_nanos())?)
+    }
+
+    pub fn saturating_add(&self, duration: Duration) -> Epoch {
+        self.checked_add(duration).unwrap_or(Epoch::MAX)
+    }
+
+    pub fn saturating_sub(&self, duration: Duration) -> Epoch {
+        self.checked_sub(duration).unwrap_or(Epoch::ZERO)
+    }
+
+    /// self - earlier, None if earlier is later than self
+    pub fn duration_since(&self, earlier: &Epoch) -> Option<Duration> {
+        let nanos = self.as_nanos().checked_sub(earlier.as_nanos())?;
+        let secs = nanos / NANOS_PER_SEC as u128;
+        if secs > u64::MAX as u128 {
+            return None;
+        }
+        Some(Duration::new(
+            secs as u64,
+            (nanos % NANOS_PER_SEC as u128) as u32,
+        ))
+    }
+
+    /// self - other in signed nanoseconds, None if that doesn't fit in an i64
+    pub fn signed_nanos_since(&self, other: &Epoch) -> Option<i64> {
+        let diff = self.as_nanos() as i128 - other.as_nanos() as i128;
+        i64::try_from(diff).ok()
+    }
+
+    /// the time since the unix epoch as a Duration, saturating if nanos carries past u64 secs
+    pub fn as_duration(&self) -> Duration {
+        Duration::from_secs(self.secs).saturating_add(Duration::from_nanos(self.nanos as u64))
+    }
+
+    /// 64 bit ntp timestamp, seconds since 1900 in the upper 32 bits and the fraction of a
+    /// second in the lower 32.  The seconds wrap every 136 years, after 2036 this is era 1.
+    pub const fn to_ntp(&self) -> u64 {
+        let epoch = self.normalized();
+        let secs = epoch.secs.wrapping_add(NTP_UNIX_OFFSET_SECS) as u32;
+        // round to the nearest fraction
+        let fraction =
+            (((epoch.nanos as u64) << 32) + NANOS_PER_SEC as u64 / 2) / NANOS_PER_SEC as u64;
+        ((secs as u64) << 32) | fraction
+    }
+
+    /// inverse of to_ntp(), ntp seconds before 1970 are taken to be in era 1 (2036-2106)
+    pub const fn from_ntp(ntp: u64) -> Epoch {
+        Self::from_ntp_parts((ntp >> 32) as u32, ntp as u32)
+    }
+
+    /// from separate seconds and fraction, like sntpc::NtpResult seconds and seconds_fraction
+    pub const fn from_ntp_parts(seconds: u32, fraction: u32) -> Epoch {
+        let seconds = seconds as u64;
+        let secs = if seconds >= NTP_UNIX_OFFSET_SECS {
+            seconds - NTP_UNIX_OFFSET_SECS
+        } else {
+            seconds + (1 << 32) - NTP_UNIX_OFFSET_SECS
+        };
+        let nanos = (fraction as u64 * NANOS_PER_SEC as u64 + (1 << 31)) >> 32;
+        Epoch::new(secs, nanos as u32)
+    }
+}
+
+impl PartialEq for Epoch {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_nanos() == other.as_nanos()
+    }
+}
+
+impl Eq for Epoch {}
+
+impl PartialOrd for Epoch {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Epoch {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_nanos().cmp(&other.as_nanos())
+    }
+}
+
+/// a duration since the unix epoch
+impl From<Duration> for Epoch {
+    fn from(duration: Duration) -> Self {
+        Epoch {
+            secs: duration.as_secs(),
+            nanos: duration.subsec_nanos(),
+        }
+    }
+}
+
+impl From<Epoch> for Duration {
+    fn from(epoch: Epoch) -> Self {
+        epoch.as_duration()
+    }
+}
+
+#[cfg(feature = "std")]
+mod system_time {
+    use super::Epoch;
+    use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
+
+    impl Epoch {
+        pub fn now() -> Epoch {
+            SystemTime::now()
+                .try_into()
+                .expect("system time is before 1970")
+        }
+    }
+
+    /// fails for times before 1970
+    impl TryFrom<SystemTime> for Epoch {
+        type Error = SystemTimeError;
+
+        fn try_from(stamp: SystemTime) -> Result<Self, Self::Error> {
+            Ok(stamp.duration_since(UNIX_EPOCH)?.into())
+        }
+    }
+
+    /// the Epoch is later than SystemTime can hold on this platform
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct SystemTimeOverflow;
+
+    impl core::fmt::Display for SystemTimeOverflow {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "epoch is out of the SystemTime range")
+        }
+    }
+
+    impl std::error::Error for SystemTimeOverflow {}
+
+    /// fails for times past the end of SystemTime, which is platform dependent
+    impl TryFrom<Epoch> for SystemTime {
+        type Error = SystemTimeOverflow;
+
+        fn try_from(epoch: Epoch) -> Result<Self, Self::Error> {
+            UNIX_EPOCH
+                .checked_add(epoch.as_duration())
+                .ok_or(SystemTimeOverflow)
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+pub use system_time::SystemTimeOverflow;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NANOS: i64 = NANOS_PER_SEC as i64;
+
+    fn epoch(secs: u64, nanos: u32) -> Epoch {
+        Epoch { secs, nanos }
+    }
+
+    #[test]
+    fn negative_nanos_borrow_from_secs() {
+        let start = epoch(10, 100);
+        assert_eq!(start.checked_add_signed_nanos(-100), Some(epoch(10, 0)));
+        assert_eq!(
+            start.checked_add_signed_nanos(-101),
+            Some(epoch(9, 999_999_999))
+        );
+        assert_eq!(
+            start.checked_add_signed_nanos(-200),
+            Some(epoch(9, 999_999_900))
+        );
+        assert_eq!(
+            start.checked_add_signed_nanos(-NANOS - 100),
+            Some(epoch(9, 0))
+        );
+        assert_eq!(
+            start.checked_add_signed_nanos(-10 * NANOS - 100),
+            Some(Epoch::ZERO)
+        );
+        assert_eq!(
+            start.checked_sub_signed_nanos(200),
+            Some(epoch(9, 999_999_900))
+        );
+        assert_eq!(start.checked_sub_signed_nanos(-200), Some(epoch(10, 300)));
+        assert_eq!(start.checked_sub_signed_nanos(-NANOS), Some(epoch(11, 100)));
+        assert_eq!(
+            start.checked_add_signed_nanos(NANOS - 100),
+            Some(epoch(11, 0))
+        );
+    }
+
+    #[test]
+    fn negative_nanos_across_zero() {
+        let start = epoch(0, 100);
+        assert_eq!(start.checked_add_signed_nanos(-100), Some(Epoch::ZERO));
+        assert_eq!(start.checked_add_signed_nanos(-101), None);
+        assert_eq!(start.checked_sub_signed_nanos(101), None);
+        assert_eq!(epoch(1, 0).checked_add_signed_nanos(-NANOS - 1), None);
+        assert_eq!(Epoch::ZERO.checked_add_signed_nanos(-1), None);
+
+        assert_eq!(start.saturating_add_signed_nanos(-101), Epoch::ZERO);
+        assert_eq!(start.saturating_sub_signed_nanos(101), Epoch::ZERO);
+        assert_eq!(start.saturating_add_signed_nanos(-99), epoch(0, 1));
+        assert_eq!(start.saturating_sub_signed_nanos(-1), epoch(0, 101));
+    }
+
+    #[test]
+    fn saturating_at_max() {
+        assert_eq!(Epoch::MAX.checked_add_signed_nanos(1), None);
+        assert_eq!(Epoch::MAX.saturating_add_signed_nanos(1), Epoch::MAX);
+        assert_eq!(Epoch::MAX.saturating_sub_signed_nanos(-1), Epoch::MAX);
+        assert_eq!(Epoch::MAX.saturating_add_micros(1), Epoch::MAX);
+        assert_eq!(
+            Epoch::MAX.saturating_add_signed_nanos(-1),
+            epoch(u64::MAX, NANOS_PER_SEC - 2)
+        );
+        assert_eq!(
+            Epoch::MAX.saturating_add(Duration::from_nanos(1)),
+            Epoch::MAX
+        );
+        assert_eq!(
+            Epoch::ZERO.saturating_sub(Duration::from_nanos(1)),
+            Epoch::ZERO
+        );
+    }
+
+    #[test]
+    fn negative_micros() {
+        assert_eq!(
+            epoch(10, 500).checked_add_micros(-1),
+            Some(epoch(9, 999_999_500))
+        );
+        assert_eq!(
+            epoch(10, 500).checked_add_micros(-10_000_000),
+            Some(epoch(0, 500))
+        );
+        assert_eq!(epoch(0, 1000).checked_add_micros(-1), Some(Epoch::ZERO));
+        assert_eq!(epoch(0, 999).checked_add_micros(-1), None);
+        assert_eq!(epoch(0, 999).saturating_add_micros(-1), Epoch::ZERO);
+        assert_eq!(epoch(0, 999).saturating_add_micros(1), epoch(0, 1999));
+        assert_eq!(
+            epoch(5, 0).checked_add_micros(1_500_000),
+            Some(epoch(6, 500_000_000))
+        );
+        assert_eq!(
+            epoch(5, 0).checked_add_micros(-1_500_000),
+            Some(epoch(3, 500_000_000))
+        );
+    }
+
+    #[test]
+    fn i64_min() {
+        // i64::MIN nanos is -9223372036.854775808 seconds
+        assert_eq!(
+            epoch(10_000_000_000, 0).checked_add_signed_nanos(i64::MIN),
+            Some(epoch(776_627_963, 145_224_192))
+        );
+        assert_eq!(
+            Epoch::ZERO.checked_sub_signed_nanos(i64::MIN),
+            Some(epoch(9_223_372_036, 854_775_808))
+        );
+        assert_eq!(epoch(1, 0).checked_add_signed_nanos(i64::MIN), None);
+        assert_eq!(
+            epoch(1, 0).saturating_add_signed_nanos(i64::MIN),
+            Epoch::ZERO
+        );
+        assert_eq!(Epoch::MAX.saturating_sub_signed_nanos(i64::MIN), Epoch::MAX);
+        assert_eq!(Epoch::ZERO.checked_add_micros(i64::MIN), None);
+        assert_eq!(Epoch::ZERO.saturating_add_micros(i64::MIN), Epoch::ZERO);
+        assert_eq!(
+            Epoch::ZERO.checked_add_micros(i64::MAX),
+            Some(epoch(9_223_372_036_854, 775_807_000))
+        );
+        assert_eq!(
+            epoch(9_223_372_036, 854_775_808).signed_nanos_since(&Epoch::ZERO),
+            None
+        );
+        assert_eq!(
+            Epoch::ZERO.signed_nanos_since(&epoch(9_223_372_036, 854_775_808)),
+            Some(i64::MIN)
+        );
+    }
+
+    #[test]
+    fn ntp_round_trip() {
+        assert_eq!(Epoch::ZERO.to_ntp(), NTP_UNIX_OFFSET_SECS << 32);
+        assert_eq!(Epoch::from_ntp(NTP_UNIX_OFFSET_SECS << 32), Epoch::ZERO);
+        assert_eq!(
+            Epoch::from_ntp_parts(0xe000_0000, 0x8000_0000).nanos,
+            500_000_000
+        );
+        for secs in [0, 1, 1_700_000_000, 2_085_978_495] {
+            for nanos in [0, 1, 499_999_999, 500_000_000, 999_999_999] {
+                let start = epoch(secs, nanos);
+                let ntp = start.to_ntp();
+                assert_eq!(Epoch::from_ntp(ntp), start, "{ntp:x}");
+                assert_eq!(Epoch::from_ntp_parts((ntp >> 32) as u32, ntp as u32), start);
+            }
+        }
+    }
+
+    #[test]
+    fn ntp_era_1() {
+        // 2036-02-07 06:28:16, where the ntp seconds wrap
+        let era_1 = epoch((1 << 32) - NTP_UNIX_OFFSET_SECS, 0);
+        assert_eq!(era_1.to_ntp(), 0);
+        assert_eq!(Epoch::from_ntp(0), era_1);
+        assert_eq!(epoch(era_1.secs - 1, 0).to_ntp() >> 32, u32::MAX as u64);
+        // 2050
+        let later = epoch(2_524_608_000, 250_000_000);
+        assert_eq!(
+            later.to_ntp() >> 32,
+            2_524_608_000 + NTP_UNIX_OFFSET_SECS - (1 << 32)
+        );
+        assert_eq!(Epoch::from_ntp(later.to_ntp()), later);
+        // 2106, the last second from_ntp() takes to be era 1
+        let last = epoch((1 << 32) - 1, 999_999_999);
+        assert_eq!(last.to_ntp() >> 32, NTP_UNIX_OFFSET_SECS - 1);
+        assert_eq!(Epoch::from_ntp(last.to_ntp()), last);
+    }
+
+    #[test]
+    fn normalization_and_ordering() {
+        let carried = epoch(1, 1_500_000_000);
+        assert_eq!(carried, epoch(2, 500_000_000));
+        let normalized = carried.normalized();
+        assert_eq!((normalized.secs, normalized.nanos), (2, 500_000_000));
+        let mut normalize = carried;
+        normalize.normalize();
+        assert_eq!((normalize.secs, normalize.nanos), (2, 500_000_000));
+
+        assert!(carried > epoch(2, 0));
+        assert!(carried < epoch(2, 600_000_000));
+        assert!(epoch(0, u32::MAX) > epoch(4, 0));
+        let mut sorted = [epoch(3, 0), carried, epoch(0, 2_100_000_000), epoch(2, 0)];
+        sorted.sort();
+        assert_eq!(
+            sorted.map(|epoch| epoch.as_nanos()),
+            [2_000_000_000, 2_100_000_000, 2_500_000_000, 3_000_000_000]
+        );
+
+        assert_eq!(Epoch::checked_new(u64::MAX, NANOS_PER_SEC), None);
+        assert_eq!(Epoch::new(u64::MAX, NANOS_PER_SEC), Epoch::MAX);
+        assert_eq!(epoch(u64::MAX, u32::MAX).normalized(), Epoch::MAX);
+        assert_eq!(
+            carried
+                .checked_add_signed_nanos(0)
+                .map(|e| (e.secs, e.nanos)),
+            Some((2, 500_000_000))
+        );
+        assert_eq!(carried.to_ntp(), epoch(2, 500_000_000).to_ntp());
+        assert_eq!(
+            carried.duration_since(&epoch(1, 0)),
+            Some(Duration::new(1, 500_000_000))
+        );
+        assert_eq!(epoch(1, 0).duration_since(&carried), None);
+        assert_eq!(
+            epoch(1, 0).signed_nanos_since(&carried),
+            Some(-1_500_000_000)
+        );
+    }
+
+    #[test]
+    fn duration_conversions() {
+        let start = Epoch::from(Duration::new(5, 7));
+        assert_eq!((start.secs, start.nanos), (5, 7));
+        assert_eq!(
+            Duration::from(epoch(1, 1_500_000_000)),
+            Duration::new(2, 500_000_000)
+        );
+        assert_eq!(Epoch::MAX.as_duration(), Duration::MAX);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn system_time() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let stamp = UNIX_EPOCH + Duration::new(1_700_000_000, 7);
+        let start = Epoch::try_from(stamp).unwrap();
+        assert_eq!((start.secs, start.nanos), (1_700_000_000, 7));
+        assert_eq!(SystemTime::try_from(start), Ok(stamp));
+        assert_eq!(
+            SystemTime::try_from(epoch(1, 1_500_000_000)),
+            Ok(UNIX_EPOCH + Duration::new(2, 500_000_000))
+        );
+        assert_eq!(SystemTime::try_from(Epoch::MAX), Err(SystemTimeOverflow));
+        assert!(Epoch::try_from(UNIX_EPOCH - Duration::from_secs(1)).is_err());
+        assert!(Epoch::now() > start);
+    }
+}
@@ -0,0 +1,554 @@
+/*!
+Firmware updates over udp, the new image is written to the dfu partition of an embassy-boot
+style bootloader, which swaps it in at the next reset
+
+```text
+uploader                        board
+FirmwareBegin    -->  start an image, or carry on with the same one where it left off
+FirmwareChunk    -->  CHUNK_LEN bytes at `next`, the last chunk can be shorter
+FirmwareVerify   -->  read the whole image back and check its crc and sha-256
+FirmwareCommit   -->  have the bootloader install it, then reset
+                 <--  FirmwareStatus answers each of them
+```
+
+Chunks are written in order, a chunk before `next` was already written and one after it is
+refused, so the uploader only has to resend from the `next` in the last FirmwareStatus after a
+lost packet.  Sending the FirmwareBegin again for the same image keeps what was written, so an
+interrupted upload resumes as long as the board wasn't reset.
+
+Updater does all of this without any io, the firmware gives it a Dfu and sends the replies.
+*/
+
+use embedded_storage::nor_flash::{NorFlash, NorFlashErrorKind, ReadNorFlash};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Message;
+
+/// the image bytes in every FirmwareChunk but the last, a multiple of the flash write size, small
+/// enough for the message to fit the 256 byte buffers the firmwares encode into
+pub const CHUNK_LEN: usize = 128;
+
+/// the crc of the whole image, the same one the messages use
+pub const IMAGE_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct FirmwareBegin {
+    /// bytes in the image
+    pub len: u32,
+    pub crc: u32,
+    pub sha256: [u8; 32],
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct FirmwareChunk {
+    /// where the data goes in the image, a multiple of CHUNK_LEN
+    pub offset: u32,
+    pub data: heapless::Vec<u8, CHUNK_LEN>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct FirmwareVerify {}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct FirmwareCommit {}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum FirmwareState {
+    /// no update in progress
+    #[default]
+    Idle,
+    Receiving,
+    /// every chunk is written
+    Received,
+    /// the crc and sha-256 of the written image match FirmwareBegin
+    Verified,
+    /// the bootloader installs the image at the next reset
+    Committed,
+}
+
+/// how the request a FirmwareStatus answers went
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum FirmwareResult {
+    #[default]
+    Ok,
+    /// the image is empty or doesn't fit in the dfu partition
+    TooLarge,
+    /// the flash write size doesn't divide CHUNK_LEN
+    Unsupported,
+    /// not expected in the current state, e.g. a chunk before FirmwareBegin
+    WrongState,
+    /// a chunk after `next`, resend from `next`
+    WrongOffset,
+    /// a chunk shorter than CHUNK_LEN that isn't the last one, or past the end of the image
+    WrongLength,
+    /// the image read back doesn't match, start again with FirmwareBegin
+    BadCrc,
+    BadSha256,
+    Flash,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct FirmwareStatus {
+    pub state: FirmwareState,
+    pub result: FirmwareResult,
+    /// the offset of the next chunk to send, bytes written so far
+    pub next: u32,
+    /// bytes in the image being received
+    pub len: u32,
+}
+
+/// the partition a new firmware is written to, and the bootloader state that says what to boot
+pub trait Dfu {
+    type Flash: NorFlash;
+
+    /// the image goes here from offset 0
+    fn partition(&mut self) -> &mut Self::Flash;
+    /// swap in the image in the partition at the next reset
+    fn mark_updated(&mut self) -> Result<(), NorFlashErrorKind>;
+    /// keep the running firmware, otherwise the bootloader goes back to the previous one at the
+    /// next reset
+    fn mark_booted(&mut self) -> Result<(), NorFlashErrorKind>;
+}
+
+/// receives an image into a Dfu, see the module docs
+pub struct Updater<D> {
+    dfu: D,
+    state: FirmwareState,
+    image: FirmwareBegin,
+    next: u32,
+    /// the partition is erased up to here for this image
+    erased: u32,
+}
+
+impl<D: Dfu> Updater<D> {
+    pub const fn new(dfu: D) -> Self {
+        Self {
+            dfu,
+            state: FirmwareState::Idle,
+            image: FirmwareBegin {
+                len: 0,
+                crc: 0,
+                sha256: [0; 32],
+            },
+            next: 0,
+            erased: 0,
+        }
+    }
+
+    pub fn dfu(&mut self) -> &mut D {
+        &mut self.dfu
+    }
+
+    pub fn state(&self) -> FirmwareState {
+        self.state
+    }
+
+    /// answer a firmware message with a FirmwareStatus, None for any other message.  Reset the
+    /// board once the reply is sent if the state is Committed
+    pub fn handle(&mut self, msg: &Message) -> Option<Message> {
+        let result = match msg {
+            Message::FirmwareBegin(begin) => self.begin(begin),
+            Message::FirmwareChunk(chunk) => self.chunk(chunk),
+            Message::FirmwareVerify(_) => self.verify(),
+            Message::FirmwareCommit(_) => self.commit(),
+            _ => {
+                return None;
+            }
+        };
+        Some(Message::FirmwareStatus(self.status(result)))
+    }
+
+    pub fn status(&self, result: FirmwareResult) -> FirmwareStatus {
+        FirmwareStatus {
+            state: self.state,
+            result,
+            next: self.next,
+            len: self.image.len,
+        }
+    }
+
+    fn begin(&mut self, begin: &FirmwareBegin) -> FirmwareResult {
+        let write_size = <D::Flash as NorFlash>::WRITE_SIZE;
+        if write_size > CHUNK_LEN || !CHUNK_LEN.is_multiple_of(write_size) {
+            return FirmwareResult::Unsupported;
+        }
+        let capacity = self.dfu.partition().capacity() as u64;
+        let padded = (begin.len as u64).div_ceil(CHUNK_LEN as u64) * CHUNK_LEN as u64;
+        if begin.len == 0 || padded > capacity {
+            return FirmwareResult::TooLarge;
+        }
+        let resume = matches!(
+            self.state,
+            FirmwareState::Receiving | FirmwareState::Received | FirmwareState::Verified
+        ) && self.image == *begin;
+        if !resume {
+            self.image = begin.clone();
+            self.state = FirmwareState::Receiving;
+            self.next = 0;
+            self.erased = 0;
+        }
+        FirmwareResult::Ok
+    }
+
+    fn chunk(&mut self, chunk: &FirmwareChunk) -> FirmwareResult {
+        match self.state {
+            FirmwareState::Receiving => {}
+            // a resend of a chunk whose reply was lost
+            FirmwareState::Received | FirmwareState::Verified if chunk.offset < self.next => {
+                return FirmwareResult::Ok;
+            }
+            _ => {
+                return FirmwareResult::WrongState;
+            }
+        }
+        if chunk.offset < self.next {
+            return FirmwareResult::Ok;
+        }
+        if chunk.offset > self.next {
+            return FirmwareResult::WrongOffset;
+        }
+        let len = chunk.data.len() as u32;
+        let end = self.next + len;
+        if end > self.image.len || (len != CHUNK_LEN as u32 && end != self.image.len) {
+            return FirmwareResult::WrongLength;
+        }
+
+        // erase a sector at a time as the image reaches it, erasing the whole partition at the
+        // start could take long enough for the uploader to give up
+        let erase_size = <D::Flash as NorFlash>::ERASE_SIZE as u32;
+        let flash = self.dfu.partition();
+        let padded = self.next + CHUNK_LEN as u32;
+        while self.erased < padded {
+            if flash.erase(self.erased, self.erased + erase_size).is_err() {
+                return FirmwareResult::Flash;
+            }
+            self.erased += erase_size;
+        }
+        // the last chunk is padded like erased flash
+        let mut buf = [0xff; CHUNK_LEN];
+        buf[..chunk.data.len()].copy_from_slice(&chunk.data);
+        let write_size = <D::Flash as NorFlash>::WRITE_SIZE;
+        let write_len = chunk.data.len().div_ceil(write_size) * write_size;
+        if flash.write(self.next, &buf[..write_len]).is_err() {
+            return FirmwareResult::Flash;
+        }
+        self.next = end;
+        if self.next == self.image.len {
+            self.state = FirmwareState::Received;
+        }
+        FirmwareResult::Ok
+    }
+
+    fn verify(&mut self) -> FirmwareResult {
+        match self.state {
+            FirmwareState::Received => {}
+            FirmwareState::Verified => {
+                return FirmwareResult::Ok;
+            }
+            _ => {
+                return FirmwareResult::WrongState;
+            }
+        }
+        let mut crc = IMAGE_CRC.digest();
+        let mut sha = Sha256::new();
+        let mut buf = [0; CHUNK_LEN];
+        let mut offset = 0;
+        while offset < self.image.len {
+            let len = (self.image.len - offset).min(CHUNK_LEN as u32) as usize;
+            if self.dfu.partition().read(offset, &mut buf[..len]).is_err() {
+                return FirmwareResult::Flash;
+            }
+            crc.update(&buf[..len]);
+            sha.update(&buf[..len]);
+            offset += len as u32;
+        }
+        // a bad image has to be sent again from the start
+        if crc.finalize() != self.image.crc {
+            self.state = FirmwareState::Idle;
+            return FirmwareResult::BadCrc;
+        }
+        if sha.finalize()[..] != self.image.sha256 {
+            self.state = FirmwareState::Idle;
+            return FirmwareResult::BadSha256;
+        }
+        self.state = FirmwareState::Verified;
+        FirmwareResult::Ok
+    }
+
+    fn commit(&mut self) -> FirmwareResult {
+        match self.state {
+            FirmwareState::Verified => {}
+            FirmwareState::Committed => {
+                return FirmwareResult::Ok;
+            }
+            _ => {
+                return FirmwareResult::WrongState;
+            }
+        }
+        if self.dfu.mark_updated().is_err() {
+            return FirmwareResult::Flash;
+        }
+        self.state = FirmwareState::Committed;
+        FirmwareResult::Ok
+    }
+}
+
+/// the FirmwareBegin for an image, for the uploader
+pub fn begin_for(image: &[u8]) -> FirmwareBegin {
+    FirmwareBegin {
+        len: image.len() as u32,
+        crc: IMAGE_CRC.checksum(image),
+        sha256: Sha256::digest(image).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flash_settings::RamFlash;
+    use std::vec::Vec;
+
+    // a sector is two chunks, with the stm32h7 write size
+    type Flash = RamFlash<{ 8 * 1024 }, 32, 1024>;
+
+    struct RamDfu {
+        flash: Flash,
+        updated: bool,
+    }
+
+    impl Dfu for RamDfu {
+        type Flash = Flash;
+
+        fn partition(&mut self) -> &mut Flash {
+            &mut self.flash
+        }
+
+        fn mark_updated(&mut self) -> Result<(), NorFlashErrorKind> {
+            self.updated = true;
+            Ok(())
+        }
+
+        fn mark_booted(&mut self) -> Result<(), NorFlashErrorKind> {
+            Ok(())
+        }
+    }
+
+    fn updater() -> Updater<RamDfu> {
+        Updater::new(RamDfu {
+            flash: Flash::new(),
+            updated: false,
+        })
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + i / 256) as u8).collect()
+    }
+
+    fn status(updater: &mut Updater<RamDfu>, msg: Message) -> FirmwareStatus {
+        match updater.handle(&msg) {
+            Some(Message::FirmwareStatus(status)) => status,
+            other => panic!("expected a FirmwareStatus, got {other:?}"),
+        }
+    }
+
+    fn chunk(image: &[u8], offset: usize) -> Message {
+        let end = (offset + CHUNK_LEN).min(image.len());
+        Message::FirmwareChunk(FirmwareChunk {
+            offset: offset as u32,
+            data: heapless::Vec::from_slice(&image[offset..end]).unwrap(),
+        })
+    }
+
+    fn verify() -> Message {
+        Message::FirmwareVerify(FirmwareVerify {})
+    }
+
+    fn commit() -> Message {
+        Message::FirmwareCommit(FirmwareCommit {})
+    }
+
+    /// begin and every chunk, checking each reply
+    fn send(updater: &mut Updater<RamDfu>, image: &[u8]) {
+        let begin = status(updater, Message::FirmwareBegin(begin_for(image)));
+        assert_eq!(begin.result, FirmwareResult::Ok);
+        for offset in (0..image.len()).step_by(CHUNK_LEN) {
+            let status = status(updater, chunk(image, offset));
+            assert_eq!(status.result, FirmwareResult::Ok);
+            assert_eq!(status.next as usize, (offset + CHUNK_LEN).min(image.len()));
+        }
+    }
+
+    fn send_with(updater: &mut Updater<RamDfu>, image: &[u8], begin: FirmwareBegin) {
+        status(updater, Message::FirmwareBegin(begin));
+        for offset in (0..image.len()).step_by(CHUNK_LEN) {
+            assert_eq!(
+                status(updater, chunk(image, offset)).result,
+                FirmwareResult::Ok
+            );
+        }
+    }
+
+    #[test]
+    fn update() {
+        let image = image(2000);
+        let mut updater = updater();
+        assert!(
+            updater
+                .handle(&Message::HealthGet(Default::default()))
+                .is_none()
+        );
+        send(&mut updater, &image);
+        assert_eq!(updater.state(), FirmwareState::Received);
+        let bytes = updater.dfu().flash.bytes();
+        assert_eq!(bytes[..image.len()], image[..]);
+        // the last chunk is padded like erased flash
+        assert!(bytes[image.len()..2048].iter().all(|b| *b == 0xff));
+
+        let verified = status(&mut updater, verify());
+        assert_eq!(
+            (verified.state, verified.result),
+            (FirmwareState::Verified, FirmwareResult::Ok)
+        );
+        assert!(!updater.dfu().updated);
+        let committed = status(&mut updater, commit());
+        assert_eq!(
+            (committed.state, committed.result),
+            (FirmwareState::Committed, FirmwareResult::Ok)
+        );
+        assert!(updater.dfu().updated);
+        // a resend of the commit whose reply was lost
+        assert_eq!(status(&mut updater, commit()).result, FirmwareResult::Ok);
+    }
+
+    #[test]
+    fn resume() {
+        let image = image(3000);
+        let mut updater = updater();
+        let begin = || Message::FirmwareBegin(begin_for(&image));
+        status(&mut updater, begin());
+        status(&mut updater, chunk(&image, 0));
+        status(&mut updater, chunk(&image, CHUNK_LEN));
+
+        // the same image carries on where it left off
+        let resumed = status(&mut updater, begin());
+        let next = 2 * CHUNK_LEN;
+        assert_eq!(
+            (resumed.result, resumed.next as usize),
+            (FirmwareResult::Ok, next)
+        );
+        for offset in (next..image.len()).step_by(CHUNK_LEN) {
+            status(&mut updater, chunk(&image, offset));
+        }
+        assert_eq!(status(&mut updater, verify()).result, FirmwareResult::Ok);
+        // still Verified
+        let resumed = status(&mut updater, begin());
+        assert_eq!(resumed.state, FirmwareState::Verified);
+
+        // another image starts again
+        let restarted = status(
+            &mut updater,
+            Message::FirmwareBegin(begin_for(&image[..1000])),
+        );
+        assert_eq!(
+            (restarted.state, restarted.next, restarted.len),
+            (FirmwareState::Receiving, 0, 1000)
+        );
+    }
+
+    #[test]
+    fn wrong_offset_and_length() {
+        let image = image(2000);
+        let mut updater = updater();
+        let early = status(&mut updater, chunk(&image, 0));
+        assert_eq!(early.result, FirmwareResult::WrongState);
+
+        status(&mut updater, Message::FirmwareBegin(begin_for(&image)));
+        // a chunk was lost, resend from next
+        let skipped = status(&mut updater, chunk(&image, CHUNK_LEN));
+        assert_eq!(
+            (skipped.result, skipped.next),
+            (FirmwareResult::WrongOffset, 0)
+        );
+        status(&mut updater, chunk(&image, 0));
+        // a chunk whose reply was lost
+        let again = status(&mut updater, chunk(&image, 0));
+        assert_eq!(
+            (again.result, again.next),
+            (FirmwareResult::Ok, CHUNK_LEN as u32)
+        );
+
+        // short but not the last
+        let short = Message::FirmwareChunk(FirmwareChunk {
+            offset: CHUNK_LEN as u32,
+            data: heapless::Vec::from_slice(&image[CHUNK_LEN..CHUNK_LEN + 50]).unwrap(),
+        });
+        assert_eq!(
+            status(&mut updater, short).result,
+            FirmwareResult::WrongLength
+        );
+        assert_eq!(
+            status(&mut updater, verify()).result,
+            FirmwareResult::WrongState
+        );
+        assert_eq!(
+            status(&mut updater, commit()).result,
+            FirmwareResult::WrongState
+        );
+    }
+
+    #[test]
+    fn bad_crc_and_sha256() {
+        let image = image(1500);
+        let mut updater = updater();
+        let mut begin = begin_for(&image);
+        begin.crc ^= 1;
+        send_with(&mut updater, &image, begin);
+        let bad = status(&mut updater, verify());
+        assert_eq!(
+            (bad.state, bad.result),
+            (FirmwareState::Idle, FirmwareResult::BadCrc)
+        );
+        assert_eq!(
+            status(&mut updater, commit()).result,
+            FirmwareResult::WrongState
+        );
+
+        let mut begin = begin_for(&image);
+        begin.sha256[0] ^= 1;
+        send_with(&mut updater, &image, begin);
+        let bad = status(&mut updater, verify());
+        assert_eq!(
+            (bad.state, bad.result),
+            (FirmwareState::Idle, FirmwareResult::BadSha256)
+        );
+        assert!(!updater.dfu().updated);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut updater = updater();
+        let empty = status(&mut updater, Message::FirmwareBegin(begin_for(&[])));
+        assert_eq!(empty.result, FirmwareResult::TooLarge);
+        let full = status(
+            &mut updater,
+            Message::FirmwareBegin(begin_for(&image(8 * 1024))),
+        );
+        assert_eq!(full.result, FirmwareResult::Ok);
+        let over = status(
+            &mut updater,
+            Message::FirmwareBegin(begin_for(&image(8 * 1024 + 1))),
+        );
+        assert_eq!(over.result, FirmwareResult::TooLarge);
+    }
+
+    #[test]
+    fn flash_error() {
+        let image = image(1000);
+        let mut updater = updater();
+        status(&mut updater, Message::FirmwareBegin(begin_for(&image)));
+        updater.dfu().flash.set_write_budget(Some(100));
+        let failed = status(&mut updater, chunk(&image, 0));
+        assert_eq!((failed.result, failed.next), (FirmwareResult::Flash, 0));
+    }
+}
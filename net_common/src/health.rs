@@ -0,0 +1,387 @@
+/*!
+The tasks of a board check in with a Supervisor, which only lets the watchdog be fed while every
+task it watches has checked in recently, and picks what the leds show from the state of the
+board.  A HealthGet sent to the board is answered with a Health saying when each task last
+checked in.
+
+Once a task misses its check in the board stays in Fault until the watchdog resets it, even if
+the task comes back.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::LedPattern;
+
+/// most tasks that can be watched
+pub const MAX_TASKS: usize = 8;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum TaskId {
+    /// the loop answering the command port
+    #[default]
+    Main,
+    /// the network stack, from its driver so a stack that stops being polled stalls
+    Net,
+    NetConfig,
+    TimeSync,
+    /// the telemetry scheduler
+    Telemetry,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum BoardState {
+    /// no network configuration yet
+    #[default]
+    NoNetwork,
+    /// no ntp server answering
+    NoSync,
+    Synced,
+    /// a task missed its check in, the watchdog resets the board soon
+    Fault,
+}
+
+/// how long a led is on then off, OFF and ON for steady
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Blink {
+    pub on_ms: u32,
+    pub off_ms: u32,
+}
+
+impl Blink {
+    pub const OFF: Blink = Blink::new(0, 1000);
+    pub const ON: Blink = Blink::new(1000, 0);
+
+    pub const fn new(on_ms: u32, off_ms: u32) -> Self {
+        Self { on_ms, off_ms }
+    }
+
+    pub fn is_on(&self, now_ms: u64) -> bool {
+        let period = (self.on_ms + self.off_ms) as u64;
+        period > 0 && now_ms % period < self.on_ms as u64
+    }
+}
+
+impl BoardState {
+    /// the green, orange and red leds, the Blink pattern shows the state and the others
+    /// override it
+    pub fn leds(&self, pattern: LedPattern) -> [Blink; 3] {
+        match pattern {
+            LedPattern::Blink => {}
+            LedPattern::Off => return [Blink::OFF; 3],
+            LedPattern::On => return [Blink::ON; 3],
+            LedPattern::Fast => return [Blink::new(100, 100); 3],
+        }
+        match self {
+            BoardState::NoNetwork => [Blink::OFF, Blink::new(250, 250), Blink::OFF],
+            BoardState::NoSync => [Blink::new(500, 500), Blink::OFF, Blink::OFF],
+            BoardState::Synced => [Blink::ON, Blink::OFF, Blink::OFF],
+            BoardState::Fault => [Blink::OFF, Blink::OFF, Blink::ON],
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct HealthGet {}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct TaskHealth {
+    pub id: TaskId,
+    /// milliseconds since the task last checked in, or since it started being watched
+    pub since_ms: u32,
+    /// the longest it can go without checking in
+    pub timeout_ms: u32,
+}
+
+impl TaskHealth {
+    pub fn is_alive(&self) -> bool {
+        self.since_ms <= self.timeout_ms
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct Health {
+    pub uptime_ms: u64,
+    pub state: BoardState,
+    /// the first task that missed its check in, only meaningful in Fault
+    pub stalled: TaskId,
+    /// the first num_tasks are watched
+    pub tasks: [TaskHealth; MAX_TASKS],
+    pub num_tasks: u8,
+}
+
+impl Health {
+    pub fn tasks(&self) -> &[TaskHealth] {
+        &self.tasks[..(self.num_tasks as usize).min(MAX_TASKS)]
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Watched {
+    id: TaskId,
+    timeout_ms: u32,
+    last_ms: u64,
+}
+
+pub struct Supervisor {
+    tasks: heapless::Vec<Watched, MAX_TASKS>,
+    stalled: Option<TaskId>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub const fn new() -> Self {
+        Self {
+            tasks: heapless::Vec::new(),
+            stalled: None,
+        }
+    }
+
+    /// start expecting a task to check in at least every timeout_ms, false if MAX_TASKS are
+    /// already watched.  Watching a task again changes its timeout
+    pub fn watch(&mut self, id: TaskId, timeout_ms: u32, now_ms: u64) -> bool {
+        if let Some(task) = self.tasks.iter_mut().find(|task| task.id == id) {
+            task.timeout_ms = timeout_ms;
+            task.last_ms = now_ms;
+            return true;
+        }
+        self.tasks
+            .push(Watched {
+                id,
+                timeout_ms,
+                last_ms: now_ms,
+            })
+            .is_ok()
+    }
+
+    /// ignored for tasks that aren't watched
+    pub fn check_in(&mut self, id: TaskId, now_ms: u64) {
+        if let Some(task) = self.tasks.iter_mut().find(|task| task.id == id) {
+            task.last_ms = task.last_ms.max(now_ms);
+        }
+    }
+
+    /// look for a task that missed its check in, true if the watchdog can be fed
+    pub fn poll(&mut self, now_ms: u64) -> bool {
+        if self.stalled.is_none() {
+            self.stalled = self
+                .tasks
+                .iter()
+                .find(|task| now_ms.saturating_sub(task.last_ms) > task.timeout_ms as u64)
+                .map(|task| task.id);
+        }
+        self.stalled.is_none()
+    }
+
+    /// the task that missed its check in, once poll has found one
+    pub fn stalled(&self) -> Option<TaskId> {
+        self.stalled
+    }
+
+    pub fn state(&self, network_up: bool, synced: bool) -> BoardState {
+        if self.stalled.is_some() {
+            BoardState::Fault
+        } else if !network_up {
+            BoardState::NoNetwork
+        } else if !synced {
+            BoardState::NoSync
+        } else {
+            BoardState::Synced
+        }
+    }
+
+    pub fn health(&self, now_ms: u64, state: BoardState) -> Health {
+        let mut health = Health {
+            uptime_ms: now_ms,
+            state,
+            stalled: self.stalled.unwrap_or_default(),
+            num_tasks: self.tasks.len() as u8,
+            ..Default::default()
+        };
+        for (task_health, task) in health.tasks.iter_mut().zip(&self.tasks) {
+            *task_health = TaskHealth {
+                id: task.id,
+                since_ms: now_ms.saturating_sub(task.last_ms).min(u32::MAX as u64) as u32,
+                timeout_ms: task.timeout_ms,
+            };
+        }
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn supervisor() -> Supervisor {
+        let mut supervisor = Supervisor::new();
+        assert!(supervisor.watch(TaskId::Main, 1000, 0));
+        assert!(supervisor.watch(TaskId::Net, 100, 0));
+        supervisor
+    }
+
+    #[test]
+    fn missed_check_in_latches() {
+        let mut supervisor = supervisor();
+        for now_ms in (0..=1000).step_by(50) {
+            supervisor.check_in(TaskId::Main, now_ms);
+            supervisor.check_in(TaskId::Net, now_ms);
+            assert!(supervisor.poll(now_ms));
+        }
+        assert_eq!(supervisor.stalled(), None);
+        // up to the timeout is still in time
+        assert!(supervisor.poll(1100));
+        assert!(!supervisor.poll(1101));
+        assert_eq!(supervisor.stalled(), Some(TaskId::Net));
+
+        // checking in again doesn't clear it, the watchdog has to reset the board
+        supervisor.check_in(TaskId::Net, 1102);
+        supervisor.check_in(TaskId::Main, 1102);
+        assert!(!supervisor.poll(1103));
+        assert_eq!(supervisor.stalled(), Some(TaskId::Net));
+        assert_eq!(supervisor.state(true, true), BoardState::Fault);
+    }
+
+    #[test]
+    fn first_stall_is_kept() {
+        let mut supervisor = supervisor();
+        supervisor.check_in(TaskId::Net, 500);
+        assert!(!supervisor.poll(1001));
+        assert_eq!(supervisor.stalled(), Some(TaskId::Main));
+        // Net missing its check in later doesn't replace it
+        assert!(!supervisor.poll(5000));
+        assert_eq!(supervisor.stalled(), Some(TaskId::Main));
+    }
+
+    #[test]
+    fn unwatched_ignored() {
+        let mut supervisor = supervisor();
+        // never checks in and never watched
+        supervisor.check_in(TaskId::Telemetry, 10);
+        supervisor.check_in(TaskId::Main, 900);
+        supervisor.check_in(TaskId::Net, 900);
+        assert!(supervisor.poll(1000));
+        assert_eq!(supervisor.health(1000, BoardState::Synced).num_tasks, 2);
+
+        // watching again changes the timeout and counts from then
+        assert!(supervisor.watch(TaskId::Net, 5000, 1000));
+        assert!(supervisor.watch(TaskId::Main, 10_000, 1000));
+        assert!(supervisor.poll(5900));
+        assert!(!supervisor.poll(6001));
+        assert_eq!(supervisor.stalled(), Some(TaskId::Net));
+
+        let mut full = Supervisor::new();
+        for _ in 0..MAX_TASKS {
+            assert!(full.watch(TaskId::Main, 1000, 0));
+        }
+        assert_eq!(full.health(0, BoardState::Synced).num_tasks, 1);
+    }
+
+    #[test]
+    fn states() {
+        let supervisor = supervisor();
+        assert_eq!(supervisor.state(false, false), BoardState::NoNetwork);
+        assert_eq!(supervisor.state(false, true), BoardState::NoNetwork);
+        assert_eq!(supervisor.state(true, false), BoardState::NoSync);
+        assert_eq!(supervisor.state(true, true), BoardState::Synced);
+    }
+
+    /// which of the green, orange and red leds are on at each of the times
+    fn lit(leds: [Blink; 3], times: &[u64]) -> std::vec::Vec<[bool; 3]> {
+        times
+            .iter()
+            .map(|&now_ms| leds.map(|led| led.is_on(now_ms)))
+            .collect()
+    }
+
+    #[test]
+    fn led_patterns() {
+        let times = [0, 249, 250, 499, 500, 999, 1000];
+        let off = [false; 3];
+        let green = [true, false, false];
+        let orange = [false, true, false];
+        let red = [false, false, true];
+
+        // orange blinking fast without a network
+        let leds = BoardState::NoNetwork.leds(LedPattern::Blink);
+        assert_eq!(
+            lit(leds, &times),
+            [orange, orange, off, off, orange, off, orange]
+        );
+        // green blinking slowly before ntp answers
+        let leds = BoardState::NoSync.leds(LedPattern::Blink);
+        assert_eq!(
+            lit(leds, &times),
+            [green, green, green, green, off, off, green]
+        );
+        // steady green once synced, steady red on a fault
+        let leds = BoardState::Synced.leds(LedPattern::Blink);
+        assert_eq!(lit(leds, &times), [green; 7]);
+        let leds = BoardState::Fault.leds(LedPattern::Blink);
+        assert_eq!(lit(leds, &times), [red; 7]);
+
+        // the other patterns override the state
+        for state in [BoardState::NoNetwork, BoardState::Fault] {
+            assert_eq!(lit(state.leds(LedPattern::Off), &times), [off; 7]);
+            assert_eq!(lit(state.leds(LedPattern::On), &times), [[true; 3]; 7]);
+            let leds = state.leds(LedPattern::Fast);
+            assert_eq!(
+                lit(leds, &[0, 99, 100, 199, 200]),
+                [[true; 3], [true; 3], off, off, [true; 3]]
+            );
+        }
+        assert!(!Blink::new(0, 0).is_on(0));
+    }
+
+    #[test]
+    fn health_reports_last_seen() {
+        let mut supervisor = supervisor();
+        assert!(supervisor.watch(TaskId::TimeSync, 10_000, 200));
+        supervisor.check_in(TaskId::Main, 700);
+        supervisor.check_in(TaskId::Net, 950);
+        // out of order check ins don't go back in time
+        supervisor.check_in(TaskId::Net, 900);
+        assert!(supervisor.poll(1000));
+
+        let health = supervisor.health(1000, supervisor.state(true, false));
+        assert_eq!(health.uptime_ms, 1000);
+        assert_eq!(health.state, BoardState::NoSync);
+        assert_eq!(
+            health.tasks(),
+            [
+                TaskHealth {
+                    id: TaskId::Main,
+                    since_ms: 300,
+                    timeout_ms: 1000
+                },
+                TaskHealth {
+                    id: TaskId::Net,
+                    since_ms: 50,
+                    timeout_ms: 100
+                },
+                TaskHealth {
+                    id: TaskId::TimeSync,
+                    since_ms: 800,
+                    timeout_ms: 10_000
+                },
+            ]
+        );
+        assert!(health.tasks().iter().all(TaskHealth::is_alive));
+
+        assert!(!supervisor.poll(1051));
+        let health = supervisor.health(1051, supervisor.state(true, true));
+        assert_eq!(
+            (health.state, health.stalled),
+            (BoardState::Fault, TaskId::Net)
+        );
+        assert!(!health.tasks()[1].is_alive());
+        assert!(health.tasks()[0].is_alive());
+        // a long stall saturates rather than wrapping
+        let health = supervisor.health(u64::MAX, BoardState::Fault);
+        assert_eq!(health.tasks()[0].since_ms, u32::MAX);
+    }
+}
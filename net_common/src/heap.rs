@@ -0,0 +1,25 @@
+/*!
+Heap usage of a board, a HeapStatsGet sent to the board is answered with a HeapStats
+
+See board_heap for the allocator that keeps these.
+*/
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct HeapStatsGet {}
+
+/// all sizes in bytes, including the rounding up of each allocation
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: u32,
+    pub used: u32,
+    /// the most ever used at once
+    pub high_water: u32,
+    /// live allocations
+    pub allocations: u32,
+    /// allocations that returned null, the firmware may not survive one
+    pub failed: u32,
+    /// the largest free block, much less than size - used when the free space is fragmented
+    pub largest_free: u32,
+}
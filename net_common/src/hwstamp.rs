@@ -0,0 +1,440 @@
+/*!
+Packet times from as far down the network stack as they can be had, instead of reading the tick
+count after a socket returns, which adds however long the task took to be scheduled.
+
+A driver wrapper hands every frame it receives or sends to Stamps with a HwClock, which records
+when the udp ones went through the driver as a time from the local TickSource, or when the mac
+stamped them if the HwClock can read that back from the dma descriptors.  The socket wrapper
+then takes the stamp matching the payload and port it received or sent, falling back to the
+tick count if there isn't one (the frame wasn't udp, or too many others went by since).
+
+The mac clock and the tick count run from the same crystal, so a mac stamp is converted by how
+long ago the mac took it rather than by an offset between the two clocks, see to_local().
+
+Nothing here touches the hardware, host code can give Stamps synthetic stamps from a HwClock of
+its own, see the tests.
+*/
+
+use crate::Epoch;
+
+/// the stamps kept for each direction, older ones are dropped
+pub const MAX_STAMPS: usize = 16;
+
+/// the clock in the ethernet mac and the times it stamped frames with, frames without one are
+/// stamped when they go through the driver
+pub trait HwClock {
+    /// the mac clock now
+    fn now(&mut self) -> Epoch;
+    /// when the mac received the frame, from its receive descriptor
+    fn rx_stamp(&mut self, _frame: &[u8]) -> Option<Epoch> {
+        None
+    }
+    /// when the mac sent the frame, from its transmit descriptor
+    fn tx_stamp(&mut self, _frame: &[u8]) -> Option<Epoch> {
+        None
+    }
+}
+
+/// a stamp from the mac clock as a time from the local TickSource, both count from the same
+/// crystal so the age of the stamp is the same on either
+pub fn to_local(stamp: Epoch, hw_now: Epoch, local_now: Epoch) -> Epoch {
+    local_now.saturating_sub(hw_now.duration_since(&stamp).unwrap_or_default())
+}
+
+/// where a Stamp was taken, later ones include more of the time spent getting the packet to or
+/// from the task
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StampSource {
+    /// the tick count when the socket returned
+    #[default]
+    Software,
+    /// the tick count as the frame went through the network driver, or the interface poll that
+    /// moved it
+    Driver,
+    /// when the mac itself received or sent the frame
+    Hardware,
+}
+
+/// when a packet was received or sent, as a time from the local TickSource
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stamp {
+    pub local: Epoch,
+    pub source: StampSource,
+}
+
+impl Stamp {
+    pub fn software(local: Epoch) -> Self {
+        Self {
+            local,
+            source: StampSource::Software,
+        }
+    }
+
+    pub fn driver(local: Epoch) -> Self {
+        Self {
+            local,
+            source: StampSource::Driver,
+        }
+    }
+
+    /// from the driver or the mac, not only when the socket returned
+    pub fn is_early(&self) -> bool {
+        self.source != StampSource::Software
+    }
+}
+
+/// the mac stamp if there is one, otherwise the driver time
+fn stamp(hw_stamp: Option<Epoch>, clock: &mut impl HwClock, local_now: Epoch) -> Stamp {
+    match hw_stamp {
+        Some(hw_stamp) => Stamp {
+            local: to_local(hw_stamp, clock.now(), local_now),
+            source: StampSource::Hardware,
+        },
+        None => Stamp::driver(local_now),
+    }
+}
+
+/// the udp part of an ethernet frame
+#[derive(Clone, Copy, Debug)]
+pub struct UdpFrame<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub payload: &'a [u8],
+}
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const IP_PROTOCOL_UDP: u8 = 17;
+const UDP_HEADER_LEN: usize = 8;
+
+/// the udp datagram in an ethernet II frame with ipv4, None for anything else including
+/// fragments
+pub fn udp_frame(frame: &[u8]) -> Option<UdpFrame<'_>> {
+    let u16_at = |offset: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(
+            frame.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    if u16_at(12)? != ETHERTYPE_IPV4 {
+        return None;
+    }
+    let ip = ETHERNET_HEADER_LEN;
+    let version_ihl = *frame.get(ip)?;
+    if version_ihl >> 4 != 4 || *frame.get(ip + 9)? != IP_PROTOCOL_UDP {
+        return None;
+    }
+    // more fragments or a fragment offset
+    if u16_at(ip + 6)? & 0x3fff != 0 {
+        return None;
+    }
+    let udp = ip + (version_ihl & 0x0f) as usize * 4;
+    let udp_len = u16_at(udp + 4)? as usize;
+    if udp_len < UDP_HEADER_LEN {
+        return None;
+    }
+    Some(UdpFrame {
+        src_port: u16_at(udp)?,
+        dst_port: u16_at(udp + 2)?,
+        payload: frame.get(udp + UDP_HEADER_LEN..udp + udp_len)?,
+    })
+}
+
+/// what a stamp is looked up by, the local port and enough of the payload to tell packets
+/// apart: the last bytes of a net_common message are its crc
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StampKey {
+    port: u16,
+    len: u16,
+    tail: [u8; 4],
+}
+
+impl StampKey {
+    pub fn new(port: u16, payload: &[u8]) -> Self {
+        let mut tail = [0; 4];
+        let start = payload.len().saturating_sub(tail.len());
+        tail[..payload.len() - start].copy_from_slice(&payload[start..]);
+        Self {
+            port,
+            len: payload.len() as u16,
+            tail,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Ring {
+    entries: [Option<(StampKey, Stamp)>; MAX_STAMPS],
+    /// the oldest entry, overwritten next
+    next: usize,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self {
+            entries: [None; MAX_STAMPS],
+            next: 0,
+        }
+    }
+
+    fn push(&mut self, key: StampKey, stamp: Stamp) {
+        self.entries[self.next] = Some((key, stamp));
+        self.next = (self.next + 1) % MAX_STAMPS;
+    }
+
+    /// the oldest stamp with the key, packets with the same key are read in the order they
+    /// were stamped
+    fn take(&mut self, key: &StampKey) -> Option<Stamp> {
+        (0..MAX_STAMPS)
+            .map(|i| (self.next + i) % MAX_STAMPS)
+            .find_map(|i| match self.entries[i] {
+                Some((entry_key, stamp)) if entry_key == *key => {
+                    self.entries[i] = None;
+                    Some(stamp)
+                }
+                _ => None,
+            })
+    }
+}
+
+/// the latest stamps of udp frames in each direction, see the module docs
+pub struct Stamps {
+    received: Ring,
+    sent: Ring,
+}
+
+impl Default for Stamps {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stamps {
+    pub const fn new() -> Self {
+        Self {
+            received: Ring::new(),
+            sent: Ring::new(),
+        }
+    }
+
+    /// keep the stamp of a frame the driver received, local_now is the TickSource at about
+    /// the same time as the clock is read
+    pub fn frame_received(&mut self, clock: &mut impl HwClock, local_now: Epoch, frame: &[u8]) {
+        let Some(udp) = udp_frame(frame) else {
+            return;
+        };
+        let stamp = stamp(clock.rx_stamp(frame), clock, local_now);
+        self.received
+            .push(StampKey::new(udp.dst_port, udp.payload), stamp);
+    }
+
+    /// keep the stamp of a frame the driver sent
+    pub fn frame_sent(&mut self, clock: &mut impl HwClock, local_now: Epoch, frame: &[u8]) {
+        let Some(udp) = udp_frame(frame) else {
+            return;
+        };
+        let stamp = stamp(clock.tx_stamp(frame), clock, local_now);
+        self.sent
+            .push(StampKey::new(udp.src_port, udp.payload), stamp);
+    }
+
+    /// the stamp of a payload received on the local port, each stamp is only given out once
+    pub fn take_received(&mut self, port: u16, payload: &[u8]) -> Option<Stamp> {
+        self.received.take(&StampKey::new(port, payload))
+    }
+
+    /// the stamp of a payload sent from the local port
+    pub fn take_sent(&mut self, port: u16, payload: &[u8]) -> Option<Stamp> {
+        self.sent.take(&StampKey::new(port, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Message, TimeStamp};
+    use core::time::Duration;
+    use std::collections::VecDeque;
+    use std::vec;
+    use std::vec::Vec;
+
+    const BOARD_PORT: u16 = 34201;
+    const HOST_PORT: u16 = 40000;
+
+    /// a mac clock a long way from the local one, with the stamps queued up by the test
+    struct SyntheticClock {
+        now: Epoch,
+        rx: VecDeque<Epoch>,
+        tx: VecDeque<Epoch>,
+    }
+
+    impl SyntheticClock {
+        fn new() -> Self {
+            Self {
+                now: Epoch::new(1_000, 0),
+                rx: VecDeque::new(),
+                tx: VecDeque::new(),
+            }
+        }
+    }
+
+    impl HwClock for SyntheticClock {
+        fn now(&mut self) -> Epoch {
+            self.now
+        }
+
+        fn rx_stamp(&mut self, _frame: &[u8]) -> Option<Epoch> {
+            self.rx.pop_front()
+        }
+
+        fn tx_stamp(&mut self, _frame: &[u8]) -> Option<Epoch> {
+            self.tx.pop_front()
+        }
+    }
+
+    /// only the clock, like the boards whose driver doesn't read the descriptors
+    struct DriverClock;
+
+    impl HwClock for DriverClock {
+        fn now(&mut self) -> Epoch {
+            Epoch::new(1_000, 0)
+        }
+    }
+
+    /// an ethernet II frame with ipv4 and udp around the payload, checksums left at 0
+    fn udp_ethernet_frame(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend(0x0800u16.to_be_bytes());
+        let ip_len = 20 + 8 + payload.len() as u16;
+        frame.extend([0x45, 0]);
+        frame.extend(ip_len.to_be_bytes());
+        // id, no fragmenting, ttl, udp
+        frame.extend([0, 0, 0x40, 0, 64, 17, 0, 0]);
+        frame.extend([192, 168, 0, 100, 192, 168, 0, 123]);
+        frame.extend(src_port.to_be_bytes());
+        frame.extend(dst_port.to_be_bytes());
+        frame.extend((8 + payload.len() as u16).to_be_bytes());
+        frame.extend([0, 0]);
+        frame.extend(payload);
+        frame
+    }
+
+    fn timestamp_payload(counter: u64) -> Vec<u8> {
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+        let msg = Message::TimeStamp(TimeStamp {
+            counter,
+            ..Default::default()
+        });
+        msg.encode::<128>(crc.digest()).unwrap().to_vec()
+    }
+
+    #[test]
+    fn udp_frames() {
+        let payload = timestamp_payload(0);
+        let frame = udp_ethernet_frame(HOST_PORT, BOARD_PORT, &payload);
+        let udp = udp_frame(&frame).unwrap();
+        assert_eq!((udp.src_port, udp.dst_port), (HOST_PORT, BOARD_PORT));
+        assert_eq!(udp.payload, &payload[..]);
+
+        let mut arp = frame.clone();
+        arp[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        assert!(udp_frame(&arp).is_none());
+        let mut fragment = frame.clone();
+        fragment[20] = 0x20;
+        assert!(udp_frame(&fragment).is_none());
+        assert!(udp_frame(&frame[..40]).is_none());
+    }
+
+    #[test]
+    fn mac_stamp_to_local() {
+        // the mac stamped it 250us before the driver got to it
+        let local_now = Epoch::new(5, 0);
+        let local = to_local(
+            Epoch::new(999, 999_750_000),
+            Epoch::new(1_000, 0),
+            local_now,
+        );
+        assert_eq!(
+            local_now.duration_since(&local),
+            Some(Duration::from_micros(250))
+        );
+    }
+
+    #[test]
+    fn received_once_on_its_port() {
+        let mut clock = SyntheticClock::new();
+        let mut stamps = Stamps::new();
+        let local_now = Epoch::new(5, 0);
+        let payload = timestamp_payload(0);
+        let frame = udp_ethernet_frame(HOST_PORT, BOARD_PORT, &payload);
+
+        clock.rx.push_back(Epoch::new(999, 999_750_000));
+        stamps.frame_received(&mut clock, local_now, &frame);
+        let expected = Stamp {
+            local: Epoch::new(4, 999_750_000),
+            source: StampSource::Hardware,
+        };
+        assert_eq!(stamps.take_received(HOST_PORT, &payload), None);
+        assert_eq!(stamps.take_received(BOARD_PORT, &payload), Some(expected));
+        assert_eq!(stamps.take_received(BOARD_PORT, &payload), None);
+
+        // a frame that isn't udp doesn't use up a stamp
+        let mut arp = frame.clone();
+        arp[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        clock.rx.push_back(Epoch::new(1_000, 0));
+        stamps.frame_received(&mut clock, local_now, &arp);
+        assert_eq!(clock.rx.len(), 1);
+    }
+
+    #[test]
+    fn driver_stamps_without_mac_stamps() {
+        let mut stamps = Stamps::new();
+        let payload = timestamp_payload(1);
+        let local_now = Epoch::new(7, 123_000);
+        let frame = udp_ethernet_frame(BOARD_PORT, HOST_PORT, &payload);
+        stamps.frame_sent(&mut DriverClock, local_now, &frame);
+        let stamp = stamps.take_sent(BOARD_PORT, &payload).unwrap();
+        assert_eq!(stamp, Stamp::driver(local_now));
+        assert!(stamp.is_early());
+        assert!(!Stamp::software(local_now).is_early());
+    }
+
+    #[test]
+    fn sent_in_order_oldest_dropped() {
+        let mut clock = SyntheticClock::new();
+        let mut stamps = Stamps::new();
+        // sent TimeStamps come back in order, the oldest are dropped once more than MAX_STAMPS
+        // haven't been taken
+        let count = MAX_STAMPS as u64 + 4;
+        for counter in 0..count {
+            let payload = timestamp_payload(counter);
+            let frame = udp_ethernet_frame(BOARD_PORT, HOST_PORT, &payload);
+            clock.now = Epoch::new(1_000 + counter, 0);
+            clock.tx.push_back(clock.now);
+            stamps.frame_sent(&mut clock, Epoch::new(counter, 0), &frame);
+        }
+        for counter in 0..count {
+            let stamp = stamps.take_sent(BOARD_PORT, &timestamp_payload(counter));
+            if counter < count - MAX_STAMPS as u64 {
+                assert_eq!(stamp, None, "{counter}");
+            } else {
+                assert_eq!(stamp.map(|stamp| stamp.local), Some(Epoch::new(counter, 0)));
+            }
+        }
+    }
+
+    #[test]
+    fn same_payload_in_order() {
+        let mut stamps = Stamps::new();
+        let payload = timestamp_payload(2);
+        let frame = udp_ethernet_frame(HOST_PORT, BOARD_PORT, &payload);
+        for secs in [1, 2] {
+            stamps.frame_received(&mut DriverClock, Epoch::new(secs, 0), &frame);
+        }
+        let first = stamps.take_received(BOARD_PORT, &payload).unwrap();
+        let second = stamps.take_received(BOARD_PORT, &payload).unwrap();
+        assert_eq!(
+            (first.local, second.local),
+            (Epoch::new(1, 0), Epoch::new(2, 0))
+        );
+    }
+}
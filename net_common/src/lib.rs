@@ -1,13 +1,41 @@
 #![no_std]
 
-use postcard::{from_bytes_crc32, to_vec_crc32};
+#[cfg(feature = "std")]
+extern crate std;
+
+use postcard::{from_bytes_crc32, to_slice_crc32};
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Serialize, Deserialize, Debug, Default)]
-pub struct Epoch {
-    pub secs: u64,
-    pub nanos: u32,
-}
+pub mod board;
+pub mod board_config;
+pub mod command;
+pub mod config;
+pub mod discipline;
+pub mod echo;
+pub mod epoch;
+pub mod firmware;
+pub mod health;
+pub mod heap;
+pub mod hwstamp;
+pub mod logging;
+pub mod netconfig;
+pub mod ptp;
+pub mod pubsub;
+pub mod telemetry;
+
+pub use board::{BoardInfo, BoardInfoGet};
+pub use config::{ConfigGet, ConfigSet, ConfigValue};
+pub use echo::{EchoReply, EchoRequest};
+pub use epoch::Epoch;
+pub use firmware::{
+    FirmwareBegin, FirmwareChunk, FirmwareCommit, FirmwareStatus, FirmwareVerify,
+};
+pub use health::{Health, HealthGet};
+pub use heap::{HeapStats, HeapStatsGet};
+pub use logging::LogRecord;
+pub use netconfig::NetStatus;
+pub use ptp::{PtpDelayReq, PtpDelayResp, PtpFollowUp, PtpSync};
+pub use pubsub::Topic;
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
 pub struct TimeStamp {
@@ -18,6 +46,9 @@ pub struct TimeStamp {
     pub ntp_seconds: u32,
     pub ntp_seconds_fraction: u32,
     pub ntp_roundtrip: u64,
+    /// when the TimeStamp before this one went through the network driver on its way out, ZERO
+    /// if it didn't get a driver stamp (see hwstamp)
+    pub previous_tx: Epoch,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default)]
@@ -26,6 +57,18 @@ pub struct SmallArray {
     pub data: [u8; 32],
 }
 
+/// largest BenchData payload, keeps the encoded message inside the 256 byte buffers the firmwares
+/// encode into
+pub const BENCH_MAX_DATA: usize = 220;
+
+/// throughput test packet, the data is filler to make the packet the size being tested
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct BenchData {
+    pub epoch: Epoch,
+    pub sequence: u64,
+    pub data: heapless::Vec<u8, BENCH_MAX_DATA>,
+}
+
 // need nightly only feature generic_const_exprs
 // for here W * H needs to equal SZ
 // could use use serde_big_array::BigArray;
@@ -45,62 +88,298 @@ pub struct Image {
 pub enum Message {
     TimeStamp(TimeStamp),
     Array(SmallArray),
+    Bench(BenchData),
+    ConfigGet(ConfigGet),
+    ConfigSet(ConfigSet),
+    ConfigValue(ConfigValue),
+    PtpSync(PtpSync),
+    PtpFollowUp(PtpFollowUp),
+    PtpDelayReq(PtpDelayReq),
+    PtpDelayResp(PtpDelayResp),
+    NetStatus(NetStatus),
+    Log(LogRecord),
+    HeapStatsGet(HeapStatsGet),
+    HeapStats(HeapStats),
+    EchoRequest(EchoRequest),
+    EchoReply(EchoReply),
+    BoardInfoGet(BoardInfoGet),
+    BoardInfo(BoardInfo),
+    FirmwareBegin(FirmwareBegin),
+    FirmwareChunk(FirmwareChunk),
+    FirmwareVerify(FirmwareVerify),
+    FirmwareCommit(FirmwareCommit),
+    FirmwareStatus(FirmwareStatus),
+    HealthGet(HealthGet),
+    Health(Health),
     Error(()),
 }
 
 impl Message {
+    /// the first two bytes of every message, the third byte is the topic (0 for unicast)
+    /// and the fourth is the message type
+    pub const MAGIC: [u8; 2] = [0x5E, 0xA7];
     pub const DATA: [u8; 4] = [0x5E, 0xA7, 0x00, 0x01];
     pub const ARRAY: [u8; 4] = [0x5E, 0xA7, 0x00, 0x02];
+    pub const BENCH: [u8; 4] = [0x5E, 0xA7, 0x00, 0x03];
+    pub const CONFIG_GET: [u8; 4] = [0x5E, 0xA7, 0x00, 0x04];
+    pub const CONFIG_SET: [u8; 4] = [0x5E, 0xA7, 0x00, 0x05];
+    pub const CONFIG_VALUE: [u8; 4] = [0x5E, 0xA7, 0x00, 0x06];
+    pub const PTP_SYNC: [u8; 4] = [0x5E, 0xA7, 0x00, 0x07];
+    pub const PTP_FOLLOW_UP: [u8; 4] = [0x5E, 0xA7, 0x00, 0x08];
+    pub const PTP_DELAY_REQ: [u8; 4] = [0x5E, 0xA7, 0x00, 0x09];
+    pub const PTP_DELAY_RESP: [u8; 4] = [0x5E, 0xA7, 0x00, 0x0A];
+    pub const NET_STATUS: [u8; 4] = [0x5E, 0xA7, 0x00, 0x0B];
+    pub const LOG: [u8; 4] = [0x5E, 0xA7, 0x00, 0x0C];
+    pub const HEAP_STATS_GET: [u8; 4] = [0x5E, 0xA7, 0x00, 0x0D];
+    pub const HEAP_STATS: [u8; 4] = [0x5E, 0xA7, 0x00, 0x0E];
+    pub const ECHO_REQUEST: [u8; 4] = [0x5E, 0xA7, 0x00, 0x0F];
+    pub const ECHO_REPLY: [u8; 4] = [0x5E, 0xA7, 0x00, 0x10];
+    pub const BOARD_INFO_GET: [u8; 4] = [0x5E, 0xA7, 0x00, 0x11];
+    pub const BOARD_INFO: [u8; 4] = [0x5E, 0xA7, 0x00, 0x12];
+    pub const FIRMWARE_BEGIN: [u8; 4] = [0x5E, 0xA7, 0x00, 0x13];
+    pub const FIRMWARE_CHUNK: [u8; 4] = [0x5E, 0xA7, 0x00, 0x14];
+    pub const FIRMWARE_VERIFY: [u8; 4] = [0x5E, 0xA7, 0x00, 0x15];
+    pub const FIRMWARE_COMMIT: [u8; 4] = [0x5E, 0xA7, 0x00, 0x16];
+    pub const FIRMWARE_STATUS: [u8; 4] = [0x5E, 0xA7, 0x00, 0x17];
+    pub const HEALTH_GET: [u8; 4] = [0x5E, 0xA7, 0x00, 0x18];
+    pub const HEALTH: [u8; 4] = [0x5E, 0xA7, 0x00, 0x19];
+
+    /// the header bytes for this message tagged with the given topic
+    pub fn header(&self, topic: Topic) -> Option<[u8; 4]> {
+        let mut header = match self {
+            Self::TimeStamp(_) => Self::DATA,
+            Self::Array(_) => Self::ARRAY,
+            Self::Bench(_) => Self::BENCH,
+            Self::ConfigGet(_) => Self::CONFIG_GET,
+            Self::ConfigSet(_) => Self::CONFIG_SET,
+            Self::ConfigValue(_) => Self::CONFIG_VALUE,
+            Self::PtpSync(_) => Self::PTP_SYNC,
+            Self::PtpFollowUp(_) => Self::PTP_FOLLOW_UP,
+            Self::PtpDelayReq(_) => Self::PTP_DELAY_REQ,
+            Self::PtpDelayResp(_) => Self::PTP_DELAY_RESP,
+            Self::NetStatus(_) => Self::NET_STATUS,
+            Self::Log(_) => Self::LOG,
+            Self::HeapStatsGet(_) => Self::HEAP_STATS_GET,
+            Self::HeapStats(_) => Self::HEAP_STATS,
+            Self::EchoRequest(_) => Self::ECHO_REQUEST,
+            Self::EchoReply(_) => Self::ECHO_REPLY,
+            Self::BoardInfoGet(_) => Self::BOARD_INFO_GET,
+            Self::BoardInfo(_) => Self::BOARD_INFO,
+            Self::FirmwareBegin(_) => Self::FIRMWARE_BEGIN,
+            Self::FirmwareChunk(_) => Self::FIRMWARE_CHUNK,
+            Self::FirmwareVerify(_) => Self::FIRMWARE_VERIFY,
+            Self::FirmwareCommit(_) => Self::FIRMWARE_COMMIT,
+            Self::FirmwareStatus(_) => Self::FIRMWARE_STATUS,
+            Self::HealthGet(_) => Self::HEALTH_GET,
+            Self::Health(_) => Self::HEALTH,
+            Self::Error(()) => {
+                return None;
+            }
+        };
+        header[2] = topic.0;
+        Some(header)
+    }
+
+    /// check the message type of encoded bytes without decoding them, ignoring the topic
+    pub fn matches_header(msg_bytes: &[u8], header: [u8; 4]) -> bool {
+        msg_bytes.len() >= 4 && msg_bytes[..2] == header[..2] && msg_bytes[3] == header[3]
+    }
 
     // TODO(lucasw) make Message have a const to define the return message size
     pub fn encode<const SZ: usize>(
         &self,
         crc_digest: crc::Digest<'_, u32>,
+    ) -> Result<heapless::Vec<u8, SZ>, postcard::Error> {
+        self.encode_topic::<SZ>(Topic::NONE, crc_digest)
+    }
+
+    /// encode with the topic id in the header, for publishing to a multicast group
+    pub fn encode_topic<const SZ: usize>(
+        &self,
+        topic: Topic,
+        crc_digest: crc::Digest<'_, u32>,
     ) -> Result<heapless::Vec<u8, SZ>, postcard::Error> {
         let mut vec = heapless::Vec::<u8, SZ>::new();
-        match self {
-            Self::TimeStamp(some_data) => {
-                for byte in &Message::DATA {
-                    if vec.push(*byte).is_err() {
-                        return Err(postcard::Error::SerializeBufferFull);
-                    }
-                }
-                vec.extend(to_vec_crc32::<TimeStamp, SZ>(some_data, crc_digest)?);
-            }
-            Self::Array(small_array) => {
-                for byte in &Message::ARRAY {
-                    if vec.push(*byte).is_err() {
-                        return Err(postcard::Error::SerializeBufferFull);
-                    }
-                }
-                vec.extend(to_vec_crc32::<SmallArray, SZ>(small_array, crc_digest)?);
+        // filling to capacity can't fail
+        let _ = vec.resize_default(SZ);
+        let len = self.encode_topic_into(topic, &mut vec, crc_digest)?;
+        vec.truncate(len);
+        Ok(vec)
+    }
+
+    /// encode_topic() into a buffer the caller already has, e.g. from a BufferPool, returns
+    /// the length of the encoded message
+    pub fn encode_topic_into(
+        &self,
+        topic: Topic,
+        buf: &mut [u8],
+        crc_digest: crc::Digest<'_, u32>,
+    ) -> Result<usize, postcard::Error> {
+        let Some(header) = self.header(topic) else {
+            // TODO(lucasw) need a different error for this?
+            return Err(postcard::Error::WontImplement);
+        };
+        if buf.len() < header.len() {
+            return Err(postcard::Error::SerializeBufferFull);
+        }
+        let (buf_header, body) = buf.split_at_mut(header.len());
+        buf_header.copy_from_slice(&header);
+        let body_len = match self {
+            Self::TimeStamp(some_data) => to_slice_crc32(some_data, body, crc_digest)?.len(),
+            Self::Array(small_array) => to_slice_crc32(small_array, body, crc_digest)?.len(),
+            Self::Bench(bench) => to_slice_crc32(bench, body, crc_digest)?.len(),
+            Self::ConfigGet(get) => to_slice_crc32(get, body, crc_digest)?.len(),
+            Self::ConfigSet(set) => to_slice_crc32(set, body, crc_digest)?.len(),
+            Self::ConfigValue(value) => to_slice_crc32(value, body, crc_digest)?.len(),
+            Self::PtpSync(sync) => to_slice_crc32(sync, body, crc_digest)?.len(),
+            Self::PtpFollowUp(follow_up) => to_slice_crc32(follow_up, body, crc_digest)?.len(),
+            Self::PtpDelayReq(req) => to_slice_crc32(req, body, crc_digest)?.len(),
+            Self::PtpDelayResp(resp) => to_slice_crc32(resp, body, crc_digest)?.len(),
+            Self::NetStatus(status) => to_slice_crc32(status, body, crc_digest)?.len(),
+            Self::Log(record) => to_slice_crc32(record, body, crc_digest)?.len(),
+            Self::HeapStatsGet(get) => to_slice_crc32(get, body, crc_digest)?.len(),
+            Self::HeapStats(stats) => to_slice_crc32(stats, body, crc_digest)?.len(),
+            Self::EchoRequest(request) => to_slice_crc32(request, body, crc_digest)?.len(),
+            Self::EchoReply(reply) => to_slice_crc32(reply, body, crc_digest)?.len(),
+            Self::BoardInfoGet(board_info_get) => {
+                to_slice_crc32(board_info_get, body, crc_digest)?.len()
             }
+            Self::BoardInfo(board_info) => to_slice_crc32(board_info, body, crc_digest)?.len(),
+            Self::FirmwareBegin(begin) => to_slice_crc32(begin, body, crc_digest)?.len(),
+            Self::FirmwareChunk(chunk) => to_slice_crc32(chunk, body, crc_digest)?.len(),
+            Self::FirmwareVerify(verify) => to_slice_crc32(verify, body, crc_digest)?.len(),
+            Self::FirmwareCommit(commit) => to_slice_crc32(commit, body, crc_digest)?.len(),
+            Self::FirmwareStatus(status) => to_slice_crc32(status, body, crc_digest)?.len(),
+            Self::HealthGet(get) => to_slice_crc32(get, body, crc_digest)?.len(),
+            Self::Health(health) => to_slice_crc32(health, body, crc_digest)?.len(),
             Self::Error(()) => {
-                // TODO(lucasw) need a different error for this?
                 return Err(postcard::Error::WontImplement);
             }
-        }
-
-        Ok(vec)
+        };
+        Ok(header.len() + body_len)
     }
 
     pub fn decode(
         msg_bytes: &[u8],
         crc_digest: crc::Digest<'_, u32>,
     ) -> Result<Self, postcard::Error> {
-        // TODO(lucasw) return an error instead of unwrap
-        let header: [u8; 4] = msg_bytes[..4].try_into().unwrap();
+        let (_topic, msg) = Self::decode_topic(msg_bytes, crc_digest)?;
+        Ok(msg)
+    }
+
+    /// decode a message and the topic it was published on (Topic::NONE if sent unicast)
+    pub fn decode_topic(
+        msg_bytes: &[u8],
+        crc_digest: crc::Digest<'_, u32>,
+    ) -> Result<(Topic, Self), postcard::Error> {
+        let Some(header) = msg_bytes.get(..4) else {
+            return Err(postcard::Error::DeserializeUnexpectedEnd);
+        };
+        let topic = Topic(header[2]);
+        let header = [header[0], header[1], 0x00, header[3]];
 
         match header {
             Self::DATA => {
                 let data: TimeStamp = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
-                Ok(Message::TimeStamp(data))
+                Ok((topic, Message::TimeStamp(data)))
             }
             Self::ARRAY => {
                 let array: SmallArray = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
-                Ok(Message::Array(array))
+                Ok((topic, Message::Array(array)))
+            }
+            Self::BENCH => {
+                let bench: BenchData = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::Bench(bench)))
+            }
+            Self::CONFIG_GET => {
+                let get: ConfigGet = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::ConfigGet(get)))
+            }
+            Self::CONFIG_SET => {
+                let set: ConfigSet = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::ConfigSet(set)))
+            }
+            Self::CONFIG_VALUE => {
+                let value: ConfigValue = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::ConfigValue(value)))
+            }
+            Self::PTP_SYNC => {
+                let sync: PtpSync = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::PtpSync(sync)))
+            }
+            Self::PTP_FOLLOW_UP => {
+                let follow_up: PtpFollowUp = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::PtpFollowUp(follow_up)))
+            }
+            Self::PTP_DELAY_REQ => {
+                let req: PtpDelayReq = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::PtpDelayReq(req)))
+            }
+            Self::PTP_DELAY_RESP => {
+                let resp: PtpDelayResp = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::PtpDelayResp(resp)))
+            }
+            Self::NET_STATUS => {
+                let status: NetStatus = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::NetStatus(status)))
+            }
+            Self::LOG => {
+                let record: LogRecord = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::Log(record)))
+            }
+            Self::HEAP_STATS_GET => {
+                let get: HeapStatsGet = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::HeapStatsGet(get)))
+            }
+            Self::HEAP_STATS => {
+                let stats: HeapStats = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::HeapStats(stats)))
+            }
+            Self::ECHO_REQUEST => {
+                let request: EchoRequest = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::EchoRequest(request)))
+            }
+            Self::ECHO_REPLY => {
+                let reply: EchoReply = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::EchoReply(reply)))
+            }
+            Self::BOARD_INFO_GET => {
+                let board_info_get: BoardInfoGet = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::BoardInfoGet(board_info_get)))
+            }
+            Self::BOARD_INFO => {
+                let board_info: BoardInfo = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::BoardInfo(board_info)))
+            }
+            Self::FIRMWARE_BEGIN => {
+                let begin: FirmwareBegin = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::FirmwareBegin(begin)))
+            }
+            Self::FIRMWARE_CHUNK => {
+                let chunk: FirmwareChunk = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::FirmwareChunk(chunk)))
+            }
+            Self::FIRMWARE_VERIFY => {
+                let verify: FirmwareVerify = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::FirmwareVerify(verify)))
+            }
+            Self::FIRMWARE_COMMIT => {
+                let commit: FirmwareCommit = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::FirmwareCommit(commit)))
+            }
+            Self::FIRMWARE_STATUS => {
+                let status: FirmwareStatus = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::FirmwareStatus(status)))
+            }
+            Self::HEALTH_GET => {
+                let get: HealthGet = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::HealthGet(get)))
+            }
+            Self::HEALTH => {
+                let health: Health = from_bytes_crc32(&msg_bytes[4..], crc_digest)?;
+                Ok((topic, Message::Health(health)))
             }
-            _ => Ok(Message::Error(())),
+            _ => Ok((topic, Message::Error(()))),
         }
     }
 }
@@ -0,0 +1,426 @@
+/*!
+Log records from the boards, sent to the host as Message::Log instead of semihosting output
+(which halts the cpu without a debugger attached)
+
+The firmware logger formats each record into a LogBuffer as it is logged, and a network task
+sends whatever is buffered to LOG_PORT on the host, see net_loopback log_viewer.  When the
+buffer is full the oldest records are dropped, the sequence numbers show the gap.
+*/
+
+use core::fmt;
+use heapless::{Deque, String, Vec};
+use serde::{Deserialize, Serialize};
+
+use crate::Epoch;
+
+/// the host listens for Log messages on this
+pub const LOG_PORT: u16 = 34203;
+/// longer module paths and texts are truncated
+pub const LOG_MAX_MODULE: usize = 32;
+pub const LOG_MAX_TEXT: usize = 160;
+
+/// the same levels and order as the log crate
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+
+    /// case insensitive
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL
+            .into_iter()
+            .find(|level| level.name().eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(feature = "log")]
+impl From<log::Level> for Level {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => Level::Error,
+            log::Level::Warn => Level::Warn,
+            log::Level::Info => Level::Info,
+            log::Level::Debug => Level::Debug,
+            log::Level::Trace => Level::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+impl From<Level> for log::Level {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => log::Level::Error,
+            Level::Warn => log::Level::Warn,
+            Level::Info => log::Level::Info,
+            Level::Debug => log::Level::Debug,
+            Level::Trace => log::Level::Trace,
+        }
+    }
+}
+
+/// the most verbose level logged for a module and everything under it (so "net_time" also
+/// sets "net_time::peer"), and for any other module, None logs nothing
+pub struct LevelFilters<const N: usize> {
+    default: Option<Level>,
+    modules: Vec<(String<LOG_MAX_MODULE>, Option<Level>), N>,
+}
+
+impl<const N: usize> Default for LevelFilters<N> {
+    fn default() -> Self {
+        Self::new(Some(Level::Info))
+    }
+}
+
+impl<const N: usize> LevelFilters<N> {
+    pub const fn new(default: Option<Level>) -> Self {
+        Self {
+            default,
+            modules: Vec::new(),
+        }
+    }
+
+    pub fn set_default(&mut self, level: Option<Level>) {
+        self.default = level;
+    }
+
+    /// replaces any earlier level for the same module, false if there is no room for another
+    /// module or the path is longer than LOG_MAX_MODULE
+    pub fn set(&mut self, module: &str, level: Option<Level>) -> bool {
+        if let Some(entry) = self.modules.iter_mut().find(|(name, _)| name == module) {
+            entry.1 = level;
+            return true;
+        }
+        let mut name = String::new();
+        if name.push_str(module).is_err() {
+            return false;
+        }
+        self.modules.push((name, level)).is_ok()
+    }
+
+    /// back to the default level
+    pub fn remove(&mut self, module: &str) {
+        self.modules.retain(|(name, _)| name != module);
+    }
+
+    /// from the longest module path that matches
+    pub fn level(&self, module: &str) -> Option<Level> {
+        self.modules
+            .iter()
+            .filter(|(name, _)| {
+                module
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    pub fn enabled(&self, module: &str, level: Level) -> bool {
+        self.level(module).is_some_and(|max| level <= max)
+    }
+
+    /// the most verbose level any module logs, for log::set_max_level
+    pub fn max(&self) -> Option<Level> {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+/// one line of log output as the boards print it to a debugger, `[secs.micros] LEVEL module:
+/// text`
+pub struct LogLine<'a> {
+    pub epoch: Epoch,
+    pub level: Level,
+    pub module: &'a str,
+    pub args: fmt::Arguments<'a>,
+}
+
+impl fmt::Display for LogLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{}.{:06}] {:<5} {}: {}",
+            self.epoch.secs,
+            self.epoch.nanos / 1000,
+            self.level.name(),
+            self.module,
+            self.args,
+        )
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct LogRecord {
+    /// one more than the previous record from the same board
+    pub sequence: u32,
+    pub level: Level,
+    /// the board clock when it was logged
+    pub epoch: Epoch,
+    pub module: String<LOG_MAX_MODULE>,
+    pub text: String<LOG_MAX_TEXT>,
+}
+
+/// stops writing at the capacity instead of failing the whole write
+struct Truncate<'a, const N: usize>(&'a mut String<N>);
+
+impl<const N: usize> fmt::Write for Truncate<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            self.0.push(c).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+impl LogRecord {
+    /// the module and text are truncated to fit
+    pub fn new(
+        sequence: u32,
+        level: Level,
+        epoch: Epoch,
+        module: &str,
+        args: fmt::Arguments<'_>,
+    ) -> Self {
+        let mut record = Self {
+            sequence,
+            level,
+            epoch,
+            ..Default::default()
+        };
+        // the end of the path is the interesting part
+        let skip = module.len().saturating_sub(LOG_MAX_MODULE);
+        let module = module.get(skip..).unwrap_or(module).trim_start_matches(':');
+        let _ = fmt::Write::write_str(&mut Truncate(&mut record.module), module);
+        let _ = fmt::write(&mut Truncate(&mut record.text), args);
+        record
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        LogLine {
+            epoch: self.epoch,
+            level: self.level,
+            module: &self.module,
+            args: format_args!("{}", self.text),
+        }
+        .fmt(f)
+    }
+}
+
+/// records waiting to be sent, oldest first
+pub struct LogBuffer<const N: usize> {
+    records: Deque<LogRecord, N>,
+    next_sequence: u32,
+}
+
+impl<const N: usize> Default for LogBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            records: Deque::new(),
+            next_sequence: 0,
+        }
+    }
+
+    /// drops the oldest record if full
+    pub fn push(&mut self, level: Level, epoch: Epoch, module: &str, args: fmt::Arguments<'_>) {
+        let record = LogRecord::new(self.next_sequence, level, epoch, module, args);
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        let _ = self.records.push_back(record);
+    }
+
+    /// for a log::Log implementation, the board supplies the time.  The module is the record's
+    /// target, the same one LevelFilters are checked against in Log::enabled(), which is the
+    /// module path unless the log call sets `target:`
+    #[cfg(feature = "log")]
+    pub fn push_record(&mut self, record: &log::Record<'_>, epoch: Epoch) {
+        let module = record.target();
+        self.push(record.level().into(), epoch, module, *record.args());
+    }
+
+    pub fn pop(&mut self) -> Option<LogRecord> {
+        self.records.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+    use std::string::ToString;
+
+    #[test]
+    fn default_level() {
+        let filters = LevelFilters::<8>::default();
+        assert_eq!(filters.level("net_time"), Some(Level::Info));
+        assert!(filters.enabled("net_time", Level::Warn));
+        assert!(!filters.enabled("net_time", Level::Debug));
+
+        let mut filters = LevelFilters::<8>::new(None);
+        assert!(!filters.enabled("net_time", Level::Error));
+        assert_eq!(filters.max(), None);
+        filters.set_default(Some(Level::Trace));
+        assert!(filters.enabled("net_time", Level::Trace));
+    }
+
+    #[test]
+    fn most_specific_module_wins() {
+        let mut filters = LevelFilters::<8>::new(Some(Level::Info));
+        assert!(filters.set("net_time", Some(Level::Debug)));
+        assert!(filters.set("net_time::peer", Some(Level::Warn)));
+        assert!(filters.set("smoltcp", None));
+
+        assert_eq!(filters.level("net_time"), Some(Level::Debug));
+        assert_eq!(filters.level("net_time::packet"), Some(Level::Debug));
+        assert_eq!(filters.level("net_time::peer"), Some(Level::Warn));
+        assert_eq!(filters.level("net_time::peer::select"), Some(Level::Warn));
+        assert_eq!(filters.level("smoltcp::iface"), None);
+        // only whole path segments match
+        assert_eq!(filters.level("net_timex"), Some(Level::Info));
+        assert_eq!(filters.level("net"), Some(Level::Info));
+        assert_eq!(filters.max(), Some(Level::Debug));
+
+        // the same order set the other way around
+        let mut filters = LevelFilters::<8>::new(Some(Level::Info));
+        assert!(filters.set("net_time::peer", Some(Level::Warn)));
+        assert!(filters.set("net_time", Some(Level::Debug)));
+        assert_eq!(filters.level("net_time::peer::select"), Some(Level::Warn));
+    }
+
+    #[test]
+    fn set_replaces_and_remove() {
+        let mut filters = LevelFilters::<2>::new(Some(Level::Info));
+        assert!(filters.set("net_time", Some(Level::Debug)));
+        assert!(filters.set("net_time", Some(Level::Trace)));
+        assert_eq!(filters.level("net_time"), Some(Level::Trace));
+        assert!(filters.set("smoltcp", Some(Level::Warn)));
+        // full
+        assert!(!filters.set("embassy_net", Some(Level::Warn)));
+        assert_eq!(filters.level("embassy_net"), Some(Level::Info));
+
+        filters.remove("net_time");
+        assert_eq!(filters.level("net_time"), Some(Level::Info));
+        assert!(filters.set("embassy_net", Some(Level::Warn)));
+
+        let long = "x".repeat(LOG_MAX_MODULE + 1);
+        let mut filters = LevelFilters::<2>::new(Some(Level::Info));
+        assert!(!filters.set(&long, Some(Level::Trace)));
+        assert!(filters.set(&long[1..], Some(Level::Trace)));
+    }
+
+    #[test]
+    fn truncated_record() {
+        let epoch = Epoch::from(Duration::from_micros(12_345_678));
+        // the end of the module path is kept, without the separator it was cut at
+        let module = std::format!("nucleo_postcard::net::{}", "x".repeat(30));
+        let text = "a".repeat(LOG_MAX_TEXT + 10);
+        let record = LogRecord::new(3, Level::Warn, epoch, &module, format_args!("{text}"));
+        assert_eq!(record.module.as_str(), "x".repeat(30));
+        assert_eq!(record.text.as_str(), &text[..LOG_MAX_TEXT]);
+
+        // a character that doesn't fit is left out whole
+        let text = std::format!("{}é", "a".repeat(LOG_MAX_TEXT - 1));
+        let record = LogRecord::new(4, Level::Warn, epoch, "net_time", format_args!("{text}"));
+        assert_eq!(record.text.as_str(), &text[..LOG_MAX_TEXT - 1]);
+        assert_eq!(record.module.as_str(), "net_time");
+    }
+
+    #[test]
+    fn log_line() {
+        let epoch = Epoch::from(Duration::from_micros(12_345_678));
+        let line = LogLine {
+            epoch,
+            level: Level::Warn,
+            module: "net_time::peer",
+            args: format_args!("no reply from {}", 3),
+        };
+        assert_eq!(
+            line.to_string(),
+            "[12.345678] WARN  net_time::peer: no reply from 3"
+        );
+
+        let record = LogRecord::new(0, Level::Info, epoch, "net_time", format_args!("hi"));
+        assert_eq!(record.to_string(), "[12.345678] INFO  net_time: hi");
+    }
+
+    #[test]
+    fn buffer_drops_the_oldest() {
+        let mut buffer = LogBuffer::<2>::new();
+        for i in 0..3 {
+            buffer.push(
+                Level::Info,
+                Epoch::default(),
+                "net_time",
+                format_args!("{i}"),
+            );
+        }
+        assert_eq!(buffer.len(), 2);
+        let record = buffer.pop().unwrap();
+        assert_eq!((record.sequence, record.text.as_str()), (1, "1"));
+        let record = buffer.pop().unwrap();
+        assert_eq!((record.sequence, record.text.as_str()), (2, "2"));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn push_record_uses_the_target() {
+        let mut buffer = LogBuffer::<2>::new();
+        let mut filters = LevelFilters::<2>::new(Some(Level::Info));
+        assert!(filters.set("sntp", Some(Level::Debug)));
+        let args = format_args!("poll");
+        let record = log::Record::builder()
+            .level(log::Level::Debug)
+            .target("sntp")
+            .module_path(Some("net_time::peer"))
+            .args(args)
+            .build();
+        // the filter and the buffer both see sntp, not the module path
+        assert!(filters.enabled(record.target(), record.level().into()));
+        buffer.push_record(&record, Epoch::default());
+        assert_eq!(buffer.pop().unwrap().module.as_str(), "sntp");
+    }
+}
@@ -0,0 +1,320 @@
+/*!
+Choosing a board's ipv4 settings, and telling the host tools what was chosen
+
+The board asks for a dhcp lease first, if none arrives within the timeout it falls back to the
+static settings built into the firmware and stays with them.  A lease that is lost starts the
+timeout over.  There is no network code here, the firmware tells AddressPolicy what dhcp is
+doing and applies whatever settings it returns, so the same decisions can be checked on a host.
+
+The host doesn't know a dhcp address in advance, so the board publishes a NetStatus to the
+Topic::NET_STATUS multicast group whenever it changes and periodically after that.
+*/
+
+use core::net::Ipv4Addr;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_DNS_SERVERS: usize = 3;
+/// longest hostname that can be resolved, the dns limit
+pub const MAX_HOSTNAME_LEN: usize = 253;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub enum AddressSource {
+    /// waiting for a dhcp lease
+    #[default]
+    Unconfigured,
+    Dhcp,
+    /// the fallback settings built into the firmware
+    Static,
+}
+
+/// unused addresses are 0.0.0.0
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct Ipv4Settings {
+    pub address: [u8; 4],
+    pub prefix_len: u8,
+    pub gateway: [u8; 4],
+    pub dns_servers: [[u8; 4]; MAX_DNS_SERVERS],
+}
+
+impl Ipv4Settings {
+    pub fn gateway(&self) -> Option<Ipv4Addr> {
+        let gateway = Ipv4Addr::from(self.gateway);
+        (!gateway.is_unspecified()).then_some(gateway)
+    }
+
+    pub fn dns_servers(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.dns_servers
+            .iter()
+            .map(|ip| Ipv4Addr::from(*ip))
+            .filter(|ip| !ip.is_unspecified())
+    }
+}
+
+/// published by the boards, see the module docs
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct NetStatus {
+    pub source: AddressSource,
+    /// all zero while unconfigured
+    pub settings: Ipv4Settings,
+    pub uptime_ms: u64,
+}
+
+/// a remote given either as an ip or a name that needs resolving
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Host<'a> {
+    Ip(Ipv4Addr),
+    Name(&'a str),
+}
+
+impl<'a> Host<'a> {
+    /// None if it is neither an ipv4 address nor a valid hostname
+    pub fn parse(host: &'a str) -> Option<Self> {
+        let host = host.trim();
+        if let Ok(ip) = host.parse::<Ipv4Addr>() {
+            return Some(Host::Ip(ip));
+        }
+        let valid_label = |label: &str| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-')
+        };
+        // a trailing dot is a fully qualified name
+        let name = host.strip_suffix('.').unwrap_or(host);
+        if name.len() > MAX_HOSTNAME_LEN || !name.split('.').all(valid_label) {
+            return None;
+        }
+        // otherwise a mistyped ip like 192.168.0 would be looked up as a name
+        if name.rsplit('.').next()?.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        Some(Host::Name(host))
+    }
+}
+
+pub struct AddressPolicy {
+    fallback: Ipv4Settings,
+    /// 0 to go straight to the fallback
+    dhcp_timeout_ms: u64,
+    source: AddressSource,
+    settings: Ipv4Settings,
+    /// when the current wait for a lease started
+    waiting_since_ms: u64,
+}
+
+impl AddressPolicy {
+    pub const fn new(fallback: Ipv4Settings, dhcp_timeout_ms: u64) -> Self {
+        Self {
+            fallback,
+            dhcp_timeout_ms,
+            source: AddressSource::Unconfigured,
+            settings: fallback,
+            waiting_since_ms: 0,
+        }
+    }
+
+    /// whether to start the network with dhcp or the fallback settings
+    pub fn uses_dhcp(&self) -> bool {
+        self.dhcp_timeout_ms > 0
+    }
+
+    pub fn source(&self) -> AddressSource {
+        self.source
+    }
+
+    pub fn status(&self, now_ms: u64) -> NetStatus {
+        NetStatus {
+            source: self.source,
+            settings: match self.source {
+                AddressSource::Unconfigured => Ipv4Settings::default(),
+                _ => self.settings,
+            },
+            uptime_ms: now_ms,
+        }
+    }
+
+    /// what dhcp currently has, None without a lease, returns true if the status changed
+    pub fn dhcp_update(&mut self, lease: Option<Ipv4Settings>, now_ms: u64) -> bool {
+        if self.source == AddressSource::Static {
+            return false;
+        }
+        match lease {
+            Some(lease) if self.source != AddressSource::Dhcp || lease != self.settings => {
+                self.source = AddressSource::Dhcp;
+                self.settings = lease;
+                true
+            }
+            None if self.source == AddressSource::Dhcp => {
+                self.source = AddressSource::Unconfigured;
+                self.waiting_since_ms = now_ms;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// the static settings to apply, once, when dhcp has taken too long
+    pub fn poll(&mut self, now_ms: u64) -> Option<Ipv4Settings> {
+        if self.source != AddressSource::Unconfigured
+            || now_ms.saturating_sub(self.waiting_since_ms) < self.dhcp_timeout_ms
+        {
+            return None;
+        }
+        self.source = AddressSource::Static;
+        self.settings = self.fallback;
+        Some(self.fallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::String;
+
+    const TIMEOUT_MS: u64 = 10_000;
+
+    fn settings(last: u8) -> Ipv4Settings {
+        Ipv4Settings {
+            address: [192, 168, 0, last],
+            prefix_len: 24,
+            gateway: [192, 168, 0, 1],
+            dns_servers: [[192, 168, 0, 1], [0; 4], [0; 4]],
+        }
+    }
+
+    #[test]
+    fn falls_back_at_the_timeout() {
+        let mut policy = AddressPolicy::new(settings(123), TIMEOUT_MS);
+        assert!(policy.uses_dhcp());
+        assert_eq!(policy.source(), AddressSource::Unconfigured);
+        assert_eq!(policy.status(5).settings, Ipv4Settings::default());
+        assert_eq!(policy.poll(TIMEOUT_MS - 1), None);
+        assert!(!policy.dhcp_update(None, TIMEOUT_MS - 1));
+        assert_eq!(policy.poll(TIMEOUT_MS), Some(settings(123)));
+        assert_eq!(policy.source(), AddressSource::Static);
+        let status = policy.status(TIMEOUT_MS);
+        assert_eq!(status.source, AddressSource::Static);
+        assert_eq!(status.settings, settings(123));
+        assert_eq!(status.uptime_ms, TIMEOUT_MS);
+        // only once
+        assert_eq!(policy.poll(TIMEOUT_MS + 1), None);
+    }
+
+    #[test]
+    fn lease_before_the_timeout() {
+        let mut policy = AddressPolicy::new(settings(123), TIMEOUT_MS);
+        assert!(policy.dhcp_update(Some(settings(50)), 1000));
+        assert_eq!(policy.source(), AddressSource::Dhcp);
+        assert_eq!(policy.status(1000).settings, settings(50));
+        // the same lease again isn't a change, a renewed one with another address is
+        assert!(!policy.dhcp_update(Some(settings(50)), 2000));
+        assert!(policy.dhcp_update(Some(settings(51)), 3000));
+        assert_eq!(policy.poll(TIMEOUT_MS * 10), None);
+        assert_eq!(policy.source(), AddressSource::Dhcp);
+    }
+
+    #[test]
+    fn no_dhcp() {
+        let mut policy = AddressPolicy::new(settings(123), 0);
+        assert!(!policy.uses_dhcp());
+        assert_eq!(policy.poll(0), Some(settings(123)));
+        assert_eq!(policy.source(), AddressSource::Static);
+    }
+
+    #[test]
+    fn lost_lease_restarts_the_timeout() {
+        let mut policy = AddressPolicy::new(settings(123), TIMEOUT_MS);
+        assert!(policy.dhcp_update(Some(settings(50)), 1000));
+        let lost_ms = 60_000;
+        assert!(policy.dhcp_update(None, lost_ms));
+        assert_eq!(policy.source(), AddressSource::Unconfigured);
+        assert_eq!(policy.status(lost_ms).settings, Ipv4Settings::default());
+        assert!(!policy.dhcp_update(None, lost_ms + 1));
+        // long past the first timeout, but not since the lease was lost
+        assert_eq!(policy.poll(lost_ms + TIMEOUT_MS - 1), None);
+        assert_eq!(policy.poll(lost_ms + TIMEOUT_MS), Some(settings(123)));
+    }
+
+    #[test]
+    fn static_is_sticky() {
+        let mut policy = AddressPolicy::new(settings(123), TIMEOUT_MS);
+        assert_eq!(policy.poll(TIMEOUT_MS), Some(settings(123)));
+        // a lease that turns up late or goes away doesn't change anything
+        assert!(!policy.dhcp_update(Some(settings(50)), TIMEOUT_MS + 1));
+        assert!(!policy.dhcp_update(None, TIMEOUT_MS + 2));
+        assert_eq!(policy.source(), AddressSource::Static);
+        assert_eq!(policy.status(TIMEOUT_MS + 3).settings, settings(123));
+        assert_eq!(policy.poll(TIMEOUT_MS * 10), None);
+    }
+
+    #[test]
+    fn settings_addresses() {
+        let settings = settings(123);
+        assert_eq!(settings.gateway(), Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert!(settings.dns_servers().eq([Ipv4Addr::new(192, 168, 0, 1)]));
+        assert_eq!(Ipv4Settings::default().gateway(), None);
+        assert_eq!(Ipv4Settings::default().dns_servers().count(), 0);
+    }
+
+    #[test]
+    fn parse_hosts() {
+        assert_eq!(
+            Host::parse("192.168.0.100"),
+            Some(Host::Ip(Ipv4Addr::new(192, 168, 0, 100)))
+        );
+        assert_eq!(
+            Host::parse(" 10.0.0.1 "),
+            Some(Host::Ip(Ipv4Addr::new(10, 0, 0, 1)))
+        );
+        for name in [
+            "pool.ntp.org",
+            "ntp",
+            "time-1.lab.example",
+            "lab.example.",
+            "a1.b2",
+        ] {
+            assert_eq!(Host::parse(name), Some(Host::Name(name)), "{name}");
+        }
+        assert_eq!(Host::parse(" ntp "), Some(Host::Name("ntp")));
+    }
+
+    #[test]
+    fn parse_rejects() {
+        for host in [
+            "",
+            ".",
+            "192.168.0",
+            "192.168.0.256",
+            "10.1",
+            "-ntp.example",
+            "ntp-.example",
+            "ntp.-example",
+            "ntp.example-",
+            "ntp..example",
+            "ntp.example..",
+            "ntp_1.example",
+            "ntp example",
+        ] {
+            assert_eq!(Host::parse(host), None, "{host}");
+        }
+
+        let label_63 = "a".repeat(63);
+        assert!(Host::parse(&label_63).is_some());
+        let label_64 = "a".repeat(64);
+        assert_eq!(Host::parse(&label_64), None);
+        assert_eq!(
+            Host::parse(&[&label_63, label_64.as_str(), "org"].join(".")),
+            None
+        );
+
+        // 4 labels of 63 and the dots is 255
+        let too_long = [label_63.as_str(); 4].join(".");
+        assert_eq!(Host::parse(&too_long), None);
+        let mut longest = String::from(&too_long[..MAX_HOSTNAME_LEN - 1]);
+        longest.push('b');
+        assert_eq!(Host::parse(&longest).map(|_| ()), Some(()));
+    }
+}
@@ -0,0 +1,377 @@
+/*!
+A two step PTP (IEEE 1588) style exchange between a master (net_loopback ptp_master) and the
+boards as slaves, over the usual udp messages instead of the real PTP wire format
+
+```text
+master          slave
+  t1  -- Sync -->  t2
+      -- FollowUp(t1) -->
+  t4  <-- DelayReq -- t3
+      -- DelayResp(t4) -->
+```
+
+t1 and t4 are from the master clock, t2 and t3 from the slave local clock.  Assuming the path
+delay is the same both ways:
+
+offset (master - slave) = ((t1 - t2) + (t4 - t3)) / 2
+path delay = ((t2 - t1) + (t4 - t3)) / 2
+
+PtpSlave doesn't do any io, the firmware passes in received messages with their local receive
+time and sends whatever it returns.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::Epoch;
+use crate::Message;
+use crate::discipline::Sample;
+
+/// the master sends from and listens for DelayReq on this, the slaves on the usual 34201
+pub const PTP_MASTER_PORT: u16 = 34202;
+
+/// sent by the master at t1, the slave stamps when it arrives (t2)
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PtpSync {
+    pub sequence: u16,
+}
+
+/// the time the master actually sent the Sync with the same sequence
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PtpFollowUp {
+    pub sequence: u16,
+    pub origin: Epoch,
+}
+
+/// sent by the slave at t3 in response to a FollowUp, the sequence is the Sync sequence
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PtpDelayReq {
+    pub sequence: u16,
+}
+
+/// when the master received the DelayReq (t4)
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct PtpDelayResp {
+    pub sequence: u16,
+    pub receive: Epoch,
+}
+
+/// the result of one complete exchange
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PtpMeasurement {
+    pub sequence: u16,
+    /// master time - slave time at t2
+    pub offset_ns: i64,
+    /// one way path delay
+    pub delay_ns: i64,
+    /// the same as a discipline sample, taken at t2 with a round trip of twice the path delay
+    pub sample: Sample,
+}
+
+pub enum SlaveOutput {
+    /// send this back to the master as a Message::PtpDelayReq, calling delay_req_sent()
+    /// right before the send like the master stamps the Sync
+    DelayReq(PtpDelayReq),
+    Measured(PtpMeasurement),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    Idle,
+    /// waiting for the FollowUp with t1
+    Synced {
+        sequence: u16,
+        t2: Epoch,
+    },
+    /// waiting for the DelayResp with t4
+    DelayReqSent {
+        sequence: u16,
+        t1: Epoch,
+        t2: Epoch,
+        t3: Epoch,
+    },
+}
+
+pub struct PtpSlave {
+    state: State,
+    last: Option<PtpMeasurement>,
+}
+
+impl Default for PtpSlave {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PtpSlave {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Idle,
+            last: None,
+        }
+    }
+
+    pub fn last(&self) -> Option<PtpMeasurement> {
+        self.last
+    }
+
+    /// local is when the message was received on the slave clock, non-ptp messages and any
+    /// out of sequence ptp messages are ignored
+    pub fn handle(&mut self, msg: &Message, local: Epoch) -> Option<SlaveOutput> {
+        match (msg, self.state) {
+            // a new Sync always starts over, a lost DelayResp shouldn't block the next exchange
+            (Message::PtpSync(sync), _) => {
+                self.state = State::Synced {
+                    sequence: sync.sequence,
+                    t2: local,
+                };
+                None
+            }
+            (Message::PtpFollowUp(follow_up), State::Synced { sequence, t2 })
+                if follow_up.sequence == sequence =>
+            {
+                self.state = State::DelayReqSent {
+                    sequence,
+                    t1: follow_up.origin,
+                    t2,
+                    // replaced by delay_req_sent()
+                    t3: local,
+                };
+                Some(SlaveOutput::DelayReq(PtpDelayReq { sequence }))
+            }
+            (
+                Message::PtpDelayResp(resp),
+                State::DelayReqSent {
+                    sequence,
+                    t1,
+                    t2,
+                    t3,
+                },
+            ) if resp.sequence == sequence => {
+                self.state = State::Idle;
+                let measurement = measure(sequence, t1, t2, t3, resp.receive)?;
+                self.last = Some(measurement);
+                Some(SlaveOutput::Measured(measurement))
+            }
+            _ => None,
+        }
+    }
+
+    /// the local time the DelayReq is sent (t3), otherwise the FollowUp receive time is used
+    pub fn delay_req_sent(&mut self, local: Epoch) {
+        if let State::DelayReqSent { t3, .. } = &mut self.state {
+            *t3 = local;
+        }
+    }
+}
+
+/// offset and delay from the four timestamps, None if they don't fit in i64 nanoseconds
+pub fn measure(
+    sequence: u16,
+    t1: Epoch,
+    t2: Epoch,
+    t3: Epoch,
+    t4: Epoch,
+) -> Option<PtpMeasurement> {
+    // master to slave including the offset, and slave to master
+    let ms = t2.signed_nanos_since(&t1)? as i128;
+    let sm = t4.signed_nanos_since(&t3)? as i128;
+    let offset_ns = i64::try_from((sm - ms) / 2).ok()?;
+    let delay_ns = i64::try_from((ms + sm) / 2).ok()?;
+    Some(PtpMeasurement {
+        sequence,
+        offset_ns,
+        delay_ns,
+        sample: Sample {
+            local: t2,
+            offset_ns,
+            rtt_ns: delay_ns.max(0) as u64 * 2,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::time::Duration;
+
+    /// master - slave
+    const OFFSET_NS: i64 = 5_000_123_000;
+    /// master to slave and back, the measured offset is off by half the difference
+    const TO_SLAVE: Duration = Duration::from_micros(300);
+    const TO_MASTER: Duration = Duration::from_micros(100);
+
+    fn master_to_slave(master: Epoch) -> Epoch {
+        master
+            .saturating_add(TO_SLAVE)
+            .saturating_sub_signed_nanos(OFFSET_NS)
+    }
+
+    fn slave_to_master(slave: Epoch) -> Epoch {
+        slave
+            .saturating_add(TO_MASTER)
+            .saturating_add_signed_nanos(OFFSET_NS)
+    }
+
+    fn sync(sequence: u16) -> Message {
+        Message::PtpSync(PtpSync { sequence })
+    }
+
+    fn follow_up(sequence: u16, origin: Epoch) -> Message {
+        Message::PtpFollowUp(PtpFollowUp { sequence, origin })
+    }
+
+    fn delay_resp(sequence: u16, receive: Epoch) -> Message {
+        Message::PtpDelayResp(PtpDelayResp { sequence, receive })
+    }
+
+    fn expect_delay_req(output: Option<SlaveOutput>, sequence: u16) {
+        match output {
+            Some(SlaveOutput::DelayReq(req)) => assert_eq!(req.sequence, sequence),
+            _ => panic!("expected a DelayReq"),
+        }
+    }
+
+    fn expect_measured(output: Option<SlaveOutput>) -> PtpMeasurement {
+        match output {
+            Some(SlaveOutput::Measured(measurement)) => measurement,
+            _ => panic!("expected a measurement"),
+        }
+    }
+
+    /// a whole exchange starting with the master sending the Sync at t1, the slave sends the
+    /// DelayReq turnaround after the FollowUp
+    fn exchange(slave: &mut PtpSlave, sequence: u16, t1: Epoch) -> PtpMeasurement {
+        let t2 = master_to_slave(t1);
+        assert!(slave.handle(&sync(sequence), t2).is_none());
+        let follow_up_rx = t2.saturating_add(Duration::from_micros(50));
+        expect_delay_req(
+            slave.handle(&follow_up(sequence, t1), follow_up_rx),
+            sequence,
+        );
+        let t3 = follow_up_rx.saturating_add(Duration::from_micros(20));
+        slave.delay_req_sent(t3);
+        let t4 = slave_to_master(t3);
+        let resp_rx = t3.saturating_add(Duration::from_millis(1));
+        expect_measured(slave.handle(&delay_resp(sequence, t4), resp_rx))
+    }
+
+    #[test]
+    fn offset_and_delay() {
+        let mut slave = PtpSlave::new();
+        let t1 = Epoch::new(1_700_000_000, 0);
+        let measurement = exchange(&mut slave, 7, t1);
+        assert_eq!(measurement.sequence, 7);
+        let asymmetry_ns = (TO_MASTER.as_nanos() as i64 - TO_SLAVE.as_nanos() as i64) / 2;
+        assert_eq!(measurement.offset_ns, OFFSET_NS + asymmetry_ns);
+        assert_eq!(measurement.delay_ns, 200_000);
+        assert_eq!(measurement.sample.local, master_to_slave(t1));
+        assert_eq!(measurement.sample.offset_ns, measurement.offset_ns);
+        assert_eq!(measurement.sample.rtt_ns, 400_000);
+        assert_eq!(slave.last(), Some(measurement));
+
+        // and again after the first
+        let t1 = t1.saturating_add(Duration::from_secs(1));
+        assert_eq!(
+            exchange(&mut slave, 8, t1).offset_ns,
+            OFFSET_NS + asymmetry_ns
+        );
+    }
+
+    #[test]
+    fn measure_symmetric() {
+        let t1 = Epoch::new(100, 0);
+        let t2 = Epoch::new(90, 1000);
+        let t3 = Epoch::new(90, 5000);
+        let t4 = Epoch::new(100, 6000);
+        let measurement = measure(1, t1, t2, t3, t4).unwrap();
+        assert_eq!(measurement.offset_ns, 10_000_000_000);
+        assert_eq!(measurement.delay_ns, 1000);
+        // too far apart for i64 nanoseconds
+        assert_eq!(measure(1, Epoch::ZERO, Epoch::MAX, t3, t4), None);
+    }
+
+    #[test]
+    fn out_of_sequence() {
+        let mut slave = PtpSlave::new();
+        let t1 = Epoch::new(1000, 0);
+        let t2 = master_to_slave(t1);
+        // a FollowUp or DelayResp without a Sync
+        assert!(slave.handle(&follow_up(1, t1), t2).is_none());
+        assert!(slave.handle(&delay_resp(1, t1), t2).is_none());
+
+        assert!(slave.handle(&sync(2), t2).is_none());
+        // the FollowUp for another Sync
+        assert!(slave.handle(&follow_up(1, t1), t2).is_none());
+        expect_delay_req(slave.handle(&follow_up(2, t1), t2), 2);
+        // a repeated FollowUp is ignored once the DelayReq is out
+        assert!(slave.handle(&follow_up(2, t1), t2).is_none());
+        // a DelayResp for another exchange
+        assert!(slave.handle(&delay_resp(1, t1), t2).is_none());
+        assert!(slave.last().is_none());
+        let measurement = expect_measured(slave.handle(&delay_resp(2, slave_to_master(t2)), t2));
+        assert_eq!(measurement.sequence, 2);
+        // only once
+        assert!(
+            slave
+                .handle(&delay_resp(2, slave_to_master(t2)), t2)
+                .is_none()
+        );
+        // anything else isn't ptp
+        assert!(
+            slave
+                .handle(&Message::HeapStatsGet(Default::default()), t2)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn new_sync_restarts() {
+        let mut slave = PtpSlave::new();
+        let t1 = Epoch::new(1000, 0);
+        // lost FollowUp
+        assert!(slave.handle(&sync(1), master_to_slave(t1)).is_none());
+        // lost DelayResp
+        let t1 = t1.saturating_add(Duration::from_secs(1));
+        assert!(slave.handle(&sync(2), master_to_slave(t1)).is_none());
+        expect_delay_req(slave.handle(&follow_up(2, t1), master_to_slave(t1)), 2);
+
+        // the late responses to the abandoned exchanges don't count
+        let t1 = t1.saturating_add(Duration::from_secs(1));
+        let t2 = master_to_slave(t1);
+        assert!(slave.handle(&sync(3), t2).is_none());
+        assert!(slave.handle(&follow_up(1, t1), t2).is_none());
+        assert!(slave.handle(&delay_resp(2, t1), t2).is_none());
+        let measurement = exchange(&mut slave, 4, t1);
+        assert_eq!(measurement.sequence, 4);
+        assert_eq!(measurement.delay_ns, 200_000);
+    }
+
+    #[test]
+    fn delay_req_sent_overrides_t3() {
+        let t1 = Epoch::new(1000, 0);
+        let t2 = master_to_slave(t1);
+        let follow_up_rx = t2.saturating_add(Duration::from_micros(50));
+        let t3 = follow_up_rx.saturating_add(Duration::from_micros(400));
+        let t4 = slave_to_master(t3);
+
+        // without it the FollowUp receive time is taken as t3, and the 400us turnaround
+        // shows up as path delay
+        let mut slave = PtpSlave::new();
+        slave.handle(&sync(1), t2);
+        slave.handle(&follow_up(1, t1), follow_up_rx);
+        let without = expect_measured(slave.handle(&delay_resp(1, t4), t4));
+        assert_eq!(without.delay_ns, 200_000 + 200_000);
+
+        let mut slave = PtpSlave::new();
+        slave.handle(&sync(1), t2);
+        slave.handle(&follow_up(1, t1), follow_up_rx);
+        slave.delay_req_sent(t3);
+        let with = expect_measured(slave.handle(&delay_resp(1, t4), t4));
+        assert_eq!(with.delay_ns, 200_000);
+        assert_eq!(with.offset_ns, without.offset_ns - 200_000);
+
+        // not waiting for a DelayResp, nothing to override
+        slave.delay_req_sent(t3);
+        assert!(slave.handle(&delay_resp(1, t4), t4).is_none());
+    }
+}
@@ -0,0 +1,55 @@
+/*!
+Multicast publish/subscribe
+
+Broadcasting to 255.255.255.255 or 192.168.0.255 from the boards only delivers one packet,
+so instead each topic is published to its own multicast group and any number of host tools
+can join the groups they are interested in.
+
+The topic id is also carried in the third byte of the message header so a subscriber can
+tell topics apart when several groups share the same port.
+*/
+
+/// all topics are published to this port, the group address selects the topic
+pub const MULTICAST_PORT: u16 = 34210;
+
+/// administratively scoped (local network only) multicast range 239.255.0.0/16
+pub const MULTICAST_PREFIX: [u8; 3] = [239, 255, 94];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Topic(pub u8);
+
+impl Topic {
+    /// not published, the message was sent unicast
+    pub const NONE: Topic = Topic(0);
+    pub const TIMESTAMP: Topic = Topic(1);
+    pub const ARRAY: Topic = Topic(2);
+    /// the boards' addresses, see netconfig
+    pub const NET_STATUS: Topic = Topic(3);
+    /// see health
+    pub const HEALTH: Topic = Topic(4);
+
+    pub const ALL: [Topic; 4] = [
+        Topic::TIMESTAMP,
+        Topic::ARRAY,
+        Topic::NET_STATUS,
+        Topic::HEALTH,
+    ];
+
+    /// the multicast group ip this topic is published to
+    pub fn multicast_group(&self) -> [u8; 4] {
+        [
+            MULTICAST_PREFIX[0],
+            MULTICAST_PREFIX[1],
+            MULTICAST_PREFIX[2],
+            self.0,
+        ]
+    }
+
+    /// inverse of multicast_group(), None if the ip isn't in the topic range
+    pub fn from_multicast_group(ip: [u8; 4]) -> Option<Topic> {
+        if ip[..3] != MULTICAST_PREFIX || ip[3] == Topic::NONE.0 {
+            return None;
+        }
+        Some(Topic(ip[3]))
+    }
+}
@@ -0,0 +1,463 @@
+/*!
+Periodic telemetry from several producers sharing one link.  Each producer registers with a
+Scheduler with its own period and priority, and poll() says which one sends next: the highest
+priority one that is due, as long as the bandwidth budget for all of them together isn't used
+up, so lower priorities only get what the higher ones leave of the budget.  Once a message is
+encoded grant() checks its length against the budget, so the budget is never overdrawn except
+by a single message larger than the burst.  A producer that falls behind skips the sends it
+missed rather than catching up in a burst.
+
+Messages are encoded into buffers from a BufferPool, which are allocated up front and go back to
+the pool when dropped.
+
+Nothing here does io or reads a clock, the firmware passes in the time, so the tests run it
+against a simulated millisecond clock.
+*/
+
+use core::cell::UnsafeCell;
+use core::ops::Deref;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{Message, Topic};
+
+/// most producers a Scheduler takes
+pub const MAX_PRODUCERS: usize = 8;
+
+/// ethernet, ipv4 and udp headers, counted against the budget along with each message
+pub const FRAME_OVERHEAD: usize = 14 + 20 + 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProducerId(u8);
+
+/// how a producer has done so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProducerStats {
+    pub sent: u32,
+    /// periods that went by without a send, because higher priorities or the budget held it up,
+    /// counted by poll() as they go by
+    pub missed: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Producer {
+    /// 0 for disabled
+    period_ms: u32,
+    /// higher goes first
+    priority: u8,
+    next_ms: u64,
+    stats: ProducerStats,
+}
+
+impl Producer {
+    /// count the periods gone by without a send, next_ms becomes the latest one that is due
+    fn catch_up(&mut self, now_ms: u64) {
+        if self.period_ms == 0 || self.next_ms > now_ms {
+            return;
+        }
+        let period = self.period_ms as u64;
+        let behind = (now_ms - self.next_ms) / period;
+        self.stats.missed += behind as u32;
+        self.next_ms += behind * period;
+    }
+
+    /// done with the send that is due, the next one is a period after it
+    fn advance(&mut self, now_ms: u64) {
+        if self.period_ms == 0 {
+            return;
+        }
+        self.catch_up(now_ms);
+        self.next_ms += self.period_ms as u64;
+    }
+}
+
+/// a token bucket in thousandths of a byte, so slow rates don't round to nothing
+#[derive(Clone, Copy, Debug)]
+struct Budget {
+    bytes_per_sec: u32,
+    burst_bytes: u32,
+    millibytes: i64,
+    last_ms: u64,
+    /// the length of a message grant() turned down, nothing more goes until it fits
+    wanted_bytes: usize,
+}
+
+impl Budget {
+    fn refill(&mut self, now_ms: u64) {
+        let elapsed_ms = now_ms.saturating_sub(self.last_ms);
+        self.last_ms = self.last_ms.max(now_ms);
+        let full = self.burst_bytes as i64 * 1000;
+        let refill = (elapsed_ms as i64).saturating_mul(self.bytes_per_sec as i64);
+        self.millibytes = self.millibytes.saturating_add(refill).min(full);
+    }
+
+    /// millibytes needed for a message of len bytes, one larger than the burst needs a full bucket
+    fn needed(&self, len: usize) -> i64 {
+        (len.min(self.burst_bytes as usize) as i64 * 1000).max(1)
+    }
+
+    fn has(&self, len: usize) -> bool {
+        self.bytes_per_sec == 0 || self.millibytes >= self.needed(len)
+    }
+
+    /// when there will be enough in the bucket for len bytes
+    fn available_at(&self, len: usize) -> u64 {
+        if self.has(len) {
+            return self.last_ms;
+        }
+        let missing = (self.needed(len) - self.millibytes) as u64;
+        self.last_ms + missing.div_ceil(self.bytes_per_sec as u64)
+    }
+}
+
+/// decides which producer sends next, see the module docs
+pub struct Scheduler {
+    producers: heapless::Vec<Producer, MAX_PRODUCERS>,
+    budget: Budget,
+}
+
+impl Scheduler {
+    /// bytes_per_sec is the budget for all the producers together including FRAME_OVERHEAD (0
+    /// for no limit), up to burst_bytes can go out at once after a quiet spell
+    pub const fn new(bytes_per_sec: u32, burst_bytes: u32) -> Self {
+        Self {
+            producers: heapless::Vec::new(),
+            budget: Budget {
+                bytes_per_sec,
+                burst_bytes,
+                millibytes: burst_bytes as i64 * 1000,
+                last_ms: 0,
+                wanted_bytes: 0,
+            },
+        }
+    }
+
+    /// the first send is due right away, None if MAX_PRODUCERS are already registered
+    pub fn register(&mut self, period_ms: u32, priority: u8, now_ms: u64) -> Option<ProducerId> {
+        let id = ProducerId(self.producers.len() as u8);
+        self.producers
+            .push(Producer {
+                period_ms,
+                priority,
+                next_ms: now_ms,
+                stats: ProducerStats::default(),
+            })
+            .ok()?;
+        Some(id)
+    }
+
+    /// 0 stops the producer, a changed period takes effect after the next grant() or skip()
+    pub fn set_period(&mut self, id: ProducerId, period_ms: u32) {
+        if let Some(producer) = self.producers.get_mut(id.0 as usize) {
+            producer.period_ms = period_ms;
+        }
+    }
+
+    pub fn stats(&self, id: ProducerId) -> ProducerStats {
+        self.producers
+            .get(id.0 as usize)
+            .map(|producer| producer.stats)
+            .unwrap_or_default()
+    }
+
+    /// the producer that should send now, it stays due until grant() or skip() is called for it
+    pub fn poll(&mut self, now_ms: u64) -> Option<ProducerId> {
+        self.budget.refill(now_ms);
+        for producer in self.producers.iter_mut() {
+            producer.catch_up(now_ms);
+        }
+        if !self.budget.has(self.budget.wanted_bytes) {
+            return None;
+        }
+        // the highest priority, then the one that has waited longest
+        self.producers
+            .iter()
+            .enumerate()
+            .filter(|(_, producer)| producer.period_ms > 0 && producer.next_ms <= now_ms)
+            .min_by_key(|(_, producer)| (u8::MAX - producer.priority, producer.next_ms))
+            .map(|(i, _)| ProducerId(i as u8))
+    }
+
+    /// the producer has len bytes to send including FRAME_OVERHEAD, true if the budget has room
+    /// for them and it should send now, it is then due again a period after it was last due.
+    /// Otherwise poll() returns nothing until next_wake(), when there will be room
+    pub fn grant(&mut self, id: ProducerId, len: usize, now_ms: u64) -> bool {
+        self.budget.refill(now_ms);
+        if !self.budget.has(len) {
+            self.budget.wanted_bytes = len;
+            return false;
+        }
+        let Some(producer) = self.producers.get_mut(id.0 as usize) else {
+            return false;
+        };
+        if self.budget.bytes_per_sec > 0 {
+            self.budget.millibytes -= len as i64 * 1000;
+        }
+        self.budget.wanted_bytes = 0;
+        producer.stats.sent += 1;
+        producer.advance(now_ms);
+        true
+    }
+
+    /// the producer had nothing to send this time, it is due again a period after it was last due
+    pub fn skip(&mut self, id: ProducerId, now_ms: u64) {
+        if let Some(producer) = self.producers.get_mut(id.0 as usize) {
+            producer.advance(now_ms);
+        }
+    }
+
+    /// when poll() could next return a producer, None if they are all stopped
+    pub fn next_wake(&self) -> Option<u64> {
+        let due = self
+            .producers
+            .iter()
+            .filter(|producer| producer.period_ms > 0)
+            .map(|producer| producer.next_ms)
+            .min()?;
+        Some(due.max(self.budget.available_at(self.budget.wanted_bytes)))
+    }
+}
+
+/// SZ byte buffers to encode messages into, shared by anything with a reference to the pool
+pub struct BufferPool<const N: usize, const SZ: usize> {
+    buffers: [UnsafeCell<[u8; SZ]>; N],
+    taken: [AtomicBool; N],
+}
+
+// each buffer is only reachable through the one PoolBuffer that took it
+unsafe impl<const N: usize, const SZ: usize> Sync for BufferPool<N, SZ> {}
+
+impl<const N: usize, const SZ: usize> Default for BufferPool<N, SZ> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const SZ: usize> BufferPool<N, SZ> {
+    pub const fn new() -> Self {
+        Self {
+            buffers: [const { UnsafeCell::new([0; SZ]) }; N],
+            taken: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// a free buffer, None if they are all taken
+    pub fn take(&self) -> Option<PoolBuffer<'_>> {
+        let i = self.taken.iter().position(|taken| {
+            taken
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        Some(PoolBuffer {
+            // the flag just taken keeps anyone else from this buffer until the drop
+            buf: unsafe { &mut *self.buffers[i].get() },
+            taken: &self.taken[i],
+            len: 0,
+        })
+    }
+
+    pub fn available(&self) -> usize {
+        self.taken
+            .iter()
+            .filter(|taken| !taken.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+/// a buffer from a BufferPool, it goes back when dropped.  Derefs to what was encoded into it
+pub struct PoolBuffer<'a> {
+    buf: &'a mut [u8],
+    taken: &'a AtomicBool,
+    len: usize,
+}
+
+impl PoolBuffer<'_> {
+    /// replace the contents with the encoded message
+    pub fn encode(
+        &mut self,
+        msg: &Message,
+        topic: Topic,
+        crc_digest: crc::Digest<'_, u32>,
+    ) -> Result<&[u8], postcard::Error> {
+        self.len = 0;
+        self.len = msg.encode_topic_into(topic, self.buf, crc_digest)?;
+        Ok(&self.buf[..self.len])
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+impl Deref for PoolBuffer<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Drop for PoolBuffer<'_> {
+    fn drop(&mut self) {
+        self.taken.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HeapStats, TimeStamp};
+    use std::vec::Vec;
+
+    struct Run {
+        timestamps: ProducerStats,
+        heap: ProducerStats,
+        bytes: usize,
+        order: Vec<&'static str>,
+    }
+
+    /// the producers nucleo_embassy uses, polled every ms for duration_ms
+    fn run(bytes_per_sec: u32, burst_bytes: u32, duration_ms: u64) -> Run {
+        let buffers = BufferPool::<2, 256>::new();
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+        let mut scheduler = Scheduler::new(bytes_per_sec, burst_bytes);
+        // registered low priority first, so priority has to beat registration order
+        let heap_id = scheduler.register(100, 0, 0).unwrap();
+        let timestamp_id = scheduler.register(10, 2, 0).unwrap();
+
+        let mut bytes = 0;
+        let mut order = Vec::new();
+        for now_ms in 0..duration_ms {
+            // nothing is due before next_wake()
+            let wake = scheduler.next_wake().unwrap();
+            while let Some(id) = scheduler.poll(now_ms) {
+                assert!(wake <= now_ms, "{wake} {now_ms}");
+                let (name, msg) = if id == timestamp_id {
+                    let msg = TimeStamp {
+                        tick_ms: now_ms,
+                        ..Default::default()
+                    };
+                    ("timestamp", Message::TimeStamp(msg))
+                } else {
+                    ("heap", Message::HeapStats(HeapStats::default()))
+                };
+                let mut buf = buffers.take().unwrap();
+                let len = buf.encode(&msg, Topic::NONE, crc.digest()).unwrap().len();
+                assert!(Message::decode(&buf, crc.digest()).is_ok());
+                if !scheduler.grant(id, len + FRAME_OVERHEAD, now_ms) {
+                    break;
+                }
+                bytes += len + FRAME_OVERHEAD;
+                if order.len() < 4 {
+                    order.push(name);
+                }
+            }
+        }
+        assert_eq!(buffers.available(), 2);
+        Run {
+            timestamps: scheduler.stats(timestamp_id),
+            heap: scheduler.stats(heap_id),
+            bytes,
+            order,
+        }
+    }
+
+    #[test]
+    fn every_producer_gets_its_rate() {
+        let run = run(100_000, 1500, 2000);
+        assert_eq!(run.order[..2], ["timestamp", "heap"]);
+        assert_eq!(run.timestamps.sent, 200);
+        assert_eq!(run.heap.sent, 20);
+        assert_eq!(run.timestamps.missed + run.heap.missed, 0);
+    }
+
+    #[test]
+    fn budget_holds() {
+        let (bytes_per_sec, burst_bytes, duration_ms) = (2000, 200, 2000);
+        let run = run(bytes_per_sec, burst_bytes, duration_ms);
+        let allowed = (bytes_per_sec as u64 * duration_ms / 1000 + burst_bytes as u64) as usize;
+        assert!(run.bytes <= allowed, "{} {allowed}", run.bytes);
+        // most of the budget got used
+        assert!(run.bytes > allowed * 9 / 10, "{} {allowed}", run.bytes);
+        assert!(run.timestamps.missed > 0);
+        assert!(run.timestamps.sent > run.heap.sent);
+        // every period is either sent or missed, less the one due at the end
+        let periods = (duration_ms / 10) as u32;
+        assert!(run.timestamps.sent + run.timestamps.missed + 1 >= periods);
+    }
+
+    #[test]
+    fn missed_counted_by_poll() {
+        let mut scheduler = Scheduler::new(0, 0);
+        let id = scheduler.register(10, 0, 0).unwrap();
+        assert_eq!(scheduler.poll(35), Some(id));
+        // 0, 10 and 20 went by, 30 is still due
+        assert_eq!(scheduler.stats(id).missed, 3);
+        assert!(scheduler.grant(id, 100, 35));
+        assert_eq!(scheduler.poll(39), None);
+        assert_eq!(scheduler.next_wake(), Some(40));
+
+        // nothing to send isn't a miss
+        assert_eq!(scheduler.poll(40), Some(id));
+        scheduler.skip(id, 40);
+        assert_eq!(scheduler.poll(49), None);
+        assert_eq!(scheduler.stats(id), ProducerStats { sent: 1, missed: 3 });
+
+        scheduler.set_period(id, 0);
+        assert_eq!(scheduler.poll(100), None);
+        assert_eq!(scheduler.next_wake(), None);
+    }
+
+    #[test]
+    fn grant_waits_for_room() {
+        let mut scheduler = Scheduler::new(1000, 100);
+        let low = scheduler.register(10, 0, 0).unwrap();
+        let high = scheduler.register(10, 1, 0).unwrap();
+        assert_eq!(scheduler.poll(0), Some(high));
+        assert!(scheduler.grant(high, 60, 0));
+        // 40 bytes left
+        assert_eq!(scheduler.poll(0), Some(low));
+        assert!(!scheduler.grant(low, 60, 0));
+        assert_eq!(scheduler.next_wake(), Some(20));
+        // held for the refused one even though there is something in the bucket
+        assert_eq!(scheduler.poll(19), None);
+        assert_eq!(scheduler.poll(20), Some(high));
+        assert!(scheduler.grant(high, 60, 20));
+        assert_eq!(scheduler.stats(high).missed, 1);
+
+        // empty
+        assert_eq!(scheduler.poll(20), None);
+        assert_eq!(scheduler.next_wake(), Some(21));
+
+        // larger than the burst goes once the bucket is full
+        assert_eq!(scheduler.poll(21), Some(low));
+        assert_eq!(scheduler.stats(low).missed, 2);
+        assert!(!scheduler.grant(low, 150, 21));
+        assert_eq!(scheduler.next_wake(), Some(120));
+        assert_eq!(scheduler.poll(119), None);
+        assert_eq!(scheduler.poll(120), Some(high));
+        assert!(scheduler.grant(high, 150, 120));
+        // 50 bytes overdrawn
+        assert_eq!(scheduler.next_wake(), Some(171));
+    }
+
+    #[test]
+    fn buffers_come_back() {
+        let buffers = BufferPool::<2, 64>::new();
+        let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+        let mut first = buffers.take().unwrap();
+        assert_eq!(first.capacity(), 64);
+        assert!(first.is_empty());
+        let msg = Message::HeapStats(HeapStats::default());
+        let len = first.encode(&msg, Topic::NONE, crc.digest()).unwrap().len();
+        assert_eq!(first.len(), len);
+        let second = buffers.take().unwrap();
+        assert!(buffers.take().is_none());
+        assert_eq!(buffers.available(), 0);
+        drop(first);
+        assert_eq!(buffers.available(), 1);
+        let third = buffers.take().unwrap();
+        assert!(third.is_empty());
+        drop((second, third));
+        assert_eq!(buffers.available(), 2);
+    }
+}
@@ -0,0 +1,80 @@
+/*!
+Check board config files the way the firmware build.rs does and print what a board built with
+each one would use, without building the firmware
+
+```
+cargo run --bin board_config -- ../boards/default.toml ../boards/sim.toml
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::board_config::BoardConfig;
+use std::net::Ipv4Addr;
+
+fn print_config(path: &str, config: &BoardConfig) {
+    let mac = match config.mac {
+        Some(mac) => {
+            let mac: Vec<String> = mac.iter().map(|b| format!("{b:02x}")).collect();
+            mac.join(":")
+        }
+        None => "from the unique device id".into(),
+    };
+    let dns_servers: Vec<Ipv4Addr> = config.dns_servers.iter().map(|ip| (*ip).into()).collect();
+    let dhcp = match config.dhcp_timeout_ms {
+        0 => "off".into(),
+        ms => format!("{ms}ms then static"),
+    };
+    let ports = &config.ports;
+    println!("{path} ({})", config.name);
+    println!(
+        "  local        {}/{} gateway {} dns {dns_servers:?}",
+        Ipv4Addr::from(config.local_ip),
+        config.prefix_len,
+        Ipv4Addr::from(config.gateway),
+    );
+    println!("  dhcp         {dhcp}");
+    println!("  mac          {mac}");
+    println!(
+        "  remote       {} {:?}",
+        Ipv4Addr::from(config.remote_ip),
+        config.remote_host
+    );
+    println!(
+        "  ports        telemetry {} log {} on the remote",
+        ports.telemetry, ports.log
+    );
+    println!(
+        "               commands {} ntp {} net_status {} log {} telemetry {} on the board",
+        ports.commands,
+        ports.ntp_local,
+        ports.net_status_local,
+        ports.log_local,
+        ports.telemetry_local
+    );
+    println!(
+        "  ntp          {:?} every {}ms",
+        config.ntp_servers, config.ntp_poll_ms
+    );
+    println!("  telemetry    every {}ms", config.telemetry_period_ms);
+}
+
+fn main() {
+    let matches = Command::new("board_config")
+        .arg(arg!(<files> ... "board config toml files"))
+        .get_matches();
+
+    let mut failed = false;
+    for path in matches.get_many::<String>("files").unwrap() {
+        match BoardConfig::read(path) {
+            Ok(config) => print_config(path, &config),
+            Err(err) => {
+                eprintln!("{path}: {err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,113 @@
+/*!
+Ask one or more boards which board they are, what firmware they run and how long they've been
+up, either at the given ips or at the local_ip and commands port from their board config files
+(which is only where they are if they didn't get a dhcp lease)
+
+```
+cargo run --bin board_info -- -l 192.168.0.100 -r 192.168.0.123,192.168.0.124
+cargo run --bin board_info -- -l 192.168.0.100 -c ../boards/default.toml
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::board_config::BoardConfig;
+use net_common::{BoardInfo, BoardInfoGet, Epoch, Message};
+use net_loopback::Link;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// send the request and wait for the reply
+fn request(link: &Link) -> Result<BoardInfo, net_loopback::Error> {
+    link.send(&Message::BoardInfoGet(BoardInfoGet {}))?;
+    loop {
+        let (msg, _src, _rx_stamp) = link.recv()?;
+        if let Message::BoardInfo(info) = msg {
+            return Ok(info);
+        }
+    }
+}
+
+/// e.g. 3d 04:05:06
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let hms = format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60);
+    if days > 0 {
+        format!("{days}d {hms}")
+    } else {
+        hms
+    }
+}
+
+fn print_info(remote: &str, info: &BoardInfo) {
+    let unique_id: String = info.unique_id.iter().map(|b| format!("{b:02x}")).collect();
+    let mac: Vec<String> = info.mac.iter().map(|b| format!("{b:02x}")).collect();
+    let built_ago = Epoch::now()
+        .duration_since(&info.build_time)
+        .map(format_duration)
+        .unwrap_or_else(|| "in the future".into());
+    println!("{remote}");
+    println!("  unique id    {unique_id}");
+    println!("  mac          {}", mac.join(":"));
+    println!(
+        "  firmware     {} built {} ({built_ago} ago)",
+        info.git_hash, info.build_time.secs
+    );
+    println!(
+        "  uptime       {}",
+        format_duration(Duration::from_millis(info.uptime_ms))
+    );
+    println!("  reset cause  {:?}", info.reset_cause);
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("board_info")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ips <REMOTE_IPS> "comma separated ips of the boards"
+            )
+            .default_value("192.168.0.123"),
+            arg!(
+                -c --configs <CONFIGS> "comma separated board config files, instead of remote_ips"
+            ),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let remote_ips = matches.get_one::<String>("remote_ips").unwrap();
+
+    let remotes = match matches.get_one::<String>("configs") {
+        Some(configs) => configs
+            .split(',')
+            .map(|path| {
+                let config = BoardConfig::read(path.trim())?;
+                let ip = Ipv4Addr::from(config.local_ip);
+                Ok(format!("{ip}:{}", config.ports.commands))
+            })
+            .collect::<Result<Vec<_>, net_loopback::Error>>()?,
+        None => remote_ips
+            .split(',')
+            .map(|ip| format!("{}:34201", ip.trim()))
+            .collect(),
+    };
+
+    for remote in remotes {
+        // any local port, the board replies to the sender
+        let link = Link::bind(format!("{local_ip}:0"))?.with_remote(&remote)?;
+        link.set_timeout(Some(Duration::from_secs(1)))?;
+        match request(&link) {
+            Ok(info) => print_info(&remote, &info),
+            Err(err) if err.is_timeout() => {
+                eprintln!("no reply from {remote}");
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+/*!
+Get and set the runtime parameters of a board
+
+```
+cargo run --bin config -- -l 192.168.0.100 -r 192.168.0.123 get
+cargo run --bin config -- -l 192.168.0.100 -r 192.168.0.123 get ntp_poll_ms
+cargo run --bin config -- -l 192.168.0.100 -r 192.168.0.123 set led_pattern fast
+cargo run --bin config -- -l 192.168.0.100 -r 192.168.0.123 set remote_ip 192.168.0.101
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::config::{ConfigStatus, LedPattern, ParamId};
+use net_common::{ConfigGet, ConfigSet, ConfigValue, Message};
+use net_loopback::Link;
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+fn parse_param(name: &str) -> ParamId {
+    ParamId::from_name(name).unwrap_or_else(|| {
+        let names: Vec<_> = ParamId::ALL.iter().map(|id| id.name()).collect();
+        eprintln!("unknown parameter '{name}', expected one of {names:?}");
+        std::process::exit(1);
+    })
+}
+
+fn parse_value(id: ParamId, value: &str) -> Option<u32> {
+    match id {
+        ParamId::RemoteIp | ParamId::LocalIp => value.parse::<Ipv4Addr>().ok().map(u32::from),
+        ParamId::LedPattern => LedPattern::ALL
+            .into_iter()
+            .find(|pattern| pattern.name() == value)
+            .map(|pattern| pattern as u32)
+            .or_else(|| value.parse().ok()),
+        _ => value.parse().ok(),
+    }
+}
+
+fn format_value(id: ParamId, value: u32) -> String {
+    match id {
+        ParamId::RemoteIp | ParamId::LocalIp => Ipv4Addr::from(value).to_string(),
+        ParamId::LedPattern => match LedPattern::from_u32(value) {
+            Some(pattern) => pattern.name().to_string(),
+            None => format!("unknown pattern {value}"),
+        },
+        _ => value.to_string(),
+    }
+}
+
+/// send the request and wait for the ConfigValue about the same parameter
+fn request(link: &Link, msg: &Message, id: ParamId) -> Result<ConfigValue, net_loopback::Error> {
+    link.send(msg)?;
+    loop {
+        let (msg, _src, _rx_stamp) = link.recv()?;
+        if let Message::ConfigValue(value) = msg
+            && value.id == id
+        {
+            return Ok(value);
+        }
+    }
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("config")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ip <REMOTE_IP> "ip of remote device"
+            )
+            .default_value("192.168.0.123"),
+        ])
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("get")
+                .about("print one parameter, or all of them")
+                .arg(arg!([PARAM] "parameter name")),
+        )
+        .subcommand(
+            Command::new("set")
+                .about("change a parameter")
+                .arg(arg!(<PARAM> "parameter name"))
+                .arg(arg!(<VALUE> "new value")),
+        )
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let remote_ip = matches.get_one::<String>("remote_ip").unwrap();
+    // any local port, the board replies to the sender
+    let link = Link::bind(format!("{local_ip}:0"))?.with_remote(format!("{remote_ip}:34201"))?;
+    link.set_timeout(Some(Duration::from_secs(1)))?;
+
+    let requests: Vec<(ParamId, Message)> = match matches.subcommand() {
+        Some(("get", sub_matches)) => {
+            let ids = match sub_matches.get_one::<String>("PARAM") {
+                Some(name) => vec![parse_param(name)],
+                None => ParamId::ALL.to_vec(),
+            };
+            ids.into_iter()
+                .map(|id| (id, Message::ConfigGet(ConfigGet { id })))
+                .collect()
+        }
+        Some(("set", sub_matches)) => {
+            let id = parse_param(sub_matches.get_one::<String>("PARAM").unwrap());
+            let value = sub_matches.get_one::<String>("VALUE").unwrap();
+            let Some(value) = parse_value(id, value) else {
+                eprintln!("invalid value '{value}' for {}", id.name());
+                std::process::exit(1);
+            };
+            vec![(id, Message::ConfigSet(ConfigSet { id, value }))]
+        }
+        _ => unreachable!("subcommand_required"),
+    };
+
+    for (id, msg) in requests {
+        match request(&link, &msg, id) {
+            Ok(value) => {
+                let formatted = format_value(value.id, value.value);
+                match value.status {
+                    ConfigStatus::Ok => println!("{} = {formatted}", id.name()),
+                    status => println!("{} = {formatted} ({status:?})", id.name()),
+                }
+            }
+            Err(err) if err.is_timeout() => {
+                eprintln!("no reply from {remote_ip} about {}", id.name());
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
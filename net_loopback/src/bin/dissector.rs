@@ -0,0 +1,31 @@
+/*!
+Write a Lua Wireshark dissector for the net_common messages, generated from the Rust types so
+it never drifts from them
+
+```
+cargo run --bin dissector -- -o ~/.local/lib/wireshark/plugins/net_common.lua
+```
+
+*/
+
+use clap::{Command, arg};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Command::new("dissector")
+        .args(&[arg!(
+            -o --output <OUTPUT> "lua file to write, stdout if not given"
+        )])
+        .get_matches();
+
+    let lua = net_loopback::dissector::generate()?;
+    match matches.get_one::<String>("output") {
+        Some(output) => {
+            std::fs::write(output, lua)?;
+            eprintln!("wrote {output}");
+        }
+        None => {
+            print!("{lua}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+/*!
+Send a new firmware to a board over udp and have the bootloader install it, see net_common
+firmware.  The image is the raw binary of the firmware:
+
+```
+arm-none-eabi-objcopy -O binary target/thumbv7em-none-eabihf/release/nucleo_embassy fw.bin
+cargo run --release --bin firmware_update -- -l 192.168.0.100 -r 192.168.0.123 fw.bin
+```
+
+Running it again with the same image after an interrupted upload carries on from where the
+board got to.  The board resets into the new firmware right after the commit, use --no_commit to
+only upload and verify it.
+
+*/
+
+use clap::{Command, arg};
+use net_common::firmware::{CHUNK_LEN, FirmwareResult, FirmwareState, begin_for};
+use net_common::{FirmwareChunk, FirmwareCommit, FirmwareStatus, FirmwareVerify, Message};
+use net_loopback::Link;
+use std::time::{Duration, Instant};
+
+/// each request is sent this many times before giving up, the board doesn't answer while it
+/// erases a flash sector
+const TRIES: usize = 10;
+
+/// send the request until a FirmwareStatus comes back
+fn request(link: &Link, msg: &Message) -> Result<FirmwareStatus, net_loopback::Error> {
+    let mut tries = 0;
+    loop {
+        link.send(msg)?;
+        tries += 1;
+        loop {
+            match link.recv() {
+                Ok((Message::FirmwareStatus(status), _src, _rx_stamp)) => {
+                    return Ok(status);
+                }
+                Ok(_) => {}
+                Err(err) if err.is_timeout() && tries < TRIES => {
+                    break;
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
+
+fn fail(what: &str, status: &FirmwareStatus) -> ! {
+    eprintln!("{what} failed: {status:?}");
+    std::process::exit(1);
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("firmware_update")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ip <REMOTE_IP> "ip of remote device"
+            )
+            .default_value("192.168.0.123"),
+            arg!(--no_commit "upload and verify the image without installing it"),
+            arg!(<IMAGE> "the firmware as a raw binary"),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let remote_ip = matches.get_one::<String>("remote_ip").unwrap();
+    let path = matches.get_one::<String>("IMAGE").unwrap();
+    let image = std::fs::read(path).map_err(net_loopback::Error::Io)?;
+
+    let link = Link::bind(format!("{local_ip}:0"))?.with_remote(format!("{remote_ip}:34201"))?;
+    link.set_timeout(Some(Duration::from_secs(1)))?;
+
+    let begin = begin_for(&image);
+    println!(
+        "{path}: {} bytes, crc {:08x}, sha-256 {}",
+        begin.len,
+        begin.crc,
+        begin
+            .sha256
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>()
+    );
+    let status = request(&link, &Message::FirmwareBegin(begin))?;
+    if status.result != FirmwareResult::Ok {
+        fail("begin", &status);
+    }
+    if status.next > 0 {
+        println!("resuming at {}", status.next);
+    }
+
+    let start = Instant::now();
+    let mut next = status.next as usize;
+    let resumed_at = next;
+    let mut last_shown = next;
+    while next < image.len() {
+        let end = (next + CHUNK_LEN).min(image.len());
+        let chunk = FirmwareChunk {
+            offset: next as u32,
+            data: image[next..end].iter().copied().collect(),
+        };
+        let status = request(&link, &Message::FirmwareChunk(chunk))?;
+        match (status.state, status.result) {
+            // a wrong offset comes with where the board wants to carry on from
+            (
+                FirmwareState::Receiving | FirmwareState::Received,
+                FirmwareResult::Ok | FirmwareResult::WrongOffset,
+            ) => {
+                next = status.next as usize;
+            }
+            _ => fail("chunk", &status),
+        }
+        if next - last_shown >= 64 * 1024 || next == image.len() {
+            println!(
+                "{next} / {} bytes, {:.1} KB/s",
+                image.len(),
+                (next - resumed_at) as f64 / 1024.0 / start.elapsed().as_secs_f64()
+            );
+            last_shown = next;
+        }
+    }
+
+    let status = request(&link, &Message::FirmwareVerify(FirmwareVerify {}))?;
+    if status.state != FirmwareState::Verified {
+        fail("verify", &status);
+    }
+    println!("verified");
+    if matches.get_flag("no_commit") {
+        return Ok(());
+    }
+
+    let status = request(&link, &Message::FirmwareCommit(FirmwareCommit {}))?;
+    if status.state != FirmwareState::Committed {
+        fail("commit", &status);
+    }
+    println!("committed, the board resets into the new firmware");
+    Ok(())
+}
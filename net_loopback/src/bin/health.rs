@@ -0,0 +1,87 @@
+/*!
+Print the state of a board and when each of its tasks last checked in with the supervisor, once
+or every period
+
+```
+cargo run --bin health -- -l 192.168.0.100 -r 192.168.0.123
+cargo run --bin health -- -l 192.168.0.100 -r 192.168.0.123 --period_ms 1000
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::health::BoardState;
+use net_common::{Health, HealthGet, Message};
+use net_loopback::Link;
+use std::time::Duration;
+
+/// send the request and wait for the reply
+fn request(link: &Link) -> Result<Health, net_loopback::Error> {
+    link.send(&Message::HealthGet(HealthGet {}))?;
+    loop {
+        let (msg, _src, _rx_stamp) = link.recv()?;
+        if let Message::Health(health) = msg {
+            return Ok(health);
+        }
+    }
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("health")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ip <REMOTE_IP> "ip of remote device"
+            )
+            .default_value("192.168.0.123"),
+            arg!(
+                -p --period_ms <PERIOD_MS> "keep requesting with this period, 0 to only ask once"
+            )
+            .default_value("0"),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let remote_ip = matches.get_one::<String>("remote_ip").unwrap();
+    let period_ms: u64 = matches
+        .get_one::<String>("period_ms")
+        .unwrap()
+        .parse()
+        .expect("period_ms must be an integer");
+    // any local port, the board replies to the sender
+    let link = Link::bind(format!("{local_ip}:0"))?.with_remote(format!("{remote_ip}:34201"))?;
+    link.set_timeout(Some(Duration::from_secs(1)))?;
+
+    loop {
+        match request(&link) {
+            Ok(health) => {
+                let state = match health.state {
+                    BoardState::Fault => format!("Fault, {:?} stalled", health.stalled),
+                    state => format!("{state:?}"),
+                };
+                println!("up {:.1}s {state}", health.uptime_ms as f64 / 1000.0);
+                for task in health.tasks() {
+                    println!(
+                        "  {:<10} seen {:>6}ms ago, timeout {:>6}ms{}",
+                        format!("{:?}", task.id),
+                        task.since_ms,
+                        task.timeout_ms,
+                        if task.is_alive() { "" } else { " STALLED" }
+                    );
+                }
+            }
+            Err(err) if err.is_timeout() => {
+                eprintln!("no reply from {remote_ip}");
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        if period_ms == 0 {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(period_ms));
+    }
+}
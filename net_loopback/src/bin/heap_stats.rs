@@ -0,0 +1,82 @@
+/*!
+Print the heap usage of a board, once or every period
+
+```
+cargo run --bin heap_stats -- -l 192.168.0.100 -r 192.168.0.123
+cargo run --bin heap_stats -- -l 192.168.0.100 -r 192.168.0.123 --period_ms 1000
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::{HeapStats, HeapStatsGet, Message};
+use net_loopback::Link;
+use std::time::Duration;
+
+/// send the request and wait for the reply
+fn request(link: &Link) -> Result<HeapStats, net_loopback::Error> {
+    link.send(&Message::HeapStatsGet(HeapStatsGet {}))?;
+    loop {
+        let (msg, _src, _rx_stamp) = link.recv()?;
+        if let Message::HeapStats(stats) = msg {
+            return Ok(stats);
+        }
+    }
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("heap_stats")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ip <REMOTE_IP> "ip of remote device"
+            )
+            .default_value("192.168.0.123"),
+            arg!(
+                -p --period_ms <PERIOD_MS> "keep requesting with this period, 0 to only ask once"
+            )
+            .default_value("0"),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let remote_ip = matches.get_one::<String>("remote_ip").unwrap();
+    let period_ms: u64 = matches
+        .get_one::<String>("period_ms")
+        .unwrap()
+        .parse()
+        .expect("period_ms must be an integer");
+    // any local port, the board replies to the sender
+    let link = Link::bind(format!("{local_ip}:0"))?.with_remote(format!("{remote_ip}:34201"))?;
+    link.set_timeout(Some(Duration::from_secs(1)))?;
+
+    println!(
+        "{:>10} {:>10} {:>10} {:>12} {:>8} {:>12}",
+        "size", "used", "high water", "allocations", "failed", "largest free"
+    );
+    loop {
+        match request(&link) {
+            Ok(stats) => println!(
+                "{:>10} {:>10} {:>10} {:>12} {:>8} {:>12}",
+                stats.size,
+                stats.used,
+                stats.high_water,
+                stats.allocations,
+                stats.failed,
+                stats.largest_free,
+            ),
+            Err(err) if err.is_timeout() => {
+                eprintln!("no reply from {remote_ip}");
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        if period_ms == 0 {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_millis(period_ms));
+    }
+}
@@ -0,0 +1,82 @@
+/*!
+Print the log records the boards send to LOG_PORT, with the board time they were logged at
+
+```
+cargo run --bin log_viewer -- -l 192.168.0.100 --level debug
+```
+
+Records the board had to drop (its buffer filled before the network was up, or it logged faster
+than it could send) show up as gaps in the sequence numbers and are reported.
+*/
+
+use clap::{Command, arg};
+use net_common::logging::{LOG_PORT, Level, LogRecord};
+use net_common::Message;
+use net_loopback::Link;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+fn print_record(src: &SocketAddr, record: &LogRecord) {
+    println!(
+        "[{}.{:06}] {src} {:<5} {}: {}",
+        record.epoch.secs,
+        record.epoch.nanos / 1000,
+        record.level.name(),
+        record.module,
+        record.text,
+    );
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("log_viewer")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("0.0.0.0"),
+            arg!(
+                --level <LEVEL> "the most verbose level to print: error, warn, info, debug or trace"
+            )
+            .default_value("trace"),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let level_name = matches.get_one::<String>("level").unwrap();
+    let max_level = Level::from_name(level_name).expect("Invalid log level");
+
+    let link = Link::bind(format!("{local_ip}:{LOG_PORT}"))?;
+    println!("{:?}", link.socket());
+
+    // the next sequence number expected from each board
+    let mut next_sequence = HashMap::<SocketAddr, u32>::new();
+    for rx in link.messages() {
+        let (msg, src, _rx_stamp) = match rx {
+            Ok(rx) => rx,
+            Err(err) => {
+                eprintln!("{err:?}");
+                continue;
+            }
+        };
+        let Message::Log(record) = msg else {
+            eprintln!("unexpected {msg:?} from {src}");
+            continue;
+        };
+
+        if let Some(expected) = next_sequence.get(&src) {
+            let dropped = record.sequence.wrapping_sub(*expected);
+            // a lower sequence is most likely a reboot rather than billions of dropped records
+            if record.sequence < *expected {
+                println!("---- {src} restarted (sequence {})", record.sequence);
+            } else if dropped > 0 {
+                println!("---- {src} dropped {dropped} records");
+            }
+        }
+        next_sequence.insert(src, record.sequence.wrapping_add(1));
+
+        if record.level <= max_level {
+            print_record(&src, &record);
+        }
+    }
+
+    Ok(())
+}
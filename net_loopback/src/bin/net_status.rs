@@ -0,0 +1,88 @@
+/*!
+List the boards on the network and the addresses they are using, from the NetStatus they
+publish to the Topic::NET_STATUS multicast group (the boards do this even without the multicast
+feature, the address from dhcp isn't known in advance)
+
+```
+cargo run --bin net_status -- -l 192.168.0.100
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::netconfig::NetStatus;
+use net_common::{Message, Topic};
+use net_loopback::Link;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+
+fn print_status(src: &SocketAddr, status: &NetStatus) {
+    let settings = &status.settings;
+    let dns: Vec<String> = settings.dns_servers().map(|ip| ip.to_string()).collect();
+    println!(
+        "{src:<22} {:<13} {:>18} {:>15} {:>10.1} {}",
+        format!("{:?}", status.source),
+        format!(
+            "{}/{}",
+            Ipv4Addr::from(settings.address),
+            settings.prefix_len
+        ),
+        settings
+            .gateway()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "-".to_string()),
+        status.uptime_ms as f64 / 1e3,
+        dns.join(","),
+    );
+}
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("net_status")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of the local interface to join the multicast group on"
+            )
+            .default_value("0.0.0.0"),
+            arg!(
+                --all "print every status received, not only changes"
+            ),
+        ])
+        .get_matches();
+    let local_ip: Ipv4Addr = matches
+        .get_one::<String>("local_ip")
+        .unwrap()
+        .parse()
+        .expect("Invalid IPv4 address");
+    let print_all = matches.get_flag("all");
+
+    let link = Link::subscribe(local_ip, &[Topic::NET_STATUS])?;
+    println!(
+        "{:<22} {:<13} {:>18} {:>15} {:>10} dns",
+        "board", "source", "address", "gateway", "uptime s"
+    );
+
+    // the latest status of each board, keyed by where it was sent from
+    let mut boards = BTreeMap::<SocketAddr, NetStatus>::new();
+    loop {
+        match link.recv_topic() {
+            Ok((Topic::NET_STATUS, Message::NetStatus(status), src, _rx_stamp)) => {
+                let changed = boards.get(&src).is_none_or(|last| {
+                    last.source != status.source || last.settings != status.settings
+                });
+                // a reboot with the same settings
+                let rebooted = boards
+                    .get(&src)
+                    .is_some_and(|last| status.uptime_ms < last.uptime_ms);
+                if changed || rebooted || print_all {
+                    print_status(&src, &status);
+                }
+                boards.insert(src, status);
+            }
+            // other topics joined by other sockets on this computer
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("{err:?}");
+            }
+        }
+    }
+}
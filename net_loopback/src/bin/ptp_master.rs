@@ -0,0 +1,137 @@
+/*!
+PTP style master, send Sync/FollowUp to the boards and answer their DelayReq, see net_common ptp
+
+```
+cargo run --release --bin ptp_master -- -l 192.168.0.100 -r 192.168.0.123,192.168.0.124
+```
+
+Without a board run the slave side on this computer, both ends use the same clock so the
+offset is only the asymmetry of the software timestamps, tens of microseconds:
+
+```
+cargo run --release --bin ptp_master -- --slave
+cargo run --release --bin ptp_master -- -r 127.0.0.1
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::ptp::{PTP_MASTER_PORT, PtpSlave, SlaveOutput};
+use net_common::{Epoch, Message, PtpDelayResp, PtpFollowUp, PtpSync};
+use net_loopback::Link;
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("ptp_master")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ips <REMOTE_IPS> "comma separated ips of the slave boards"
+            )
+            .default_value("192.168.0.123"),
+            arg!(
+                -p --period_ms <PERIOD_MS> "milliseconds between Sync messages"
+            )
+            .default_value("1000"),
+            arg!(
+                --slave "answer Sync/FollowUp like a board would, instead of being the master"
+            ),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+
+    if matches.get_flag("slave") {
+        return slave(&format!("{local_ip}:34201"));
+    }
+
+    let remotes: Vec<String> = matches
+        .get_one::<String>("remote_ips")
+        .unwrap()
+        .split(',')
+        .map(|ip| format!("{}:34201", ip.trim()))
+        .collect();
+    let period = Duration::from_millis(
+        matches
+            .get_one::<String>("period_ms")
+            .unwrap()
+            .parse()
+            .expect("period must be an integer"),
+    );
+
+    let link = Link::bind(format!("{local_ip}:{PTP_MASTER_PORT}"))?;
+    link.set_timeout(Some(Duration::from_millis(10)))?;
+    println!("ptp master on {:?} for {remotes:?}", link.socket());
+
+    let mut sequence: u16 = 0;
+    loop {
+        let next_sync = Instant::now() + period;
+        sequence = sequence.wrapping_add(1);
+        for remote in &remotes {
+            // stamped just before the send, the time in the send call counts as path delay
+            let origin = Epoch::now();
+            link.send_to(&Message::PtpSync(PtpSync { sequence }), remote)?;
+            link.send_to(
+                &Message::PtpFollowUp(PtpFollowUp { sequence, origin }),
+                remote,
+            )?;
+        }
+
+        // answer DelayReqs until the next Sync
+        while Instant::now() < next_sync {
+            match link.recv() {
+                Ok((Message::PtpDelayReq(req), src, rx_stamp)) => {
+                    let receive = Epoch::try_from(rx_stamp).expect("time went backwards");
+                    let resp = Message::PtpDelayResp(PtpDelayResp {
+                        sequence: req.sequence,
+                        receive,
+                    });
+                    link.send_to(&resp, src)?;
+                }
+                Ok(_) => {}
+                Err(err) if err.is_timeout() => {}
+                Err(err) => {
+                    eprintln!("{err:?}");
+                }
+            }
+        }
+    }
+}
+
+/// simulate the board side, print the offset and delay of each exchange
+fn slave(local_ip_port: &str) -> Result<(), net_loopback::Error> {
+    let link = Link::bind(local_ip_port)?;
+    println!("ptp slave on {:?}", link.socket());
+    println!("{:>8} {:>12} {:>12}", "sequence", "offset us", "delay us");
+
+    let mut ptp = PtpSlave::new();
+    for rx in link.messages() {
+        let (msg, src, rx_stamp) = match rx {
+            Ok(rx) => rx,
+            Err(err) => {
+                eprintln!("{err:?}");
+                continue;
+            }
+        };
+        let local = Epoch::try_from(rx_stamp).expect("time went backwards");
+        match ptp.handle(&msg, local) {
+            Some(SlaveOutput::DelayReq(req)) => {
+                // stamped before sending, the same as the master does with Sync
+                ptp.delay_req_sent(Epoch::now());
+                link.send_to(&Message::PtpDelayReq(req), src)?;
+            }
+            Some(SlaveOutput::Measured(measurement)) => {
+                println!(
+                    "{:>8} {:>12.3} {:>12.3}",
+                    measurement.sequence,
+                    measurement.offset_ns as f64 / 1e3,
+                    measurement.delay_ns as f64 / 1e3
+                );
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
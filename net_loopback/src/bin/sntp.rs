@@ -0,0 +1,196 @@
+/*!
+Run the net_time sntp sync the boards use against ntp servers, printing each exchange and the
+disciplined offset of this computer's clock
+
+```
+cargo run --bin sntp -- -s 192.168.0.100,192.168.0.101
+```
+
+There is also a fake server that can add an offset and asymmetric delay to its replies, to
+see how the filtering and server selection cope without a board or a real server:
+
+```
+cargo run --bin sntp -- --serve --port 12300 --offset_ms 250 --jitter_ms 20
+cargo run --bin sntp -- --serve --port 12301 --offset_ms 240
+cargo run --bin sntp -- -s 127.0.0.1:12300,127.0.0.1:12301
+```
+
+Stopping the selected fake server should switch to the other after a few seconds.
+
+*/
+
+use clap::{Command, arg};
+use net_common::Epoch;
+use net_time::packet::{MODE_CLIENT, MODE_SERVER, Packet, VERSION};
+use net_time::std_impl::SystemTicks;
+use net_time::{Config, Error, TimeSync};
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::time::Duration;
+
+fn main() -> Result<(), std::io::Error> {
+    let matches = Command::new("sntp")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("0.0.0.0"),
+            arg!(
+                -s --server_ips <SERVER_IPS> "comma separated ntp server ips, each with an optional :port"
+            )
+            .default_value("192.168.0.100"),
+            arg!(
+                --port <PORT> "ntp server port, when not given with the server ip"
+            )
+            .default_value("123"),
+            arg!(
+                --poll_ms <POLL_MS> "milliseconds between requests"
+            )
+            .default_value("1000"),
+            arg!(
+                --serve "answer sntp requests instead of sending them"
+            ),
+            arg!(
+                --offset_ms <OFFSET_MS> "served time is ahead of this computer's clock by this much"
+            )
+            .default_value("0"),
+            arg!(
+                --jitter_ms <JITTER_MS> "served replies are delayed by up to this much"
+            )
+            .default_value("0"),
+        ])
+        .get_matches();
+    let local_ip: Ipv4Addr = matches
+        .get_one::<String>("local_ip")
+        .unwrap()
+        .parse()
+        .expect("local ip must be ipv4");
+    let port: u16 = matches
+        .get_one::<String>("port")
+        .unwrap()
+        .parse()
+        .expect("port must be an integer");
+    let parse_ms = |name: &str| -> i64 {
+        matches
+            .get_one::<String>(name)
+            .unwrap()
+            .parse()
+            .unwrap_or_else(|_| panic!("{name} must be an integer"))
+    };
+
+    if matches.get_flag("serve") {
+        return serve(
+            SocketAddrV4::new(local_ip, port),
+            parse_ms("offset_ms"),
+            parse_ms("jitter_ms"),
+        );
+    }
+
+    let servers: Vec<SocketAddrV4> = matches
+        .get_one::<String>("server_ips")
+        .unwrap()
+        .split(',')
+        .map(|server| {
+            let server = server.trim();
+            server.parse().unwrap_or_else(|_| {
+                let ip: Ipv4Addr = server.parse().expect("server ip must be ipv4");
+                SocketAddrV4::new(ip, port)
+            })
+        })
+        .collect();
+    let mut socket = UdpSocket::bind(SocketAddrV4::new(local_ip, 0))?;
+    socket.set_read_timeout(Some(Duration::from_millis(1)))?;
+
+    let config = Config {
+        poll_interval: Duration::from_millis(parse_ms("poll_ms") as u64),
+        ..Config::DEFAULT
+    };
+    let mut sync = TimeSync::new(SystemTicks, &servers, config);
+    println!("syncing with {servers:?} from {:?}", socket.local_addr()?);
+    println!(
+        "{:>22} {:>14} {:>10} {:>14} {:>12}",
+        "server", "offset ms", "rtt ms", "filtered ms", "freq ppm"
+    );
+    loop {
+        match sync.poll(&mut socket) {
+            Ok(Some(result)) => {
+                let local = result.sample.local;
+                // the offset the discipline is converging on, and has actually applied
+                let corrected = sync.corrected(local);
+                let applied_ms =
+                    corrected.signed_nanos_since(&local).unwrap_or(i64::MAX) as f64 / 1e6;
+                let (_, freq_ppb) = sync.discipline().estimate(local).unwrap_or_default();
+                // * marks the selected server like ntpq
+                let server = format!(
+                    "{}{}",
+                    if result.selected { "*" } else { " " },
+                    result.server
+                );
+                println!(
+                    "{server:>22} {:>14.3} {:>10.3} {:>14.3} {:>12.3}",
+                    result.sample.offset_ns as f64 / 1e6,
+                    result.sample.rtt_ns as f64 / 1e6,
+                    applied_ms,
+                    freq_ppb as f64 / 1e3,
+                );
+            }
+            Ok(None) => {}
+            Err(Error::Timeout(server)) => {
+                let peer = sync.peers().iter().find(|peer| peer.addr() == server).unwrap();
+                eprintln!(
+                    "no reply from {server:?}, {} failures, reach {:03o}",
+                    peer.failures(),
+                    peer.reach()
+                );
+                if sync.is_holdover() {
+                    eprintln!("no servers reachable, holding over");
+                }
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+            }
+        }
+    }
+}
+
+/// a minimal sntp server, stratum 1 from the system clock plus offset_ms
+fn serve(local: SocketAddrV4, offset_ms: i64, jitter_ms: i64) -> Result<(), std::io::Error> {
+    let socket = UdpSocket::bind(local)?;
+    println!("serving sntp on {local:?}, {offset_ms}ms offset, up to {jitter_ms}ms delay");
+    let now = || Epoch::now().saturating_add_signed_nanos(offset_ms * 1_000_000);
+
+    // no rand dependency, xorshift is plenty for jitter
+    let mut state = Epoch::now().nanos as u64 | 1;
+    let mut buf = [0; 1024];
+    loop {
+        let (num, src) = socket.recv_from(&mut buf)?;
+        let receive = now();
+        let Some(request) = Packet::from_bytes(&buf[..num]) else {
+            continue;
+        };
+        if request.mode != MODE_CLIENT {
+            continue;
+        }
+
+        let transmit = now();
+        let reply = Packet {
+            version: VERSION,
+            mode: MODE_SERVER,
+            stratum: 1,
+            reference_id: u32::from_be_bytes(*b"LOCL"),
+            reference: receive.to_ntp(),
+            originate: request.transmit,
+            receive: receive.to_ntp(),
+            transmit: transmit.to_ntp(),
+            ..Default::default()
+        };
+        // delaying the reply after stamping it makes the path asymmetric, which is what
+        // throws the offset off
+        if jitter_ms > 0 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            std::thread::sleep(Duration::from_micros(state % (jitter_ms as u64 * 1000)));
+        }
+        socket.send_to(&reply.to_bytes(), src)?;
+    }
+}
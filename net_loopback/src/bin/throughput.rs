@@ -0,0 +1,240 @@
+/*!
+Measure how much data the link to a board can sustain: stream BenchData packets of a given size
+at a series of offered rates, the board echoes them back and the achieved throughput, loss and
+latency under load are reported for each rate
+
+```
+cargo run --release --bin throughput -- -l 192.168.0.100 -r 192.168.0.123 --size 200 --rates 100,1000,4000
+```
+
+Without a board run the echo side on this computer:
+
+```
+cargo run --release --bin throughput -- --echo
+cargo run --release --bin throughput -- -r 127.0.0.1
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::{BENCH_MAX_DATA, BenchData, Epoch, Message};
+use net_loopback::{CRC, Link};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("throughput")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of local computer running this program"
+            )
+            .default_value("127.0.0.1"),
+            arg!(
+                -r --remote_ip <REMOTE_IP> "ip of remote device"
+            )
+            .default_value("192.168.0.123"),
+            arg!(
+                -s --size <SIZE> "bytes of filler data in each packet"
+            )
+            .default_value("200"),
+            arg!(
+                --rates <RATES> "comma separated offered loads in packets per second"
+            )
+            .default_value("100,1000,4000"),
+            arg!(
+                -d --duration <SECONDS> "how long to send at each rate"
+            )
+            .default_value("5"),
+            arg!(
+                --echo "echo back BenchData packets like a board would, instead of sending"
+            ),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+
+    if matches.get_flag("echo") {
+        return echo(&format!("{local_ip}:34201"));
+    }
+
+    let size: usize = matches
+        .get_one::<String>("size")
+        .unwrap()
+        .parse()
+        .expect("size must be an integer");
+    if size > BENCH_MAX_DATA {
+        eprintln!("size {size} is larger than {BENCH_MAX_DATA}");
+        std::process::exit(1);
+    }
+    let rates: Vec<f64> = matches
+        .get_one::<String>("rates")
+        .unwrap()
+        .split(',')
+        .map(|rate| rate.trim().parse().expect("rates must be numbers"))
+        .collect();
+    let duration = Duration::from_secs_f64(
+        matches
+            .get_one::<String>("duration")
+            .unwrap()
+            .parse()
+            .expect("duration must be a number"),
+    );
+
+    let remote_ip = matches.get_one::<String>("remote_ip").unwrap();
+    let remote_ip_port = format!("{remote_ip}:34201");
+    let link = Link::bind(format!("{local_ip}:34200"))?.with_remote(&remote_ip_port)?;
+    link.set_timeout(Some(Duration::from_millis(100)))?;
+    println!(
+        "sending {size} byte payloads from {:?} to {remote_ip_port:?}",
+        link.socket()
+    );
+
+    println!(
+        "{:>10} {:>8} {:>8} {:>7} {:>12} {:>12} {:>9} {:>9} {:>9}",
+        "offered/s",
+        "sent",
+        "rcvd",
+        "loss%",
+        "offered Mb/s",
+        "echoed Mb/s",
+        "min ms",
+        "mean ms",
+        "max ms"
+    );
+    let mut sequence = 0;
+    for rate in rates {
+        let stats = run(&link, size, rate, duration, sequence)?;
+        sequence += stats.sent;
+        let bits = |packets: u64| (packets * stats.packet_bytes as u64 * 8) as f64;
+        println!(
+            "{rate:>10.0} {:>8} {:>8} {:>7.2} {:>12.3} {:>12.3} {:>9.3} {:>9.3} {:>9.3}",
+            stats.sent,
+            stats.latencies.len(),
+            100.0 * (1.0 - stats.latencies.len() as f64 / stats.sent.max(1) as f64),
+            bits(stats.sent) / duration.as_secs_f64() / 1e6,
+            bits(stats.latencies.len() as u64) / duration.as_secs_f64() / 1e6,
+            stats.latencies.iter().cloned().fold(f64::NAN, f64::min) * 1e3,
+            stats.latencies.iter().sum::<f64>() / stats.latencies.len() as f64 * 1e3,
+            stats.latencies.iter().cloned().fold(f64::NAN, f64::max) * 1e3,
+        );
+    }
+
+    Ok(())
+}
+
+struct RunStats {
+    sent: u64,
+    /// size of each encoded packet on the wire, not counting udp/ip/ethernet headers
+    packet_bytes: usize,
+    /// round trip seconds of every echoed packet
+    latencies: Vec<f64>,
+}
+
+/// send at the given packets per second for the duration, while another thread collects
+/// the echoes
+fn run(
+    link: &Link,
+    size: usize,
+    rate: f64,
+    duration: Duration,
+    first_sequence: u64,
+) -> Result<RunStats, net_loopback::Error> {
+    let done = Arc::new(AtomicBool::new(false));
+    let rx_link = link.try_clone()?;
+    let rx_done = done.clone();
+    let receiver = std::thread::spawn(move || {
+        let mut latencies = Vec::new();
+        while !rx_done.load(Ordering::Relaxed) {
+            match rx_link.recv() {
+                // ignore stragglers from the previous rate
+                Ok((Message::Bench(bench), _src, rx_stamp)) if bench.sequence >= first_sequence => {
+                    let rx_stamp = Epoch::try_from(rx_stamp).expect("time went backwards");
+                    // the echoing board clock isn't involved, both stamps are from this computer
+                    if let Some(latency) = rx_stamp.duration_since(&bench.epoch) {
+                        latencies.push(latency.as_secs_f64());
+                    }
+                }
+                Ok(_) => {}
+                Err(err) if err.is_timeout() => {}
+                Err(err) => {
+                    eprintln!("{err:?}");
+                }
+            }
+        }
+        latencies
+    });
+
+    let mut bench = BenchData::default();
+    bench.data.resize(size, 0xA5).unwrap();
+
+    let period = Duration::from_secs_f64(1.0 / rate);
+    let start = Instant::now();
+    let mut sent = 0;
+    let mut packet_bytes = 0;
+    while start.elapsed() < duration {
+        let next_send = start + period * sent as u32;
+        let now = Instant::now();
+        if next_send > now {
+            std::thread::sleep(next_send - now);
+        }
+
+        bench.sequence = first_sequence + sent;
+        bench.epoch = Epoch::now();
+        let msg_bytes = match net_loopback::encode(&Message::Bench(bench.clone()), CRC.digest()) {
+            Ok(msg_bytes) => msg_bytes,
+            Err(err) => {
+                eprintln!("{err:?}");
+                continue;
+            }
+        };
+        packet_bytes = msg_bytes.len();
+        if let Err(err) = link.socket().send_to(&msg_bytes, link.remote().unwrap()) {
+            // the kernel buffer filling up counts as loss
+            eprintln!("send error {err:?}");
+        }
+        sent += 1;
+    }
+
+    // give the last echoes time to arrive
+    std::thread::sleep(Duration::from_millis(500));
+    done.store(true, Ordering::Relaxed);
+    let latencies = receiver.join().expect("receiver thread panicked");
+
+    Ok(RunStats {
+        sent,
+        packet_bytes,
+        latencies,
+    })
+}
+
+/// simulate the board side, send any BenchData straight back to the sender and count them
+fn echo(local_ip_port: &str) -> Result<(), net_loopback::Error> {
+    let link = Link::bind(local_ip_port)?;
+    let socket = link.socket();
+    println!("echoing BenchData received on {socket:?}");
+
+    let mut buf = [0; 2048];
+    let mut count = 0;
+    let mut bytes = 0;
+    let mut last_report = Instant::now();
+    loop {
+        let (num_bytes, src) = socket.recv_from(&mut buf)?;
+        // no need to decode, only check the header
+        if Message::matches_header(&buf[..num_bytes], Message::BENCH) {
+            socket.send_to(&buf[..num_bytes], src)?;
+            count += 1;
+            bytes += num_bytes;
+        }
+
+        let elapsed = last_report.elapsed();
+        if elapsed > Duration::from_secs(1) {
+            println!(
+                "echoed {count} packets, {:.3} Mbit/s",
+                (bytes * 8) as f64 / elapsed.as_secs_f64() / 1e6
+            );
+            count = 0;
+            bytes = 0;
+            last_report = Instant::now();
+        }
+    }
+}
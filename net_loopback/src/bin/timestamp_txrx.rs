@@ -1,6 +1,7 @@
 /*!
-Send timestamp message to a receiver which will fill it's own timestamp field and return
-the message as quickly as possible, then measure the elapsed time here
+Ping a board with EchoRequests, it replies to this computer with its own receive and transmit
+timestamps, then measure the round trip and the offset of the board clock from this one here.
+Each instance uses its own port so several can ping the same board.
 
 receive on command line:
 
@@ -17,11 +18,10 @@ netcat -ul 34201 | hexdump -C
 */
 
 use clap::{Command, arg};
-use net_common::{Epoch, Message, TimeStamp};
-use std::net::UdpSocket;
+use net_loopback::{Link, RoundTripStats};
 use std::time::Duration;
 
-fn main() -> std::io::Result<()> {
+fn main() -> Result<(), net_loopback::Error> {
     let matches = Command::new("timestamp_txrx")
         .args(&[
             arg!(
@@ -35,23 +35,23 @@ fn main() -> std::io::Result<()> {
         ])
         .get_matches();
     let local_ip = matches.get_one::<String>("local_ip").unwrap();
-    let local_ip_port = format!("{local_ip}:34200");
+    // any port, the board replies to the sender
+    let local_ip_port = format!("{local_ip}:0");
     println!(
         "ip and port of this device {local_ip_port:?} (note 127.0.0.1 may not work with remote device)"
     );
-    let socket = UdpSocket::bind(local_ip_port)?;
-    // 1 second timeout on receiving
-    let recv_timeout = Duration::new(1, 0);
-    let result = socket.set_read_timeout(Some(recv_timeout));
-    println!("set socket {socket:?} recv timeout to {recv_timeout:?}, {result:?}");
-
     let remote_ip = matches.get_one::<String>("remote_ip").unwrap();
     let remote_ip_port = format!("{remote_ip}:34201");
-    println!("this socket is {socket:?}");
+    let link = Link::bind(local_ip_port)?.with_remote(&remote_ip_port)?;
+    // 1 second timeout on receiving
+    let recv_timeout = Duration::new(1, 0);
+    let result = link.set_timeout(Some(recv_timeout));
+    println!(
+        "set socket {:?} recv timeout to {recv_timeout:?}, {result:?}",
+        link.socket()
+    );
     println!("sending to {remote_ip_port:?}");
 
-    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
-
     let mut counter = 0;
     /*
     let mut array = SmallArray::default();
@@ -61,73 +61,39 @@ fn main() -> std::io::Result<()> {
 
     let delay_ms = 500;
     let accum_num = 1000 / delay_ms;
-    let mut elapsed_accum = 0.0;
+    let mut stats = RoundTripStats::default();
 
     loop {
         std::thread::sleep(std::time::Duration::from_millis(delay_ms));
-        let tx_stamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .expect("time went backwards");
-        // if let Message::TimeStamp(ref mut data) = tx_timestamp
-        let tx_timestamp = Message::TimeStamp(TimeStamp {
-            epoch: Epoch {
-                secs: tx_stamp.as_secs(),
-                nanos: tx_stamp.subsec_nanos(),
-            },
-            counter,
-            tick_ms: 0,
-            ..Default::default()
-        });
+        let rv = link.ping(counter);
         counter += 1;
-
-        let msg_bytes = {
-            match net_loopback::encode(&tx_timestamp, crc.digest()) {
-                Ok(msg_bytes) => msg_bytes,
-                Err(err) => {
-                    eprintln!("{err:?}");
-                    continue;
-                }
-            }
-        };
-        let tx_rv = socket.send_to(&msg_bytes, &remote_ip_port);
-        // println!("sent {data:?} encoded as {msg_bytes:X?}, rv {rv:?}");
-
-        let mut rx_buffer = [0; 256];
-        match socket.recv(&mut rx_buffer) {
-            Ok(num_bytes) => {
-                if num_bytes > 0 {
-                    let rx_stamp = std::time::SystemTime::now()
+        match rv {
+            Ok(ping) => {
+                let elapsed = ping.roundtrip();
+                stats.add(elapsed);
+                if counter % accum_num == 0 {
+                    let rx_stamp = ping
+                        .rx_stamp
                         .duration_since(std::time::UNIX_EPOCH)
                         .expect("time went backwards");
-                    match Message::decode(&rx_buffer[..num_bytes], crc.digest()) {
-                        Ok(Message::TimeStamp(data)) => {
-                            let elapsed = (rx_stamp - tx_stamp).as_secs_f64();
-                            elapsed_accum += elapsed;
-                            if counter % accum_num == 0 {
-                                println!(
-                                    "[{rx_stamp:.03?}], elapsed avg {:.2}ms, cur {:.3}ms, received data {data:?}, (sent {tx_timestamp:?})",
-                                    (elapsed_accum / accum_num as f64) * 1e3,
-                                    elapsed * 1e3
-                                );
-                                elapsed_accum = 0.0;
-                            }
-                        }
-                        Ok(Message::Array(array)) => {
-                            eprintln!("unexpected response {array:?}, tx rv was {tx_rv:?}");
-                        }
-                        Ok(Message::Error(err)) => {
-                            eprintln!("unexpected response {err:?}, tx rv was {tx_rv:?}");
-                        }
-                        Err(err) => {
-                            eprintln!("error {err:?}, tx rv was {tx_rv:?}");
-                        }
-                    }
-                } else {
-                    eprintln!("didn't receive anything");
+                    let measurement = ping.measure().unwrap_or_default();
+                    println!(
+                        "[{rx_stamp:.03?}], elapsed avg {:.2}ms, cur {:.3}ms, board offset \
+                         {:.3}ms, turnaround {:.3}ms, counter {}",
+                        stats.mean().unwrap_or_default().as_secs_f64() * 1e3,
+                        elapsed.as_secs_f64() * 1e3,
+                        measurement.offset_ns as f64 / 1e6,
+                        measurement.turnaround_ns as f64 / 1e6,
+                        ping.reply.counter,
+                    );
+                    stats.reset();
                 }
             }
+            Err(err) if err.is_timeout() => {
+                eprintln!("didn't receive anything");
+            }
             Err(err) => {
-                eprintln!("recv err {err:?}, tx rv was {tx_rv:?}");
+                eprintln!("error {err:?}");
             }
         }
 
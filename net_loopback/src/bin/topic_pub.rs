@@ -0,0 +1,71 @@
+/*!
+Publish TimeStamp messages to a topic multicast group the same way the boards do, to try out
+topic_sub without a board attached
+
+```
+cargo run --bin topic_pub -- --topic 1
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::pubsub::MULTICAST_PORT;
+use net_common::{Epoch, Message, TimeStamp, Topic};
+use net_loopback::Link;
+use std::net::Ipv4Addr;
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("topic_pub")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of the local interface to publish from"
+            )
+            .default_value("0.0.0.0"),
+            arg!(
+                -t --topic <TOPIC> "topic id to publish on"
+            )
+            .default_value("1"),
+            arg!(
+                -d --delay_ms <DELAY_MS> "milliseconds between messages"
+            )
+            .default_value("500"),
+        ])
+        .get_matches();
+    let local_ip = matches.get_one::<String>("local_ip").unwrap();
+    let topic = Topic(
+        matches
+            .get_one::<String>("topic")
+            .unwrap()
+            .parse()
+            .expect("topic id is 1-255"),
+    );
+    let delay_ms: u64 = matches
+        .get_one::<String>("delay_ms")
+        .unwrap()
+        .parse()
+        .expect("delay must be an integer");
+
+    let link = Link::bind(format!("{local_ip}:0"))?;
+    // loop back to any subscribers on this computer
+    link.socket().set_multicast_loop_v4(true)?;
+    let group = Ipv4Addr::from(topic.multicast_group());
+    println!(
+        "publishing topic {topic:?} to {group}:{MULTICAST_PORT} from {:?}",
+        link.socket()
+    );
+
+    let mut counter = 0;
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(delay_ms));
+        let msg = Message::TimeStamp(TimeStamp {
+            epoch: Epoch::now(),
+            counter,
+            ..Default::default()
+        });
+        counter += 1;
+
+        if let Err(err) = link.publish(&msg, topic) {
+            eprintln!("{err:?}");
+        }
+    }
+}
@@ -0,0 +1,66 @@
+/*!
+Subscribe to topics published to multicast groups by the boards (or topic_pub), any number
+of these can run at once on the same computer and all will receive every message
+
+```
+cargo run --bin topic_sub -- --topics 1,2
+```
+
+*/
+
+use clap::{Command, arg};
+use net_common::Topic;
+use net_common::pubsub::MULTICAST_PORT;
+use net_loopback::Link;
+use std::net::Ipv4Addr;
+
+fn main() -> Result<(), net_loopback::Error> {
+    let matches = Command::new("topic_sub")
+        .args(&[
+            arg!(
+                -l --local_ip <LOCAL_IP> "ip of the local interface to join the multicast groups on"
+            )
+            .default_value("0.0.0.0"),
+            arg!(
+                -t --topics <TOPICS> "comma separated topic ids to subscribe to"
+            )
+            .default_value("1,2"),
+        ])
+        .get_matches();
+    let local_ip: Ipv4Addr = matches
+        .get_one::<String>("local_ip")
+        .unwrap()
+        .parse()
+        .expect("Invalid IPv4 address");
+    let topics: Vec<Topic> = matches
+        .get_one::<String>("topics")
+        .unwrap()
+        .split(',')
+        .map(|id| Topic(id.trim().parse().expect("topic ids are 1-255")))
+        .collect();
+
+    let link = Link::subscribe(local_ip, &topics)?;
+    for topic in &topics {
+        let group = Ipv4Addr::from(topic.multicast_group());
+        println!("subscribed to topic {topic:?} on {group}:{MULTICAST_PORT} via {local_ip}");
+    }
+
+    loop {
+        match link.recv_topic() {
+            Ok((topic, msg, src, rx_stamp)) => {
+                // other groups joined by other sockets on this computer on the same port
+                // may be delivered here too
+                if !topics.contains(&topic) {
+                    continue;
+                }
+                let rx_stamp = rx_stamp
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .expect("time went backwards");
+                println!("[{rx_stamp:.3?}] {topic:?} from {src:?}: {msg:?}");
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,429 @@
+/*!
+Generate a Lua Wireshark dissector for net_common messages from the Rust types, so captures of
+the board traffic show decoded TimeStamp/SmallArray/etc. fields instead of opaque bytes
+
+Copy the output of the `dissector` binary into the wireshark personal plugins directory
+(Help > About > Folders), e.g. `~/.local/lib/wireshark/plugins/net_common.lua`
+*/
+
+use crate::schema::{self, Kind};
+use net_common::logging::LOG_PORT;
+use net_common::ptp::PTP_MASTER_PORT;
+use net_common::pubsub::MULTICAST_PORT;
+use net_common::{Message, Topic};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// one sample of every message type, new Message variants need to be added here as well as
+/// to payload_kind(), the tests check every type byte net_common decodes has one
+pub(crate) fn samples() -> Vec<Message> {
+    vec![
+        Message::TimeStamp(Default::default()),
+        Message::Array(Default::default()),
+        Message::Bench(Default::default()),
+        Message::ConfigGet(Default::default()),
+        Message::ConfigSet(Default::default()),
+        Message::ConfigValue(Default::default()),
+        Message::PtpSync(Default::default()),
+        Message::PtpFollowUp(Default::default()),
+        Message::PtpDelayReq(Default::default()),
+        Message::PtpDelayResp(Default::default()),
+        Message::NetStatus(Default::default()),
+        Message::Log(Default::default()),
+        Message::HeapStatsGet(Default::default()),
+        Message::HeapStats(Default::default()),
+        Message::EchoRequest(Default::default()),
+        Message::EchoReply(Default::default()),
+        Message::BoardInfoGet(Default::default()),
+        Message::BoardInfo(Default::default()),
+        Message::FirmwareBegin(Default::default()),
+        Message::FirmwareChunk(Default::default()),
+        Message::FirmwareVerify(Default::default()),
+        Message::FirmwareCommit(Default::default()),
+        Message::FirmwareStatus(Default::default()),
+        Message::HealthGet(Default::default()),
+        Message::Health(Default::default()),
+    ]
+}
+
+/// the name and wire layout of the message payload
+fn payload_kind(msg: &Message) -> Result<Option<(&'static str, Kind)>, schema::Error> {
+    let name_kind = match msg {
+        Message::TimeStamp(data) => ("TimeStamp", schema::record(data)?),
+        Message::Array(small_array) => ("SmallArray", schema::record(small_array)?),
+        Message::Bench(bench) => ("BenchData", schema::record(bench)?),
+        Message::ConfigGet(get) => ("ConfigGet", schema::record(get)?),
+        Message::ConfigSet(set) => ("ConfigSet", schema::record(set)?),
+        Message::ConfigValue(value) => ("ConfigValue", schema::record(value)?),
+        Message::PtpSync(sync) => ("PtpSync", schema::record(sync)?),
+        Message::PtpFollowUp(follow_up) => ("PtpFollowUp", schema::record(follow_up)?),
+        Message::PtpDelayReq(req) => ("PtpDelayReq", schema::record(req)?),
+        Message::PtpDelayResp(resp) => ("PtpDelayResp", schema::record(resp)?),
+        Message::NetStatus(status) => ("NetStatus", schema::record(status)?),
+        Message::Log(record) => ("LogRecord", schema::record(record)?),
+        Message::HeapStatsGet(get) => ("HeapStatsGet", schema::record(get)?),
+        Message::HeapStats(stats) => ("HeapStats", schema::record(stats)?),
+        Message::EchoRequest(request) => ("EchoRequest", schema::record(request)?),
+        Message::EchoReply(reply) => ("EchoReply", schema::record(reply)?),
+        Message::BoardInfoGet(board_info_get) => ("BoardInfoGet", schema::record(board_info_get)?),
+        Message::BoardInfo(board_info) => ("BoardInfo", schema::record(board_info)?),
+        Message::FirmwareBegin(begin) => ("FirmwareBegin", schema::record(begin)?),
+        Message::FirmwareChunk(chunk) => ("FirmwareChunk", schema::record(chunk)?),
+        Message::FirmwareVerify(verify) => ("FirmwareVerify", schema::record(verify)?),
+        Message::FirmwareCommit(commit) => ("FirmwareCommit", schema::record(commit)?),
+        Message::FirmwareStatus(status) => ("FirmwareStatus", schema::record(status)?),
+        Message::HealthGet(get) => ("HealthGet", schema::record(get)?),
+        Message::Health(health) => ("Health", schema::record(health)?),
+        Message::Error(()) => {
+            return Ok(None);
+        }
+    };
+    Ok(Some(name_kind))
+}
+
+/// the ports the boards and host tools use
+const PORTS: [u16; 5] = [34200, 34201, MULTICAST_PORT, PTP_MASTER_PORT, LOG_PORT];
+
+const PREAMBLE: &str = r#"
+local net_common = Proto("net_common", "net_common postcard message")
+
+local bad_crc = ProtoExpert.new("net_common.bad_crc", "CRC32-ISCSI mismatch",
+    expert.group.CHECKSUM, expert.severity.ERROR)
+local malformed = ProtoExpert.new("net_common.malformed", "Payload truncated or malformed",
+    expert.group.MALFORMED, expert.severity.ERROR)
+net_common.experts = { bad_crc, malformed }
+
+-- postcard varint, little endian base 128, returns nil if it runs off the end
+local function read_varint(tvb, offset)
+    local value = UInt64(0)
+    local shift = 0
+    local len = 0
+    while offset + len < tvb:len() and len < 10 do
+        local byte = tvb(offset + len, 1):uint()
+        value = value + UInt64(bit.band(byte, 0x7f)):lshift(shift)
+        len = len + 1
+        shift = shift + 7
+        if byte < 0x80 then
+            return value, len
+        end
+    end
+    return nil, len
+end
+
+local function zigzag(value)
+    local magnitude = value:rshift(1)
+    local signed = Int64.new(magnitude:lower(), magnitude:higher())
+    if value:band(1):tonumber() == 1 then
+        return -signed - 1
+    end
+    return signed
+end
+
+local function has_bytes(tvb, offset, len)
+    return offset + len <= tvb:len()
+end
+
+-- CRC-32/ISCSI (castagnoli), what postcard appends little endian after the payload
+local crc_table = {}
+for i = 0, 255 do
+    local crc = i
+    for _ = 1, 8 do
+        if bit.band(crc, 1) == 1 then
+            crc = bit.bxor(bit.rshift(crc, 1), 0x82F63B78)
+        else
+            crc = bit.rshift(crc, 1)
+        end
+    end
+    crc_table[i] = crc
+end
+
+local function crc32c(tvb, offset, len)
+    local crc = 0xFFFFFFFF
+    for i = offset, offset + len - 1 do
+        local byte = tvb(i, 1):uint()
+        crc = bit.bxor(bit.rshift(crc, 8), crc_table[bit.band(bit.bxor(crc, byte), 0xFF)])
+    end
+    crc = bit.bxor(crc, 0xFFFFFFFF)
+    if crc < 0 then
+        crc = crc + 4294967296
+    end
+    return crc
+end
+"#;
+
+const DISSECTOR: &str = r#"
+function net_common.dissector(tvb, pinfo, tree)
+    if tvb:len() < 4 or tvb(0, 1):uint() ~= MAGIC0 or tvb(1, 1):uint() ~= MAGIC1 then
+        return 0
+    end
+    pinfo.cols.protocol = "net_common"
+
+    local subtree = tree:add(net_common, tvb(), "net_common")
+    subtree:add(fields.magic, tvb(0, 2))
+    subtree:add(fields.topic, tvb(2, 1))
+    subtree:add(fields.type, tvb(3, 1))
+
+    local topic = tvb(2, 1):uint()
+    local msg_type = tvb(3, 1):uint()
+    local name = message_types[msg_type] or string.format("unknown type 0x%02x", msg_type)
+    if topic ~= 0 then
+        pinfo.cols.info = string.format("%s (topic %d)", name, topic)
+    else
+        pinfo.cols.info = name
+    end
+
+    local dissect = dissectors[msg_type]
+    if dissect == nil then
+        return tvb:len()
+    end
+
+    local payload_tree = subtree:add(net_common, tvb(4), name)
+    local offset = dissect(tvb, payload_tree, 4)
+    if offset == nil or not has_bytes(tvb, offset, 4) then
+        payload_tree:add_proto_expert_info(malformed)
+        return tvb:len()
+    end
+
+    local crc_item = subtree:add_le(fields.crc, tvb(offset, 4))
+    if crc32c(tvb, 4, offset - 4) ~= tvb(offset, 4):le_uint() then
+        crc_item:add_proto_expert_info(bad_crc)
+    end
+    return offset + 4
+end
+"#;
+
+/// lua ProtoField constructor for a leaf
+fn proto_field(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::Bool | Kind::U8 => "ProtoField.uint8",
+        Kind::I8 => "ProtoField.int8",
+        Kind::Unsigned(_) => "ProtoField.uint64",
+        Kind::Enum(..) => "ProtoField.uint32",
+        Kind::Signed(_) => "ProtoField.int64",
+        Kind::F32 => "ProtoField.float",
+        Kind::F64 => "ProtoField.double",
+        Kind::Bytes(_) | Kind::VarBytes => "ProtoField.bytes",
+        Kind::Struct(_) | Kind::Tuple(_) | Kind::Unit => unreachable!("not a leaf"),
+    }
+}
+
+/// lua statements that add one leaf to `tree` at `offset` and advance it
+fn dissect_leaf(out: &mut String, field: &str, kind: &Kind) -> std::fmt::Result {
+    let fixed = |out: &mut String, size: usize, add: &str| {
+        writeln!(
+            out,
+            "    if not has_bytes(tvb, offset, {size}) then return nil end"
+        )?;
+        writeln!(
+            out,
+            "    tree:{add}(fields[\"{field}\"], tvb(offset, {size}))"
+        )?;
+        writeln!(out, "    offset = offset + {size}")
+    };
+    match kind {
+        Kind::Bool | Kind::U8 | Kind::I8 => fixed(out, 1, "add"),
+        Kind::F32 => fixed(out, 4, "add_le"),
+        Kind::F64 => fixed(out, 8, "add_le"),
+        Kind::Bytes(size) => fixed(out, *size, "add"),
+        Kind::Unsigned(_) | Kind::Signed(_) | Kind::Enum(..) => {
+            let value = match kind {
+                Kind::Signed(_) => "zigzag(value)",
+                Kind::Enum(..) => "value:tonumber()",
+                _ => "value",
+            };
+            writeln!(out, "    value, len = read_varint(tvb, offset)")?;
+            writeln!(out, "    if value == nil then return nil end")?;
+            writeln!(
+                out,
+                "    tree:add(fields[\"{field}\"], tvb(offset, len), {value})"
+            )?;
+            writeln!(out, "    offset = offset + len")
+        }
+        Kind::VarBytes => {
+            writeln!(out, "    value, len = read_varint(tvb, offset)")?;
+            writeln!(out, "    if value == nil then return nil end")?;
+            writeln!(out, "    offset = offset + len")?;
+            writeln!(out, "    len = value:tonumber()")?;
+            writeln!(
+                out,
+                "    if not has_bytes(tvb, offset, len) then return nil end"
+            )?;
+            writeln!(
+                out,
+                "    if len > 0 then tree:add(fields[\"{field}\"], tvb(offset, len)) end"
+            )?;
+            writeln!(out, "    offset = offset + len")
+        }
+        Kind::Struct(_) | Kind::Tuple(_) | Kind::Unit => unreachable!("not a leaf"),
+    }
+}
+
+fn generate_fmt(out: &mut String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut types = Vec::new();
+    for msg in samples() {
+        let Some((name, kind)) = payload_kind(&msg)? else {
+            continue;
+        };
+        // the type is the last byte of the header
+        let header = msg
+            .header(Topic::NONE)
+            .expect("only Message::Error has no header");
+        types.push((header[3], name, kind.leaves("")));
+    }
+
+    writeln!(
+        out,
+        "-- net_common wireshark dissector, generated by `cargo run --bin dissector` in"
+    )?;
+    writeln!(
+        out,
+        "-- net_loopback from the net_common message types, regenerate rather than edit"
+    )?;
+    out.push_str(PREAMBLE);
+
+    writeln!(out)?;
+    writeln!(out, "local MAGIC0 = 0x{:02x}", Message::MAGIC[0])?;
+    writeln!(out, "local MAGIC1 = 0x{:02x}", Message::MAGIC[1])?;
+
+    writeln!(out)?;
+    writeln!(out, "local message_types = {{")?;
+    for (msg_type, name, _) in &types {
+        writeln!(out, "    [0x{msg_type:02x}] = \"{name}\",")?;
+    }
+    writeln!(out, "}}")?;
+
+    // variant names of the enums, by name since several messages share them
+    let mut enums = BTreeMap::new();
+    for (_, _, leaves) in &types {
+        for (_, kind) in leaves {
+            if let Kind::Enum(name, variants) = kind {
+                enums.insert(*name, *variants);
+            }
+        }
+    }
+    for (name, variants) in &enums {
+        writeln!(out)?;
+        writeln!(out, "local {name}_values = {{")?;
+        for (i, variant) in variants.iter().enumerate() {
+            writeln!(out, "    [{i}] = \"{variant}\",")?;
+        }
+        writeln!(out, "}}")?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "local fields = {{")?;
+    writeln!(
+        out,
+        "    magic = ProtoField.bytes(\"net_common.magic\", \"Magic\"),"
+    )?;
+    writeln!(
+        out,
+        "    topic = ProtoField.uint8(\"net_common.topic\", \"Topic\"),"
+    )?;
+    writeln!(
+        out,
+        "    type = ProtoField.uint8(\"net_common.type\", \"Type\", base.HEX, message_types),"
+    )?;
+    writeln!(
+        out,
+        "    crc = ProtoField.uint32(\"net_common.crc\", \"CRC32-ISCSI\", base.HEX),"
+    )?;
+    for (_, name, leaves) in &types {
+        for (path, kind) in leaves {
+            let filter = format!("net_common.{}.{path}", name.to_lowercase());
+            let values = match kind {
+                Kind::Enum(name, _) => format!(", base.DEC, {name}_values"),
+                _ => String::new(),
+            };
+            writeln!(
+                out,
+                "    [\"{name}.{path}\"] = {}(\"{filter}\", \"{path}\"{values}),",
+                proto_field(kind)
+            )?;
+        }
+    }
+    writeln!(out, "}}")?;
+    writeln!(out, "local field_list = {{}}")?;
+    writeln!(out, "for _, field in pairs(fields) do")?;
+    writeln!(out, "    table.insert(field_list, field)")?;
+    writeln!(out, "end")?;
+    writeln!(out, "net_common.fields = field_list")?;
+
+    for (_, name, leaves) in &types {
+        writeln!(out)?;
+        writeln!(out, "local function dissect_{name}(tvb, tree, offset)")?;
+        writeln!(out, "    local value, len")?;
+        for (path, kind) in leaves {
+            dissect_leaf(out, &format!("{name}.{path}"), kind)?;
+        }
+        writeln!(out, "    return offset")?;
+        writeln!(out, "end")?;
+    }
+
+    writeln!(out)?;
+    writeln!(out, "local dissectors = {{")?;
+    for (msg_type, name, _) in &types {
+        writeln!(out, "    [0x{msg_type:02x}] = dissect_{name},")?;
+    }
+    writeln!(out, "}}")?;
+
+    out.push_str(DISSECTOR);
+
+    writeln!(out)?;
+    writeln!(out, "local udp_port = DissectorTable.get(\"udp.port\")")?;
+    for port in PORTS {
+        writeln!(out, "udp_port:add({port}, net_common)")?;
+    }
+    Ok(())
+}
+
+/// the complete lua dissector source
+pub fn generate() -> Result<String, Box<dyn std::error::Error>> {
+    let mut out = String::new();
+    generate_fmt(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the type bytes net_common decodes, found by decoding a header without a body since
+    /// unknown types decode as Message::Error and known ones fail on the missing body
+    fn known_types() -> Vec<u8> {
+        (0..=u8::MAX)
+            .filter(|&msg_type| {
+                let header = [Message::MAGIC[0], Message::MAGIC[1], 0x00, msg_type];
+                Message::decode(&header, crate::CRC.digest()).is_err()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_type_has_a_sample() {
+        let sampled: Vec<u8> = samples()
+            .iter()
+            .map(|msg| msg.header(Topic::NONE).unwrap()[3])
+            .collect();
+        assert_eq!(sampled, known_types());
+    }
+
+    #[test]
+    fn every_type_has_a_dissector() {
+        let lua = generate().unwrap();
+        for msg_type in known_types() {
+            assert!(
+                lua.contains(&format!("    [0x{msg_type:02x}] = dissect_")),
+                "no dissector for 0x{msg_type:02x}"
+            );
+        }
+    }
+
+    #[test]
+    fn enum_value_strings() {
+        let lua = generate().unwrap();
+        assert!(lua.contains("local FirmwareState_values = {\n    [0] = \"Idle\","));
+        assert!(lua.contains("base.DEC, FirmwareState_values)"));
+        assert!(lua.contains("base.DEC, TaskId_values)"));
+    }
+}
@@ -1,30 +1,160 @@
-use net_common::Message;
-use postcard::to_stdvec_crc32;
+use net_common::board_config::ConfigError;
+use net_common::{Message, Topic};
+
+pub mod dissector;
+pub mod link;
+pub mod schema;
+
+pub use link::{Link, Ping, RoundTripStats};
+
+// larger than any message fits in a udp packet
+const MAX_ENCODED_LEN: usize = 65536;
+
+/// all messages are checksummed with this
+pub static CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Postcard(postcard::Error),
+    /// a board config file that couldn't be read or is invalid
+    Config(ConfigError),
+}
+
+impl Error {
+    /// the socket read timeout expired without receiving anything
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(err) if matches!(
+                err.kind(),
+                std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+            )
+        )
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "io error: {err}"),
+            Error::Postcard(err) => write!(f, "postcard error: {err}"),
+            Error::Config(err) => write!(f, "board config error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<postcard::Error> for Error {
+    fn from(err: postcard::Error) -> Self {
+        Error::Postcard(err)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 // TODO(lucasw) can't put this in net_common because not no_std (though could put a no_std Vec into
 // net_common?)
 pub fn encode(
     message: &Message,
     crc_digest: crc::Digest<'_, u32>,
-) -> Result<Vec<u8>, postcard::Error> {
-    let mut vec = Vec::new();
-    match &message {
-        Message::TimeStamp(data) => {
-            for byte in &Message::DATA {
-                vec.push(*byte);
+) -> std::result::Result<Vec<u8>, postcard::Error> {
+    encode_topic(message, Topic::NONE, crc_digest)
+}
+
+/// encode with the topic id in the header, for publishing to a multicast group, see
+/// Message::encode_topic_into()
+pub fn encode_topic(
+    message: &Message,
+    topic: Topic,
+    crc_digest: crc::Digest<'_, u32>,
+) -> std::result::Result<Vec<u8>, postcard::Error> {
+    // grow the buffer until the message fits
+    let mut vec = vec![0; 256];
+    loop {
+        match message.encode_topic_into(topic, &mut vec, crc_digest.clone()) {
+            Ok(len) => {
+                vec.truncate(len);
+                return Ok(vec);
             }
-            vec.append(&mut to_stdvec_crc32(&data, crc_digest.clone())?);
-        }
-        Message::Array(small_array) => {
-            for byte in &Message::ARRAY {
-                vec.push(*byte);
+            Err(postcard::Error::SerializeBufferFull) if vec.len() < MAX_ENCODED_LEN => {
+                vec.resize(vec.len() * 2, 0);
             }
-            vec.append(&mut to_stdvec_crc32(&small_array, crc_digest.clone())?);
+            Err(err) => return Err(err),
         }
-        Message::Error(()) => {
-            // TODO(lucasw) return a more appropriate error than this?
-            return Err(postcard::Error::WontImplement);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_topic_round_trip() {
+        for msg in crate::dissector::samples() {
+            let msg_bytes = encode_topic(&msg, Topic::ARRAY, CRC.digest()).unwrap();
+            assert_eq!(msg_bytes[2], Topic::ARRAY.0);
+            let (topic, decoded) = Message::decode_topic(&msg_bytes, CRC.digest()).unwrap();
+            assert_eq!(topic, Topic::ARRAY);
+            // nothing to compare Messages with, the encoding is deterministic
+            let again = encode_topic(&decoded, topic, CRC.digest()).unwrap();
+            assert_eq!(msg_bytes, again);
         }
     }
-    Ok(vec)
+
+    #[test]
+    fn largest_messages_fit_the_firmware_buffers() {
+        let mut bench = net_common::BenchData {
+            epoch: net_common::Epoch::MAX,
+            sequence: u64::MAX,
+            ..Default::default()
+        };
+        bench.data.resize(net_common::BENCH_MAX_DATA, 0xa5).unwrap();
+        let mut chunk = net_common::firmware::FirmwareChunk {
+            offset: u32::MAX,
+            ..Default::default()
+        };
+        chunk
+            .data
+            .resize(net_common::firmware::CHUNK_LEN, 0xa5)
+            .unwrap();
+        let mut record = net_common::logging::LogRecord {
+            sequence: u32::MAX,
+            epoch: net_common::Epoch::MAX,
+            ..Default::default()
+        };
+        let module = "m".repeat(net_common::logging::LOG_MAX_MODULE);
+        record.module.push_str(&module).unwrap();
+        let text = "t".repeat(net_common::logging::LOG_MAX_TEXT);
+        record.text.push_str(&text).unwrap();
+
+        for msg in [
+            Message::Bench(bench),
+            Message::FirmwareChunk(chunk),
+            Message::Log(record),
+        ] {
+            let msg_bytes = msg
+                .encode_topic::<256>(Topic::HEALTH, CRC.digest())
+                .unwrap();
+            assert!(Message::decode(&msg_bytes, CRC.digest()).is_ok());
+        }
+    }
+
+    #[test]
+    fn error_does_not_encode() {
+        assert!(encode(&Message::Error(()), CRC.digest()).is_err());
+    }
 }
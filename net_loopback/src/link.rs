@@ -0,0 +1,222 @@
+/*!
+A UdpSocket that sends and receives net_common messages, the building block for the binaries
+in this crate and for other tools that talk to the boards
+
+```no_run
+use net_loopback::Link;
+
+let link = Link::bind("192.168.0.100:34200")?.with_remote("192.168.0.123:34201")?;
+for rx in link.messages() {
+    let (msg, src, rx_stamp) = rx?;
+    println!("[{rx_stamp:?}] {msg:?} from {src:?}");
+}
+# Ok::<(), net_loopback::Error>(())
+```
+*/
+
+use crate::{CRC, Error, Result};
+use net_common::pubsub::MULTICAST_PORT;
+use net_common::echo::EchoMeasurement;
+use net_common::{EchoReply, EchoRequest, Epoch, Message, Topic};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime};
+
+/// big enough for any message including BenchData
+const RX_BUFFER_SIZE: usize = 2048;
+
+pub struct Link {
+    socket: UdpSocket,
+    /// where send() goes
+    remote: Option<SocketAddr>,
+}
+
+impl Link {
+    pub fn bind(local: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(local)?,
+            remote: None,
+        })
+    }
+
+    /// bind to the multicast port and join the group of each topic, any number of these can
+    /// be subscribed at once on the same computer
+    pub fn subscribe(local_ip: Ipv4Addr, topics: &[Topic]) -> Result<Self> {
+        // std UdpSocket can't set SO_REUSEADDR before binding, which is needed for several
+        // subscribers to share the port
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        let bind_addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, MULTICAST_PORT));
+        socket.bind(&bind_addr.into())?;
+        let socket: UdpSocket = socket.into();
+
+        for topic in topics {
+            let group = Ipv4Addr::from(topic.multicast_group());
+            socket.join_multicast_v4(&group, &local_ip)?;
+        }
+
+        Ok(Self {
+            socket,
+            remote: None,
+        })
+    }
+
+    /// set the destination for send()
+    pub fn with_remote(mut self, remote: impl ToSocketAddrs) -> Result<Self> {
+        self.remote = remote.to_socket_addrs()?.next();
+        Ok(self)
+    }
+
+    pub fn remote(&self) -> Option<SocketAddr> {
+        self.remote
+    }
+
+    /// None blocks forever in recv()
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.socket.set_read_timeout(timeout)?)
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// another handle to the same socket, e.g. to receive on another thread
+    pub fn try_clone(&self) -> Result<Self> {
+        Ok(Self {
+            socket: self.socket.try_clone()?,
+            remote: self.remote,
+        })
+    }
+
+    /// send to the remote set with with_remote()
+    pub fn send(&self, msg: &Message) -> Result<usize> {
+        let Some(remote) = self.remote else {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "no remote set",
+            )));
+        };
+        self.send_to(msg, remote)
+    }
+
+    pub fn send_to(&self, msg: &Message, addr: impl ToSocketAddrs) -> Result<usize> {
+        let msg_bytes = crate::encode(msg, CRC.digest())?;
+        Ok(self.socket.send_to(&msg_bytes, addr)?)
+    }
+
+    /// send to the multicast group of the topic
+    pub fn publish(&self, msg: &Message, topic: Topic) -> Result<usize> {
+        let msg_bytes = crate::encode_topic(msg, topic, CRC.digest())?;
+        let group = Ipv4Addr::from(topic.multicast_group());
+        Ok(self.socket.send_to(&msg_bytes, (group, MULTICAST_PORT))?)
+    }
+
+    /// wait for the next message, also returns who sent it and when it arrived
+    pub fn recv(&self) -> Result<(Message, SocketAddr, SystemTime)> {
+        let (_topic, msg, src, rx_stamp) = self.recv_topic()?;
+        Ok((msg, src, rx_stamp))
+    }
+
+    /// recv() plus the topic the message was published on
+    pub fn recv_topic(&self) -> Result<(Topic, Message, SocketAddr, SystemTime)> {
+        let mut buf = [0; RX_BUFFER_SIZE];
+        let (rx_num, src) = self.socket.recv_from(&mut buf)?;
+        let rx_stamp = SystemTime::now();
+        let (topic, msg) = Message::decode_topic(&buf[..rx_num], CRC.digest())?;
+        Ok((topic, msg, src, rx_stamp))
+    }
+
+    /// every received message, timeouts and decode failures are returned as errors and
+    /// the iteration continues after them
+    pub fn messages(&self) -> Messages<'_> {
+        Messages { link: self }
+    }
+
+    /// send an EchoRequest stamped with the current time to the remote and wait for the
+    /// EchoReply with the same counter, other messages and late replies to earlier pings
+    /// received in the meantime are dropped
+    pub fn ping(&self, counter: u64) -> Result<Ping> {
+        let tx_stamp = SystemTime::now();
+        let msg = Message::EchoRequest(EchoRequest {
+            counter,
+            origin: Epoch::try_from(tx_stamp).expect("time went backwards"),
+        });
+        self.send(&msg)?;
+
+        loop {
+            let (msg, _src, rx_stamp) = self.recv()?;
+            if let Message::EchoReply(reply) = msg
+                && reply.counter == counter
+            {
+                return Ok(Ping {
+                    tx_stamp,
+                    rx_stamp,
+                    reply,
+                });
+            }
+        }
+    }
+}
+
+pub struct Messages<'a> {
+    link: &'a Link,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = Result<(Message, SocketAddr, SystemTime)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.link.recv())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Ping {
+    pub tx_stamp: SystemTime,
+    pub rx_stamp: SystemTime,
+    pub reply: EchoReply,
+}
+
+impl Ping {
+    pub fn roundtrip(&self) -> Duration {
+        self.rx_stamp
+            .duration_since(self.tx_stamp)
+            .unwrap_or_default()
+    }
+
+    /// the offset of the board clock from this computer's, and the round trip without the
+    /// board's turnaround
+    pub fn measure(&self) -> Option<EchoMeasurement> {
+        let arrival = Epoch::try_from(self.rx_stamp).ok()?;
+        self.reply.measure(arrival)
+    }
+}
+
+/// accumulate round trip times to report min/mean/max over a period
+#[derive(Clone, Debug, Default)]
+pub struct RoundTripStats {
+    pub count: u32,
+    pub sum: Duration,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl RoundTripStats {
+    pub fn add(&mut self, roundtrip: Duration) {
+        self.count += 1;
+        self.sum += roundtrip;
+        self.min = Some(self.min.map_or(roundtrip, |min| min.min(roundtrip)));
+        self.max = Some(self.max.map_or(roundtrip, |max| max.max(roundtrip)));
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum / self.count)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
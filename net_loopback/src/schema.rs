@@ -0,0 +1,519 @@
+/*!
+Find out the postcard wire layout of a type by serializing a value of it with a Serializer
+that records field names and types instead of bytes, so anything generated from the layout
+(like the wireshark dissector) can't drift from the Rust definitions in net_common.
+
+A value only shows the variant of an enum it has, the names of all of them come from
+deserializing the type with a Deserializer that makes up a value and notes the variants of each
+enum it is asked for.
+*/
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Impossible};
+
+/// how a value appears in postcard's wire format
+#[derive(Clone, Debug, PartialEq)]
+pub enum Kind {
+    Bool,
+    U8,
+    I8,
+    /// u16/u32/u64 as a varint
+    Unsigned(u8),
+    /// i16/i32/i64 as a zigzag varint
+    Signed(u8),
+    F32,
+    F64,
+    /// fixed size array of u8, e.g. [u8; 32]
+    Bytes(usize),
+    /// varint length followed by that many bytes, e.g. heapless::Vec<u8, N>
+    VarBytes,
+    Struct(Vec<(&'static str, Kind)>),
+    /// fixed size array or tuple of anything other than u8
+    Tuple(Vec<Kind>),
+    Unit,
+    /// an enum without data as its varint variant index, the name of the enum and its
+    /// variants in order
+    Enum(&'static str, &'static [&'static str]),
+}
+
+impl Kind {
+    /// flatten nested structs into (dotted.path, kind) leaves in wire order
+    pub fn leaves(&self, prefix: &str) -> Vec<(String, Kind)> {
+        let join = |name: &str| {
+            if prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{prefix}.{name}")
+            }
+        };
+        match self {
+            Kind::Struct(fields) => fields
+                .iter()
+                .flat_map(|(name, kind)| kind.leaves(&join(name)))
+                .collect(),
+            Kind::Tuple(elements) => elements
+                .iter()
+                .enumerate()
+                .flat_map(|(i, kind)| kind.leaves(&join(&i.to_string())))
+                .collect(),
+            Kind::Unit => Vec::new(),
+            kind => vec![(prefix.to_string(), kind.clone())],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+impl de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error(msg.to_string())
+    }
+}
+
+/// the layout of T, options/enums with data/maps aren't supported since their layout depends
+/// on the value
+pub fn record<T: Serialize + DeserializeOwned>(value: &T) -> Result<Kind, Error> {
+    let kind = value.serialize(Recorder)?;
+    let variants = RefCell::new(HashMap::new());
+    T::deserialize(VariantFinder(&variants))?;
+    kind.with_variants(&variants.into_inner())
+}
+
+impl Kind {
+    /// fill in the variants of the enums the Recorder only saw one value of
+    fn with_variants(
+        self,
+        variants: &HashMap<&'static str, &'static [&'static str]>,
+    ) -> Result<Kind, Error> {
+        Ok(match self {
+            Kind::Enum(name, _) => {
+                let found = variants
+                    .get(name)
+                    .ok_or_else(|| Error(format!("no variants found for {name}")))?;
+                Kind::Enum(name, found)
+            }
+            Kind::Struct(fields) => Kind::Struct(
+                fields
+                    .into_iter()
+                    .map(|(name, kind)| Ok((name, kind.with_variants(variants)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            Kind::Tuple(elements) => Kind::Tuple(
+                elements
+                    .into_iter()
+                    .map(|kind| kind.with_variants(variants))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            kind => kind,
+        })
+    }
+}
+
+fn unsupported(what: &str) -> Error {
+    Error(format!("{what} can't be described by a fixed layout"))
+}
+
+struct Recorder;
+
+impl ser::Serializer for Recorder {
+    type Ok = Kind;
+    type Error = Error;
+    type SerializeSeq = SeqRecorder;
+    type SerializeTuple = TupleRecorder;
+    type SerializeTupleStruct = TupleRecorder;
+    type SerializeTupleVariant = Impossible<Kind, Error>;
+    type SerializeMap = Impossible<Kind, Error>;
+    type SerializeStruct = StructRecorder;
+    type SerializeStructVariant = Impossible<Kind, Error>;
+
+    fn serialize_bool(self, _v: bool) -> Result<Kind, Error> {
+        Ok(Kind::Bool)
+    }
+    fn serialize_i8(self, _v: i8) -> Result<Kind, Error> {
+        Ok(Kind::I8)
+    }
+    fn serialize_i16(self, _v: i16) -> Result<Kind, Error> {
+        Ok(Kind::Signed(16))
+    }
+    fn serialize_i32(self, _v: i32) -> Result<Kind, Error> {
+        Ok(Kind::Signed(32))
+    }
+    fn serialize_i64(self, _v: i64) -> Result<Kind, Error> {
+        Ok(Kind::Signed(64))
+    }
+    fn serialize_u8(self, _v: u8) -> Result<Kind, Error> {
+        Ok(Kind::U8)
+    }
+    fn serialize_u16(self, _v: u16) -> Result<Kind, Error> {
+        Ok(Kind::Unsigned(16))
+    }
+    fn serialize_u32(self, _v: u32) -> Result<Kind, Error> {
+        Ok(Kind::Unsigned(32))
+    }
+    fn serialize_u64(self, _v: u64) -> Result<Kind, Error> {
+        Ok(Kind::Unsigned(64))
+    }
+    fn serialize_f32(self, _v: f32) -> Result<Kind, Error> {
+        Ok(Kind::F32)
+    }
+    fn serialize_f64(self, _v: f64) -> Result<Kind, Error> {
+        Ok(Kind::F64)
+    }
+    fn serialize_char(self, _v: char) -> Result<Kind, Error> {
+        Err(unsupported("char"))
+    }
+    fn serialize_str(self, _v: &str) -> Result<Kind, Error> {
+        Ok(Kind::VarBytes)
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Kind, Error> {
+        Ok(Kind::VarBytes)
+    }
+    fn serialize_none(self) -> Result<Kind, Error> {
+        Err(unsupported("Option"))
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<Kind, Error> {
+        Err(unsupported("Option"))
+    }
+    fn serialize_unit(self) -> Result<Kind, Error> {
+        Ok(Kind::Unit)
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Kind, Error> {
+        Ok(Kind::Unit)
+    }
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Kind, Error> {
+        // a varint discriminant, the variants are filled in later
+        Ok(Kind::Enum(name, &[]))
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Kind, Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Kind, Error> {
+        Err(unsupported("enum with data"))
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<SeqRecorder, Error> {
+        Ok(SeqRecorder)
+    }
+    fn serialize_tuple(self, len: usize) -> Result<TupleRecorder, Error> {
+        Ok(TupleRecorder(Vec::with_capacity(len)))
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<TupleRecorder, Error> {
+        Ok(TupleRecorder(Vec::with_capacity(len)))
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Error> {
+        Err(unsupported("enum with data"))
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Error> {
+        Err(unsupported("map"))
+    }
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<StructRecorder, Error> {
+        Ok(StructRecorder(Vec::with_capacity(len)))
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Error> {
+        Err(unsupported("enum with data"))
+    }
+}
+
+/// sequences are only supported for bytes, the length and so the elements of the (default)
+/// value being recorded may be empty so the element type can't be checked
+struct SeqRecorder;
+
+impl ser::SerializeSeq for SeqRecorder {
+    type Ok = Kind;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        match value.serialize(Recorder)? {
+            Kind::U8 => Ok(()),
+            _ => Err(unsupported("sequence of anything other than u8")),
+        }
+    }
+
+    fn end(self) -> Result<Kind, Error> {
+        Ok(Kind::VarBytes)
+    }
+}
+
+struct TupleRecorder(Vec<Kind>);
+
+impl TupleRecorder {
+    fn finish(self) -> Kind {
+        if !self.0.is_empty() && self.0.iter().all(|kind| *kind == Kind::U8) {
+            Kind::Bytes(self.0.len())
+        } else {
+            Kind::Tuple(self.0)
+        }
+    }
+}
+
+impl ser::SerializeTuple for TupleRecorder {
+    type Ok = Kind;
+    type Error = Error;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(value.serialize(Recorder)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Kind, Error> {
+        Ok(self.finish())
+    }
+}
+
+impl ser::SerializeTupleStruct for TupleRecorder {
+    type Ok = Kind;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        self.0.push(value.serialize(Recorder)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Kind, Error> {
+        Ok(self.finish())
+    }
+}
+
+struct StructRecorder(Vec<(&'static str, Kind)>);
+
+impl ser::SerializeStruct for StructRecorder {
+    type Ok = Kind;
+    type Error = Error;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Error> {
+        self.0.push((key, value.serialize(Recorder)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Kind, Error> {
+        Ok(Kind::Struct(self.0))
+    }
+}
+
+/// a Deserializer making up the first variant, zeros and empty sequences for anything asked
+/// of it, while noting the variants of every enum by name
+#[derive(Clone, Copy)]
+struct VariantFinder<'a>(&'a RefCell<HashMap<&'static str, &'static [&'static str]>>);
+
+impl<'de> de::Deserializer<'de> for VariantFinder<'_> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(unsupported("a self describing type"))
+    }
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bool(false)
+    }
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i8(0)
+    }
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i16(0)
+    }
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i32(0)
+    }
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_i64(0)
+    }
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u8(0)
+    }
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u16(0)
+    }
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u32(0)
+    }
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u64(0)
+    }
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f32(0.0)
+    }
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_f64(0.0)
+    }
+    fn deserialize_char<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(unsupported("char"))
+    }
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_str("")
+    }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_str("")
+    }
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bytes(&[])
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_bytes(&[])
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_none()
+    }
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements(self, 0))
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements(self, len))
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements(self, len))
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+        Err(unsupported("map"))
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements(self, fields.len()))
+    }
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.0.borrow_mut().insert(name, variants);
+        visitor.visit_enum(self)
+    }
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_u32(0)
+    }
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_unit()
+    }
+}
+
+/// the first variant
+impl<'de> de::EnumAccess<'de> for VariantFinder<'_> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), Error> {
+        let index: de::value::U32Deserializer<Error> = 0u32.into_deserializer();
+        Ok((seed.deserialize(index)?, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantFinder<'_> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<(), Error> {
+        Ok(())
+    }
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, Error> {
+        seed.deserialize(self)
+    }
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements(self, len))
+    }
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_seq(Elements(self, fields.len()))
+    }
+}
+
+/// the given number of made up elements
+struct Elements<'a>(VariantFinder<'a>, usize);
+
+impl<'de> de::SeqAccess<'de> for Elements<'_> {
+    type Error = Error;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, Error> {
+        if self.1 == 0 {
+            return Ok(None);
+        }
+        self.1 -= 1;
+        seed.deserialize(self.0).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.1)
+    }
+}
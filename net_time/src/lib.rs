@@ -0,0 +1,671 @@
+/*!
+Sntp time sync shared by the firmwares and host tools
+
+TimeSync is the request/response state machine for up to MAX_SERVERS servers, with retry
+backoff, selection of the best server (see peer) and the clock discipline from net_common, it
+doesn't do any io itself.  Either drive it with poll() and a non-blocking
+NtpUdpSocket (std), or with poll_transmit()/handle_receive()/next_wake() from an
+async task (embassy, nucleo_postcard).  The local clock comes from a TickSource, usually the
+time since boot.
+*/
+
+#![no_std]
+
+#[cfg(feature = "std")]
+extern crate std;
+
+use core::convert::Infallible;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::time::Duration;
+
+use net_common::Epoch;
+use net_common::discipline::{Discipline, Sample};
+
+pub mod packet;
+pub mod peer;
+#[cfg(feature = "std")]
+pub mod std_impl;
+
+use packet::{LEAP_UNSYNCHRONIZED, MODE_SERVER, PACKET_SIZE, Packet};
+pub use peer::{MAX_SERVERS, Peer};
+use peer::{State, select, short_to_nanos};
+
+/// a monotonic local clock, e.g. ticks since boot
+pub trait TickSource {
+    fn now(&self) -> Epoch;
+}
+
+/// a udp socket that never blocks
+pub trait NtpUdpSocket {
+    type Error;
+
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddrV4) -> Result<(), Self::Error>;
+
+    /// Ok(None) if nothing has been received
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddrV4)>, Self::Error>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error<E = Infallible> {
+    Socket(E),
+    /// no reply from this server within Config::timeout
+    Timeout(SocketAddrV4),
+    /// too short, or not a server reply
+    InvalidPacket,
+    /// not from the server, no request outstanding, or a reply to an older request
+    UnexpectedReply,
+    /// the server isn't synchronized or sent a kiss-o'-death
+    Unsynchronized,
+}
+
+impl Error {
+    fn into_socket_error<E>(self) -> Error<E> {
+        match self {
+            Error::Socket(never) => match never {},
+            Error::Timeout(server) => Error::Timeout(server),
+            Error::InvalidPacket => Error::InvalidPacket,
+            Error::UnexpectedReply => Error::UnexpectedReply,
+            Error::Unsynchronized => Error::Unsynchronized,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// time between requests while the server is answering
+    pub poll_interval: Duration,
+    /// how long to wait for a reply
+    pub timeout: Duration,
+    /// the poll interval doubles after each failure up to this
+    pub max_backoff: Duration,
+}
+
+impl Config {
+    pub const DEFAULT: Config = Config {
+        poll_interval: Duration::from_secs(1),
+        timeout: Duration::from_secs(1),
+        max_backoff: Duration::from_secs(64),
+    };
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// one completed exchange, the field names and units (microseconds) match sntpc::NtpResult
+/// which this replaced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NtpResult {
+    /// the server transmit time in ntp seconds and fraction
+    pub seconds: u32,
+    pub seconds_fraction: u32,
+    pub roundtrip: u64,
+    /// server time - local time
+    pub offset: i64,
+    pub stratum: u8,
+    /// the same exchange in nanoseconds, what gets fed to the discipline
+    pub sample: Sample,
+    /// the server that answered
+    pub server: SocketAddrV4,
+    /// the server was the selected source and the sample went into the discipline
+    pub selected: bool,
+}
+
+impl Default for NtpResult {
+    fn default() -> Self {
+        Self {
+            seconds: 0,
+            seconds_fraction: 0,
+            roundtrip: 0,
+            offset: 0,
+            stratum: 0,
+            sample: Sample::default(),
+            server: SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0),
+            selected: false,
+        }
+    }
+}
+
+pub struct TimeSync<T: TickSource> {
+    ticks: T,
+    peers: [Peer; MAX_SERVERS],
+    num_peers: usize,
+    /// index into peers of the server disciplining the clock
+    selected: Option<usize>,
+    /// the server the samples in the discipline came from, which can differ from selected
+    /// until the new selection answers
+    sampled: Option<usize>,
+    pub config: Config,
+    discipline: Discipline,
+}
+
+impl<T: TickSource> TimeSync<T> {
+    /// only the first MAX_SERVERS servers are used
+    pub const fn new(ticks: T, servers: &[SocketAddrV4], config: Config) -> Self {
+        let mut peers = [Peer::EMPTY; MAX_SERVERS];
+        let mut num_peers = 0;
+        while num_peers < servers.len() && num_peers < MAX_SERVERS {
+            peers[num_peers] = Peer::new(servers[num_peers]);
+            num_peers += 1;
+        }
+        Self {
+            ticks,
+            peers,
+            num_peers,
+            selected: None,
+            sampled: None,
+            config,
+            discipline: Discipline::new(),
+        }
+    }
+
+    /// abandons any outstanding requests and starts over with the new servers, the clock
+    /// slews to the new servers from where it is rather than starting over
+    pub fn set_servers(&mut self, servers: &[SocketAddrV4]) {
+        self.peers = [Peer::EMPTY; MAX_SERVERS];
+        self.num_peers = servers.len().min(MAX_SERVERS);
+        for (peer, server) in self.peers.iter_mut().zip(servers) {
+            *peer = Peer::new(*server);
+        }
+        self.selected = None;
+        self.sampled = None;
+    }
+
+    pub fn peers(&self) -> &[Peer] {
+        &self.peers[..self.num_peers]
+    }
+
+    /// the server disciplining the clock
+    pub fn selected(&self) -> Option<SocketAddrV4> {
+        self.selected.map(|index| self.peers[index].addr())
+    }
+
+    /// the latest result from the selected server
+    pub fn last_result(&self) -> Option<NtpResult> {
+        self.peers[self.selected?].last_result()
+    }
+
+    pub fn discipline(&self) -> &Discipline {
+        &self.discipline
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.discipline.is_synced()
+    }
+
+    /// synced earlier but no server has answered recently, the clock carries on with the
+    /// last frequency estimate
+    pub fn is_holdover(&self) -> bool {
+        self.selected.is_none() && self.discipline.is_synced()
+    }
+
+    /// a sample from another source, e.g. a ptp exchange, the lowest round trip samples win
+    /// whichever source they come from
+    pub fn add_sample(&mut self, sample: Sample) -> bool {
+        self.discipline.add_sample(sample)
+    }
+
+    /// the disciplined time now
+    pub fn now(&mut self) -> Epoch {
+        let local = self.ticks.now();
+        self.corrected(local)
+    }
+
+    /// the disciplined time at a local time from the same TickSource, see Discipline::now()
+    pub fn corrected(&mut self, local: Epoch) -> Epoch {
+        self.discipline.now(local)
+    }
+
+    /// the local time at which poll_transmit() or poll_timeout() next have something to do,
+    /// Epoch::MAX without any servers
+    pub fn next_wake(&self) -> Epoch {
+        self.peers()
+            .iter()
+            .map(|peer| match peer.state {
+                State::Idle { next_poll } => next_poll,
+                State::Waiting { sent, .. } => sent.saturating_add(self.config.timeout),
+            })
+            .min()
+            .unwrap_or(Epoch::MAX)
+    }
+
+    fn backoff(&self, failures: u32) -> Duration {
+        let doublings = failures.min(16);
+        self.config
+            .poll_interval
+            .saturating_mul(1 << doublings)
+            .min(self.config.max_backoff)
+            .max(self.config.poll_interval)
+    }
+
+    fn fail(&mut self, index: usize, now: Epoch) {
+        let failures = self.peers[index].failures.saturating_add(1);
+        let next_poll = now.saturating_add(self.backoff(failures));
+        let peer = &mut self.peers[index];
+        peer.failures = failures;
+        peer.state = State::Idle { next_poll };
+        self.selected = select(self.peers(), self.selected);
+    }
+
+    /// Err(Timeout) once for each request that has gone unanswered too long, the next request
+    /// to that server is then delayed by the backoff
+    pub fn poll_timeout(&mut self) -> Result<(), Error> {
+        let now = self.ticks.now();
+        for index in 0..self.num_peers {
+            if let State::Waiting { sent, .. } = self.peers[index].state
+                && now >= sent.saturating_add(self.config.timeout)
+            {
+                self.fail(index, now);
+                return Err(Error::Timeout(self.peers[index].addr()));
+            }
+        }
+        Ok(())
+    }
+
+    /// if it is time for a request fill in buf and return where to send it, call again until
+    /// None when there is more than one server
+    pub fn poll_transmit(&mut self, buf: &mut [u8; PACKET_SIZE]) -> Option<SocketAddrV4> {
+        let now = self.ticks.now();
+        let peer = self.peers[..self.num_peers]
+            .iter_mut()
+            .find(|peer| matches!(peer.state, State::Idle { next_poll } if now >= next_poll))?;
+        let originate = now.to_ntp();
+        *buf = Packet::request(originate).to_bytes();
+        peer.reach <<= 1;
+        peer.state = State::Waiting {
+            sent: now,
+            originate,
+        };
+        Some(peer.addr())
+    }
+
+    /// process a packet received on the ntp socket, a good reply from the selected server
+    /// updates the discipline
+    pub fn handle_receive(&mut self, buf: &[u8], from: SocketAddrV4) -> Result<NtpResult, Error> {
+        // stamp before anything else
+        let received = self.ticks.now();
+        let Some(index) = self.peers().iter().position(|peer| peer.addr() == from) else {
+            return Err(Error::UnexpectedReply);
+        };
+        let State::Waiting { sent, originate } = self.peers[index].state else {
+            return Err(Error::UnexpectedReply);
+        };
+        let Some(reply) = Packet::from_bytes(buf) else {
+            return Err(Error::InvalidPacket);
+        };
+        if reply.mode != MODE_SERVER {
+            return Err(Error::InvalidPacket);
+        }
+        // a late reply to an earlier request, keep waiting for the current one
+        if reply.originate != originate {
+            return Err(Error::UnexpectedReply);
+        }
+        if reply.stratum == 0 || reply.leap == LEAP_UNSYNCHRONIZED || reply.transmit == 0 {
+            self.fail(index, received);
+            return Err(Error::Unsynchronized);
+        }
+
+        // t1 and t4 are local, t2 and t3 server
+        let server_rx = Epoch::from_ntp(reply.receive);
+        let server_tx = Epoch::from_ntp(reply.transmit);
+        let to_server = server_rx.signed_nanos_since(&sent).unwrap_or(0);
+        let from_server = server_tx.signed_nanos_since(&received).unwrap_or(0);
+        let offset_ns = ((to_server as i128 + from_server as i128) / 2) as i64;
+        let rtt_ns = received
+            .signed_nanos_since(&sent)
+            .unwrap_or(0)
+            .saturating_sub(server_tx.signed_nanos_since(&server_rx).unwrap_or(0))
+            .max(0) as u64;
+
+        let sample = Sample {
+            local: received,
+            offset_ns,
+            rtt_ns,
+        };
+        let next_poll = sent.saturating_add(self.config.poll_interval);
+        let peer = &mut self.peers[index];
+        peer.update_jitter(offset_ns);
+        peer.root_distance_ns = (rtt_ns / 2)
+            .saturating_add(short_to_nanos(reply.root_delay) / 2)
+            .saturating_add(short_to_nanos(reply.root_dispersion));
+        peer.reach |= 1;
+        peer.failures = 0;
+        peer.state = State::Idle { next_poll };
+        peer.last_result = Some(NtpResult {
+            seconds: (reply.transmit >> 32) as u32,
+            seconds_fraction: reply.transmit as u32,
+            roundtrip: rtt_ns / 1000,
+            offset: offset_ns / 1000,
+            stratum: reply.stratum,
+            sample,
+            server: from,
+            selected: false,
+        });
+
+        self.selected = select(self.peers(), self.selected);
+        let peer = &mut self.peers[index];
+        let result = peer.last_result.as_mut().expect("just set");
+        if self.selected == Some(index) {
+            result.selected = true;
+            if self.sampled != Some(index) {
+                self.discipline.clear_samples();
+                self.sampled = Some(index);
+            }
+            self.discipline.add_sample(sample);
+        }
+        Ok(*result)
+    }
+
+    /// do whatever is due with a non-blocking socket, call this often (at least every
+    /// few milliseconds while waiting for a reply, the receive time stamp is taken here)
+    pub fn poll<S: NtpUdpSocket>(
+        &mut self,
+        socket: &mut S,
+    ) -> Result<Option<NtpResult>, Error<S::Error>> {
+        let mut buf = [0; PACKET_SIZE];
+        // anything received is either a reply or something stale that needs flushing
+        while let Some((num, from)) = socket.recv_from(&mut buf).map_err(Error::Socket)? {
+            match self.handle_receive(&buf[..num], from) {
+                Ok(result) => {
+                    return Ok(Some(result));
+                }
+                Err(Error::UnexpectedReply) => {}
+                Err(err) => {
+                    return Err(err.into_socket_error());
+                }
+            }
+        }
+        self.poll_timeout().map_err(Error::into_socket_error)?;
+        while let Some(server) = self.poll_transmit(&mut buf) {
+            socket.send_to(&buf, server).map_err(Error::Socket)?;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use packet::NTP_PORT;
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(192, 168, 0, 100), NTP_PORT);
+    /// server time - local time
+    const OFFSET: Duration = Duration::from_secs(1_700_000_000);
+
+    #[derive(Clone, Default)]
+    struct FakeTicks(Rc<Cell<Epoch>>);
+
+    impl FakeTicks {
+        fn advance(&self, duration: Duration) {
+            self.0.set(self.0.get().saturating_add(duration));
+        }
+    }
+
+    impl TickSource for FakeTicks {
+        fn now(&self) -> Epoch {
+            self.0.get()
+        }
+    }
+
+    /// records the requests, and hands out whatever replies the test queued
+    #[derive(Default)]
+    struct FakeSocket {
+        sent: Vec<(Packet, SocketAddrV4)>,
+        inbox: VecDeque<([u8; PACKET_SIZE], SocketAddrV4)>,
+    }
+
+    impl NtpUdpSocket for FakeSocket {
+        type Error = ();
+
+        fn send_to(&mut self, buf: &[u8], addr: SocketAddrV4) -> Result<(), ()> {
+            self.sent.push((Packet::from_bytes(buf).unwrap(), addr));
+            Ok(())
+        }
+
+        fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddrV4)>, ()> {
+            Ok(self.inbox.pop_front().map(|(packet, from)| {
+                buf[..PACKET_SIZE].copy_from_slice(&packet);
+                (PACKET_SIZE, from)
+            }))
+        }
+    }
+
+    impl FakeSocket {
+        /// the server's answer to a request, it got it one_way after it was sent and replied
+        /// straight away
+        fn reply(&mut self, request: &Packet, one_way: Duration, from: SocketAddrV4) {
+            let sent = Epoch::from_ntp(request.transmit);
+            let server_time = sent.saturating_add(one_way).saturating_add(OFFSET).to_ntp();
+            let reply = Packet {
+                version: 4,
+                mode: MODE_SERVER,
+                stratum: 2,
+                originate: request.transmit,
+                receive: server_time,
+                transmit: server_time,
+                ..Default::default()
+            };
+            self.inbox.push_back((reply.to_bytes(), from));
+        }
+
+        fn last_request(&self) -> Packet {
+            self.sent.last().unwrap().0
+        }
+    }
+
+    fn time_sync() -> (TimeSync<FakeTicks>, FakeTicks, FakeSocket) {
+        let ticks = FakeTicks::default();
+        ticks.advance(Duration::from_secs(10));
+        let sync = TimeSync::new(ticks.clone(), &[SERVER], Config::DEFAULT);
+        (sync, ticks, FakeSocket::default())
+    }
+
+    #[test]
+    fn request_response() {
+        let (mut sync, ticks, mut socket) = time_sync();
+        assert_eq!(sync.poll(&mut socket), Ok(None));
+        assert_eq!(socket.sent.len(), 1);
+        let (request, to) = socket.sent[0];
+        assert_eq!(to, SERVER);
+        assert_eq!(request.mode, packet::MODE_CLIENT);
+        assert_eq!(Epoch::from_ntp(request.transmit), ticks.now());
+        // nothing more is sent while waiting
+        assert_eq!(sync.poll(&mut socket), Ok(None));
+        assert_eq!(socket.sent.len(), 1);
+
+        ticks.advance(Duration::from_millis(10));
+        socket.reply(&request, Duration::from_millis(5), SERVER);
+        let result = sync.poll(&mut socket).unwrap().unwrap();
+        assert_eq!(result.offset, OFFSET.as_micros() as i64);
+        assert_eq!(result.roundtrip, 10_000);
+        assert_eq!(result.stratum, 2);
+        assert_eq!(result.server, SERVER);
+        assert!(result.selected);
+        assert!(!sync.is_synced());
+        // synced from the first time the corrected clock is read
+        assert_eq!(sync.now(), ticks.now().saturating_add(OFFSET));
+        assert!(sync.is_synced());
+        assert_eq!(sync.selected(), Some(SERVER));
+        assert_eq!(sync.last_result(), Some(result));
+        assert_eq!(sync.peers()[0].reach(), 1);
+
+        // the next request a poll interval after the last one
+        let sent = Epoch::from_ntp(request.transmit);
+        assert_eq!(
+            sync.next_wake(),
+            sent.saturating_add(Config::DEFAULT.poll_interval)
+        );
+        ticks.advance(Duration::from_millis(980));
+        assert_eq!(sync.poll(&mut socket), Ok(None));
+        assert_eq!(socket.sent.len(), 1);
+        ticks.advance(Duration::from_millis(10));
+        assert_eq!(sync.poll(&mut socket), Ok(None));
+        assert_eq!(socket.sent.len(), 2);
+    }
+
+    #[test]
+    fn timeout_backoff() {
+        let (mut sync, ticks, mut socket) = time_sync();
+        let config = Config::DEFAULT;
+        assert_eq!(sync.poll(&mut socket), Ok(None));
+        let mut expected_backoff = config.poll_interval;
+        for failures in 1..10 {
+            ticks.advance(config.timeout);
+            assert_eq!(sync.poll(&mut socket), Err(Error::Timeout(SERVER)));
+            assert_eq!(sync.peers()[0].failures(), failures);
+            expected_backoff = (expected_backoff * 2).min(config.max_backoff);
+            assert_eq!(
+                sync.next_wake(),
+                ticks.now().saturating_add(expected_backoff)
+            );
+
+            // nothing until the backoff is up
+            let requests = socket.sent.len();
+            ticks.advance(expected_backoff - Duration::from_millis(1));
+            assert_eq!(sync.poll(&mut socket), Ok(None));
+            assert_eq!(socket.sent.len(), requests);
+            ticks.advance(Duration::from_millis(1));
+            assert_eq!(sync.poll(&mut socket), Ok(None));
+            assert_eq!(socket.sent.len(), requests + 1);
+        }
+        assert_eq!(expected_backoff, config.max_backoff);
+        assert_eq!(sync.peers()[0].reach(), 0);
+        assert!(!sync.is_synced());
+
+        // an answer resets the backoff
+        let request = socket.last_request();
+        socket.reply(&request, Duration::from_millis(1), SERVER);
+        assert!(sync.poll(&mut socket).unwrap().is_some());
+        assert_eq!(sync.peers()[0].failures(), 0);
+        assert_eq!(
+            sync.next_wake(),
+            Epoch::from_ntp(request.transmit).saturating_add(config.poll_interval)
+        );
+    }
+
+    #[test]
+    fn flushes_stale_replies() {
+        let (mut sync, ticks, mut socket) = time_sync();
+        sync.poll(&mut socket).unwrap();
+        let stale = socket.last_request();
+        ticks.advance(Config::DEFAULT.timeout);
+        assert_eq!(sync.poll(&mut socket), Err(Error::Timeout(SERVER)));
+        ticks.advance(Duration::from_secs(2));
+        sync.poll(&mut socket).unwrap();
+        let current = socket.last_request();
+        assert_ne!(stale.transmit, current.transmit);
+
+        // a late reply to the first request, one from somewhere else, then the real one
+        ticks.advance(Duration::from_millis(4));
+        socket.reply(&stale, Duration::from_millis(2), SERVER);
+        let elsewhere = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), NTP_PORT);
+        socket.reply(&current, Duration::from_millis(2), elsewhere);
+        socket.reply(&current, Duration::from_millis(2), SERVER);
+        let result = sync.poll(&mut socket).unwrap().unwrap();
+        assert_eq!(result.offset, OFFSET.as_micros() as i64);
+        assert_eq!(result.roundtrip, 4000);
+        assert!(socket.inbox.is_empty());
+
+        // replies with nothing outstanding are dropped too
+        socket.reply(&current, Duration::from_millis(2), SERVER);
+        assert_eq!(sync.poll(&mut socket), Ok(None));
+        assert!(socket.inbox.is_empty());
+        assert_eq!(
+            sync.handle_receive(&Packet::default().to_bytes(), SERVER),
+            Err(Error::UnexpectedReply)
+        );
+    }
+
+    #[test]
+    fn rejects_unsynchronized_replies() {
+        let (mut sync, ticks, mut socket) = time_sync();
+        for (leap, stratum) in [(LEAP_UNSYNCHRONIZED, 2), (0, 0)] {
+            sync.poll(&mut socket).unwrap();
+            let request = socket.last_request();
+            socket.reply(&request, Duration::from_millis(1), SERVER);
+            let (mut reply, from) = socket.inbox.pop_back().unwrap();
+            reply[0] = reply[0] & 0x3f | leap << 6;
+            reply[1] = stratum;
+            socket.inbox.push_back((reply, from));
+            ticks.advance(Duration::from_millis(2));
+            assert_eq!(sync.poll(&mut socket), Err(Error::Unsynchronized));
+            assert!(!sync.is_synced());
+            assert_eq!(sync.selected(), None);
+            ticks.advance(Duration::from_secs(10));
+        }
+        assert_eq!(sync.peers()[0].failures(), 2);
+
+        // a client packet isn't a reply
+        sync.poll(&mut socket).unwrap();
+        let request = socket.last_request();
+        assert_eq!(
+            sync.handle_receive(&request.to_bytes(), SERVER),
+            Err(Error::InvalidPacket)
+        );
+        assert_eq!(
+            sync.handle_receive(&request.to_bytes()[..40], SERVER),
+            Err(Error::InvalidPacket)
+        );
+    }
+
+    /// the same against a fake server on a loopback std::net::UdpSocket
+    #[test]
+    fn std_socket() {
+        use std::net::UdpSocket;
+        use std::time::Instant;
+
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let std::net::SocketAddr::V4(server_addr) = server.local_addr().unwrap() else {
+            panic!("bound to ipv4");
+        };
+        let serving = std::thread::spawn(move || {
+            let mut buf = [0; PACKET_SIZE];
+            let (num, from) = server.recv_from(&mut buf).unwrap();
+            let request = Packet::from_bytes(&buf[..num]).unwrap();
+            let server_time = Epoch::from(OFFSET).to_ntp();
+            let reply = Packet {
+                version: 4,
+                mode: MODE_SERVER,
+                stratum: 1,
+                originate: request.transmit,
+                receive: server_time,
+                transmit: server_time,
+                ..Default::default()
+            };
+            server.send_to(&reply.to_bytes(), from).unwrap();
+        });
+
+        let mut socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let mut sync = TimeSync::new(
+            std_impl::InstantTicks::default(),
+            &[server_addr],
+            Config::DEFAULT,
+        );
+        let start = Instant::now();
+        let result = loop {
+            if let Some(result) = sync.poll(&mut socket).unwrap() {
+                break result;
+            }
+            assert!(start.elapsed() < Duration::from_secs(1), "no reply");
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        serving.join().unwrap();
+        // the ticks started at zero just before the request
+        let offset = OFFSET.as_micros() as i64;
+        assert!(
+            (offset - 1_000_000..=offset).contains(&result.offset),
+            "{}",
+            result.offset
+        );
+        assert!(result.selected);
+        assert!(sync.now() > Epoch::from(OFFSET));
+        assert!(sync.is_synced());
+    }
+}
@@ -0,0 +1,84 @@
+/*!
+The 48 byte (s)ntp v4 packet, rfc 5905 figure 8, without the optional extension fields
+*/
+
+pub const PACKET_SIZE: usize = 48;
+pub const NTP_PORT: u16 = 123;
+
+pub const VERSION: u8 = 4;
+pub const MODE_CLIENT: u8 = 3;
+pub const MODE_SERVER: u8 = 4;
+/// leap indicator for a server that isn't synchronized itself
+pub const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// the timestamps are 64 bit ntp fixed point, see Epoch::to_ntp()
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Packet {
+    pub leap: u8,
+    pub version: u8,
+    pub mode: u8,
+    /// 0 is a kiss-o'-death, the reference_id says why
+    pub stratum: u8,
+    pub poll: i8,
+    pub precision: i8,
+    pub root_delay: u32,
+    pub root_dispersion: u32,
+    pub reference_id: u32,
+    pub reference: u64,
+    /// the client transmit time copied back by the server
+    pub originate: u64,
+    /// when the server received the request
+    pub receive: u64,
+    /// when the packet was sent
+    pub transmit: u64,
+}
+
+impl Packet {
+    /// a client request, the server will copy transmit into originate in the reply
+    pub fn request(transmit: u64) -> Self {
+        Self {
+            version: VERSION,
+            mode: MODE_CLIENT,
+            transmit,
+            ..Default::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; PACKET_SIZE] {
+        let mut bytes = [0; PACKET_SIZE];
+        bytes[0] = (self.leap & 0x3) << 6 | (self.version & 0x7) << 3 | (self.mode & 0x7);
+        bytes[1] = self.stratum;
+        bytes[2] = self.poll as u8;
+        bytes[3] = self.precision as u8;
+        bytes[4..8].copy_from_slice(&self.root_delay.to_be_bytes());
+        bytes[8..12].copy_from_slice(&self.root_dispersion.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.reference_id.to_be_bytes());
+        bytes[16..24].copy_from_slice(&self.reference.to_be_bytes());
+        bytes[24..32].copy_from_slice(&self.originate.to_be_bytes());
+        bytes[32..40].copy_from_slice(&self.receive.to_be_bytes());
+        bytes[40..48].copy_from_slice(&self.transmit.to_be_bytes());
+        bytes
+    }
+
+    /// None if too short, anything after the first 48 bytes is ignored
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let bytes: &[u8; PACKET_SIZE] = bytes.get(..PACKET_SIZE)?.try_into().ok()?;
+        let u32_at = |i: usize| u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_be_bytes(bytes[i..i + 8].try_into().unwrap());
+        Some(Self {
+            leap: bytes[0] >> 6,
+            version: (bytes[0] >> 3) & 0x7,
+            mode: bytes[0] & 0x7,
+            stratum: bytes[1],
+            poll: bytes[2] as i8,
+            precision: bytes[3] as i8,
+            root_delay: u32_at(4),
+            root_dispersion: u32_at(8),
+            reference_id: u32_at(12),
+            reference: u64_at(16),
+            originate: u64_at(24),
+            receive: u64_at(32),
+            transmit: u64_at(40),
+        })
+    }
+}
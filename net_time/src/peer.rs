@@ -0,0 +1,131 @@
+/*!
+Per server state and the choice of which server disciplines the clock
+
+Each server is polled independently with its own backoff and an 8 bit reachability register
+like ntpd's, shifted left on every request and with the low bit set when a good reply comes
+back.  Of the servers that answered recently the lowest stratum wins, then the lowest root
+distance (half the round trip, the server's own root delay and dispersion, and the jitter of
+its offsets).  The current choice is kept unless it stops answering or another server is a lot
+better, so two servers that disagree slightly don't take turns pulling the clock around.
+*/
+
+use core::net::{Ipv4Addr, SocketAddrV4};
+
+use net_common::Epoch;
+
+use crate::NtpResult;
+
+/// more servers than this are ignored
+pub const MAX_SERVERS: usize = 4;
+
+/// a server has to have answered one of this many of its latest requests to be selected
+const CANDIDATE_REACH_MASK: u8 = 0b0000_0111;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum State {
+    Idle { next_poll: Epoch },
+    Waiting { sent: Epoch, originate: u64 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Peer {
+    addr: SocketAddrV4,
+    pub(crate) state: State,
+    /// the ntp reachability register, the low bit is the latest request
+    pub(crate) reach: u8,
+    /// consecutive failed exchanges
+    pub(crate) failures: u32,
+    pub(crate) last_result: Option<NtpResult>,
+    /// half the round trip plus the server's root delay and dispersion from the last reply
+    pub(crate) root_distance_ns: u64,
+    /// smoothed difference between successive offsets
+    pub(crate) jitter_ns: u64,
+}
+
+impl Peer {
+    pub(crate) const EMPTY: Peer = Peer::new(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+
+    pub(crate) const fn new(addr: SocketAddrV4) -> Self {
+        Self {
+            addr,
+            state: State::Idle {
+                next_poll: Epoch::ZERO,
+            },
+            reach: 0,
+            failures: 0,
+            last_result: None,
+            root_distance_ns: 0,
+            jitter_ns: 0,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddrV4 {
+        self.addr
+    }
+
+    pub fn reach(&self) -> u8 {
+        self.reach
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn last_result(&self) -> Option<NtpResult> {
+        self.last_result
+    }
+
+    /// the worst case error of this server's time as seen from here, plus its jitter
+    pub fn distance_ns(&self) -> u64 {
+        self.root_distance_ns.saturating_add(self.jitter_ns)
+    }
+
+    /// answered recently enough to be selected
+    pub fn is_candidate(&self) -> bool {
+        self.reach & CANDIDATE_REACH_MASK != 0 && self.last_result.is_some()
+    }
+
+    pub(crate) fn update_jitter(&mut self, offset_ns: i64) {
+        if let Some(last) = self.last_result {
+            let diff = offset_ns
+                .saturating_sub(last.sample.offset_ns)
+                .unsigned_abs();
+            self.jitter_ns = (self.jitter_ns.saturating_mul(3).saturating_add(diff)) / 4;
+        }
+    }
+
+    /// lower is better
+    fn rank(&self) -> (u8, u64) {
+        let stratum = self
+            .last_result
+            .map(|result| result.stratum)
+            .unwrap_or(u8::MAX);
+        (stratum, self.distance_ns())
+    }
+}
+
+/// ntp short format (16.16 seconds) to nanoseconds
+pub(crate) fn short_to_nanos(short: u32) -> u64 {
+    (short as u64 * 1_000_000_000) >> 16
+}
+
+/// the index of the server to discipline the clock with, None if none have answered recently
+pub(crate) fn select(peers: &[Peer], current: Option<usize>) -> Option<usize> {
+    let best = peers
+        .iter()
+        .enumerate()
+        .filter(|(_, peer)| peer.is_candidate())
+        .min_by_key(|(_, peer)| peer.rank())
+        .map(|(index, _)| index)?;
+
+    let Some(current) = current.filter(|&index| peers[index].is_candidate()) else {
+        return Some(best);
+    };
+    let (best_stratum, best_distance) = peers[best].rank();
+    let (current_stratum, current_distance) = peers[current].rank();
+    if best_stratum < current_stratum || best_distance < current_distance / 2 {
+        Some(best)
+    } else {
+        Some(current)
+    }
+}
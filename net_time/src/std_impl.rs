@@ -0,0 +1,68 @@
+/*!
+TickSource and NtpUdpSocket for std, so the same sync logic runs on a computer
+*/
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, SocketAddrV4, UdpSocket};
+use std::time::Instant;
+
+use net_common::Epoch;
+
+use crate::{NtpUdpSocket, TickSource};
+
+/// time since creation, like the tick count since boot on a board
+pub struct InstantTicks {
+    start: Instant,
+}
+
+impl Default for InstantTicks {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl TickSource for InstantTicks {
+    fn now(&self) -> Epoch {
+        self.start.elapsed().into()
+    }
+}
+
+/// the system clock, the ntp offset is then how far off the system clock is
+#[derive(Default)]
+pub struct SystemTicks;
+
+impl TickSource for SystemTicks {
+    fn now(&self) -> Epoch {
+        Epoch::now()
+    }
+}
+
+/// the socket needs set_nonblocking(true), or a short read timeout
+impl NtpUdpSocket for UdpSocket {
+    type Error = std::io::Error;
+
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddrV4) -> Result<(), Self::Error> {
+        UdpSocket::send_to(self, buf, addr)?;
+        Ok(())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddrV4)>, Self::Error> {
+        loop {
+            match UdpSocket::recv_from(self, buf) {
+                Ok((num, SocketAddr::V4(src))) => {
+                    return Ok(Some((num, src)));
+                }
+                // ntp over ipv6 isn't supported, drop it
+                Ok((_, SocketAddr::V6(_))) => {}
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return Ok(None);
+                }
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,3 @@
+#!/bin/bash
+# release, it has to fit in the first flash sector (see memory.x)
+cargo build --release
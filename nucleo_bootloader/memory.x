@@ -0,0 +1,20 @@
+/* the same partitions as ../nucleo_embassy/memory.x, with the bootloader as the FLASH that gets
+   linked and the firmware it starts as the active partition */
+MEMORY
+{
+  FLASH            : ORIGIN = 0x08000000, LENGTH = 128K
+  BOOTLOADER_STATE : ORIGIN = 0x08020000, LENGTH = 128K
+  ACTIVE           : ORIGIN = 0x08040000, LENGTH = 640K
+  DFU              : ORIGIN = 0x08100000, LENGTH = 768K
+  RAM        (rwx) : ORIGIN = 0x24000000, LENGTH = 512K
+}
+
+/* offsets from the start of flash, see BootLoaderConfig::from_linkerfile_blocking() */
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(FLASH);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(FLASH);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(FLASH);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(FLASH);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(FLASH);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(FLASH);
@@ -0,0 +1,45 @@
+/*!
+The embassy-boot bootloader nucleo_embassy runs after, from the first flash sector
+
+At every reset it swaps in the firmware update waiting in the dfu partition if the state
+partition says there is one, or swaps the previous firmware back if the update was never marked
+booted, then starts the firmware in the active partition.  The partitions are in memory.x and
+have to match the ones nucleo_embassy is linked with.
+*/
+
+#![no_main]
+#![no_std]
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{ExceptionFrame, entry, exception};
+use embassy_boot_stm32::{BootLoader, BootLoaderConfig};
+use embassy_stm32::flash::{FLASH_BASE, Flash};
+use embassy_sync::blocking_mutex::Mutex;
+
+/// the swap copies a page at a time through a buffer on the stack, it has to be a whole erase
+/// sector
+const PAGE_SIZE: usize = 128 * 1024;
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_stm32::init(Default::default());
+
+    // all of the flash rather than one bank, the dfu partition is in bank 2
+    let flash = Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH)));
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bootloader = BootLoader::prepare::<_, _, _, PAGE_SIZE>(config);
+    unsafe { bootloader.load(FLASH_BASE as u32 + active_offset) }
+}
+
+#[exception]
+unsafe fn HardFault(_frame: &ExceptionFrame) -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+// an interrupted swap carries on at the next reset
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
@@ -1,41 +1,17 @@
-use std::fs::File;
-use std::io::Write;
-use std::net::Ipv4Addr;
-use std::path::Path;
-use std::{env, option_env};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
 
 fn main() {
-    let out_dir = env::var("OUT_DIR").expect("No out dir");
-    let dest_path = Path::new(&out_dir).join("constants.rs");
-    let mut f = File::create(&dest_path).expect("Could not create file");
+    // CONFIG from the BOARD_CONFIG file, GIT_HASH and BUILD_TIME_SECS, see net_common
+    // board_config
+    net_common::board_config::build_script();
 
-    {
-        let ip_string = {
-            // set environmental variable to your IP address
-            let ip_string = option_env!("REMOTE_IP");
-            ip_string.unwrap_or("192.168.0.100")
-        };
-        let ipv4_addr: Ipv4Addr = ip_string.parse().expect("Invalid IPv4 address");
-        // Get the 4-byte octet representation
-        let octets: [u8; 4] = ipv4_addr.octets();
-        println!(
-            "cargo:warning=remote ip (should be the computer linked to the target device): {octets:?}"
-        );
-        write!(&mut f, "pub const REMOTE_IP: [u8; 4] = {octets:?};").expect("Could not write file");
+    // the board is linked to start after the bootloader, where cortex-m-rt's link.x finds it
+    if env::var_os("CARGO_FEATURE_BOARD").is_some() {
+        let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+        fs::copy("memory.x", out_dir.join("memory.x")).unwrap();
+        println!("cargo:rustc-link-search={}", out_dir.display());
     }
-
-    {
-        let ip_string = {
-            // set environmental variable to the ip the device will have
-            let ip_string = option_env!("LOCAL_IP");
-            ip_string.unwrap_or("192.168.0.123")
-        };
-        let ipv4_addr: Ipv4Addr = ip_string.parse().expect("Invalid IPv4 address");
-        // Get the 4-byte octet representation
-        let octets: [u8; 4] = ipv4_addr.octets();
-        println!("cargo:warning=local ip (set to the desired ip of the target device): {octets:?}");
-        write!(&mut f, "pub const LOCAL_IP: [u8; 4] = {octets:?};").expect("Could not write file");
-    }
-
-    println!("cargo:rerun-if-env-changed=REMOTE_IP");
+    println!("cargo:rerun-if-changed=memory.x");
 }
@@ -1,2 +1,7 @@
 #!/bin/bash
-REMOTE_IP=`ip a | grep enp4s0 | tail -n 1 | awk '{print $2}' | sed -e "s/\/.*//"` cargo build
+set -e
+# the bootloader the firmware is linked to start after, see ../nucleo_bootloader
+(cd ../nucleo_bootloader && ./cargo_build.sh)
+# the board settings are in boards/default.toml, or another file in boards/ given with
+# BOARD_CONFIG
+BOARD_CONFIG=${BOARD_CONFIG:-../boards/default.toml} cargo build
@@ -0,0 +1,21 @@
+/* the stm32h753zi flash as laid out for the embassy-boot bootloader in ../nucleo_bootloader,
+   which has to use the same partitions, in 128KB sectors:
+   the bootloader, its state, this firmware (the active partition) and the dfu partition a
+   sector larger than it for the swap, the last two sectors of bank 2 hold the saved
+   parameters (see SETTINGS_OFFSET in src/platform/stm32.rs) */
+MEMORY
+{
+  BOOTLOADER       : ORIGIN = 0x08000000, LENGTH = 128K
+  BOOTLOADER_STATE : ORIGIN = 0x08020000, LENGTH = 128K
+  FLASH            : ORIGIN = 0x08040000, LENGTH = 640K
+  DFU              : ORIGIN = 0x08100000, LENGTH = 768K
+  /* axi sram, the ethernet dma can't reach the dtcm */
+  RAM        (rwx) : ORIGIN = 0x24000000, LENGTH = 512K
+}
+
+/* offsets from the start of flash, see FirmwareUpdaterConfig::from_linkerfile_blocking() */
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
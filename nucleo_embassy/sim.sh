@@ -0,0 +1,14 @@
+#!/bin/bash
+# run the firmware on linux against a tap interface instead of the board, the host side of the
+# tap is 192.168.69.1 and the simulated board 192.168.69.2
+set -e
+TAP_IFACE=${TAP_IFACE:-tap0}
+if ! ip link show $TAP_IFACE > /dev/null 2>&1; then
+    sudo ip tuntap add name $TAP_IFACE mode tap user $USER
+    sudo ip link set $TAP_IFACE up
+    sudo ip addr add 192.168.69.1/24 dev $TAP_IFACE
+fi
+# the addresses above and a fake ntp server on an unprivileged port
+export BOARD_CONFIG=${BOARD_CONFIG:-../boards/sim.toml}
+export TAP_IFACE
+cargo run --no-default-features --features sim --target x86_64-unknown-linux-gnu "$@"
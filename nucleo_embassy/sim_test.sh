@@ -0,0 +1,33 @@
+#!/bin/bash
+# build and run the simulation, then check it answers the net_loopback tools, for ci
+set -e
+cd "$(dirname "$0")"
+(cd ../net_loopback && cargo build --bins)
+TOOLS=../net_loopback/target/debug
+
+# a committed firmware update ends up here instead of with a bootloader
+IMAGE=$(mktemp)
+export DFU_FILE=$(mktemp)
+
+$TOOLS/sntp --serve -l 192.168.69.1 --port 12300 &
+SNTP_PID=$!
+./sim.sh --quiet &
+SIM_PID=$!
+trap "kill $SNTP_PID $SIM_PID 2> /dev/null; rm -f $IMAGE $DFU_FILE" EXIT
+
+# give it time to build and start
+for i in $(seq 60); do
+    if $TOOLS/config -l 192.168.69.1 -r 192.168.69.2 get ntp_poll_ms 2> /dev/null | grep -q "= 1000"; then
+        break
+    fi
+    sleep 2
+done
+$TOOLS/config -l 192.168.69.1 -r 192.168.69.2 set led_pattern fast | grep "led_pattern = fast"
+# pings are answered to the sender with the board receive and transmit times
+timeout 30 $TOOLS/timestamp_txrx -l 192.168.69.1 -r 192.168.69.2 | grep -m 1 "elapsed avg"
+# last since the simulation exits after a commit, the way the board resets
+head -c 100000 /dev/urandom > $IMAGE
+$TOOLS/firmware_update -l 192.168.69.1 -r 192.168.69.2 $IMAGE
+wait $SIM_PID
+cmp -n 100000 $IMAGE $DFU_FILE
+echo "sim test passed"
@@ -0,0 +1,188 @@
+/*!
+The ethernet device wrapped to stamp udp frames as they go through the driver, and a udp socket
+that gives out those stamps with its packets, see net_common::hwstamp
+
+The stamps aren't delayed by how long it takes the task waiting on the socket to run, only by
+how long the network runner takes to get to the driver.
+*/
+
+use core::cell::RefCell;
+use core::task::Context;
+
+use embassy_net::udp::{BindError, RecvError, SendError, UdpMetadata, UdpSocket};
+use embassy_net_driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, with_timeout};
+use net_common::health::TaskId;
+use net_common::hwstamp::{HwClock, Stamp, Stamps};
+use net_time::TickSource;
+
+use crate::{EmbassyTicks, supervisor};
+
+// the longest send_to_stamped() waits for its packet to reach the driver, e.g. while the
+// remote mac address is looked up
+const FLUSH_TIMEOUT_MS: u64 = 10;
+
+static STAMPS: Mutex<CriticalSectionRawMutex, RefCell<Stamps>> =
+    Mutex::new(RefCell::new(Stamps::new()));
+
+/// a Driver that records when the udp frames went through it
+pub struct StampingDevice<D, C> {
+    inner: D,
+    clock: RefCell<C>,
+}
+
+impl<D, C> StampingDevice<D, C> {
+    pub fn new(inner: D, clock: C) -> Self {
+        Self {
+            inner,
+            clock: RefCell::new(clock),
+        }
+    }
+}
+
+pub struct StampingRxToken<'a, T, C> {
+    inner: T,
+    clock: &'a RefCell<C>,
+}
+
+pub struct StampingTxToken<'a, T, C> {
+    inner: T,
+    clock: &'a RefCell<C>,
+}
+
+impl<T: RxToken, C: HwClock> RxToken for StampingRxToken<'_, T, C> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.inner.consume(|frame| {
+            let local_now = EmbassyTicks.now();
+            let mut clock = self.clock.borrow_mut();
+            STAMPS.lock(|stamps| {
+                stamps
+                    .borrow_mut()
+                    .frame_received(&mut *clock, local_now, frame)
+            });
+            f(frame)
+        })
+    }
+}
+
+impl<T: TxToken, C: HwClock> TxToken for StampingTxToken<'_, T, C> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        self.inner.consume(len, |frame| {
+            // the frame is only filled in by f
+            let rv = f(frame);
+            let local_now = EmbassyTicks.now();
+            let mut clock = self.clock.borrow_mut();
+            STAMPS.lock(|stamps| {
+                stamps
+                    .borrow_mut()
+                    .frame_sent(&mut *clock, local_now, frame)
+            });
+            rv
+        })
+    }
+}
+
+impl<D: Driver, C: HwClock> Driver for StampingDevice<D, C> {
+    type RxToken<'a>
+        = StampingRxToken<'a, D::RxToken<'a>, C>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = StampingTxToken<'a, D::TxToken<'a>, C>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.inner.receive(cx)?;
+        Some((
+            StampingRxToken {
+                inner: rx,
+                clock: &self.clock,
+            },
+            StampingTxToken {
+                inner: tx,
+                clock: &self.clock,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        Some(StampingTxToken {
+            inner: self.inner.transmit(cx)?,
+            clock: &self.clock,
+        })
+    }
+
+    fn link_state(&mut self, cx: &mut Context) -> LinkState {
+        // the runner asks on every poll of the stack, so this shows it is still making progress
+        // and not parked on a driver that stopped waking it
+        supervisor::check_in(TaskId::Net);
+        self.inner.link_state(cx)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        self.inner.hardware_address()
+    }
+}
+
+/// a UdpSocket whose packets come with the stamps from a StampingDevice, or the tick count
+/// when the socket returned if there isn't one
+pub struct StampedSocket<'a> {
+    socket: UdpSocket<'a>,
+    port: u16,
+}
+
+impl<'a> StampedSocket<'a> {
+    pub fn bind(mut socket: UdpSocket<'a>, port: u16) -> Result<Self, BindError> {
+        socket.bind(port)?;
+        Ok(Self { socket, port })
+    }
+
+    pub async fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Result<(usize, UdpMetadata, Stamp), RecvError> {
+        let (num, meta) = self.socket.recv_from(buf).await?;
+        let software = EmbassyTicks.now();
+        let stamp = STAMPS.lock(|stamps| stamps.borrow_mut().take_received(self.port, &buf[..num]));
+        Ok((num, meta, stamp.unwrap_or(Stamp::software(software))))
+    }
+
+    /// without waiting for the stamp
+    pub async fn send_to(
+        &mut self,
+        buf: &[u8],
+        remote: impl Into<UdpMetadata>,
+    ) -> Result<(), SendError> {
+        self.socket.send_to(buf, remote).await
+    }
+
+    /// send and wait for the packet to go through the driver to get its stamp
+    pub async fn send_to_stamped(
+        &mut self,
+        buf: &[u8],
+        remote: impl Into<UdpMetadata>,
+    ) -> Result<Stamp, SendError> {
+        self.socket.send_to(buf, remote).await?;
+        let flushed =
+            with_timeout(Duration::from_millis(FLUSH_TIMEOUT_MS), self.socket.flush()).await;
+        let software = EmbassyTicks.now();
+        let stamp = match flushed {
+            Ok(()) => STAMPS.lock(|stamps| stamps.borrow_mut().take_sent(self.port, buf)),
+            Err(_) => None,
+        };
+        Ok(stamp.unwrap_or(Stamp::software(software)))
+    }
+}
@@ -1,183 +1,305 @@
 #![no_std]
 
-use core::alloc::{GlobalAlloc, Layout};
-use core::cell::UnsafeCell;
-// use core::net::{IpAddr, Ipv4Addr};
-use core::ptr::null_mut;
-use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+#[cfg(feature = "sim")]
+extern crate std;
 
-use cortex_m_semihosting::hprintln;
+#[cfg(all(feature = "board", feature = "sim"))]
+compile_error!("the board and sim features are exclusive, use --no-default-features for sim");
+#[cfg(not(any(feature = "board", feature = "sim")))]
+compile_error!("one of the board or sim features is needed");
 
+use core::cell::RefCell;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::time::Duration;
+
+#[cfg(feature = "board")]
+use board_heap::LockedHeap;
 use embassy_executor::task;
-use embassy_net::udp::{PacketMetadata, UdpMetadata, UdpSocket};
-use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV4, IpAddress, IpEndpoint, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::Mutex;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::watch::Watch;
-use embassy_time::{Instant, Timer};
-
-use net_common::Epoch;
-
-use sntpc::net::SocketAddr;
-use sntpc::{
-    NtpContext, NtpResult, NtpTimestampGenerator, NtpUdpSocket,
-    Result,
-};
-use sntpc::sync::get_ntp_correction;
+use embassy_time::{Instant, TimeoutError, Timer, with_deadline};
+use log::{info, warn};
+
+use net_common::board::Identity;
+use net_common::board_config::split_port;
+use net_common::command::{Board, Dispatcher, Response};
+use net_common::config::Params;
+use net_common::discipline::Sample;
+use net_common::firmware::{Dfu, Updater};
+use net_common::health::TaskId;
+use net_common::netconfig::{AddressPolicy, Host, Ipv4Settings, MAX_DNS_SERVERS};
+use net_common::pubsub::MULTICAST_PORT;
+use net_common::{BoardInfo, Epoch, Health, HeapStats, Message, Topic};
+
+use net_time::packet::{NTP_PORT, PACKET_SIZE};
+use net_time::{Config, MAX_SERVERS, NtpResult, TickSource, TimeSync};
+
+pub mod hwstamp;
+pub mod net_log;
+pub mod platform;
+pub mod settings;
+pub mod supervisor;
+pub mod telemetry;
 
 include!(concat!(env!("OUT_DIR"), "/constants.rs"));
 
 // with more subscribers increase the '1' here
 pub static NTP_WATCH: Watch<CriticalSectionRawMutex, NtpResult, 1> = Watch::new();
 
+// runtime parameters, changed by ConfigSet messages received in main
+pub static PARAMS: Mutex<CriticalSectionRawMutex, RefCell<Params>> =
+    Mutex::new(RefCell::new(CONFIG.params()));
+
+// the sntp exchanges and the disciplined clock now() returns, time_sync sets the servers
+pub static CLOCK: Mutex<CriticalSectionRawMutex, RefCell<TimeSync<EmbassyTicks>>> =
+    Mutex::new(RefCell::new(TimeSync::new(EmbassyTicks, &[], Config::DEFAULT)));
+
+/// a copy of the current runtime parameters
+pub fn params() -> Params {
+    PARAMS.lock(|params| *params.borrow())
+}
+
+// the simulation uses the std allocator
+#[cfg(feature = "board")]
 const ARENA_SIZE: usize = 128 * 1024;
-const MAX_SUPPORTED_ALIGN: usize = 4096;
-#[repr(C, align(4096))] // 4096 == MAX_SUPPORTED_ALIGN
-struct SimpleAllocator {
-    arena: UnsafeCell<[u8; ARENA_SIZE]>,
-    remaining: AtomicUsize, // we allocate from the top, counting down
+
+#[cfg(feature = "board")]
+#[global_allocator]
+static ALLOCATOR: LockedHeap<ARENA_SIZE> = LockedHeap::new();
+
+/// heap usage to answer a HeapStatsGet with
+#[cfg(feature = "board")]
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.stats()
 }
 
-unsafe impl Sync for SimpleAllocator {}
+/// all zero, the simulation uses the std allocator
+#[cfg(feature = "sim")]
+pub fn heap_stats() -> HeapStats {
+    HeapStats::default()
+}
 
-unsafe impl GlobalAlloc for SimpleAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let size = layout.size();
-        let align = layout.align();
+/// to answer a BoardInfoGet with
+pub fn board_info(identity: &Identity) -> BoardInfo {
+    let build_time = Epoch::from(Duration::from_secs(BUILD_TIME_SECS));
+    BoardInfo::new(identity, GIT_HASH, build_time, Instant::now().as_millis())
+}
 
-        // `Layout` contract forbids making a `Layout` with align=0, or align not power of 2.
-        // So we can safely use a mask to ensure alignment without worrying about UB.
-        let align_mask_to_round_down = !(align - 1);
+// the net_common commands answered from the statics, the locks are only held for each call so
+// the firmware updater isn't writing flash inside a critical section
+struct Firmware<'a, D> {
+    stack: Stack<'static>,
+    identity: &'a Identity,
+    updater: Option<&'a mut Updater<D>>,
+    // a copy of PARAMS for ConfigSet to change
+    params: Params,
+}
 
-        if align > MAX_SUPPORTED_ALIGN {
-            return null_mut();
-        }
+impl<D: Dfu> Board for Firmware<'_, D> {
+    fn corrected(&mut self, local: Epoch) -> Epoch {
+        CLOCK.lock(|clock| clock.borrow_mut().corrected(local))
+    }
 
-        let mut allocated = 0;
-        if self
-            .remaining
-            .fetch_update(Relaxed, Relaxed, |mut remaining| {
-                if size > remaining {
-                    return None;
-                }
-                remaining -= size;
-                remaining &= align_mask_to_round_down;
-                allocated = remaining;
-                Some(remaining)
-            })
-            .is_err()
-        {
-            return null_mut();
-        }
+    fn local_now(&self) -> Epoch {
+        EmbassyTicks.now()
+    }
 
-        unsafe { self.arena.get().cast::<u8>().add(allocated) }
+    fn add_sample(&mut self, sample: Sample) {
+        CLOCK.lock(|clock| clock.borrow_mut().add_sample(sample));
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        todo!()
+    fn params(&mut self) -> &mut Params {
+        &mut self.params
     }
 
-    unsafe fn realloc(&self, _ptr: *mut u8, _layout: Layout, _new_size: usize) -> *mut u8 {
-        todo!()
+    fn heap_stats(&self) -> HeapStats {
+        heap_stats()
+    }
+
+    fn board_info(&self) -> BoardInfo {
+        board_info(self.identity)
+    }
+
+    fn params_changed(&mut self) {
+        PARAMS.lock(|params| *params.borrow_mut() = self.params);
+        settings::save_later(self.params);
+    }
+
+    fn health(&mut self) -> Option<Health> {
+        Some(supervisor::health(self.stack))
+    }
+
+    // TODO(lucasw) erasing each 128KB sector of the dfu partition blocks the executor for
+    // around a second, time sync and the other tasks stall while an update is received
+    fn firmware_update(&mut self, msg: &Message) -> Option<Message> {
+        self.updater.as_mut()?.handle(msg)
     }
 }
 
-#[global_allocator]
-static ALLOCATOR: SimpleAllocator = SimpleAllocator {
-    arena: UnsafeCell::new([0x55; ARENA_SIZE]),
-    remaining: AtomicUsize::new(ARENA_SIZE),
-};
-
-// nucleo-h7xx ethernet.rs says systick timer is 1ms
-// adapted from sntpc examples simple-no-std + smoltcp
-#[derive(Copy, Clone)]
-pub struct TimestampGen {
-    pub instant: Instant,
+/// answer a packet received on the command port, see net_common::command.  A ConfigSet changes
+/// PARAMS and saves them later, reset the board after replying if the updater is Committed
+pub fn dispatch<D: Dfu>(
+    dispatcher: &mut Dispatcher,
+    rx: &[u8],
+    rx_local: Epoch,
+    stack: Stack<'static>,
+    identity: &Identity,
+    updater: Option<&mut Updater<D>>,
+    crc: &crc::Crc<u32>,
+) -> Response {
+    let mut board = Firmware {
+        stack,
+        identity,
+        updater,
+        params: params(),
+    };
+    dispatcher.handle(rx, rx_local, &mut board, crc.digest())
 }
 
-impl Default for TimestampGen {
-    fn default() -> Self {
-        Self {
-            instant: Instant::from_ticks(0),
-        }
+/// the embassy tick count since boot as the local clock for net_time
+#[derive(Copy, Clone, Default)]
+pub struct EmbassyTicks;
+
+impl TickSource for EmbassyTicks {
+    fn now(&self) -> Epoch {
+        local_epoch(Instant::now())
     }
 }
 
-impl NtpTimestampGenerator for TimestampGen {
-    fn init(&mut self) {
-        self.instant = Instant::now();
+/// the settings from the board config used without dhcp, with the local_ip parameter
+pub fn static_settings() -> Ipv4Settings {
+    Ipv4Settings {
+        address: params().local_ip,
+        ..CONFIG.static_settings()
     }
+}
 
-    fn timestamp_sec(&self) -> u64 {
-        self.instant.as_secs()
+pub fn static_config(settings: &Ipv4Settings) -> StaticConfigV4 {
+    StaticConfigV4 {
+        address: Ipv4Cidr::new(Ipv4Addr::from(settings.address), settings.prefix_len),
+        gateway: settings.gateway(),
+        dns_servers: settings.dns_servers().collect(),
     }
+}
 
-    fn timestamp_subsec_micros(&self) -> u32 {
-        (self.instant - Instant::from_secs(self.timestamp_sec())).as_micros() as u32
+fn settings_from(config: &StaticConfigV4) -> Ipv4Settings {
+    let mut dns_servers = [[0; 4]; MAX_DNS_SERVERS];
+    for (dns_server, ip) in dns_servers.iter_mut().zip(&config.dns_servers) {
+        *dns_server = ip.octets();
+    }
+    Ipv4Settings {
+        address: config.address.address().octets(),
+        prefix_len: config.address.prefix_len(),
+        gateway: config.gateway.map(|ip| ip.octets()).unwrap_or_default(),
+        dns_servers,
     }
 }
 
-pub struct EmbassyUdpSocketWrapper<'a> {
-    // TODO(lucasw) use something other than ref cell?
-    // pub socket: RefCell<&'b mut UdpSocket<'a>>,
-    pub socket: UdpSocket<'a>,
+/// an ip as is, or the first address dns returns for a name
+pub async fn resolve(stack: Stack<'static>, host: &str) -> Option<Ipv4Addr> {
+    resolve_host(stack, Host::parse(host)?).await
 }
 
-impl NtpUdpSocket for EmbassyUdpSocketWrapper<'_> {
-    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
-        let endpoint = match addr {
-            SocketAddr::V4(v4_ip_and_port) => {
-                let v4 = v4_ip_and_port.ip().octets();
-                IpEndpoint::new(
-                    Ipv4Address::new(v4[0], v4[1], v4[2], v4[3]).into(),
-                    v4_ip_and_port.port(),
-                )
+/// the address of a parsed host, None if the lookup fails
+async fn resolve_host(stack: Stack<'static>, host: Host<'_>) -> Option<Ipv4Addr> {
+    match host {
+        Host::Ip(ip) => Some(ip),
+        Host::Name(name) => match stack.dns_query(name, DnsQueryType::A).await {
+            Ok(addrs) => addrs.iter().find_map(|addr| match addr {
+                IpAddress::Ipv4(ip) => Some(*ip),
+            }),
+            Err(err) => {
+                warn!("dns lookup of {} failed {:?}", name, err);
+                None
             }
-            SocketAddr::V6(_) => return Err(sntpc::Error::Network),
-        };
-
-        let local_ip_addr = IpAddress::Ipv4(Ipv4Address::new(
-            LOCAL_IP[0],
-            LOCAL_IP[1],
-            LOCAL_IP[2],
-            LOCAL_IP[3],
-        ));
-        let udp_endpoint = UdpMetadata {
-            // TODO(lucasw) experiment with broadcast
-            // 255 has the same behavior as with nucleo-h7xx- one packet is received then no more
-            endpoint,
-            local_address: Some(local_ip_addr),
-            meta: smoltcp::phy::PacketMeta::default(),
-        };
-
-        let rv = self.socket.send_to(buf, udp_endpoint).await;
-        if rv.is_ok() {
-            return Ok(buf.len());
-        }
-        hprintln!("{:?}", rv);
-
-        Err(sntpc::Error::Network)
+        },
     }
+}
 
-    async fn recv_from(
-        &self,
-        buf: &mut [u8],
-        // ) -> Result<(usize, SocketAddr), sntpc::Error> {
-    ) -> Result<(usize, SocketAddr)> {
-        let result = self.socket.recv_from(&mut buf[..]).await;
-
-        if let Ok((size, meta)) = result {
-            let sockaddr = SocketAddr::new(meta.endpoint.addr.into(), meta.endpoint.port);
+// how often the board announces its address even when it hasn't changed
+const NET_STATUS_PERIOD_MS: u64 = 10_000;
 
-            return Ok((size, sockaddr));
+/// switch to the static settings if dhcp doesn't come through, publish a NetStatus whenever
+/// the address changes and every NET_STATUS_PERIOD_MS, and resolve the remote_host from the board
+/// config
+#[task]
+pub async fn net_config(stack: Stack<'static>, mut policy: AddressPolicy) -> ! {
+    let mut rx_meta0: [PacketMetadata; 1] = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer0: [u8; 64] = [0; 64];
+    let mut tx_meta0: [PacketMetadata; 2] = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer0: [u8; 256] = [0; 256];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta0,
+        &mut rx_buffer0,
+        &mut tx_meta0,
+        &mut tx_buffer0,
+    );
+    socket.bind(CONFIG.ports.net_status_local).unwrap();
+
+    // sent to the multicast group even without the multicast feature, the host can't know
+    // a dhcp address ahead of time
+    let group = Topic::NET_STATUS.multicast_group();
+    let endpoint = IpEndpoint::new(IpAddress::Ipv4(Ipv4Addr::from(group)), MULTICAST_PORT);
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+    let mut remote_resolved = CONFIG.remote_host.is_empty();
+    let mut last_sent: Option<Instant> = None;
+    // resolving the remote_host can wait on dns for a while
+    supervisor::watch(TaskId::NetConfig, 30_000);
+    loop {
+        supervisor::check_in(TaskId::NetConfig);
+        let now_ms = Instant::now().as_millis();
+        let mut changed = false;
+        if policy.uses_dhcp() {
+            let lease = stack.config_v4().map(|config| settings_from(&config));
+            changed |= policy.dhcp_update(lease, now_ms);
+        }
+        if let Some(settings) = policy.poll(now_ms) {
+            stack.set_config_v4(ConfigV4::Static(static_config(&settings)));
+            changed = true;
         }
+        let status = policy.status(now_ms);
+        if changed {
+            info!("network {:?} {:?}", status.source, status.settings);
+        }
+
+        if stack.is_config_up() {
+            if !remote_resolved && let Some(ip) = resolve(stack, CONFIG.remote_host).await {
+                info!("{} is {:?}", CONFIG.remote_host, ip);
+                PARAMS.lock(|params| params.borrow_mut().remote_ip = ip.octets());
+                remote_resolved = true;
+            }
 
-        Err(sntpc::Error::Network)
+            let due = last_sent.is_none_or(|last_sent| {
+                Instant::now().duration_since(last_sent).as_millis() >= NET_STATUS_PERIOD_MS
+            });
+            if changed || due {
+                let msg = Message::NetStatus(status);
+                match msg.encode_topic::<128>(Topic::NET_STATUS, crc.digest()) {
+                    Ok(msg_bytes) => {
+                        if let Err(err) = socket.send_to(&msg_bytes, endpoint).await {
+                            warn!("net status send error {:?}", err);
+                        }
+                    }
+                    Err(err) => {
+                        warn!("{:?}", err);
+                    }
+                }
+                last_sent = Some(Instant::now());
+            }
+        }
+        Timer::after_millis(500).await;
     }
 }
 
 #[task]
-pub async fn time_sync(stack: Stack<'static>, ntp_server_ip: [u8; 4]) -> ! {
-    hprintln!("setting up time sync");
+pub async fn time_sync(stack: Stack<'static>, ntp_servers: &'static [&'static str]) -> ! {
+    info!("setting up time sync");
     let sender = NTP_WATCH.sender();
     /*
     let ntp_result0 = NtpResult::default();
@@ -203,105 +325,123 @@ pub async fn time_sync(stack: Stack<'static>, ntp_server_ip: [u8; 4]) -> ! {
     );
 
     // TODO(lucasw) this port is never used (?), but the socket needs to be bound to something
-    let local_port = 35201;
-    socket.bind(local_port).unwrap();
-
-    let ntp_port = 123;
-    let remote_sock_addr = sntpc::net::SocketAddr::new(
-        core::net::IpAddr::V4(core::net::Ipv4Addr::new(
-            ntp_server_ip[0],
-            ntp_server_ip[1],
-            ntp_server_ip[2],
-            ntp_server_ip[3],
-        )),
-        ntp_port,
-    );
-
-    let timestamp_gen = TimestampGen::default();
-    let context = NtpContext::new(timestamp_gen);
+    socket.bind(CONFIG.ports.ntp_local).unwrap();
+
+    // names need dns, which needs an address first
+    // TODO(lucasw) resolve again every so often, names given to pools change
+    stack.wait_config_up().await;
+    let mut servers = heapless::Vec::<SocketAddrV4, MAX_SERVERS>::new();
+    while servers.is_empty() {
+        for server in ntp_servers.iter().take(MAX_SERVERS) {
+            // an optional port, e.g. for a fake server in the simulation
+            let ip_port = match split_port(server, NTP_PORT) {
+                Some((host, port)) => resolve_host(stack, host).await.map(|ip| (ip, port)),
+                None => None,
+            };
+            match ip_port {
+                Some((ip, port)) => {
+                    let _ = servers.push(SocketAddrV4::new(ip, port));
+                }
+                None => {
+                    warn!("skipping ntp server {}", server);
+                }
+            }
+        }
+        if servers.is_empty() {
+            Timer::after_secs(5).await;
+        }
+    }
+    CLOCK.lock(|clock| clock.borrow_mut().set_servers(&servers));
 
-    // TODO(lucasw) do this before calling time sync, pass in the wrapper?
-    let sock_wrapper = EmbassyUdpSocketWrapper {
-        socket,
-    };
+    let mut tx_buf = [0; PACKET_SIZE];
+    let mut rx_buf = [0; 128];
 
-    hprintln!("starting time sync with ntp server: {:?}", remote_sock_addr);
+    info!("starting time sync with ntp servers: {:?}", servers);
+    let mut selected = None;
+    supervisor::watch(TaskId::TimeSync, 10_000);
     loop {
-        Timer::after_millis(1000).await;
-
-        let rv = get_ntp_correction(&remote_sock_addr, &sock_wrapper, context).await;
-        match rv {
-            Ok(new_rx_result) => {
-                // TODO(lucasw) store last rx_result and compare offsets
-                sender.send(new_rx_result);
-                /*
-                hprintln!(
-                    "[{:?}] sntp offset: {:.2}s",
-                    now,
-                    new_rx_result.offset as f64 / 1e6
-                );
-                */
-
-                /*
-                let timestamp_msg = TimeStamp {
-                    counter,
-                    // TODO(lucasw) these are for the timestamp received from the other
-                    // computer via a TimeStamp message, maybe get rid of them?
-                    seconds: 0,
-                    nanoseconds: 0,
-                    tick_ms: now,
-                    ntp_offset: new_rx_result.offset,
-                    ntp_seconds: new_rx_result.seconds,
-                    ntp_seconds_fraction: new_rx_result.seconds_fraction,
-                    ntp_roundtrip: new_rx_result.roundtrip,
+        supervisor::check_in(TaskId::TimeSync);
+        // one timeout per pass, any others are due immediately so the recv below won't wait
+        let timeout = CLOCK.lock(|clock| {
+            let mut clock = clock.borrow_mut();
+            clock.config.poll_interval = Duration::from_millis(params().ntp_poll_ms as u64);
+            clock.poll_timeout()
+        });
+        if let Err(err) = timeout {
+            warn!("sntp {:?}", err);
+        }
+        // one request per server that is due
+        while let Some(server) = CLOCK.lock(|clock| clock.borrow_mut().poll_transmit(&mut tx_buf))
+        {
+            let endpoint = IpEndpoint::new(IpAddress::Ipv4(*server.ip()), server.port());
+            if let Err(err) = socket.send_to(&tx_buf, endpoint).await {
+                warn!("sntp send error {:?}", err);
+            }
+        }
+
+        let (new_selected, holdover) =
+            CLOCK.lock(|clock| (clock.borrow().selected(), clock.borrow().is_holdover()));
+        if new_selected != selected {
+            selected = new_selected;
+            match selected {
+                Some(server) => info!("sntp selected {:?}", server),
+                None if holdover => warn!("sntp no servers reachable, holding over"),
+                None => warn!("sntp no servers reachable"),
+            }
+        }
+
+        // sleep until the next request or timeout unless a reply arrives first
+        let wake = CLOCK.lock(|clock| clock.borrow().next_wake());
+        let deadline = Instant::from_micros(wake.as_duration().as_micros() as u64)
+            .min(Instant::now() + embassy_time::Duration::from_millis(supervisor::CHECK_IN_MS));
+        match with_deadline(deadline, socket.recv_from(&mut rx_buf)).await {
+            Ok(Ok((num, meta))) => {
+                #[allow(irrefutable_let_patterns)]
+                let IpAddress::Ipv4(ip) = meta.endpoint.addr else {
+                    continue;
                 };
-                let tx_rv = send_message(
-                    &Message::TimeStamp(timestamp_msg),
-                    &crc,
-                    socket_handle,
-                    remote_endpoint,
-                );
-                match tx_rv {
-                    Ok(num_bytes) => {}
-                    Err(Error::Smoltcp(smoltcp::Error::Exhausted)) => {
-                        hprintln!("exhausted");
+                let from = SocketAddrV4::new(ip, meta.endpoint.port);
+                let rv =
+                    CLOCK.lock(|clock| clock.borrow_mut().handle_receive(&rx_buf[..num], from));
+                match rv {
+                    // only the selected server's results are published, the others are
+                    // only used to choose between them
+                    Ok(new_rx_result) if new_rx_result.selected => {
+                        sender.send(new_rx_result);
+                        /*
+                        info!(
+                            "[{:?}] sntp offset: {:.2}s",
+                            now,
+                            new_rx_result.offset as f64 / 1e6
+                        );
+                        */
                     }
+                    Ok(_) => {}
+                    // stale or stray packets
+                    Err(net_time::Error::UnexpectedReply) => {}
                     Err(e) => {
-                        hprintln!("UdpSocket::send error: {:?}", e);
+                        warn!("sntp response error {:?}", e);
                     }
                 }
-                */
             }
-            Err(e) => {
-                hprintln!("sntp response error {:?}", e);
-                // TODO(lucasw) how to recover, just wait and try again?
-                continue;
+            Ok(Err(err)) => {
+                warn!("sntp receive error {:?}", err);
             }
+            Err(TimeoutError) => {}
         }
     }
 }
 
-const MS_PER_S: u64 = 1_000;
-const US_PER_S: u64 = 1_000_000;
-const NS_PER_S: u64 = 1_000_000_000;
+/// the tick clock since boot
+pub(crate) fn local_epoch(instant: Instant) -> Epoch {
+    Epoch::from(Duration::from_micros(instant.as_micros()))
+}
 
-/// get unix epoch seconds
-/// TODO(lucasw) return seconds and subsecond nanos in a tuple?
-pub fn now(ntp_result: Option<NtpResult>) -> (Epoch, Instant) {
+/// get unix epoch seconds from the disciplined clock, or the time since boot before the first
+/// ntp sample
+pub fn now() -> (Epoch, Instant) {
     let tick_instant = Instant::now();
-    let mut epoch = Epoch {
-        secs: tick_instant.as_secs(),
-        nanos: ((tick_instant.as_millis() - tick_instant.as_secs() * MS_PER_S) * US_PER_S) as u32,
-    };
-    let Some(ntp_result) = ntp_result else {
-        return (epoch, tick_instant);
-    };
-    let ntp_us_offset = ntp_result.offset;
-    let offset_secs = ntp_us_offset / US_PER_S as i64;
-    epoch.secs += offset_secs as u64;
-    epoch.nanos += ((ntp_us_offset - (offset_secs * US_PER_S as i64)) * MS_PER_S as i64) as u32;
-    let more_secs = epoch.nanos / NS_PER_S as u32;
-    epoch.secs += more_secs as u64;
-    epoch.nanos -= more_secs * NS_PER_S as u32;
+    let local = local_epoch(tick_instant);
+    let epoch = CLOCK.lock(|clock| clock.borrow_mut().corrected(local));
     (epoch, tick_instant)
 }
@@ -0,0 +1,108 @@
+/*!
+A log::Log implementation that buffers records and sends them to the host as Message::Log,
+see net_loopback log_viewer, so the board doesn't need a debugger attached for semihosting
+
+Records logged before the network is up, or faster than they can be sent, wait in LOG_BUFFER
+and the oldest are dropped when it fills.  The simulation also prints them to stdout.
+*/
+
+use core::cell::RefCell;
+use core::net::Ipv4Addr;
+
+use embassy_executor::task;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+use log::{LevelFilter, Metadata, Record};
+
+use net_common::Message;
+use net_common::logging::LogBuffer;
+
+use crate::{CLOCK, CONFIG, local_epoch, params};
+
+// each record is ~200 bytes
+const LOG_BUFFER_LEN: usize = 32;
+
+static LOG_BUFFER: Mutex<CriticalSectionRawMutex, RefCell<LogBuffer<LOG_BUFFER_LEN>>> =
+    Mutex::new(RefCell::new(LogBuffer::new()));
+
+// wakes log_sender when a record is pushed
+static LOG_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+struct NetLogger;
+
+static LOGGER: NetLogger = NetLogger;
+
+impl log::Log for NetLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let local = local_epoch(Instant::now());
+        // the clock may already be borrowed if this is logged from inside a CLOCK.lock
+        let epoch = CLOCK.lock(|clock| match clock.try_borrow_mut() {
+            Ok(mut clock) => clock.corrected(local),
+            Err(_) => local,
+        });
+        #[cfg(feature = "sim")]
+        std::println!(
+            "[{}.{:06}] {:<5} {}: {}",
+            epoch.secs,
+            epoch.nanos / 1000,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        LOG_BUFFER.lock(|buffer| buffer.borrow_mut().push_record(record, epoch));
+        LOG_SIGNAL.signal(());
+    }
+
+    fn flush(&self) {}
+}
+
+/// call once before logging anything
+pub fn init(level: LevelFilter) {
+    // only fails if called twice
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
+
+/// send the buffered records to the remote at the log port from the board config
+#[task]
+pub async fn log_sender(stack: Stack<'static>) -> ! {
+    let mut rx_meta0: [PacketMetadata; 1] = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer0: [u8; 16] = [0; 16];
+    let mut tx_meta0: [PacketMetadata; 4] = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer0: [u8; 1024] = [0; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta0,
+        &mut rx_buffer0,
+        &mut tx_meta0,
+        &mut tx_buffer0,
+    );
+    socket.bind(CONFIG.ports.log_local).unwrap();
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+    stack.wait_config_up().await;
+    loop {
+        LOG_SIGNAL.wait().await;
+        while let Some(record) = LOG_BUFFER.lock(|buffer| buffer.borrow_mut().pop()) {
+            // the remote can change with a ConfigSet or a dns lookup
+            let remote_ip = Ipv4Addr::from(params().remote_ip);
+            let endpoint = IpEndpoint::new(IpAddress::Ipv4(remote_ip), CONFIG.ports.log);
+            // errors here can't be logged without logging again, they show up as sequence
+            // gaps in the viewer
+            if let Ok(msg_bytes) = Message::Log(record).encode::<256>(crc.digest()) {
+                let _ = socket.send_to(&msg_bytes, endpoint).await;
+            }
+        }
+    }
+}
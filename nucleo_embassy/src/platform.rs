@@ -0,0 +1,78 @@
+/*!
+The hardware the application needs, the stm32h753zi nucleo board with the `board` feature or
+a linux tap interface with `sim`, so the udp loop and time sync can run without a board
+
+The network device only has to be an embassy_net Driver, the leds and the random seed are
+behind the traits here.
+*/
+
+use net_common::Epoch;
+use net_common::board::Identity;
+use net_common::hwstamp::HwClock;
+use net_time::TickSource;
+
+use crate::EmbassyTicks;
+
+#[cfg(feature = "sim")]
+mod sim;
+#[cfg(feature = "board")]
+mod stm32;
+
+#[cfg(feature = "sim")]
+pub use sim::{
+    BoardDfu, BoardLed, BoardRng, BoardWatchdog, Device, SETTINGS_PAGES, SETTINGS_START,
+    SettingsFlash, init, reset,
+};
+#[cfg(feature = "board")]
+pub use stm32::{
+    BoardDfu, BoardLed, BoardRng, BoardWatchdog, Device, SETTINGS_PAGES, SETTINGS_START,
+    SettingsFlash, init, reset,
+};
+
+/// how long the watchdog waits to be fed before resetting the board, longer than erasing a
+/// flash sector blocks everything for
+pub const WATCHDOG_TIMEOUT_MS: u32 = 10_000;
+
+/// a gpio on the board, only a state in the simulation
+pub trait Led {
+    fn set(&mut self, on: bool);
+}
+
+/// random numbers for the network stack
+pub trait SeedSource {
+    fn seed(&mut self) -> u64;
+}
+
+/// resets the board unless fed at least every WATCHDOG_TIMEOUT_MS once started, see supervisor
+pub trait Watchdog {
+    fn start(&mut self);
+    fn feed(&mut self);
+}
+
+pub struct Peripherals {
+    pub device: Device,
+    /// the unique id, the mac made from it and why the board reset
+    pub identity: Identity,
+    /// green, orange and red
+    pub leds: [BoardLed; 3],
+    pub rng: BoardRng,
+    /// SETTINGS_PAGES erase sectors from SETTINGS_START are kept for the saved parameters
+    pub settings_flash: SettingsFlash,
+    /// where firmware updates are written, see net_common::firmware, None if there is no
+    /// bootloader to install them
+    pub dfu: Option<BoardDfu>,
+    pub watchdog: BoardWatchdog,
+}
+
+/// the clock udp frames are stamped with, see hwstamp, neither device gives back when the frames
+/// were actually sent or received so they get driver stamps from the tick count
+// TODO(lucasw) the h7 mac can stamp frames itself, but embassy-stm32's eth driver would have to
+// set the TTSE bit in each transmit descriptor, handle the context descriptors the receive stamps
+// come in and read both back, for an rx_stamp() and tx_stamp() here
+pub struct BoardHwClock;
+
+impl HwClock for BoardHwClock {
+    fn now(&mut self) -> Epoch {
+        EmbassyTicks.now()
+    }
+}
@@ -0,0 +1,132 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use std::boxed::Box;
+
+use embassy_net_tuntap::TunTapDevice;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+use flash_settings::RamFlash;
+use net_common::board::{Identity, ResetCause};
+use net_common::firmware::Dfu;
+
+use super::{Led, Peripherals, SeedSource, WATCHDOG_TIMEOUT_MS, Watchdog};
+
+pub type Device = TunTapDevice;
+/// the stm32 write size with smaller sectors
+// TODO(lucasw) keep it in a file, the saved parameters are lost when the simulation exits
+pub type SettingsFlash = RamFlash<{ 2 * 4096 }, 32, 4096>;
+
+pub const SETTINGS_START: u32 = 0;
+pub const SETTINGS_PAGES: u32 = 2;
+
+pub type DfuFlash = RamFlash<{ 256 * 1024 }, 32, 4096>;
+
+/// there is no bootloader, a committed update is written to DFU_FILE from the environment
+/// (dfu.bin by default) instead
+pub struct BoardDfu {
+    flash: Box<DfuFlash>,
+}
+
+impl Dfu for BoardDfu {
+    type Flash = DfuFlash;
+
+    fn partition(&mut self) -> &mut DfuFlash {
+        &mut self.flash
+    }
+
+    fn mark_updated(&mut self) -> Result<(), NorFlashErrorKind> {
+        let path = std::env::var("DFU_FILE").unwrap_or_else(|_| "dfu.bin".into());
+        std::fs::write(&path, self.flash.bytes()).map_err(|_| NorFlashErrorKind::Other)
+    }
+
+    fn mark_booted(&mut self) -> Result<(), NorFlashErrorKind> {
+        Ok(())
+    }
+}
+
+/// exits instead, there is nothing to restart the simulation
+pub fn reset() -> ! {
+    std::process::exit(0)
+}
+
+/// only remembers its state, printing every blink would bury everything else
+pub struct BoardLed {
+    pub on: bool,
+}
+
+impl Led for BoardLed {
+    fn set(&mut self, on: bool) {
+        self.on = on;
+    }
+}
+
+static LAST_FED: std::sync::Mutex<Option<std::time::Instant>> = std::sync::Mutex::new(None);
+
+/// exits the simulation if it isn't fed in time, watching from a thread of its own since a
+/// stalled executor couldn't notice
+pub struct BoardWatchdog;
+
+impl Watchdog for BoardWatchdog {
+    fn start(&mut self) {
+        self.feed();
+        std::thread::spawn(|| {
+            loop {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                let starved = LAST_FED.lock().unwrap().is_some_and(|fed| {
+                    fed.elapsed() > std::time::Duration::from_millis(WATCHDOG_TIMEOUT_MS as u64)
+                });
+                if starved {
+                    std::eprintln!("watchdog timeout");
+                    std::process::exit(1);
+                }
+            }
+        });
+    }
+
+    fn feed(&mut self) {
+        *LAST_FED.lock().unwrap() = Some(std::time::Instant::now());
+    }
+}
+
+/// xorshift seeded from the system clock, it only has to differ between runs
+pub struct BoardRng(u64);
+
+impl SeedSource for BoardRng {
+    fn seed(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// the tap interface is TAP_IFACE from the environment at run time, tap0 by default, it has
+/// to exist already, see sim.sh.  Its name is the unique id so each simulated board differs
+pub fn init() -> Peripherals {
+    let iface = std::env::var("TAP_IFACE").unwrap_or_else(|_| "tap0".into());
+    let device = TunTapDevice::new(&iface)
+        .unwrap_or_else(|err| panic!("couldn't open tap interface {iface}: {err}"));
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or_default();
+    // the tap device has its own mac, this one is only reported in BoardInfo
+    let mut unique_id = [0; 12];
+    for (id, byte) in unique_id.iter_mut().zip(iface.bytes()) {
+        *id = byte;
+    }
+    Peripherals {
+        device,
+        identity: Identity::new(unique_id, ResetCause::Unknown).with_mac(crate::CONFIG.mac),
+        leds: [
+            BoardLed { on: false },
+            BoardLed { on: false },
+            BoardLed { on: true },
+        ],
+        rng: BoardRng(nanos | 1),
+        settings_flash: SettingsFlash::new(),
+        dfu: Some(BoardDfu {
+            flash: Box::new(DfuFlash::new()),
+        }),
+        watchdog: BoardWatchdog,
+    }
+}
@@ -0,0 +1,174 @@
+use core::cell::RefCell;
+
+use embassy_boot::{
+    AlignedBuffer, BlockingFirmwareState, FirmwareUpdaterConfig, FirmwareUpdaterError,
+};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::eth::{Ethernet, GenericPhy, PacketQueue};
+use embassy_stm32::flash::{Blocking, Flash, WRITE_SIZE};
+use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::peripherals::ETH;
+use embassy_stm32::wdg::IndependentWatchdog;
+use embassy_stm32::{bind_interrupts, eth, pac, peripherals, rng, uid};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embedded_storage::nor_flash::NorFlashErrorKind;
+use net_common::board::{Identity, ResetCause};
+use net_common::firmware::Dfu;
+use static_cell::StaticCell;
+
+use super::{Led, Peripherals, SeedSource, WATCHDOG_TIMEOUT_MS, Watchdog};
+
+pub type Device = Ethernet<'static, ETH, GenericPhy>;
+pub type BoardLed = Output<'static>;
+pub type BoardRng = rng::Rng<'static, peripherals::RNG>;
+pub type BoardWatchdog = IndependentWatchdog<'static, peripherals::IWDG1>;
+/// part of the flash, the settings and both embassy-boot partitions share the one peripheral
+pub type FlashPartition = BlockingPartition<'static, NoopRawMutex, Flash<'static, Blocking>>;
+pub type SettingsFlash = FlashPartition;
+
+// the bootloader, its state and the dfu partition are in memory.x, the firmware is linked after
+// the first two, and the embassy-boot-stm32 bootloader in ../nucleo_bootloader installs updates
+const SECTOR_SIZE: u32 = 0x2_0000;
+/// the last two sectors of bank 2, after the dfu partition
+const SETTINGS_OFFSET: u32 = 0x1c_0000;
+
+pub const SETTINGS_START: u32 = 0;
+pub const SETTINGS_PAGES: u32 = 2;
+
+/// the dfu and state partitions of embassy-boot
+pub struct BoardDfu {
+    dfu: FlashPartition,
+    state: FlashPartition,
+    aligned: AlignedBuffer<WRITE_SIZE>,
+}
+
+fn updater_error(err: FirmwareUpdaterError) -> NorFlashErrorKind {
+    match err {
+        FirmwareUpdaterError::Flash(kind) => kind,
+        _ => NorFlashErrorKind::Other,
+    }
+}
+
+impl Dfu for BoardDfu {
+    type Flash = FlashPartition;
+
+    fn partition(&mut self) -> &mut FlashPartition {
+        &mut self.dfu
+    }
+
+    fn mark_updated(&mut self) -> Result<(), NorFlashErrorKind> {
+        BlockingFirmwareState::new(&mut self.state, &mut self.aligned.0)
+            .mark_updated()
+            .map_err(updater_error)
+    }
+
+    fn mark_booted(&mut self) -> Result<(), NorFlashErrorKind> {
+        BlockingFirmwareState::new(&mut self.state, &mut self.aligned.0)
+            .mark_booted()
+            .map_err(updater_error)
+    }
+}
+
+pub fn reset() -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+bind_interrupts!(struct Irqs {
+    ETH => eth::InterruptHandler;
+    HASH_RNG => rng::InterruptHandler<peripherals::RNG>;
+});
+
+impl Led for Output<'static> {
+    fn set(&mut self, on: bool) {
+        if on {
+            self.set_high();
+        } else {
+            self.set_low();
+        }
+    }
+}
+
+impl Watchdog for BoardWatchdog {
+    fn start(&mut self) {
+        self.unleash();
+    }
+
+    fn feed(&mut self) {
+        self.pet();
+    }
+}
+
+impl SeedSource for BoardRng {
+    fn seed(&mut self) -> u64 {
+        let mut seed = [0; 8];
+        self.fill_bytes(&mut seed);
+        u64::from_le_bytes(seed)
+    }
+}
+
+/// why the board reset, clearing the flags so the next reset doesn't see them too
+fn reset_cause() -> ResetCause {
+    let rsr = pac::RCC.rsr().read().0;
+    pac::RCC.rsr().modify(|w| w.set_rmvf(true));
+    ResetCause::from_stm32h7_rsr(rsr)
+}
+
+pub fn init() -> Peripherals {
+    let identity = Identity::new(*uid::uid(), reset_cause()).with_mac(crate::CONFIG.mac);
+    let p = embassy_stm32::init(Default::default());
+    /*
+    let mut led_green = pb0.into_push_pull_output();
+    let mut led_orange = gpioe.pe1.into_push_pull_output();
+    let mut led_red = gpiob.pb14.into_push_pull_output();
+    */
+    let led_green = Output::new(p.PB0, Level::Low, Speed::Medium);
+    let led_orange = Output::new(p.PE1, Level::Low, Speed::Medium);
+    let led_red = Output::new(p.PB14, Level::High, Speed::Medium);
+
+    // different on every board unless the board config sets one, see net_common::board
+    let mac_addr = identity.mac;
+    static FLASH: StaticCell<Mutex<NoopRawMutex, RefCell<Flash<'static, Blocking>>>> =
+        StaticCell::new();
+    let flash: &'static _ = FLASH.init(Mutex::new(RefCell::new(Flash::new_blocking(p.FLASH))));
+    // the partitions from the symbols in memory.x
+    let FirmwareUpdaterConfig { dfu, state } =
+        FirmwareUpdaterConfig::from_linkerfile_blocking(flash, flash);
+    static PACKETS: StaticCell<PacketQueue<4, 4>> = StaticCell::new();
+
+    let device = Ethernet::new(
+        PACKETS.init(PacketQueue::<4, 4>::new()),
+        p.ETH,
+        Irqs,
+        // these match nucleo-h7xx/src/ethernet.rs Pins
+        p.PA1,  // ref_clk
+        p.PA2,  // mdio
+        p.PC1,  // eth_mdc
+        p.PA7,  // CRS_DV: Carrier Sense
+        p.PC4,  // RX_D0: Received Bit 0
+        p.PC5,  // RX_D1: Received Bit 1
+        p.PG13, // TX_D0: Transmit Bit 0
+        p.PB13, // TX_D1: Transmit Bit 1
+        p.PG11, // TX_EN: Transmit Enable
+        GenericPhy::new_auto(),
+        mac_addr,
+    );
+
+    Peripherals {
+        device,
+        identity,
+        leds: [led_green, led_orange, led_red],
+        rng: rng::Rng::new(p.RNG, Irqs),
+        settings_flash: BlockingPartition::new(
+            flash,
+            SETTINGS_OFFSET,
+            SETTINGS_PAGES * SECTOR_SIZE,
+        ),
+        dfu: Some(BoardDfu {
+            dfu,
+            state,
+            aligned: AlignedBuffer([0; WRITE_SIZE]),
+        }),
+        watchdog: IndependentWatchdog::new(p.IWDG1, WATCHDOG_TIMEOUT_MS * 1000),
+    }
+}
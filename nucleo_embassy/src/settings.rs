@@ -0,0 +1,97 @@
+/*!
+The runtime parameters saved to flash after every ConfigSet so they survive a reset, see
+flash_settings.  They are stored along with the board config parameters they replaced, a build
+with a different board config starts from its own parameters instead.
+
+Saving can erase a flash sector, so the saver task does it rather than the receive loop, which
+only calls save_later().
+*/
+
+use embassy_executor::task;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, with_timeout};
+use flash_settings::Settings;
+use log::{info, warn};
+use net_common::config::Params;
+
+use crate::CONFIG;
+use crate::platform::{SETTINGS_PAGES, SETTINGS_START, SettingsFlash};
+
+const PARAMS_KEY: u16 = 1;
+/// change this when Params changes, parameters saved with another version are ignored
+const PARAMS_VERSION: u8 = 1;
+/// how long the saver waits for more changes, so a burst of ConfigSets is written once
+const SAVE_DELAY_MS: u64 = 1000;
+
+// the latest parameters for the saver task to write
+static TO_SAVE: Signal<CriticalSectionRawMutex, Params> = Signal::new();
+
+/// without usable flash the parameters only last until the next reset
+pub struct SavedParams(Option<Settings<SettingsFlash>>);
+
+impl SavedParams {
+    pub fn mount(flash: SettingsFlash) -> Self {
+        match Settings::mount(flash, SETTINGS_START, SETTINGS_PAGES) {
+            Ok(settings) => {
+                info!(
+                    "settings in page {} sequence {}, {} bytes free",
+                    settings.page(),
+                    settings.sequence(),
+                    settings.free()
+                );
+                Self(Some(settings))
+            }
+            Err(err) => {
+                warn!("settings flash not usable {:?}", err);
+                Self(None)
+            }
+        }
+    }
+
+    /// the parameters saved by the last ConfigSet, None to use the ones from the board config
+    pub fn load(&mut self) -> Option<Params> {
+        let settings = self.0.as_mut()?;
+        let (defaults, params) = match settings.load::<(Params, Params)>(PARAMS_KEY, PARAMS_VERSION)
+        {
+            Ok(saved) => saved?,
+            Err(err) => {
+                warn!("couldn't load saved parameters {:?}", err);
+                return None;
+            }
+        };
+        if defaults != CONFIG.params() {
+            info!("the board config changed, not using the saved parameters");
+            return None;
+        }
+        Some(params)
+    }
+
+    // TODO(lucasw) filling a page erases a whole 128KB sector on the board, which still blocks
+    // the executor for a second or more, the flash is only blocking in embassy-stm32
+    pub fn save(&mut self, params: &Params) {
+        let Some(settings) = &mut self.0 else {
+            return;
+        };
+        if let Err(err) = settings.store(PARAMS_KEY, PARAMS_VERSION, &(CONFIG.params(), *params)) {
+            warn!("couldn't save parameters {:?}", err);
+        }
+    }
+}
+
+/// have the saver task write the parameters
+pub fn save_later(params: Params) {
+    TO_SAVE.signal(params);
+}
+
+#[task]
+pub async fn saver(mut saved: SavedParams) -> ! {
+    loop {
+        let mut params = TO_SAVE.wait().await;
+        let delay = Duration::from_millis(SAVE_DELAY_MS);
+        while let Ok(newer) = with_timeout(delay, TO_SAVE.wait()).await {
+            params = newer;
+        }
+        saved.save(&params);
+    }
+}
@@ -0,0 +1,88 @@
+/*!
+Feed the watchdog only while every watched task keeps checking in, and show the state of the
+board on the leds, see net_common::health
+
+Each task calls watch() once it gets to the loop that should keep running, then check_in() on
+every pass, waking up at least every CHECK_IN_MS to do so.  The net task checks in from beside
+the network runner, so that only shows the executor and the ethernet driver are still running.
+*/
+
+use core::cell::RefCell;
+
+use embassy_executor::task;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Instant, Timer};
+use log::{error, warn};
+use net_common::Health;
+use net_common::health::{BoardState, Supervisor, TaskId};
+
+use crate::platform::{BoardLed, BoardWatchdog, Led, Watchdog};
+use crate::{CLOCK, params};
+
+/// the longest a watched task waits for anything before checking in
+pub const CHECK_IN_MS: u64 = 1000;
+
+// how often the leds are updated
+const LED_TICK_MS: u64 = 50;
+
+static SUPERVISOR: Mutex<CriticalSectionRawMutex, RefCell<Supervisor>> =
+    Mutex::new(RefCell::new(Supervisor::new()));
+
+/// expect the task to check in at least every timeout_ms from now on
+pub fn watch(id: TaskId, timeout_ms: u32) {
+    let now_ms = Instant::now().as_millis();
+    if !SUPERVISOR.lock(|supervisor| supervisor.borrow_mut().watch(id, timeout_ms, now_ms)) {
+        warn!("too many tasks to watch {:?}", id);
+    }
+}
+
+pub fn check_in(id: TaskId) {
+    let now_ms = Instant::now().as_millis();
+    SUPERVISOR.lock(|supervisor| supervisor.borrow_mut().check_in(id, now_ms));
+}
+
+fn state(stack: Stack<'static>) -> BoardState {
+    let synced = CLOCK.lock(|clock| clock.borrow().selected().is_some());
+    SUPERVISOR.lock(|supervisor| supervisor.borrow().state(stack.is_config_up(), synced))
+}
+
+/// the answer to a HealthGet
+pub fn health(stack: Stack<'static>) -> Health {
+    let state = state(stack);
+    let now_ms = Instant::now().as_millis();
+    SUPERVISOR.lock(|supervisor| supervisor.borrow().health(now_ms, state))
+}
+
+/// start the watchdog and feed it until a task stops checking in, the leds are green, orange
+/// and red
+#[task]
+pub async fn supervisor(
+    stack: Stack<'static>,
+    mut watchdog: BoardWatchdog,
+    mut leds: [BoardLed; 3],
+) -> ! {
+    watchdog.start();
+    let mut reported = false;
+    loop {
+        let now_ms = Instant::now().as_millis();
+        let alive = SUPERVISOR.lock(|supervisor| supervisor.borrow_mut().poll(now_ms));
+        if alive {
+            watchdog.feed();
+        } else if !reported {
+            let stalled = SUPERVISOR.lock(|supervisor| supervisor.borrow().stalled());
+            error!(
+                "{:?} stopped checking in, the watchdog resets the board next",
+                stalled
+            );
+            reported = true;
+        }
+
+        let blinks = state(stack).leds(params().led_pattern);
+        for (led, blink) in leds.iter_mut().zip(blinks) {
+            led.set(blink.is_on(now_ms));
+        }
+        Timer::after_millis(LED_TICK_MS).await;
+    }
+}
@@ -0,0 +1,264 @@
+/*!
+What the board sends to the remote without being asked, scheduled by a
+net_common::telemetry::Scheduler: TimeStamps with the ntp results before the health reports,
+and those before the sensor readings, all held to TELEMETRY_BYTES_PER_SEC together.  Another
+producer is one more register() and an arm in Producers::produce().
+*/
+
+use embassy_executor::task;
+use embassy_net::udp::{PacketMetadata, UdpMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::{Duration, Instant, Timer};
+use log::{info, warn};
+use net_common::config::Params;
+use net_common::health::TaskId;
+use net_common::pubsub::MULTICAST_PORT;
+use net_common::telemetry::{BufferPool, FRAME_OVERHEAD, PoolBuffer, ProducerId, Scheduler};
+use net_common::{Epoch, Message, SmallArray, TimeStamp, Topic};
+use net_time::NtpResult;
+
+use crate::hwstamp::StampedSocket;
+use crate::supervisor::{self, CHECK_IN_MS};
+use crate::{CLOCK, CONFIG, NTP_WATCH, now, params};
+
+/// the most telemetry sent, counting the packet headers
+const TELEMETRY_BYTES_PER_SEC: u32 = 16 * 1024;
+const TELEMETRY_BURST_BYTES: u32 = 1500;
+
+// how often to look for a new ntp result when telemetry_period_ms is 0
+const NTP_CHECK_MS: u32 = 100;
+const HEALTH_PERIOD_MS: u32 = 1000;
+// how often to look for new sensor readings
+const SENSOR_PERIOD_MS: u32 = 100;
+
+/// encode buffers for anything sending telemetry, a Health is the largest at around 110 bytes
+pub static BUFFERS: BufferPool<2, 256> = BufferPool::new();
+
+// TODO(lucasw) nothing reads any sensors yet, whatever does publishes here
+static SENSOR_WATCH: Watch<CriticalSectionRawMutex, SmallArray, 1> = Watch::new();
+
+/// the latest sensor readings, each one is sent once as a SmallArray stamped with now
+pub fn publish_sensors(data: [u8; 32]) {
+    let (epoch, _) = now();
+    SENSOR_WATCH.sender().send(SmallArray { epoch, data });
+}
+
+// publish to a multicast group per topic so several host tools can receive the telemetry at
+// once, otherwise send only to the remote_ip parameter (remote.ip in the board config by default)
+#[cfg(feature = "multicast")]
+const MULTICAST: bool = true;
+#[cfg(not(feature = "multicast"))]
+const MULTICAST: bool = false;
+
+fn topic(topic: Topic) -> Topic {
+    if MULTICAST { topic } else { Topic::NONE }
+}
+
+/// the current address, from dhcp or the static settings
+fn local_ip_addr(stack: Stack<'static>) -> Option<Ipv4Address> {
+    stack.config_v4().map(|config| config.address.address())
+}
+
+/// the multicast group of the topic, or the configured remote
+fn remote_endpoint(
+    topic: Topic,
+    params: &Params,
+    local_ip_addr: Option<Ipv4Address>,
+) -> UdpMetadata {
+    // broadcast to 255 has the same behavior as with nucleo-h7xx- one packet is received then
+    // no more, so use multicast to reach more than one receiver
+    let (remote_ip, remote_port) = if topic == Topic::NONE {
+        (params.remote_ip, params.remote_port)
+    } else {
+        (topic.multicast_group(), MULTICAST_PORT)
+    };
+    UdpMetadata {
+        endpoint: IpEndpoint::new(
+            Ipv4Address::new(remote_ip[0], remote_ip[1], remote_ip[2], remote_ip[3]).into(),
+            remote_port,
+        ),
+        local_address: local_ip_addr.map(IpAddress::Ipv4),
+        meta: smoltcp::phy::PacketMeta::default(),
+    }
+}
+
+fn timestamp_period_ms(params: &Params) -> u32 {
+    match params.telemetry_period_ms {
+        0 => NTP_CHECK_MS,
+        period_ms => period_ms,
+    }
+}
+
+/// send each producer's messages at its rate, see the module docs
+#[task]
+pub async fn telemetry(stack: Stack<'static>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 1];
+    let mut rx_buffer = [0; 64];
+    let mut tx_meta = [PacketMetadata::EMPTY; 8];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = StampedSocket::bind(
+        UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        ),
+        CONFIG.ports.telemetry_local,
+    )
+    .unwrap();
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+
+    stack.wait_config_up().await;
+    info!(
+        "sending telemetry with topics {:?} {:?} {:?}",
+        topic(Topic::TIMESTAMP),
+        topic(Topic::HEALTH),
+        topic(Topic::ARRAY)
+    );
+
+    let mut scheduler = Scheduler::new(TELEMETRY_BYTES_PER_SEC, TELEMETRY_BURST_BYTES);
+    let now_ms = Instant::now().as_millis();
+    let mut producers = Producers {
+        timestamps: scheduler
+            .register(timestamp_period_ms(&params()), 2, now_ms)
+            .unwrap(),
+        health: scheduler.register(HEALTH_PERIOD_MS, 1, now_ms).unwrap(),
+        sensors: scheduler.register(SENSOR_PERIOD_MS, 0, now_ms).unwrap(),
+        ntp_receiver: NTP_WATCH.receiver().unwrap(),
+        sensor_receiver: SENSOR_WATCH.receiver().unwrap(),
+        counter: 0,
+        previous_tx: Epoch::ZERO,
+    };
+    // encoded but not granted yet, it goes before anything else once there is room for it
+    let mut pending: Option<(ProducerId, PoolBuffer<'static>, Topic)> = None;
+    supervisor::watch(TaskId::Telemetry, 10_000);
+    loop {
+        supervisor::check_in(TaskId::Telemetry);
+        let params = params();
+        scheduler.set_period(producers.timestamps, timestamp_period_ms(&params));
+        let check_in = Instant::now() + Duration::from_millis(CHECK_IN_MS);
+        let wake = scheduler.next_wake().map_or(check_in, |wake_ms| {
+            Instant::from_millis(wake_ms).min(check_in)
+        });
+        Timer::at(wake).await;
+
+        let now_ms = Instant::now().as_millis();
+        if let Some((id, buf, msg_topic)) = pending.take() {
+            if !scheduler.grant(id, buf.len() + FRAME_OVERHEAD, now_ms) {
+                pending = Some((id, buf, msg_topic));
+                continue;
+            }
+            let remote = remote_endpoint(msg_topic, &params, local_ip_addr(stack));
+            producers.send(&mut socket, id, &buf, remote).await;
+        }
+        while let Some(id) = scheduler.poll(now_ms) {
+            let Some((msg, msg_topic)) = producers.produce(id, stack, &params) else {
+                scheduler.skip(id, now_ms);
+                continue;
+            };
+            // skipped rather than retried straight away, which would spin until one is free
+            let Some(mut buf) = BUFFERS.take() else {
+                warn!("no telemetry buffers free");
+                scheduler.skip(id, now_ms);
+                continue;
+            };
+            if let Err(err) = buf.encode(&msg, msg_topic, crc.digest()) {
+                warn!("{:?}", err);
+                scheduler.skip(id, now_ms);
+                continue;
+            }
+            // kept rather than produced again later, an ntp result or sensor reading is only
+            // handed out once
+            if !scheduler.grant(id, buf.len() + FRAME_OVERHEAD, now_ms) {
+                pending = Some((id, buf, msg_topic));
+                break;
+            }
+            let remote = remote_endpoint(msg_topic, &params, local_ip_addr(stack));
+            producers.send(&mut socket, id, &buf, remote).await;
+        }
+    }
+}
+
+struct Producers {
+    timestamps: ProducerId,
+    health: ProducerId,
+    sensors: ProducerId,
+    ntp_receiver: Receiver<'static, CriticalSectionRawMutex, NtpResult, 1>,
+    sensor_receiver: Receiver<'static, CriticalSectionRawMutex, SmallArray, 1>,
+    counter: u64,
+    /// when the last TimeStamp left, sent in the next one
+    previous_tx: Epoch,
+}
+
+impl Producers {
+    /// the message a producer sends now and its topic, None if it has nothing new
+    fn produce(
+        &mut self,
+        id: ProducerId,
+        stack: Stack<'static>,
+        params: &Params,
+    ) -> Option<(Message, Topic)> {
+        if id == self.timestamps {
+            // every period, or every new ntp result without one
+            let ntp_result = if params.telemetry_period_ms == 0 {
+                self.ntp_receiver.try_changed()
+            } else {
+                self.ntp_receiver.try_get()
+            }?;
+            let (epoch, tick_ms) = now();
+            let msg = TimeStamp {
+                epoch,
+                counter: self.counter,
+                tick_ms: tick_ms.as_millis(),
+                ntp_offset: ntp_result.offset,
+                ntp_seconds: ntp_result.seconds,
+                ntp_seconds_fraction: ntp_result.seconds_fraction,
+                ntp_roundtrip: ntp_result.roundtrip,
+                previous_tx: self.previous_tx,
+            };
+            Some((Message::TimeStamp(msg), topic(Topic::TIMESTAMP)))
+        } else if id == self.health {
+            Some((
+                Message::Health(supervisor::health(stack)),
+                topic(Topic::HEALTH),
+            ))
+        } else if id == self.sensors {
+            let array = self.sensor_receiver.try_changed()?;
+            Some((Message::Array(array), topic(Topic::ARRAY)))
+        } else {
+            None
+        }
+    }
+
+    /// send an encoded message, the TimeStamps stamped so the next one has when this one left
+    async fn send(
+        &mut self,
+        socket: &mut StampedSocket<'_>,
+        id: ProducerId,
+        msg_bytes: &[u8],
+        remote: UdpMetadata,
+    ) {
+        if id != self.timestamps {
+            if let Err(err) = socket.send_to(msg_bytes, remote).await {
+                warn!("telemetry send error {:?}", err);
+            }
+            return;
+        }
+        match socket.send_to_stamped(msg_bytes, remote).await {
+            Ok(stamp) => {
+                self.previous_tx = if stamp.is_early() {
+                    CLOCK.lock(|clock| clock.borrow_mut().corrected(stamp.local))
+                } else {
+                    Epoch::ZERO
+                };
+                self.counter += 1;
+            }
+            Err(err) => {
+                warn!("telemetry send error {:?}", err);
+            }
+        }
+    }
+}
@@ -1,2 +1,4 @@
 #!/bin/bash
-REMOTE_IP=`ip a | grep enp4s0 | tail -n 1 | awk '{print $2}' | sed -e "s/\/.*//"` cargo build
+# the board settings are in boards/default.toml, or another file in boards/ given with
+# BOARD_CONFIG
+BOARD_CONFIG=${BOARD_CONFIG:-../boards/default.toml} cargo build
@@ -2,11 +2,14 @@
 Adapted from git@github.com:lucasw/nucleo-h7xx.git examples/ethernet.rs
 
 Simple ethernet example that will respond to icmp pings on
-`IP_LOCAL` and periodically send a udp packet to
-`REMOTE_IP:REMOTE_PORT`
+the local_ip and periodically send a udp packet to the remote ip
+and telemetry port from the board config, see boards/default.toml
 You can start a simple listening server with netcat:
 
 nc -u -l 34200
+
+The network polling, ntp time sync, telemetry, received commands and log records each run as
+an async task on nucleo_postcard::executor, like the nucleo_embassy tasks.
 */
 
 #![allow(dead_code)]
@@ -16,7 +19,11 @@ nc -u -l 34200
 #![no_main]
 #![no_std]
 
-include!(concat!(env!("OUT_DIR"), "/constants.rs"));
+use core::convert::Infallible;
+use core::future::poll_fn;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::pin::pin;
+use core::task::Poll;
 
 use cortex_m_rt::entry;
 
@@ -35,26 +42,23 @@ use hal::pac;
 use pac::interrupt;
 
 use smoltcp;
-use smoltcp::iface::{
-    Interface, InterfaceBuilder, Neighbor, NeighborCache, Route, Routes, SocketHandle,
-};
-use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
-use smoltcp::storage::PacketMetadata;
-use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv6Cidr};
-use sntpc::NtpContext;
-use sntpc::NtpTimestampGenerator;
-use sntpc::sync::{sntp_process_response, sntp_send_request};
-
-use net_common::{Message, TimeStamp};
-use nucleo_postcard::TimestampGen;
-
-// use log::{debug, error, info};
-
-const MAC_LOCAL: [u8; 6] = [0x02, 0x00, 0x11, 0x22, 0x33, 0x44];
-// put this on the same ip address as your computer, make sure it isn't already in use
-const LOCAL_IP: [u8; 4] = [192, 168, 0, 123];
-const LOCAL_PORT: u16 = 34201;
+use smoltcp::wire::{IpEndpoint, Ipv4Address};
+use net_time::packet::NTP_PORT;
+use net_time::{MAX_SERVERS, NtpResult, TickSource};
+
+use net_common::board_config::split_port;
+use net_common::command::{Dispatcher, Response};
+use net_common::hwstamp::Stamp;
+use net_common::netconfig::Host;
+use net_common::{Epoch, Message, TimeStamp};
+use nucleo_postcard::executor;
+use nucleo_postcard::net::{self, AsyncUdpSocket, Timer, UdpBuffers};
+use nucleo_postcard::{CLOCK, CONFIG, EthernetTicks, now, params};
+
+use log::{error, info, warn};
+
+// TODO(lucasw) the ntp_local port from the board config is never used (?), but the socket needs
+// to be bound to something
 // TODO(lucasw) this only works once?  Or nc only echoes it once?  I can restart nc and receive
 // it again, and I see the openocd indicating the 1Hz sends aren't failing like when 192...255 is
 // used
@@ -64,22 +68,10 @@ const LOCAL_PORT: u16 = 34201;
 // const IP_REMOTE: [u8; 4] = [192, 255, 255, 255];
 // const IP_REMOTE: [u8; 4] = [192, 168, 0, 255];
 
-// match the port in net_loopback/src/bin/node0.rs
-const REMOTE_PORT: u16 = 34200;
-
 // mod utilities;
 
-// need to disable semihosting to run outside of openocd + gdb, also remove it in the openocd.gdb
-// monitor line
-use cortex_m_semihosting::hprintln;
-// TODO(lucasw) instead of hprintln use the (usb) serial port for debug messages?
-// dummy hprintln
-/*
-#[macro_export]
-macro_rules! hprintln {
-    ( $( $x:expr ),* ) => {};
-}
-*/
+// the log messages are sent to the log port on the remote with the default log-net feature,
+// logger::set_sinks() can add or switch to rtt, semihosting or itm with a debugger attached
 
 #[derive(Debug)]
 pub enum Error {
@@ -88,58 +80,154 @@ pub enum Error {
 }
 pub type Result<T> = core::result::Result<T, Error>;
 
-// TODO(lucasw) return result
-fn send_message(
+async fn send_message(
     data: &Message,
     crc: &crc::Crc<u32>,
-    socket_handle: SocketHandle,
+    socket: AsyncUdpSocket,
     remote_endpoint: IpEndpoint,
 ) -> Result<usize> {
-    let msg_bytes = {
-        match data.encode::<128>(crc.digest()) {
-            Ok(msg_bytes) => msg_bytes,
-            // Err(postcard::Error(err)) => {
-            Err(err) => {
-                return Err(Error::Postcard(err));
+    let msg_bytes = data.encode::<256>(crc.digest()).map_err(Error::Postcard)?;
+    socket
+        .send_to(&msg_bytes, remote_endpoint)
+        .await
+        .map_err(Error::Smoltcp)?;
+    Ok(msg_bytes.len())
+}
+
+/// send_message() with when the packet left, see net::AsyncUdpSocket::send_to_stamped
+async fn send_message_stamped(
+    data: &Message,
+    crc: &crc::Crc<u32>,
+    socket: AsyncUdpSocket,
+    remote_endpoint: IpEndpoint,
+) -> Result<Stamp> {
+    let msg_bytes = data.encode::<256>(crc.digest()).map_err(Error::Postcard)?;
+    socket
+        .send_to_stamped(&msg_bytes, remote_endpoint)
+        .await
+        .map_err(Error::Smoltcp)
+}
+
+/// send a TimeStamp to the configured remote with every new ntp result, and every
+/// telemetry_period_ms if that isn't 0
+async fn telemetry(socket: AsyncUdpSocket) -> Infallible {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+    let mut counter = 0;
+    let mut last_telemetry_ms = net::now_ms();
+    let mut last_result: Option<NtpResult> = None;
+    // when the last TimeStamp left, sent in the next one
+    let mut previous_tx = Epoch::ZERO;
+    loop {
+        // sleep until a new ntp result or the end of the period, a period set while it is 0
+        // starts with the next ntp result
+        let new_result = poll_fn(|cx| {
+            if let Poll::Ready(result) = nucleo_postcard::poll_ntp_result(cx.waker()) {
+                return Poll::Ready(Some(result));
+            }
+            let period_ms = params().telemetry_period_ms as u64;
+            if period_ms == 0 {
+                return Poll::Pending;
+            }
+            let due_ms = last_telemetry_ms + period_ms;
+            if net::now_ms() >= due_ms {
+                return Poll::Ready(None);
+            }
+            executor::wake_at(due_ms, cx.waker());
+            Poll::Pending
+        })
+        .await;
+        last_telemetry_ms = net::now_ms();
+        // nothing to send before the first ntp result
+        let Some(ntp_result) = new_result.or(last_result) else {
+            continue;
+        };
+        last_result = Some(ntp_result);
+        let params = params();
+
+        let (epoch, tick_ms) = now();
+        let msg = TimeStamp {
+            epoch,
+            counter,
+            tick_ms,
+            ntp_offset: ntp_result.offset,
+            ntp_seconds: ntp_result.seconds,
+            ntp_seconds_fraction: ntp_result.seconds_fraction,
+            ntp_roundtrip: ntp_result.roundtrip,
+            previous_tx,
+        };
+        let remote_endpoint = IpEndpoint::new(
+            Ipv4Address::from_bytes(&params.remote_ip).into(),
+            params.remote_port,
+        );
+        let msg = Message::TimeStamp(msg);
+        match send_message_stamped(&msg, &crc, socket, remote_endpoint).await {
+            Ok(stamp) => {
+                previous_tx = cortex_m::interrupt::free(|cs| {
+                    CLOCK.borrow(cs).borrow_mut().corrected(stamp.local)
+                });
+            }
+            Err(e) => {
+                warn!("UdpSocket::send error: {:?}", e);
             }
         }
-    };
+        counter += 1;
+    }
+}
 
-    // send something
-    let rv = nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-        let socket = ethernet_interface
-            .interface
-            .as_mut()
-            .unwrap()
-            .get_socket::<UdpSocket>(socket_handle);
-        socket.send_slice(&msg_bytes, remote_endpoint)
-    });
-    match rv {
-        Ok(()) => {
-            return Ok((msg_bytes.len()));
-            // hprintln!("sent message, {} bytes", msg_bytes.len());
-            // hprintln!(msg);
-        }
-        // Err(smoltcp::Error(err)) => {
-        Err(err) => {
-            // Err(Error::Smoltcp(err)) => {
-            return Err(Error::Smoltcp(err));
+/// answer the messages received on the commands port, see net_common::command
+async fn commands(socket: AsyncUdpSocket) -> Infallible {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+    let mut rx_buf = [0; 1024];
+    let mut dispatcher = Dispatcher::new();
+    loop {
+        let (num, endpoint, rx_stamp) = match socket.recv_from_stamped(&mut rx_buf).await {
+            Ok(rx) => rx,
+            Err(err) => {
+                warn!("receive error {:?}", err);
+                continue;
+            }
+        };
+        // the receive time for ptp and echo, from the interface poll that received it
+        let rx_local = rx_stamp.local;
+
+        let rv = match nucleo_postcard::dispatch(&mut dispatcher, &rx_buf[..num], rx_local, &crc) {
+            Response::Echo => socket
+                .send_to(&rx_buf[..num], endpoint)
+                .await
+                .map(|()| num)
+                .map_err(Error::Smoltcp),
+            // the ptp delay uses when the DelayReq actually went out
+            Response::Reply(reply @ Message::PtpDelayReq(_)) => {
+                send_message_stamped(&reply, &crc, socket, endpoint)
+                    .await
+                    .map(|stamp| {
+                        dispatcher.delay_req_sent(stamp.local);
+                        0
+                    })
+            }
+            Response::Reply(reply) => send_message(&reply, &crc, socket, endpoint).await,
+            Response::Nothing => Ok(0),
+        };
+        if let Err(err) = rv {
+            warn!("reply error {:?}", err);
         }
-    };
+    }
 }
 
 #[entry]
 fn main() -> ! {
-    // TODO(lucasw) option_env
-    let local_endpoint = IpEndpoint::new(Ipv4Address::from_bytes(&LOCAL_IP).into(), LOCAL_PORT);
-    // let ip_remote = IpAddress::BROADCAST;
-    let remote_endpoint = IpEndpoint::new(Ipv4Address::from_bytes(&REMOTE_IP).into(), REMOTE_PORT);
-    let ntp_endpoint = IpEndpoint::new(Ipv4Address::from_bytes(&REMOTE_IP).into(), 123);
-    //  IpEndpoint::new(ip_remote, REMOTE_IP_PORT);
+    // put the local_ip on the same subnet as your computer, make sure it isn't already in use
+    let local_ip = Ipv4Address::from_bytes(&CONFIG.local_ip);
+    let local_endpoint = IpEndpoint::new(local_ip.into(), CONFIG.ports.commands);
+    let ntp_endpoint = IpEndpoint::new(local_ip.into(), CONFIG.ports.ntp_local);
 
     // - board setup ----------------------------------------------------------
 
-    hprintln!("Setting up board");
+    nucleo_postcard::logger::init();
+    info!("Setting up board");
+    // the mac is different on every board, see net_common::board
+    let identity = nucleo_postcard::read_identity();
+    info!("mac {:02x?}, reset cause {:?}", identity.mac, identity.reset_cause);
 
     let board = nucleo::Board::take().unwrap();
 
@@ -157,14 +245,13 @@ fn main() -> ! {
         dp.GPIOG.split(ccdr.peripheral.GPIOG),
     );
 
-    nucleo_postcard::logger::init();
-
     // - ethernet interface ---------------------------------------------------
 
-    hprintln!(
-        "Bringing up ethernet interface with local ip {:?} {}",
-        LOCAL_IP,
-        LOCAL_PORT
+    info!(
+        "Bringing up ethernet interface with local ip {:?} {} from board config {}",
+        CONFIG.local_ip,
+        CONFIG.ports.commands,
+        CONFIG.name
     );
 
     let timeout_timer = dp
@@ -173,298 +260,74 @@ fn main() -> ! {
     let timeout_timer = nucleo::timer::CountDownTimer::new(timeout_timer);
     let timeout_timer = match nucleo::ethernet::EthernetInterface::start(
         pins.ethernet,
-        &MAC_LOCAL,
-        &LOCAL_IP,
+        &identity.mac,
+        // TODO(lucasw) nucleo-h7xx always uses a /24, the netmask and gateway from the board
+        // config aren't used
+        &CONFIG.local_ip,
         ccdr.peripheral.ETH1MAC,
         &ccdr.clocks,
         timeout_timer,
     ) {
         Ok(tim17) => tim17,
         Err(e) => {
-            hprintln!("Failed to start ethernet interface: {:?}", e);
+            error!("Failed to start ethernet interface: {:?}", e);
             loop {}
         }
     };
 
     // wait for link to come up
-    hprintln!("Waiting for link to come up");
+    info!("Waiting for link to come up");
     nucleo::ethernet::EthernetInterface::interrupt_free(
         |ethernet_interface| {
             while !ethernet_interface.poll_link() {}
         },
     );
 
-    // create and bind socket
-    let socket_handle = nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-        let socket_handle = ethernet_interface.new_udp_socket();
-        let socket = ethernet_interface
-            .interface
-            .as_mut()
-            .unwrap()
-            .get_socket::<UdpSocket>(socket_handle);
-        match socket.bind(local_endpoint) {
-            Ok(()) => socket_handle,
-            Err(e) => {
-                hprintln!("Failed to bind socket to endpoint: {:?}", local_endpoint);
-                loop {}
-            }
-        }
-    });
-
-    // let msg = "nucleo says hello!\n";
+    // - sockets and tasks ----------------------------------------------------
 
-    // can't embed variable in string with hprintln
-    hprintln!(
-        "Entering main loop, will send messages to {:?} {}",
-        REMOTE_IP,
-        REMOTE_PORT
-    );
+    // commands are received on this one, telemetry and log records are sent from it
+    let buffers = cortex_m::singleton!(: UdpBuffers<4096> = UdpBuffers::new()).unwrap();
+    let socket = match AsyncUdpSocket::bind(buffers, local_endpoint) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to bind socket to endpoint: {:?} {:?}", local_endpoint, e);
+            loop {}
+        }
+    };
+    let buffers = cortex_m::singleton!(: UdpBuffers<512> = UdpBuffers::new()).unwrap();
+    let ntp_socket = match AsyncUdpSocket::bind(buffers, ntp_endpoint) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Failed to bind socket to endpoint: {:?} {:?}", ntp_endpoint, e);
+            loop {}
+        }
+    };
 
-    let ntp_port = 123;
-    let remote_sock_addr = sntpc::net::SocketAddr::new(
-        core::net::IpAddr::V4(core::net::Ipv4Addr::new(
-            REMOTE_IP[0],
-            REMOTE_IP[1],
-            REMOTE_IP[2],
-            REMOTE_IP[3],
-        )),
-        ntp_port,
+    info!(
+        "Starting tasks, will send messages to {:?} {}",
+        params().remote_ip,
+        params().remote_port
     );
 
-    // let mut rx_buffer: [u8; 128] = [0; 128];
-
-    let mut counter = 0;
-    let mut last = 0;
-    let mut ntp_tx_result = None;
-    let mut ntp_rx_result = None;
-
-    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
-
-    let timestamp_gen = TimestampGen::default();
-    let context = NtpContext::new(timestamp_gen);
-
-    // TODO(lucasw) don't do hprintln inside interrupt_free unless error
-    // (does it screw up the timer counter?
-    loop {
-        cortex_m::asm::wfi();
-
-        // poll ethernet interface
-        let now = nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-            match ethernet_interface.poll() {
-                Ok(result) => {
-                    // hprintln!("ethernet poll result '{:?}'", result);
-                } // packets were processed or emitted
-                Err(smoltcp::Error::Exhausted) => (),
-                Err(smoltcp::Error::Unrecognized) => (),
-                Err(e) => {
-                    hprintln!("ethernet::EthernetInterface.poll() -> {:?}", e);
-                }
+    // there is no dns here, names are only for nucleo_embassy
+    let mut ntp_servers = [SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, NTP_PORT); MAX_SERVERS];
+    let mut num_servers = 0;
+    for server in CONFIG.ntp_servers {
+        match split_port(server, NTP_PORT) {
+            Some((Host::Ip(ip), port)) if num_servers < MAX_SERVERS => {
+                ntp_servers[num_servers] = SocketAddrV4::new(ip, port);
+                num_servers += 1;
             }
-            ethernet_interface.now()
-        });
-
-        /*
-        // receive something, and then send a response
-        let (msg, now) =
-            nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-                let socket = ethernet_interface
-                    .interface
-                    .as_mut()
-                    .unwrap()
-                    .get_socket::<UdpSocket>(socket_handle);
-                    .get_socket::<UdpSocket>(socket_handle);
-                match socket.recv_slice(&mut rx_buffer) {
-                    Ok((num_bytes, ip_endpoint)) => {
-                        // want this stamp as close to reception as possible
-                        let now = ethernet_interface.now();
-                        // this prints so slow
-                        // hprintln!("received message: {:?}", rx_buffer);
-                        // hprintln!("received message: {} {:?}", num_bytes, ip_endpoint);
-                        (Some(Message::decode(&rx_buffer, crc.digest())), now)
-                    }
-                    Err(e) => {
-                        let now = ethernet_interface.now();
-                        if counter % 2000 == 0 {
-                            // hprintln!("nothing received: {:?}", e);
-                        }
-                        (None, now)
-                    }
-                }
-            });
-        if let Some(Ok(msg)) = msg {
-            if let Message::Data(mut data) = msg {
-                // send the received message back but with the local timestamp
-                data.stamp_ms = now;
-                send_message(&Message::Data(data), &crc, socket_handle, remote_endpoint);
-            } else {
-                todo!()
-            }
-        }
-        */
-
-        // TODO(lucasw) async awaits would simplify this
-        if let Some(last_tx_result) = ntp_tx_result {
-            let (now, new_rx_result) =
-                nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-                    let socket = ethernet_interface
-                        .interface
-                        .as_mut()
-                        .unwrap()
-                        .get_socket::<UdpSocket>(socket_handle);
-
-                    // TODO(lucasw) I can send a message and receive it within 1 ms, but the sntp
-                    // sync seems to be jittering by much more than that unless I'm interpreting it
-                    // wrong.
-                    // How do I apply the NtpResult to the current time?  I can compare that result
-                    // with receiving a packet with a timestamp from the ntp server in the
-                    // (currently commented out code) below
-                    let sock_wrapper = nucleo_postcard::SmoltcpUdpSocketWrapper {
-                        socket: socket.into(),
-                    };
-                    let rv = sntp_process_response(
-                        remote_sock_addr,
-                        &sock_wrapper,
-                        context,
-                        last_tx_result,
-                    );
-                    let now = ethernet_interface.now();
-                    (now, rv)
-                });
-
-            match new_rx_result {
-                Ok(new_rx_result) => {
-                    // TODO(lucasw) the hprintln may be fouling up timing
-                    // TODO(lucasw) store last rx_result and compare offsets
-                    /*
-                    hprintln!(
-                        "[{}] sntp offset: {:.2}s",
-                        now,
-                        // last_tx_result.originate_timestamp,
-                        rx_result.offset as f64 / 1e6
-                    );
-                    */
-
-                    let timestamp_msg = TimeStamp {
-                        // TODO(lucasw) these are for the timestamp received from the other
-                        // computer via a TimeStamp message, maybe get rid of them?
-                        epoch: net_common::Epoch { secs: 0, nanos: 0 },
-                        counter,
-                        tick_ms: now as u64,
-                        ntp_offset: new_rx_result.offset,
-                        ntp_seconds: new_rx_result.seconds,
-                        ntp_seconds_fraction: new_rx_result.seconds_fraction,
-                        ntp_roundtrip: new_rx_result.roundtrip,
-                    };
-                    let tx_rv = send_message(
-                        &Message::TimeStamp(timestamp_msg),
-                        &crc,
-                        socket_handle,
-                        remote_endpoint,
-                    );
-                    match tx_rv {
-                        Ok(num_bytes) => {}
-                        Err(Error::Smoltcp(smoltcp::Error::Exhausted)) => {
-                            hprintln!("exhausted");
-                        }
-                        Err(e) => {
-                            hprintln!("UdpSocket::send error: {:?}", e);
-                        }
-                    }
-
-                    ntp_rx_result = Some(new_rx_result);
-                    ntp_tx_result = None;
-                }
-                Err(sntpc::Error::Network) => {
-                    // do nothing, this is the receive timing out, get the response in a later loop
-                }
-                Err(e) => {
-                    hprintln!("sntp response error {:?}", e);
-                    // TODO(lucasw) how to recover, just wait and try again?
-                    ntp_tx_result = None;
-                }
+            _ => {
+                warn!("skipping ntp server {}", server);
             }
         }
-
-        /*
-        // do a recv on the ntp connection in order to flush any incoming messages before doing an ntp sync
-        {
-            nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-                let socket = ethernet_interface
-                    .interface
-                    .as_mut()
-                    .unwrap()
-                    .get_socket::<UdpSocket>(socket_handle);
-                match socket.recv_slice(&mut rx_buffer) {
-                    Ok((num_bytes, ntp_endpoint)) => {
-                        let now = ethernet_interface.now();
-                        // hprintln!("received old message?: {:?}", rx_buffer);
-                        hprintln!("received old message?  Flushing");
-                    }
-                    Err(e) => {
-                    }
-                }
-            });
-        }
-        */
-
-        // check if it has been 1 second since we last sent something
-        if (now - last) < 1000 {
-            continue;
-        }
-        last = now;
-
-        /*
-        if count % 5000 == 0 {
-            timestamp_gen.init();
-            hprintln!("now {}ms, timestamp gen {} seconds, {}", now, timestamp_gen.timestamp_sec(), timestamp_gen.timestamp_subsec_micros());
-        }
-        */
-
-        /*
-        timestamp_gen.init();
-        let t0 = timestamp_gen.duration_ticks;
-        */
-
-        nucleo::ethernet::EthernetInterface::interrupt_free(|ethernet_interface| {
-            let socket = ethernet_interface
-                .interface
-                .as_mut()
-                .unwrap()
-                .get_socket::<UdpSocket>(socket_handle);
-
-            let sock_wrapper = nucleo_postcard::SmoltcpUdpSocketWrapper {
-                socket: socket.into(),
-            };
-
-            // TODO(lucasw) if any unhandled ntp results are sitting in the buffer this
-            // fouls up, flush them above
-            // this is working
-            // { originate_timestamp: 9487534653230284800, version: 35 }
-            let new_tx_result = sntp_send_request(remote_sock_addr, &sock_wrapper, context);
-
-            match new_tx_result {
-                Ok(new_tx_result) => {
-                    // TODO(lucasw) measure how long it took to get a response
-                    // hprintln!("[{}] tx success: {:?}, now wait for response", now, new_tx_result);
-                    ntp_tx_result = Some(new_tx_result);
-                }
-                Err(e) => {
-                    hprintln!("send error: {:?}", e);
-                }
-            }
-        });
-
-        /*
-        timestamp_gen.init();
-        let t1 = timestamp_gen.duration_ticks;
-        let val = {
-            ((timestamp_gen.timestamp_sec() + (u64::from(2_208_988_800u32))) << 32)
-                + u64::from(timestamp_gen.timestamp_subsec_micros()) * u64::from(u32::MAX)
-                    / u64::from(1_000_000u32)
-        };
-        hprintln!("t0 {}, t1 {}, {}", t0, t1, val);
-        */
-
-        counter += 1;
     }
+
+    let poll_interface = pin!(net::poll_interface());
+    let time_sync = pin!(nucleo_postcard::time_sync(ntp_socket, &ntp_servers[..num_servers]));
+    let log_sender = pin!(nucleo_postcard::log_sender(socket));
+    let telemetry = pin!(telemetry(socket));
+    let commands = pin!(commands(socket));
+    executor::run(&mut [poll_interface, time_sync, log_sender, telemetry, commands])
 }
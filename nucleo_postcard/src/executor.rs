@@ -0,0 +1,143 @@
+/*!
+A minimal executor for the firmware's handful of tasks, which all run forever
+
+Each task has a bit in WOKEN that its waker sets, and only the tasks that were woken are polled,
+then the core sleeps in wfi until the next interrupt.  The futures in net.rs register their
+wakers with the smoltcp sockets or with wake_at() for a deadline, the timers are checked every
+time the core wakes up (at least every millisecond with the systick).  The interrupt handlers
+are in nucleo-h7xx and don't wake anything, so wake_on_interrupt() is for the one task that
+has to run after any of them, net::poll_interface().
+*/
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+use core::future::{Future, poll_fn};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use cortex_m::interrupt::Mutex;
+
+use crate::net::now_ms;
+
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = Infallible>>;
+
+/// most tasks run() takes, one bit each in WOKEN
+pub const MAX_TASKS: usize = 8;
+
+// the tasks to poll on the next pass
+static WOKEN: AtomicU32 = AtomicU32::new(0);
+
+// a deadline per task, the earliest if it is waiting on more than one
+static TIMERS: Mutex<RefCell<[Option<(u64, Waker)>; MAX_TASKS]>> =
+    Mutex::new(RefCell::new([const { None }; MAX_TASKS]));
+
+// woken every time the core wakes up
+static INTERRUPT_WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+/// the data pointer is the task index, not a real pointer
+fn raw_waker(index: *const ()) -> RawWaker {
+    RawWaker::new(index, &VTABLE)
+}
+
+unsafe fn clone(index: *const ()) -> RawWaker {
+    raw_waker(index)
+}
+
+unsafe fn wake(index: *const ()) {
+    WOKEN.fetch_or(1 << index as usize, Relaxed);
+}
+
+unsafe fn drop(_: *const ()) {}
+
+/// wake the task at or after deadline_ms
+pub fn wake_at(deadline_ms: u64, waker: &Waker) {
+    if now_ms() >= deadline_ms {
+        waker.wake_by_ref();
+        return;
+    }
+    cortex_m::interrupt::free(|cs| {
+        let mut timers = TIMERS.borrow(cs).borrow_mut();
+        if let Some((deadline, _)) = timers
+            .iter_mut()
+            .flatten()
+            .find(|(_, timer_waker)| timer_waker.will_wake(waker))
+        {
+            *deadline = (*deadline).min(deadline_ms);
+        } else if let Some(free) = timers.iter_mut().find(|timer| timer.is_none()) {
+            *free = Some((deadline_ms, waker.clone()));
+        } else {
+            // not one of run()'s wakers, poll again straight away rather than never
+            waker.wake_by_ref();
+        }
+    });
+}
+
+/// wake the task the next time the core wakes up from any interrupt
+pub fn wake_on_interrupt(waker: &Waker) {
+    cortex_m::interrupt::free(|cs| {
+        INTERRUPT_WAKER.borrow(cs).replace(Some(waker.clone()));
+    });
+}
+
+fn wake_expired(now_ms: u64) {
+    cortex_m::interrupt::free(|cs| {
+        for timer in TIMERS.borrow(cs).borrow_mut().iter_mut() {
+            if timer
+                .as_ref()
+                .is_some_and(|(deadline, _)| now_ms >= *deadline)
+                && let Some((_, waker)) = timer.take()
+            {
+                waker.wake();
+            }
+        }
+    });
+}
+
+pub fn run(tasks: &mut [Task<'_>]) -> ! {
+    assert!(tasks.len() <= MAX_TASKS, "more than MAX_TASKS tasks");
+    // the vtable functions don't touch the data pointer
+    let wakers: [Waker; MAX_TASKS] =
+        core::array::from_fn(|index| unsafe { Waker::from_raw(raw_waker(index as *const ())) });
+    // every task gets polled once to start with
+    WOKEN.store((1 << tasks.len()) - 1, Relaxed);
+    loop {
+        wake_expired(now_ms());
+        let woken = WOKEN.swap(0, Relaxed);
+        for (index, task) in tasks.iter_mut().enumerate() {
+            if woken & (1 << index) != 0 {
+                let mut cx = Context::from_waker(&wakers[index]);
+                // none of them return
+                let _ = task.as_mut().poll(&mut cx);
+            }
+        }
+
+        // with interrupts masked between the check and the wfi a wake from an interrupt
+        // can't be missed, the wfi still returns as soon as one is pending
+        cortex_m::interrupt::free(|_| {
+            if WOKEN.load(Relaxed) == 0 {
+                cortex_m::asm::wfi();
+            }
+        });
+        let interrupt_waker = cortex_m::interrupt::free(|cs| INTERRUPT_WAKER.borrow(cs).take());
+        if let Some(waker) = interrupt_waker {
+            waker.wake();
+        }
+    }
+}
+
+/// let the other tasks run before continuing
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
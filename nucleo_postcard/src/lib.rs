@@ -1,149 +1,293 @@
 #![no_std]
 
-use core::alloc::{GlobalAlloc, Layout};
-use core::cell::RefCell;
-use core::cell::UnsafeCell;
-use core::net::{IpAddr, Ipv4Addr};
-use core::ptr::null_mut;
-use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
-
-use smoltcp::socket::UdpSocket;
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use core::net::{Ipv4Addr, SocketAddrV4};
+use core::task::{Poll, Waker};
+use core::time::Duration;
+
+use board_heap::LockedHeap;
+use cortex_m::interrupt::Mutex;
+use log::{info, warn};
+use nucleo_h7xx::hal::pac;
 use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
-use sntpc::net::SocketAddr;
-use sntpc::{NtpTimestampGenerator, NtpUdpSocket, Result};
 
+use net_common::board::{Identity, ResetCause};
+use net_common::command::{Board, Dispatcher, Response};
+use net_common::config::Params;
+use net_common::discipline::Sample;
+use net_common::{BoardInfo, Epoch, HeapStats, Message};
+use net_time::packet::PACKET_SIZE;
+use net_time::{Config, NtpResult, TickSource, TimeSync};
+
+use net::{AsyncUdpSocket, Timer, with_deadline};
+
+pub mod executor;
 pub mod logger;
+pub mod net;
+
+include!(concat!(env!("OUT_DIR"), "/constants.rs"));
 
 const ARENA_SIZE: usize = 128 * 1024;
-const MAX_SUPPORTED_ALIGN: usize = 4096;
-#[repr(C, align(4096))] // 4096 == MAX_SUPPORTED_ALIGN
-struct SimpleAllocator {
-    arena: UnsafeCell<[u8; ARENA_SIZE]>,
-    remaining: AtomicUsize, // we allocate from the top, counting down
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap<ARENA_SIZE> = LockedHeap::new();
+
+/// heap usage to answer a HeapStatsGet with
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.stats()
 }
 
-unsafe impl Sync for SimpleAllocator {}
+// runtime parameters, changed by ConfigSet messages received in postcard_rx_tx
+pub static PARAMS: Mutex<RefCell<Params>> = Mutex::new(RefCell::new(CONFIG.params()));
 
-unsafe impl GlobalAlloc for SimpleAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let size = layout.size();
-        let align = layout.align();
+// the sntp exchanges and the disciplined clock now() returns, time_sync sets the servers
+pub static CLOCK: Mutex<RefCell<TimeSync<EthernetTicks>>> =
+    Mutex::new(RefCell::new(TimeSync::new(EthernetTicks, &[], Config::DEFAULT)));
 
-        // `Layout` contract forbids making a `Layout` with align=0, or align not power of 2.
-        // So we can safely use a mask to ensure alignment without worrying about UB.
-        let align_mask_to_round_down = !(align - 1);
+// the latest result from the selected server, until the telemetry task takes it
+static NEW_NTP_RESULT: Mutex<Cell<Option<NtpResult>>> = Mutex::new(Cell::new(None));
 
-        if align > MAX_SUPPORTED_ALIGN {
-            return null_mut();
-        }
+// the telemetry task waiting for NEW_NTP_RESULT
+static NTP_RESULT_WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
 
-        let mut allocated = 0;
-        if self
-            .remaining
-            .fetch_update(Relaxed, Relaxed, |mut remaining| {
-                if size > remaining {
-                    return None;
-                }
-                remaining -= size;
-                remaining &= align_mask_to_round_down;
-                allocated = remaining;
-                Some(remaining)
-            })
-            .is_err()
-        {
-            return null_mut();
+/// a copy of the current runtime parameters
+pub fn params() -> Params {
+    cortex_m::interrupt::free(|cs| *PARAMS.borrow(cs).borrow())
+}
+
+/// the ntp result time_sync got since the last call, or Pending and wake the task when it gets
+/// one, only one task can wait at a time
+pub fn poll_ntp_result(waker: &Waker) -> Poll<NtpResult> {
+    cortex_m::interrupt::free(|cs| match NEW_NTP_RESULT.borrow(cs).take() {
+        Some(result) => Poll::Ready(result),
+        None => {
+            NTP_RESULT_WAKER.borrow(cs).replace(Some(waker.clone()));
+            Poll::Pending
         }
+    })
+}
 
-        unsafe { self.arena.get().cast::<u8>().add(allocated) }
-    }
+// read_identity() sets this at startup
+static IDENTITY: Mutex<Cell<Identity>> = Mutex::new(Cell::new(Identity {
+    unique_id: [0; 12],
+    mac: [0; 6],
+    reset_cause: ResetCause::Unknown,
+}));
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        todo!()
-    }
+// the 96 bit unique device id, RM0433 61.1
+const UID_ADDRESS: usize = 0x1FF1_E800;
 
-    unsafe fn realloc(&self, _ptr: *mut u8, _layout: Layout, _new_size: usize) -> *mut u8 {
-        todo!()
-    }
+/// the unique id, the mac made from it and why the board reset (clearing the reset flags so
+/// the next reset doesn't see them too), call once at startup before using the mac
+pub fn read_identity() -> Identity {
+    let unique_id = unsafe { core::ptr::read_volatile(UID_ADDRESS as *const [u8; 12]) };
+    let rcc = unsafe { &*pac::RCC::ptr() };
+    let rsr = rcc.rsr.read().bits();
+    rcc.rsr.modify(|_, w| w.rmvf().set_bit());
+    let identity =
+        Identity::new(unique_id, ResetCause::from_stm32h7_rsr(rsr)).with_mac(CONFIG.mac);
+    cortex_m::interrupt::free(|cs| IDENTITY.borrow(cs).set(identity));
+    identity
 }
 
-#[global_allocator]
-static ALLOCATOR: SimpleAllocator = SimpleAllocator {
-    arena: UnsafeCell::new([0x55; ARENA_SIZE]),
-    remaining: AtomicUsize::new(ARENA_SIZE),
-};
+/// to answer a BoardInfoGet with
+pub fn board_info() -> BoardInfo {
+    let identity = cortex_m::interrupt::free(|cs| IDENTITY.borrow(cs).get());
+    let build_time = Epoch::from(Duration::from_secs(BUILD_TIME_SECS));
+    BoardInfo::new(&identity, GIT_HASH, build_time, net::now_ms())
+}
 
 // nucleo-h7xx ethernet.rs says systick timer is 1ms
-const TICKS_PER_SEC: u32 = 1_000;
-
-// adapted from sntpc examples simple-no-std + smoltcp
+/// the ethernet systick count since boot as the local clock for net_time
 #[derive(Copy, Clone, Default)]
-pub struct TimestampGen {
-    pub duration_ticks: u32,
+pub struct EthernetTicks;
+
+impl TickSource for EthernetTicks {
+    fn now(&self) -> Epoch {
+        Epoch::from(Duration::from_millis(net::now_ms()))
+    }
+}
+
+/// get unix epoch seconds from the disciplined clock, or the time since boot before the first
+/// ntp sample, and the tick count in ms
+pub fn now() -> (Epoch, u64) {
+    let tick_ms = net::now_ms();
+    let local = Epoch::from(Duration::from_millis(tick_ms));
+    let epoch = cortex_m::interrupt::free(|cs| CLOCK.borrow(cs).borrow_mut().corrected(local));
+    (epoch, tick_ms)
+}
+
+// CLOCK and PARAMS for the net_common commands, borrowed for one dispatch()
+struct Firmware<'a> {
+    clock: &'a mut TimeSync<EthernetTicks>,
+    params: &'a mut Params,
 }
 
-impl NtpTimestampGenerator for TimestampGen {
-    fn init(&mut self) {
-        // TODO(lucasw) does this need to be interrupt free?
-        // self.duration_ticks += 3000;
-        self.duration_ticks = nucleo_h7xx::ethernet::ATOMIC_TIME.load(Relaxed);
+impl Board for Firmware<'_> {
+    fn corrected(&mut self, local: Epoch) -> Epoch {
+        self.clock.corrected(local)
     }
 
-    fn timestamp_sec(&self) -> u64 {
-        (self.duration_ticks / TICKS_PER_SEC) as u64
+    fn local_now(&self) -> Epoch {
+        EthernetTicks.now()
     }
 
-    fn timestamp_subsec_micros(&self) -> u32 {
-        (self.duration_ticks - self.timestamp_sec() as u32 * TICKS_PER_SEC) * 1_000
+    fn add_sample(&mut self, sample: Sample) {
+        self.clock.add_sample(sample);
+    }
+
+    fn params(&mut self) -> &mut Params {
+        self.params
     }
-}
 
-pub struct SmoltcpUdpSocketWrapper<'a, 'b> {
-    // pub socket: UdpSocket<'a>,
-    pub socket: RefCell<&'b mut UdpSocket<'a>>,
+    fn heap_stats(&self) -> HeapStats {
+        heap_stats()
+    }
+
+    fn board_info(&self) -> BoardInfo {
+        board_info()
+    }
 }
 
-impl NtpUdpSocket for SmoltcpUdpSocketWrapper<'_, '_> {
-    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
-        // , sntpc::Error> {
-        let endpoint = match addr {
-            SocketAddr::V4(v4) => IpEndpoint::new(
-                IpAddress::Ipv4(Ipv4Address::from_bytes(&v4.ip().octets())),
-                v4.port(),
-            ),
-            SocketAddr::V6(_) => return Err(sntpc::Error::Network),
+/// what to send back for a packet received on the command port at rx_local (local time)
+pub fn dispatch(
+    dispatcher: &mut Dispatcher,
+    rx: &[u8],
+    rx_local: Epoch,
+    crc: &crc::Crc<u32>,
+) -> Response {
+    cortex_m::interrupt::free(|cs| {
+        let mut board = Firmware {
+            clock: &mut CLOCK.borrow(cs).borrow_mut(),
+            params: &mut PARAMS.borrow(cs).borrow_mut(),
         };
+        dispatcher.handle(rx, rx_local, &mut board, crc.digest())
+    })
+}
+
+fn endpoint(addr: SocketAddrV4) -> IpEndpoint {
+    IpEndpoint::new(
+        IpAddress::Ipv4(Ipv4Address::from_bytes(&addr.ip().octets())),
+        addr.port(),
+    )
+}
+
+/// update CLOCK from a packet received on the ntp socket, publishing the selected server's
+/// results for poll_ntp_result()
+fn receive_ntp(rx: &[u8], endpoint: IpEndpoint) {
+    let IpAddress::Ipv4(ip) = endpoint.addr else {
+        return;
+    };
+    let ip = ip.as_bytes();
+    let from = SocketAddrV4::new(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]), endpoint.port);
+    let rv =
+        cortex_m::interrupt::free(|cs| CLOCK.borrow(cs).borrow_mut().handle_receive(rx, from));
+    match rv {
+        // only the selected server's results are published, the others are
+        // only used to choose between them
+        Ok(new_rx_result) if new_rx_result.selected => {
+            let waker = cortex_m::interrupt::free(|cs| {
+                NEW_NTP_RESULT.borrow(cs).set(Some(new_rx_result));
+                NTP_RESULT_WAKER.borrow(cs).take()
+            });
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        Ok(_) => {}
+        // stale or stray packets
+        Err(net_time::Error::UnexpectedReply) => {}
+        Err(e) => {
+            warn!("sntp response error {:?}", e);
+        }
+    }
+}
+
+/// poll the ntp servers and discipline CLOCK, the same as the nucleo_embassy time_sync task
+pub async fn time_sync(socket: AsyncUdpSocket, servers: &[SocketAddrV4]) -> Infallible {
+    cortex_m::interrupt::free(|cs| CLOCK.borrow(cs).borrow_mut().set_servers(servers));
+
+    let mut tx_buf = [0; PACKET_SIZE];
+    let mut rx_buf = [0; 128];
+
+    info!("starting time sync with ntp servers: {:?}", servers);
+    let mut selected = None;
+    loop {
+        // one timeout per pass, any others are due immediately so the recv below won't wait
+        let timeout = cortex_m::interrupt::free(|cs| {
+            let mut clock = CLOCK.borrow(cs).borrow_mut();
+            clock.config.poll_interval = Duration::from_millis(params().ntp_poll_ms as u64);
+            clock.poll_timeout()
+        });
+        if let Err(err) = timeout {
+            warn!("sntp {:?}", err);
+        }
+        // flush anything left over before the requests go out, replies to requests that
+        // already timed out are dropped as UnexpectedReply and any still waited on are used
+        loop {
+            match socket.try_recv_from(&mut rx_buf) {
+                Ok(Some((num, endpoint))) => receive_ntp(&rx_buf[..num], endpoint),
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("sntp receive error {:?}", err);
+                    break;
+                }
+            }
+        }
+        // one request per server that is due
+        while let Some(server) = cortex_m::interrupt::free(|cs| {
+            CLOCK.borrow(cs).borrow_mut().poll_transmit(&mut tx_buf)
+        }) {
+            if let Err(err) = socket.send_to(&tx_buf, endpoint(server)).await {
+                warn!("sntp send error {:?}", err);
+            }
+        }
 
-        if self.socket.borrow_mut().send_slice(buf, endpoint).is_ok() {
-            return Ok(buf.len());
+        let (new_selected, holdover, wake) = cortex_m::interrupt::free(|cs| {
+            let clock = CLOCK.borrow(cs).borrow();
+            (clock.selected(), clock.is_holdover(), clock.next_wake())
+        });
+        if new_selected != selected {
+            selected = new_selected;
+            match selected {
+                Some(server) => info!("sntp selected {:?}", server),
+                None if holdover => warn!("sntp no servers reachable, holding over"),
+                None => warn!("sntp no servers reachable"),
+            }
         }
 
-        Err(sntpc::Error::Network)
+        // sleep until the next request or timeout unless a reply arrives first
+        // Epoch::MAX when there are no servers, never rather than a truncated deadline
+        let deadline_ms = u64::try_from(wake.as_duration().as_millis()).unwrap_or(u64::MAX);
+        match with_deadline(deadline_ms, socket.recv_from(&mut rx_buf)).await {
+            Ok(Ok((num, endpoint))) => receive_ntp(&rx_buf[..num], endpoint),
+            Ok(Err(err)) => {
+                warn!("sntp receive error {:?}", err);
+            }
+            Err(net::TimeoutError) => {}
+        }
     }
+}
 
-    async fn recv_from(
-        &self,
-        buf: &mut [u8],
-        // ) -> Result<(usize, SocketAddr), sntpc::Error> {
-    ) -> Result<(usize, SocketAddr)> {
-        let result = self.socket.borrow_mut().recv_slice(&mut buf[..]);
-
-        if let Ok((size, endpoint)) = result {
-            // make compiler and clippy happy as without the else branch clippy complains
-            // that not all variants covered for some reason
-            #[allow(irrefutable_let_patterns)]
-            let IpAddress::Ipv4(v4) = endpoint.addr else {
-                todo!()
+/// send the buffered log records to the log port from the board config on the remote, see
+/// net_loopback log_viewer
+pub async fn log_sender(socket: AsyncUdpSocket) -> Infallible {
+    let crc = crc::Crc::<u32>::new(&crc::CRC_32_ISCSI);
+    loop {
+        Timer::after_ms(10).await;
+        let remote_ip = params().remote_ip;
+        let log_endpoint =
+            IpEndpoint::new(Ipv4Address::from_bytes(&remote_ip).into(), CONFIG.ports.log);
+        while let Some(record) = logger::pop() {
+            // dropped records show up as gaps in the sequence numbers, logging the error here
+            // would only add more
+            let Ok(msg_bytes) = Message::Log(record).encode::<256>(crc.digest()) else {
+                continue;
             };
-            // TODO(lucasw) more compact conversion to SocketAddr?
-            let ip = v4.as_bytes();
-            let sockaddr = SocketAddr::new(
-                IpAddr::V4(Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3])),
-                endpoint.port,
-            );
-
-            return Ok((size, sockaddr));
+            if socket.send_to(&msg_bytes, log_endpoint).await.is_err() {
+                break;
+            }
         }
-
-        Err(sntpc::Error::Network)
     }
 }
@@ -1,92 +1,195 @@
+/*!
+Log to any combination of sinks chosen at runtime, with a level for each module
+
+NET buffers the records for the log_sender task to send to the host as Message::Log, see
+net_loopback log_viewer, the others (rtt, semihosting and itm) need a debugger attached.
+Sinks::NONE drops everything.  The log-* features only choose the sinks init() starts with
+and the panic handler.
+
+```text
+logger::init();
+logger::set_sinks(Sinks::NET | Sinks::RTT);
+logger::set_level("net_time", Some(Level::Debug));
+```
+
+The filtering and formatting are net_common::logging LevelFilters and LogLine.
+*/
+
+use core::cell::RefCell;
+use core::ops::BitOr;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering::Relaxed};
+
+use cortex_m::interrupt::Mutex;
+use cortex_m::peripheral::{ITM, itm};
+use log::{LevelFilter, Metadata, Record};
+use net_common::logging::{Level, LevelFilters, LogBuffer, LogLine, LogRecord};
+use net_time::TickSource;
+
+use crate::{CLOCK, EthernetTicks};
+
 cfg_if::cfg_if! {
-    if #[cfg(any(feature = "log-semihosting"))] {
+    if #[cfg(feature = "log-semihosting")] {
         use panic_semihosting as _;
+    } else if #[cfg(feature = "log-itm")] {
+        use panic_itm as _;
+    } else if #[cfg(feature = "log-rtt")] {
+        use panic_rtt_target as _;
+    } else {
+        use panic_halt as _;
+    }
+}
 
-        use lazy_static::lazy_static;
-        use log::LevelFilter;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sinks(pub u8);
 
-        pub use cortex_m_log::log::Logger;
-        use cortex_m_log::printer::semihosting;
-        use cortex_m_log::printer::semihosting::Semihosting;
-        use cortex_m_log::modes::InterruptOk;
-        use cortex_m_log::printer::semihosting::hio::HStdout;
+impl Sinks {
+    pub const NONE: Sinks = Sinks(0);
+    pub const NET: Sinks = Sinks(1);
+    pub const RTT: Sinks = Sinks(2);
+    pub const SEMIHOSTING: Sinks = Sinks(4);
+    pub const ITM: Sinks = Sinks(8);
 
-        lazy_static! {
-            static ref LOGGER: Logger<Semihosting<InterruptOk, HStdout>> = Logger {
-                level: LevelFilter::Debug,
-                inner: semihosting::InterruptOk::<_>::stdout().expect("Get Semihosting stdout"),
-            };
-        }
+    pub const fn contains(&self, other: Sinks) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
 
-        pub fn init() {
-            cortex_m_log::log::init(&LOGGER).unwrap();
-        }
+impl BitOr for Sinks {
+    type Output = Sinks;
+
+    fn bitor(self, other: Sinks) -> Sinks {
+        Sinks(self.0 | other.0)
     }
-    else if #[cfg(any(feature = "log-itm"))] {
-        use panic_itm as _;
+}
 
-        use lazy_static::lazy_static;
-        use log::LevelFilter;
+const fn feature_sink(enabled: bool, sink: Sinks) -> u8 {
+    if enabled { sink.0 } else { 0 }
+}
 
-        pub use cortex_m_log::log::Logger;
+/// what init() starts with, from the log-* features
+pub const DEFAULT_SINKS: Sinks = Sinks(
+    feature_sink(cfg!(feature = "log-net"), Sinks::NET)
+        | feature_sink(cfg!(feature = "log-rtt"), Sinks::RTT)
+        | feature_sink(cfg!(feature = "log-semihosting"), Sinks::SEMIHOSTING)
+        | feature_sink(cfg!(feature = "log-itm"), Sinks::ITM),
+);
 
-        use cortex_m_log::{
-            destination::Itm as ItmDest,
-            printer::itm::InterruptSync,
-            modes::InterruptFree,
-            printer::itm::ItmSync
-        };
+static SINKS: AtomicU8 = AtomicU8::new(0);
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+// the rtt control block can only be set up once
+static RTT_UP: AtomicBool = AtomicBool::new(false);
 
-        lazy_static! {
-            static ref LOGGER: Logger<ItmSync<InterruptFree>> = Logger {
-                level: LevelFilter::Debug,
-                inner: unsafe {
-                    InterruptSync::new(
-                        ItmDest::new(cortex_m::Peripherals::steal().ITM)
-                    )
-                },
-            };
-        }
+// room for levels for 8 modules
+static FILTERS: Mutex<RefCell<LevelFilters<8>>> =
+    Mutex::new(RefCell::new(LevelFilters::new(Some(Level::Info))));
 
-        pub fn init() {
-            cortex_m_log::log::init(&LOGGER).unwrap();
-        }
+// each record is ~200 bytes, the oldest are dropped when it fills
+static LOG_BUFFER: Mutex<RefCell<LogBuffer<16>>> = Mutex::new(RefCell::new(LogBuffer::new()));
+
+struct Logger;
 
+static LOGGER: Logger = Logger;
+
+/// install the logger with DEFAULT_SINKS, calling it again does nothing
+pub fn init() {
+    if INITIALIZED.swap(true, Relaxed) {
+        return;
     }
-    else if #[cfg(any(feature = "log-rtt"))] {
-        use panic_rtt_target as _;
+    set_sinks(DEFAULT_SINKS);
+    if log::set_logger(&LOGGER).is_ok() {
+        update_max_level();
+    }
+}
 
-        use log::{Level, Metadata, Record, LevelFilter};
-        use rtt_target::{rprintln, rtt_init_print};
+pub fn sinks() -> Sinks {
+    Sinks(SINKS.load(Relaxed))
+}
 
-        pub struct Logger {
-            level: Level,
-        }
+pub fn set_sinks(sinks: Sinks) {
+    if sinks.contains(Sinks::RTT) && !RTT_UP.swap(true, Relaxed) {
+        rtt_target::rtt_init_print!();
+    }
+    SINKS.store(sinks.0, Relaxed);
+}
 
-        static LOGGER: Logger = Logger {
-            level: Level::Debug,
-        };
+/// the most verbose level for a module and the modules under it, None turns it off, false if
+/// there is no room for another module
+pub fn set_level(module: &str, level: Option<Level>) -> bool {
+    let set = cortex_m::interrupt::free(|cs| FILTERS.borrow(cs).borrow_mut().set(module, level));
+    update_max_level();
+    set
+}
 
-        pub fn init() {
-            rtt_init_print!();
-            log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Debug)).unwrap();
-        }
+/// for all the modules without their own level
+pub fn set_default_level(level: Option<Level>) {
+    cortex_m::interrupt::free(|cs| FILTERS.borrow(cs).borrow_mut().set_default(level));
+    update_max_level();
+}
+
+// so the log macros skip formatting anything no module would log
+fn update_max_level() {
+    let max = cortex_m::interrupt::free(|cs| FILTERS.borrow(cs).borrow().max());
+    log::set_max_level(match max {
+        Some(level) => log::Level::from(level).to_level_filter(),
+        None => LevelFilter::Off,
+    });
+}
 
-        impl log::Log for Logger {
-            fn enabled(&self, metadata: &Metadata) -> bool {
-                metadata.level() <= self.level
+/// the oldest record waiting to be sent, always None without the NET sink
+pub fn pop() -> Option<LogRecord> {
+    cortex_m::interrupt::free(|cs| LOG_BUFFER.borrow(cs).borrow_mut().pop())
+}
 
-            }
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        sinks() != Sinks::NONE
+            && cortex_m::interrupt::free(|cs| {
+                FILTERS
+                    .borrow(cs)
+                    .borrow()
+                    .enabled(metadata.target(), metadata.level().into())
+            })
+    }
 
-            fn log(&self, record: &Record) {
-                rprintln!("{} - {}", record.level(), record.args());
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let sinks = sinks();
+        let local = EthernetTicks.now();
+        let level = Level::from(record.level());
+        let module = record.target();
+        let epoch = cortex_m::interrupt::free(|cs| {
+            // the local time if logging while the clock is in use
+            let epoch = match CLOCK.borrow(cs).try_borrow_mut() {
+                Ok(mut clock) => clock.corrected(local),
+                Err(_) => local,
+            };
+            if sinks.contains(Sinks::NET) {
+                let mut buffer = LOG_BUFFER.borrow(cs).borrow_mut();
+                buffer.push(level, epoch, module, *record.args());
             }
-
-            fn flush(&self) {}
+            epoch
+        });
+
+        let line = LogLine {
+            epoch,
+            level,
+            module,
+            args: *record.args(),
+        };
+        if sinks.contains(Sinks::RTT) {
+            rtt_target::rprintln!("{}", line);
+        }
+        if sinks.contains(Sinks::SEMIHOSTING) {
+            cortex_m_semihosting::hprintln!("{}", line);
+        }
+        if sinks.contains(Sinks::ITM) {
+            // stimulus port 0
+            let itm = unsafe { &mut *(ITM::PTR as *mut itm::RegisterBlock) };
+            cortex_m::itm::write_fmt(&mut itm.stim[0], format_args!("{}\n", line));
         }
     }
-    else {
-        use panic_halt as _;
-        pub fn init() {}
-    }
+
+    fn flush(&self) {}
 }
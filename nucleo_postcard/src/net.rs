@@ -0,0 +1,309 @@
+/*!
+Async access to the nucleo-h7xx ethernet interface's smoltcp sockets and the systick clock, for
+the tasks run by executor.rs
+
+Every socket operation takes the interface inside interrupt_free, when the socket would block
+(nothing received, or the transmit buffer is full) it registers the task's waker with the
+smoltcp socket and returns Pending.  poll_interface() moves the packets, which wakes the
+tasks waiting on them, it runs after every interrupt and whenever a packet is queued to send.
+
+nucleo-h7xx's EthernetInterface owns the smoltcp device, so frames can't be stamped in the
+driver like nucleo_embassy does.  The stamped socket calls use the time of the interface poll
+that moved the packet instead, which still leaves out how long the task took to run.
+*/
+
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+use core::future::{Future, poll_fn};
+use core::pin::{Pin, pin};
+use core::sync::atomic::Ordering::Relaxed;
+use core::task::{Context, Poll, Waker};
+
+use cortex_m::interrupt::Mutex;
+use log::warn;
+use net_common::Epoch;
+use net_common::hwstamp::Stamp;
+use net_time::TickSource;
+use nucleo_h7xx::ethernet::{ATOMIC_TIME, EthernetInterface};
+use smoltcp::iface::SocketHandle;
+use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+use smoltcp::wire::IpEndpoint;
+
+use crate::{EthernetTicks, executor};
+
+// smoltcp keeps one send waker per socket, a task waiting for room in a socket another task
+// also sends from retries after this in case its waker was replaced
+const SEND_RETRY_MS: u64 = 1;
+
+// poll_interface(), to send what was just queued without waiting for an interrupt
+static INTERFACE_WAKER: Mutex<RefCell<Option<Waker>>> = Mutex::new(RefCell::new(None));
+
+// the tick count at the start of the latest interface poll, and how many polls there have been
+static LAST_POLL: Mutex<Cell<(u32, Epoch)>> = Mutex::new(Cell::new((0, Epoch::ZERO)));
+
+// the tasks in send_to_stamped() waiting for the next interface poll
+static POLL_WAKERS: Mutex<RefCell<[Option<Waker>; executor::MAX_TASKS]>> =
+    Mutex::new(RefCell::new([const { None }; executor::MAX_TASKS]));
+
+fn last_poll() -> (u32, Epoch) {
+    cortex_m::interrupt::free(|cs| LAST_POLL.borrow(cs).get())
+}
+
+/// milliseconds since boot from the systick
+pub fn now_ms() -> u64 {
+    ATOMIC_TIME.load(Relaxed) as u64
+}
+
+/// wake the task after the next interface poll
+fn wake_after_poll(waker: &Waker) {
+    cortex_m::interrupt::free(|cs| {
+        let mut wakers = POLL_WAKERS.borrow(cs).borrow_mut();
+        if wakers
+            .iter()
+            .flatten()
+            .any(|poll_waker| poll_waker.will_wake(waker))
+        {
+            return;
+        }
+        if let Some(free) = wakers.iter_mut().find(|poll_waker| poll_waker.is_none()) {
+            *free = Some(waker.clone());
+        } else {
+            // not one of executor::run()'s wakers, poll again straight away rather than never
+            waker.wake_by_ref();
+        }
+    });
+}
+
+fn wake_interface() {
+    let waker = cortex_m::interrupt::free(|cs| INTERFACE_WAKER.borrow(cs).take());
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// process received packets and send queued ones after every interrupt, and when a task queues
+/// a packet, never finishes
+pub async fn poll_interface() -> Infallible {
+    poll_fn(|cx| {
+        cortex_m::interrupt::free(|cs| {
+            INTERFACE_WAKER.borrow(cs).replace(Some(cx.waker().clone()));
+        });
+        executor::wake_on_interrupt(cx.waker());
+        // received frames were already waiting, and the sent ones go out during the poll
+        let started = EthernetTicks.now();
+        cortex_m::interrupt::free(|cs| {
+            let last_poll = LAST_POLL.borrow(cs);
+            last_poll.set((last_poll.get().0.wrapping_add(1), started));
+        });
+        EthernetInterface::interrupt_free(|ethernet_interface| {
+            match ethernet_interface.poll() {
+                // packets were processed or emitted
+                Ok(_) => {}
+                Err(smoltcp::Error::Exhausted) => (),
+                Err(smoltcp::Error::Unrecognized) => (),
+                Err(e) => {
+                    warn!("ethernet::EthernetInterface.poll() -> {:?}", e);
+                }
+            }
+        });
+        cortex_m::interrupt::free(|cs| {
+            for waker in POLL_WAKERS.borrow(cs).borrow_mut().iter_mut() {
+                if let Some(waker) = waker.take() {
+                    waker.wake();
+                }
+            }
+        });
+        Poll::Pending
+    })
+    .await
+}
+
+pub struct Timer {
+    deadline_ms: u64,
+}
+
+impl Timer {
+    pub fn at(deadline_ms: u64) -> Self {
+        Self { deadline_ms }
+    }
+
+    pub fn after_ms(duration_ms: u64) -> Self {
+        Self::at(now_ms() + duration_ms)
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if now_ms() >= self.deadline_ms {
+            Poll::Ready(())
+        } else {
+            executor::wake_at(self.deadline_ms, cx.waker());
+            Poll::Pending
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeoutError;
+
+/// the output of fut, or an error if it isn't done by the deadline
+pub async fn with_deadline<F: Future>(
+    deadline_ms: u64,
+    fut: F,
+) -> Result<F::Output, TimeoutError> {
+    let mut fut = pin!(fut);
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        if now_ms() >= deadline_ms {
+            return Poll::Ready(Err(TimeoutError));
+        }
+        executor::wake_at(deadline_ms, cx.waker());
+        Poll::Pending
+    })
+    .await
+}
+
+/// packet storage for one socket, e.g. `cortex_m::singleton!(: UdpBuffers<2048> =
+/// UdpBuffers::new()).unwrap()`
+pub struct UdpBuffers<const N: usize> {
+    rx_meta: [UdpPacketMetadata; 8],
+    rx: [u8; N],
+    tx_meta: [UdpPacketMetadata; 8],
+    tx: [u8; N],
+}
+
+impl<const N: usize> UdpBuffers<N> {
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [UdpPacketMetadata::EMPTY; 8],
+            rx: [0; N],
+            tx_meta: [UdpPacketMetadata::EMPTY; 8],
+            tx: [0; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for UdpBuffers<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// a udp socket on the ethernet interface, copies share the same socket
+#[derive(Clone, Copy)]
+pub struct AsyncUdpSocket {
+    handle: SocketHandle,
+}
+
+impl AsyncUdpSocket {
+    // not EthernetInterface::new_udp_socket(), every socket it makes uses the same buffers
+    pub fn bind<const N: usize>(
+        buffers: &'static mut UdpBuffers<N>,
+        endpoint: IpEndpoint,
+    ) -> Result<Self, smoltcp::Error> {
+        let UdpBuffers {
+            rx_meta,
+            rx,
+            tx_meta,
+            tx,
+        } = buffers;
+        let mut socket = UdpSocket::new(
+            UdpSocketBuffer::new(&mut rx_meta[..], &mut rx[..]),
+            UdpSocketBuffer::new(&mut tx_meta[..], &mut tx[..]),
+        );
+        socket.bind(endpoint)?;
+        let handle = EthernetInterface::interrupt_free(|ethernet_interface| {
+            ethernet_interface.interface.as_mut().unwrap().add_socket(socket)
+        });
+        Ok(Self { handle })
+    }
+
+    fn with_socket<R>(&self, f: impl FnOnce(&mut UdpSocket) -> R) -> R {
+        EthernetInterface::interrupt_free(|ethernet_interface| {
+            let socket = ethernet_interface
+                .interface
+                .as_mut()
+                .unwrap()
+                .get_socket::<UdpSocket>(self.handle);
+            f(socket)
+        })
+    }
+
+    /// waits for room in the transmit buffer
+    pub async fn send_to(&self, buf: &[u8], endpoint: IpEndpoint) -> Result<(), smoltcp::Error> {
+        let rv = poll_fn(|cx| {
+            self.with_socket(|socket| match socket.send_slice(buf, endpoint) {
+                Err(smoltcp::Error::Exhausted) => {
+                    socket.register_send_waker(cx.waker());
+                    executor::wake_at(now_ms() + SEND_RETRY_MS, cx.waker());
+                    Poll::Pending
+                }
+                rv => Poll::Ready(rv),
+            })
+        })
+        .await;
+        wake_interface();
+        rv
+    }
+
+    /// send_to() and wait for the interface poll that sends it, unless it is held up waiting
+    /// for the remote mac address
+    pub async fn send_to_stamped(
+        &self,
+        buf: &[u8],
+        endpoint: IpEndpoint,
+    ) -> Result<Stamp, smoltcp::Error> {
+        self.send_to(buf, endpoint).await?;
+        // send_to() woke poll_interface(), which runs on the executor's next pass
+        let (polls, _) = last_poll();
+        let sent = poll_fn(|cx| match last_poll() {
+            (count, started) if count != polls => Poll::Ready(started),
+            _ => {
+                wake_after_poll(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await;
+        Ok(Stamp::driver(sent))
+    }
+
+    /// a packet if there is one already received, without waiting
+    pub fn try_recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, IpEndpoint)>, smoltcp::Error> {
+        match self.with_socket(|socket| socket.recv_slice(buf)) {
+            Ok(rx) => Ok(Some(rx)),
+            Err(smoltcp::Error::Exhausted) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// waits for a packet, only one task can wait on a socket at a time
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, IpEndpoint), smoltcp::Error> {
+        poll_fn(|cx| {
+            self.with_socket(|socket| match socket.recv_slice(buf) {
+                Err(smoltcp::Error::Exhausted) => {
+                    socket.register_recv_waker(cx.waker());
+                    Poll::Pending
+                }
+                rv => Poll::Ready(rv),
+            })
+        })
+        .await
+    }
+
+    /// recv_from() with the time of the latest interface poll, the one that received the
+    /// packet unless the task was slow enough to miss another
+    pub async fn recv_from_stamped(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, IpEndpoint, Stamp), smoltcp::Error> {
+        let (num, endpoint) = self.recv_from(buf).await?;
+        Ok((num, endpoint, Stamp::driver(last_poll().1)))
+    }
+}